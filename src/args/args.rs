@@ -1,14 +1,693 @@
-use clap::Parser;
+use clap::{Command, CommandFactory, Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
+use std::ffi::OsString;
+
+use crate::exec::ArgsOverride;
+
+/// Environment variable holding options prepended to the command line
+pub const NANSI_OPTS: &str = "NANSI_OPTS";
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(args_override_self = true)]
 pub struct Args {
-    pub nansi_file: String,
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    #[arg(required_unless_present = "inline")]
+    pub nansi_file: Option<String>,
+
+    /// Execute the NansiFile given as json (an object or a bare array of
+    /// items) instead of reading it from a file
+    #[arg(long, value_name = "JSON", conflicts_with = "nansi_file")]
+    pub inline: Option<String>,
+
+    /// Print the items that would run, with their compiled arguments, without
+    /// executing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Validate the NansiFile without executing it
+    #[arg(long)]
+    pub check: bool,
+
+    /// Write the NansiFile with its includes resolved, once validated, to OUT
+    /// (e.g. setup.nansic.json) along with the digests of its source files;
+    /// runs use setup.nansic.json next to setup.json while they are unchanged
+    #[arg(long, value_name = "OUT", conflicts_with = "inline")]
+    pub compile: Option<String>,
+
+    /// Print the labeled items with their index and command without executing them
+    #[arg(long)]
+    pub list_labels: bool,
+
+    /// Run the items in a random order respecting their prerequisites; the seed
+    /// used is printed so the order can be reproduced with --shuffle=SEED
+    #[arg(long, value_name = "SEED", require_equals = true)]
+    pub shuffle: Option<Option<u64>>,
+
+    /// With --check, compute N shuffled orders and verify that none violates
+    /// the declared order
+    #[arg(long, value_name = "N", requires = "check")]
+    pub shuffle_validate: Option<u64>,
+
+    /// Format of listings such as --list-labels
+    #[arg(long, value_name = "FORMAT", default_value = "text", value_parser = ["text", "json"])]
+    pub output: String,
+
+    /// Treat warnings about suspicious items (e.g. unexpanded tags) as failures
+    /// and exit with 1 if an item failed (items with ignore_errors do not count)
+    #[arg(long)]
+    pub strict: bool,
+
+    /// With --strict, also exit with 1 if an item was skipped because of unmet
+    /// prerequisites
+    #[arg(long, requires = "strict")]
+    pub strict_skips: bool,
+
+    /// Fail before running anything if a prerequisite refers to a label no
+    /// item has or only used further down, which can never be met (a warning
+    /// otherwise)
+    #[arg(long)]
+    pub strict_prereqs: bool,
+
+    /// Run even if a require_env or forbid_env constraint of the NansiFile is
+    /// violated, warning about it
+    #[arg(long)]
+    pub force_env_checks: bool,
+
+    /// Write an ndjson event stream to the given file descriptor (Unix)
+    #[arg(long, value_name = "FD")]
+    pub status_fd: Option<i32>,
+
+    /// Write an ndjson event stream to the given named pipe
+    #[arg(long, value_name = "NAME")]
+    pub status_pipe: Option<String>,
+
+    /// Run items even if their idempotence_key is unchanged since the last successful run
+    #[arg(long)]
+    pub force: bool,
+
+    /// Location of the state file used by idempotence_key
+    #[arg(long, value_name = "PATH")]
+    pub state_file: Option<String>,
+
+    /// Skip the remaining items once this many items failed (overrides max_failures)
+    #[arg(long, value_name = "N")]
+    pub max_failures: Option<u32>,
+
+    /// Stop the run at the first failed item and exit with an error (also set by fail_fast)
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Run up to N consecutive items marked parallel at the same time
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Command running the items marked elevate, e.g. doas or pkexec (overrides
+    /// elevation_command, sudo by default)
+    #[arg(long, value_name = "COMMAND")]
+    pub elevation_command: Option<String>,
+
+    /// Let the elevation command prompt for a password instead of failing the
+    /// elevated items when it needs one
+    #[arg(long)]
+    pub interactive_elevation: bool,
+
+    /// Run the items marked confirm without asking, for unattended runs
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Print `nansi: ok=N err=N warn=N skip=N duration_ms=N exit=N` as the
+    /// last line of stdout; fields may be appended but never reordered or
+    /// removed. Ctrl-C then cancels the remaining items instead of exiting
+    #[arg(long)]
+    pub summary_line: bool,
+
+    /// Print one line per item to stdout with its index, label, status, exit
+    /// code and duration separated by tabs (`3\tbuild\tok\t0\t2140ms`), stable
+    /// across versions; everything else is printed to stderr
+    #[arg(long, conflicts_with = "summary_line")]
+    pub porcelain: bool,
+
+    /// Print only failed items with their output, leaving out the banner,
+    /// warnings and the status lines of other items
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Print the compiled arguments of every item before it runs and its exit
+    /// code after, and details such as the number of redacted matches and the
+    /// commands of elevated items
+    #[arg(long, conflicts_with = "quiet")]
+    pub verbose: bool,
+
+    /// Print the resolved definition of failed items as json (secrets masked)
+    #[arg(long)]
+    pub echo_item_on_failure: bool,
+
+    /// Send a desktop notification with the results when the run completes
+    #[arg(long)]
+    pub notify_desktop: bool,
+
+    /// Show the duration and peak memory of each item after its status line
+    #[arg(long)]
+    pub timings: bool,
+
+    /// Keep the temporary directory of the run ({nansi.tmp_dir}) instead of removing it
+    #[arg(long)]
+    pub keep_tmp: bool,
+
+    /// Wrap the output of items in collapsible sections of the CI log (auto
+    /// detects GitHub Actions and GitLab CI)
+    #[arg(long, value_name = "FLAVOR", default_value = "auto", value_parser = ["auto", "github", "gitlab", "none"])]
+    pub ci_folding: String,
+
+    /// Repeat the last N lines of the output of each failed item at the end of
+    /// the run (0 to disable)
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub failure_preview_lines: usize,
+
+    /// Exit with CODE if an item changed something (see changed_when)
+    #[arg(long, value_name = "CODE")]
+    pub changed_exit_code: Option<i32>,
+
+    /// Skip the items that succeeded in a trace of an earlier run of the same
+    /// NansiFile and treat them as satisfied prerequisites
+    #[arg(long, value_name = "TRACE")]
+    pub import_state: Option<String>,
+
+    /// Stream the output of items is printed to unless set by the item
+    #[arg(long, value_name = "STREAM", default_value = "stdout", value_parser = ["stdout", "stderr"])]
+    pub output_stream: String,
+
+    /// Stream status lines and other messages are printed to
+    #[arg(long, value_name = "STREAM", default_value = "stdout", value_parser = ["stdout", "stderr"])]
+    pub status_stream: String,
+
+    /// Show the item being run on a line redrawn in place (auto uses it on
+    /// terminals supporting it, plain prints only the status lines)
+    #[arg(long, value_name = "MODE", default_value = "auto", value_parser = ["auto", "plain", "fancy"])]
+    pub progress: String,
+
+    /// Color status lines and messages: auto colors each stream that is a
+    /// terminal (honoring NO_COLOR and CLICOLOR_FORCE)
+    #[arg(long, value_name = "WHEN", default_value = "auto", value_parser = ["auto", "always", "never"])]
+    pub color: String,
+
+    /// Parse the NansiFile as json or yaml (auto picks yaml for .yaml and .yml
+    /// files, json otherwise)
+    #[arg(long, value_name = "FORMAT", default_value = "auto", value_parser = ["auto", "json", "yaml"])]
+    pub format: String,
+
+    /// Allow fetching the NansiFile from a plain http:// URL
+    #[arg(long)]
+    pub insecure_http: bool,
+
+    /// Abort unless the SHA-256 digest of the NansiFile bytes matches HEX
+    #[arg(long, value_name = "HEX")]
+    pub expect_sha256: Option<String>,
+
+    /// Abort unless the NansiFile has a detached ssh signature (<NansiFile>.sig,
+    /// namespace "nansi") made with the public key in PATH
+    #[arg(long, value_name = "PATH")]
+    pub verify_key: Option<String>,
+
+    /// Answer prompts from a json file mapping prompt names and item labels to responses
+    #[arg(long, value_name = "PATH")]
+    pub answers: Option<String>,
+
+    /// Record the run (options, resolved commands, output, timings) in a trace file
+    #[arg(long, value_name = "PATH")]
+    pub trace: Option<String>,
+
+    /// Append a plain-text log of the run (commands, statuses, exit codes, output) to a file
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<String>,
+
+    /// Write a report of the results of all items (args, status, exit code, output) to a file
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<String>,
+
+    /// Format of the report written with --report
+    #[arg(long, value_name = "FORMAT", default_value = "json", value_parser = ["json"])]
+    pub report_format: String,
+
+    /// Run only the items labeled LABEL, the others are skipped; may be
+    /// repeated. LABEL may be a glob (deploy-*) or a prefix of a label, a
+    /// prefix of several labels asking which to run in a terminal
+    #[arg(long, value_name = "LABEL")]
+    pub only: Vec<String>,
+
+    /// Like --only, also running the prerequisites of the items (transitively)
+    #[arg(long, value_name = "LABEL")]
+    pub only_with_deps: Vec<String>,
+
+    /// Skip the items labeled LABEL; may be repeated
+    #[arg(long, value_name = "LABEL")]
+    pub skip: Vec<String>,
+
+    /// Make the items labeled LABEL fail without running them, to rehearse how
+    /// failures are handled; comma-separated, may be repeated
+    #[arg(long, value_name = "LABEL", value_delimiter = ',')]
+    pub inject_failure: Vec<String>,
+
+    /// Run the items labeled LABEL once per line of the standard input
+    /// (trimmed, blank lines skipped), the line expanding {item}
+    #[arg(long, value_name = "LABEL")]
+    pub stdin_each: Option<String>,
+
+    /// Add arguments (split like a shell does) after the ones of the items
+    /// labeled LABEL, e.g. build="--verbose --timings"; may be repeated
+    #[arg(long, value_name = "LABEL=ARGS")]
+    pub append_args: Vec<String>,
+
+    /// Add arguments (split like a shell does) before the ones of the items
+    /// labeled LABEL; may be repeated
+    #[arg(long, value_name = "LABEL=ARGS")]
+    pub prepend_args: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Inspect trace files written with --trace
+    Trace {
+        #[command(subcommand)]
+        command: TraceCommand,
+    },
+
+    /// List the items of a NansiFile
+    List {
+        nansi_file: String,
+
+        /// Only list items whose label is not referenced by any other item
+        #[arg(long)]
+        unused_labels: bool,
+    },
+
+    /// Check a NansiFile without executing it; fails if errors are found
+    Validate { nansi_file: String },
+
+    /// List the items of a NansiFile which succeeded only after retries or
+    /// alternated between success and failure over the runs of --report files
+    Flaky {
+        nansi_file: String,
+
+        /// Reports written with --report, oldest first
+        #[arg(required = true)]
+        reports: Vec<String>,
+
+        /// Print a table (text) or a json array
+        #[arg(long, value_name = "FORMAT", default_value = "text", value_parser = ["text", "json"])]
+        output: String,
+    },
+
+    /// Print the fields of a NansiFile and of its items with their types,
+    /// defaults and descriptions
+    HelpFormat,
+
+    /// Refactor the labels of a NansiFile
+    Labels {
+        #[command(subcommand)]
+        command: LabelsCommand,
+    },
+
+    /// Execute NansiFiles submitted over HTTP (POST /runs, GET
+    /// /runs/{id}/events, GET /runs/{id}/report, POST /runs/{id}/cancel)
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7878")]
+        listen: String,
+
+        /// Exec an item of a submitted NansiFile may run, as written in the
+        /// NansiFile; may be repeated, at least one is required
+        #[arg(long, value_name = "EXEC", required = true)]
+        allow_exec: Vec<String>,
+
+        /// Number of runs executed at the same time
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        max_runs: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TraceCommand {
+    /// Print a trace file in a human readable form
+    Show { trace_file: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LabelsCommand {
+    /// Rename a label and every prerequisite referring to it
+    Rename {
+        nansi_file: String,
+
+        old: String,
+
+        new: String,
+
+        /// Save the changes to the NansiFile instead of only reporting them
+        #[arg(long)]
+        write: bool,
+    },
 }
 
 impl Args {
+    /// Parses the command line along with the options in `NANSI_OPTS`.
+    ///
+    /// The variable is parsed on its own first, so errors in it are attributed
+    /// to it. Options given on the command line then take precedence: an
+    /// option of the variable is dropped if the command line sets it too or
+    /// sets one it conflicts with (e.g. `--verbose` drops `--quiet`). The
+    /// variable is ignored when a subcommand is used.
     pub fn new() -> Result<Args, Box<dyn Error>> {
-        Ok(Args::parse())
+        let cli_args: Vec<OsString> = env::args_os().collect();
+        let opts = match env::var(NANSI_OPTS) {
+            Ok(v) if !v.trim().is_empty() => v,
+            _ => return Ok(Args::parse_from(cli_args)),
+        };
+
+        let env_args = match split_args(&opts) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("{}: {}", NANSI_OPTS, e).into()),
+        };
+
+        if let Ok(args) = Args::try_parse_from(&cli_args) {
+            if args.command.is_some() {
+                return Ok(args);
+            }
+        }
+
+        let mut command = Args::command();
+        command.build();
+        let env_options = get_options(&command, &env_args);
+
+        // The variable alone does not name the NansiFile, a placeholder after
+        // `--` stands for it without being taken as the value of an option.
+        let mut env_only_args: Vec<OsString> = vec![OsString::from("nansi")];
+        env_only_args.extend(env_args.iter().map(OsString::from));
+        if !env_options
+            .iter()
+            .any(|(id, _)| id == "nansi_file" || id == "inline")
+        {
+            env_only_args.push(OsString::from("--"));
+            env_only_args.push(OsString::from("NANSI_OPTS_PLACEHOLDER"));
+        }
+        if let Err(e) = Args::try_parse_from(env_only_args) {
+            if e.kind() == clap::error::ErrorKind::DisplayHelp
+                || e.kind() == clap::error::ErrorKind::DisplayVersion
+            {
+                e.exit();
+            }
+            return Err(format!("{}: {}", NANSI_OPTS, e.to_string().trim_end()).into());
+        }
+
+        let cli_strings: Vec<String> = cli_args
+            .iter()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let cli_ids: Vec<String> = get_options(&command, &cli_strings)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut args: Vec<OsString> = cli_args.iter().take(1).cloned().collect();
+        for (id, tokens) in env_options {
+            if !cli_ids
+                .iter()
+                .any(|cli_id| *cli_id == id || conflicts(&command, &id, cli_id))
+            {
+                args.extend(tokens.into_iter().map(OsString::from));
+            }
+        }
+        args.extend(cli_args.into_iter().skip(1));
+
+        Ok(Args::parse_from(args))
+    }
+}
+
+/// Splits arguments of the command line (without the name of the program)
+/// into options with their value, each with the id of its argument: the name
+/// of the option if it is unknown, `nansi_file` for positional arguments.
+fn get_options(command: &Command, args: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut options: Vec<(String, Vec<String>)> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            options.extend(args.map(|arg| (String::from("nansi_file"), vec![arg.clone()])));
+            break;
+        }
+
+        let Some(name) = arg.strip_prefix("--") else {
+            options.push((String::from("nansi_file"), vec![arg.clone()]));
+            continue;
+        };
+        let (name, has_value) = match name.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (name, false),
+        };
+
+        let mut tokens = vec![arg.clone()];
+        let id = match command.get_arguments().find(|a| a.get_long() == Some(name)) {
+            Some(option) => {
+                if !has_value
+                    && option.get_action().takes_values()
+                    && !option.is_require_equals_set()
+                {
+                    tokens.extend(args.next().cloned());
+                }
+                String::from(option.get_id().as_str())
+            }
+            None => String::from(name),
+        };
+        options.push((id, tokens));
+    }
+
+    options
+}
+
+/// Returns `true` if either of the arguments of ids `a` and `b` conflicts with
+/// the other.
+fn conflicts(command: &Command, a: &str, b: &str) -> bool {
+    let find = |id: &str| command.get_arguments().find(|arg| arg.get_id() == id);
+    let (Some(a), Some(b)) = (find(a), find(b)) else {
+        return false;
+    };
+
+    command
+        .get_arg_conflicts_with(a)
+        .iter()
+        .any(|arg| arg.get_id() == b.get_id())
+        || command
+            .get_arg_conflicts_with(b)
+            .iter()
+            .any(|arg| arg.get_id() == a.get_id())
+}
+
+impl Args {
+    /// Returns the arguments of `--prepend-args` and `--append-args` by label,
+    /// accumulated in the order they were given.
+    pub fn get_args_overrides(&self) -> Result<BTreeMap<String, ArgsOverride>, Box<dyn Error>> {
+        let mut overrides: BTreeMap<String, ArgsOverride> = BTreeMap::new();
+        for (option, values, prepend) in [
+            ("--prepend-args", &self.prepend_args, true),
+            ("--append-args", &self.append_args, false),
+        ] {
+            for value in values {
+                let (label, args) = match value.split_once('=') {
+                    Some((label, args)) if !label.is_empty() => (label, args),
+                    _ => return Err(format!("{} {}: expected LABEL=ARGS", option, value).into()),
+                };
+                let args = match split_args(args) {
+                    Ok(v) => v,
+                    Err(e) => return Err(format!("{} {}: {}", option, value, e).into()),
+                };
+
+                let args_override = overrides.entry(String::from(label)).or_default();
+                if prepend {
+                    args_override.prepend.extend(args);
+                } else {
+                    args_override.append.extend(args);
+                }
+            }
+        }
+
+        Ok(overrides)
+    }
+}
+
+/// Splits a string into arguments like a POSIX shell does, without expansions:
+/// arguments are separated by whitespace, single quotes preserve their content
+/// literally, double quotes allow `\"`, `\\`, `\$` and `` \` `` escapes and a
+/// backslash outside of quotes escapes the following character.
+pub fn split_args(s: &str) -> Result<Vec<String>, String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(String::from("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if "\"\\$`".contains(c) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(String::from("unterminated double quote")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(String::from("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                match chars.next() {
+                    Some(c) => arg.push(c),
+                    None => return Err(String::from("trailing backslash")),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(arg.clone());
+                    arg.clear();
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+#[test]
+fn get_args_overrides_test() {
+    let args = Args::parse_from([
+        "nansi",
+        "nansi.json",
+        "--append-args",
+        "build=--verbose --timings",
+        "--prepend-args",
+        "build=+nightly",
+        "--append-args",
+        "build='-j 4'",
+        "--append-args",
+        "test=",
+    ]);
+
+    let overrides = args.get_args_overrides().unwrap();
+    assert_eq!(overrides.len(), 2);
+    assert_eq!(overrides["build"].prepend, vec!["+nightly"]);
+    assert_eq!(
+        overrides["build"].append,
+        vec!["--verbose", "--timings", "-j 4"]
+    );
+    assert_eq!(overrides["test"], ArgsOverride::default());
+
+    let args = Args::parse_from(["nansi", "nansi.json", "--append-args=--verbose"]);
+    assert_eq!(
+        args.get_args_overrides().unwrap_err().to_string(),
+        "--append-args --verbose: expected LABEL=ARGS"
+    );
+
+    let args = Args::parse_from(["nansi", "nansi.json", "--prepend-args", "build='x"]);
+    assert_eq!(
+        args.get_args_overrides().unwrap_err().to_string(),
+        "--prepend-args build='x: unterminated single quote"
+    );
+}
+
+#[test]
+fn split_args_test() {
+    let cases = [
+        ("", vec![]),
+        ("  --quiet   --strict ", vec!["--quiet", "--strict"]),
+        (
+            "--trace '/tmp/my trace.json'",
+            vec!["--trace", "/tmp/my trace.json"],
+        ),
+        (
+            "--trace \"a \\\"b\\\" \\n\"",
+            vec!["--trace", "a \"b\" \\n"],
+        ),
+        ("a\\ b c''d \"\"", vec!["a b", "cd", ""]),
+    ];
+
+    for (s, expected) in cases {
+        assert_eq!(split_args(s).unwrap(), expected, "{}", s);
     }
+
+    assert!(split_args("--trace 'x").is_err());
+    assert!(split_args("--trace \"x").is_err());
+    assert!(split_args("x\\").is_err());
+}
+
+#[test]
+fn get_options_test() {
+    let mut command = Args::command();
+    command.build();
+    let args: Vec<String> = [
+        "--quiet",
+        "--jobs",
+        "2",
+        "--color=never",
+        "--shuffle",
+        "setup.json",
+        "--bogus",
+        "--",
+        "--other.json",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+
+    let options = get_options(&command, &args);
+    let expected: Vec<(&str, Vec<&str>)> = vec![
+        ("quiet", vec!["--quiet"]),
+        ("jobs", vec!["--jobs", "2"]),
+        ("color", vec!["--color=never"]),
+        ("shuffle", vec!["--shuffle"]),
+        ("nansi_file", vec!["setup.json"]),
+        ("bogus", vec!["--bogus"]),
+        ("nansi_file", vec!["--other.json"]),
+    ];
+    assert_eq!(options.len(), expected.len());
+    for ((id, tokens), (expected_id, expected_tokens)) in options.iter().zip(expected) {
+        assert_eq!(id, expected_id);
+        assert_eq!(tokens, &expected_tokens);
+    }
+
+    assert!(conflicts(&command, "quiet", "verbose"));
+    assert!(conflicts(&command, "verbose", "quiet"));
+    assert!(conflicts(&command, "inline", "nansi_file"));
+    assert!(!conflicts(&command, "quiet", "jobs"));
+    assert!(!conflicts(&command, "quiet", "bogus"));
 }
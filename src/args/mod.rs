@@ -1,3 +1,3 @@
 pub mod args;
 
-pub use args::Args;
+pub use args::{Args, Commands, LabelsCommand, TraceCommand};
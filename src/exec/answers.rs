@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// Predetermined responses to prompts (`--answers`), keyed by prompt or var
+/// name or by item label; used instead of reading the terminal so interactive
+/// runs can be scripted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Answers {
+    /// Path of the answers file, used in error messages
+    pub file_path: String,
+
+    pub answers: BTreeMap<String, String>,
+}
+
+impl Answers {
+    /// Loads the answers from a `json` object mapping keys to responses.
+    pub fn from(file_path: &str) -> Result<Answers, io::Error> {
+        let answers_str = match fs::read_to_string(file_path) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e)));
+            }
+        };
+
+        match serde_json::from_str(answers_str.as_str()) {
+            Ok(answers) => Ok(Answers {
+                file_path: String::from(file_path),
+                answers,
+            }),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", file_path, e),
+            )),
+        }
+    }
+
+    /// Returns the response for `key`; a key missing from the file is an error
+    /// so scripted runs fail fast instead of waiting for input.
+    pub fn get(&self, key: &str) -> Result<&str, io::Error> {
+        match self.answers.get(key) {
+            Some(answer) => Ok(answer.as_str()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no answer for \"{}\"", self.file_path, key),
+            )),
+        }
+    }
+}
+
+/// Asks `question` and returns the response, taken from `answers` if set and
+/// read from `stdin` otherwise.
+pub fn ask(answers: Option<&Answers>, key: &str, question: &str) -> Result<String, io::Error> {
+    if let Some(answers) = answers {
+        return answers.get(key).map(String::from);
+    }
+
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "{} ", question)?;
+    stdout.flush()?;
+
+    let mut response = String::new();
+    if io::stdin().lock().read_line(&mut response)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("no response for \"{}\" (stdin is closed)", key),
+        ));
+    }
+
+    Ok(String::from(response.trim_end_matches(['\r', '\n'])))
+}
+
+#[test]
+fn answers_get_test() {
+    let answers = Answers {
+        file_path: String::from("answers.json"),
+        answers: BTreeMap::from([(String::from("wipe-disk"), String::from("yes"))]),
+    };
+
+    assert_eq!(
+        ask(Some(&answers), "wipe-disk", "Wipe the disk?").unwrap(),
+        "yes"
+    );
+    assert_eq!(
+        answers.get("region").unwrap_err().to_string(),
+        "answers.json: no answer for \"region\""
+    );
+}
@@ -0,0 +1,314 @@
+use std::env;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::{
+    compile_arg, deserialize_size_mib, resolve_path, ExecContext, ExecStatus, FailureReason,
+    RunOutcome,
+};
+
+/// Checks of an `assert` item, evaluated natively instead of by a child
+/// process; all checks that are set must pass for the item to succeed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Assertions {
+    /// Name of an environment variable that must be set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_set: Option<String>,
+
+    /// Path (file or directory) that must exist, see `resolve_path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_exists: Option<String>,
+
+    /// Command that must be found on `PATH`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_on_path: Option<String>,
+
+    /// Minimum free space in MB on the file system holding `path`; also
+    /// accepts a size string such as `5GB` (see `parse_size`), rounded up
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_min_free_space_mb"
+    )]
+    pub min_free_space_mb: Option<u64>,
+
+    /// Path checked by `min_free_space_mb` (see `resolve_path`), the current
+    /// directory if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+fn deserialize_min_free_space_mb<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_size_mib(deserializer, "min_free_space_mb")
+}
+
+impl Assertions {
+    /// Returns the checks in the form `name=value`, as shown in the status line.
+    pub fn describe(&self) -> Vec<String> {
+        let mut checks: Vec<String> = Vec::new();
+        if let Some(name) = &self.env_set {
+            checks.push(format!("env_set={}", name));
+        }
+        if let Some(path) = &self.path_exists {
+            checks.push(format!("path_exists={}", path));
+        }
+        if let Some(command) = &self.command_on_path {
+            checks.push(format!("command_on_path={}", command));
+        }
+        if let Some(min_mb) = self.min_free_space_mb {
+            checks.push(format!("min_free_space_mb={}", min_mb));
+        }
+        if let Some(path) = &self.path {
+            checks.push(format!("path={}", path));
+        }
+
+        checks
+    }
+
+    /// Returns `true` if no check is set.
+    pub fn is_empty(&self) -> bool {
+        self.describe().is_empty()
+    }
+}
+
+/// Evaluates the assertions; the result of each check is appended to `lines`
+/// and the messages of failed checks make up the output of the item.
+pub(crate) fn run_assertions(
+    assertions: &Assertions,
+    ctx: &ExecContext,
+    lines: &mut Vec<String>,
+) -> Result<RunOutcome, Box<dyn Error>> {
+    let start = Instant::now();
+    let mut results: Vec<(String, Result<(), String>)> = Vec::new();
+
+    if let Some(name) = &assertions.env_set {
+        let name = compile_arg(name, ctx)?;
+        let result = match env::var_os(&name) {
+            Some(_) => Ok(()),
+            None => Err(format!("environment variable {} is not set", name)),
+        };
+        results.push((format!("env_set {}", name), result));
+    }
+
+    if let Some(path) = &assertions.path_exists {
+        let path = resolve_path(path, ctx)?;
+        let result = if path.exists() {
+            Ok(())
+        } else {
+            Err(format!("{} does not exist", path.display()))
+        };
+        results.push((format!("path_exists {}", path.display()), result));
+    }
+
+    if let Some(command) = &assertions.command_on_path {
+        let command = compile_arg(command, ctx)?;
+        let result = match find_on_path(&command) {
+            Some(_) => Ok(()),
+            None => Err(format!("{} was not found on PATH", command)),
+        };
+        results.push((format!("command_on_path {}", command), result));
+    }
+
+    if let Some(min_mb) = assertions.min_free_space_mb {
+        let path = match &assertions.path {
+            Some(path) => resolve_path(path, ctx)?,
+            None => PathBuf::from("."),
+        };
+        let result = match free_space_mb(&path) {
+            Ok(free_mb) if free_mb >= min_mb => Ok(()),
+            Ok(free_mb) => Err(format!(
+                "{} MB free on {}, at least {} MB required",
+                free_mb,
+                path.display(),
+                min_mb
+            )),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        };
+        results.push((
+            format!("min_free_space_mb {} {}", min_mb, path.display()),
+            result,
+        ));
+    }
+
+    let mut exec_status = ExecStatus::OK;
+    let mut failures: Vec<String> = Vec::new();
+    for (check, result) in results {
+        match result {
+            Ok(()) => lines.push(format!("{} {}", "[OK]", check)),
+            Err(msg) => {
+                exec_status = ExecStatus::ERR;
+                lines.push(format!("{} {}: {}", "[FAIL]", check, msg));
+                failures.push(msg);
+            }
+        }
+    }
+
+    let mut output = failures.join("\n");
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    let failure_reason = match exec_status {
+        ExecStatus::ERR => Some(FailureReason::AssertionFailed),
+        _ => None,
+    };
+
+    Ok(RunOutcome {
+        status: exec_status,
+        exit_code: None,
+        stdout: Vec::new(),
+        stderr: output.into_bytes(),
+        duration: start.elapsed(),
+        spawn_error: None,
+        merged: false,
+        failure_reason,
+    })
+}
+
+/// Returns the location of `command`, looking it up in `PATH` unless it
+/// contains a path separator.
+fn find_on_path(command: &str) -> Option<PathBuf> {
+    let command_path = Path::new(command);
+    if command_path.components().count() > 1 {
+        return if is_executable(command_path) {
+            Some(command_path.to_path_buf())
+        } else {
+            None
+        };
+    }
+
+    let path_var = env::var_os("PATH")?;
+    for dir in env::split_paths(&path_var) {
+        for candidate in get_command_candidates(&dir, command) {
+            if is_executable(&candidate) {
+                return Some(candidate);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(unix)]
+fn get_command_candidates(dir: &Path, command: &str) -> Vec<PathBuf> {
+    vec![dir.join(command)]
+}
+
+#[cfg(not(unix))]
+fn get_command_candidates(dir: &Path, command: &str) -> Vec<PathBuf> {
+    let mut candidates = vec![dir.join(command)];
+    let path_ext = env::var("PATHEXT").unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"));
+    for ext in path_ext.split(';').filter(|ext| !ext.is_empty()) {
+        candidates.push(dir.join(format!("{}{}", command, ext)));
+    }
+
+    candidates
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+fn free_space_mb(path: &Path) -> Result<u64, io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // `c_path` is a valid NUL terminated string and `stat` is a writable buffer
+    // of the expected type.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let free_bytes = stat.f_bavail as u64 * stat.f_frsize as u64;
+
+    Ok(free_bytes / (1024 * 1024))
+}
+
+#[cfg(windows)]
+fn free_space_mb(path: &Path) -> Result<u64, io::Error> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    let mut free_bytes: winapi::shared::ntdef::ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    // `wide_path` is a valid NUL terminated string and the output pointers are
+    // either valid or null as permitted by the API.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide_path.as_ptr(),
+            &mut free_bytes,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { *free_bytes.QuadPart() } / (1024 * 1024))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn free_space_mb(_path: &Path) -> Result<u64, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space cannot be determined on this platform",
+    ))
+}
+
+#[test]
+fn run_assertions_test() {
+    let ctx = ExecContext::new();
+    let mut lines: Vec<String> = Vec::new();
+
+    let assertions = Assertions {
+        env_set: Some(String::from("NANSI_SURELY_UNDEFINED_VAR")),
+        path_exists: Some(String::from("Cargo.toml")),
+        command_on_path: Some(String::from("nansi_surely_missing_command")),
+        min_free_space_mb: Some(0),
+        path: None,
+    };
+
+    let outcome = run_assertions(&assertions, &ctx, &mut lines).unwrap();
+
+    assert_eq!(outcome.status, ExecStatus::ERR);
+    assert_eq!(outcome.failure_reason, Some(FailureReason::AssertionFailed));
+    assert_eq!(
+        outcome.stderr_str().unwrap(),
+        "environment variable NANSI_SURELY_UNDEFINED_VAR is not set\nnansi_surely_missing_command was not found on PATH\n"
+    );
+    assert_eq!(
+        lines,
+        vec![
+            "[FAIL] env_set NANSI_SURELY_UNDEFINED_VAR: environment variable NANSI_SURELY_UNDEFINED_VAR is not set",
+            "[OK] path_exists Cargo.toml",
+            "[FAIL] command_on_path nansi_surely_missing_command: nansi_surely_missing_command was not found on PATH",
+            "[OK] min_free_space_mb 0 .",
+        ]
+    );
+}
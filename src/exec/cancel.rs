@@ -0,0 +1,279 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag asking a run to stop: the items not started yet are skipped,
+/// the item being run is not interrupted.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancels the run of every holder of a clone of this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Makes the first interrupt (Ctrl-C, SIGTERM) cancel `token` instead of
+/// terminating `nansi`, so the run can report what it did; the next interrupt
+/// terminates it. The command being run receives the interrupt as well, also
+/// when it runs in its own process group (see `forward_interrupts`).
+pub fn cancel_on_interrupt(token: &CancelToken) {
+    interrupt::install(token);
+}
+
+/// Cancels the token registered by `cancel_on_interrupt` on an interrupt
+/// `signal`, returns false if `nansi` should terminate instead: there is no
+/// token or it is already cancelled, and no command is being spawned (see
+/// `forward_interrupts`). The interrupt is forwarded either way
+/// (async-signal-safe).
+pub(crate) fn cancel_interrupted(signal: i32) -> bool {
+    interrupt::interrupted(signal)
+}
+
+/// Forwards the interrupts `nansi` receives to the process group of a
+/// command until the returned guard is dropped, for a command which leaves
+/// the foreground process group of the terminal and so does not get Ctrl-C
+/// from it.
+///
+/// Called before spawning the command, whose group is then passed to
+/// `InterruptForwarding::attach`: an interrupt received in between is
+/// forwarded once it is attached, and terminating `nansi` waits until then.
+#[cfg(unix)]
+pub(crate) fn forward_interrupts() -> InterruptForwarding {
+    InterruptForwarding {
+        spawning: Some(interrupt::begin_spawn()),
+        slot: None,
+    }
+}
+
+/// Forwarding of the interrupts to a process group, stopped when dropped
+#[cfg(unix)]
+pub(crate) struct InterruptForwarding {
+    /// Interrupts received when the spawn began, `None` once attached
+    spawning: Option<usize>,
+
+    slot: Option<usize>,
+}
+
+#[cfg(unix)]
+impl InterruptForwarding {
+    /// Forwards the interrupts to the process group `pgid` of the spawned
+    /// command.
+    pub(crate) fn attach(&mut self, pgid: u32) {
+        if let Some(received) = self.spawning.take() {
+            self.slot = interrupt::end_spawn(received, Some(pgid));
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for InterruptForwarding {
+    fn drop(&mut self) {
+        if let Some(received) = self.spawning.take() {
+            interrupt::end_spawn(received, None);
+        }
+        if let Some(slot) = self.slot {
+            interrupt::remove_group(slot);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+    use std::sync::{Once, OnceLock};
+
+    use super::CancelToken;
+
+    /// Number of process groups interrupts can be forwarded to at once, more
+    /// than items ever run in parallel
+    const MAX_GROUPS: usize = 256;
+
+    static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+
+    /// Process groups interrupts are forwarded to, 0 for a free slot
+    static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+
+    /// Number of interrupts received, and the signal of the last one
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+    static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+    /// Number of commands being spawned whose group is not attached yet
+    static SPAWNING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Interrupt terminating `nansi` once no command is being spawned, 0 for
+    /// none
+    static TERMINATING: AtomicI32 = AtomicI32::new(0);
+
+    extern "C" fn on_interrupt(signal: libc::c_int) {
+        if !interrupted(signal) {
+            terminate(signal);
+        }
+    }
+
+    /// Terminates `nansi` as if no handler was installed.
+    fn terminate(signal: libc::c_int) {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub fn install(token: &CancelToken) {
+        if TOKEN.set(token.clone()).is_err() {
+            return;
+        }
+
+        // `on_interrupt` only uses atomics and async-signal-safe functions
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+            libc::signal(
+                libc::SIGTERM,
+                on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+
+    /// Forwards the interrupt `signal` and cancels the token, returns false
+    /// if `nansi` should terminate right away.
+    pub fn interrupted(signal: libc::c_int) -> bool {
+        SIGNAL.store(signal, Ordering::SeqCst);
+        RECEIVED.fetch_add(1, Ordering::SeqCst);
+        forward(signal);
+        if cancel() {
+            return true;
+        }
+        if SPAWNING.load(Ordering::SeqCst) > 0 {
+            // see `end_spawn`
+            TERMINATING.store(signal, Ordering::SeqCst);
+            return true;
+        }
+
+        false
+    }
+
+    fn cancel() -> bool {
+        match TOKEN.get() {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn forward(signal: libc::c_int) {
+        for group in &GROUPS {
+            let pgid = group.load(Ordering::SeqCst);
+            if pgid > 0 {
+                // kill(2) is async-signal-safe
+                unsafe { libc::kill(-pgid, signal) };
+            }
+        }
+    }
+
+    /// Marks a command as being spawned and returns the number of interrupts
+    /// received so far, to be passed to `end_spawn`.
+    ///
+    /// The interrupts are handled from then on if they were not already,
+    /// terminating `nansi` as before once forwarded.
+    pub fn begin_spawn() -> usize {
+        static HANDLED: Once = Once::new();
+        HANDLED.call_once(|| {
+            for signal in [libc::SIGINT, libc::SIGTERM] {
+                // an ignored interrupt stays ignored, a handled one is already
+                // forwarded (see `cancel_interrupted`)
+                unsafe {
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    if libc::sigaction(signal, std::ptr::null(), &mut action) == 0
+                        && action.sa_sigaction == libc::SIG_DFL
+                    {
+                        libc::signal(
+                            signal,
+                            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                        );
+                    }
+                }
+            }
+        });
+
+        SPAWNING.fetch_add(1, Ordering::SeqCst);
+        RECEIVED.load(Ordering::SeqCst)
+    }
+
+    /// Ends the spawn begun when `received` interrupts were received: stores
+    /// the process group `pgid` of the command, if it was spawned, and
+    /// returns its slot, `None` if all are taken.
+    ///
+    /// An interrupt received during the spawn is forwarded to the group, and
+    /// `nansi` terminates if the interrupt should have terminated it.
+    pub fn end_spawn(received: usize, pgid: Option<u32>) -> Option<usize> {
+        let slot = pgid.and_then(|pgid| {
+            GROUPS.iter().position(|group| {
+                group
+                    .compare_exchange(0, pgid as i32, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+        });
+        if let Some(pgid) = pgid {
+            // the group may get the interrupt twice if it arrives right now
+            if RECEIVED.load(Ordering::SeqCst) != received {
+                unsafe { libc::kill(-(pgid as i32), SIGNAL.load(Ordering::SeqCst)) };
+            }
+        }
+
+        if SPAWNING.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let signal = TERMINATING.swap(0, Ordering::SeqCst);
+            if signal != 0 {
+                terminate(signal);
+            }
+        }
+
+        slot
+    }
+
+    pub fn remove_group(slot: usize) {
+        GROUPS[slot].store(0, Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+    use super::CancelToken;
+
+    pub fn install(_token: &CancelToken) {}
+
+    pub fn interrupted(_signal: i32) -> bool {
+        false
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &CancelToken) -> bool {
+        self.is_cancelled() == other.is_cancelled()
+    }
+}
+
+#[test]
+fn cancel_token_test() {
+    let token = CancelToken::new();
+    let clone = token.clone();
+    assert!(!clone.is_cancelled());
+
+    token.cancel();
+    assert!(clone.is_cancelled());
+    assert_ne!(clone, CancelToken::new());
+}
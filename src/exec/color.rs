@@ -0,0 +1,222 @@
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::style::StyledContent;
+
+use super::OutputStream;
+
+/// Whether stdout is colored, set once by `set_stream_colors`
+static STDOUT_COLORS: AtomicBool = AtomicBool::new(true);
+
+/// Whether stderr is colored, set once by `set_stream_colors`
+static STDERR_COLORS: AtomicBool = AtomicBool::new(true);
+
+/// Value of `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color the streams that are terminals, unless the environment says
+    /// otherwise
+    #[default]
+    Auto,
+
+    Always,
+
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice: {} (expected auto, always or never)",
+                s
+            )),
+        }
+    }
+}
+
+/// Snapshot of the variables affecting `--color=auto`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorEnv {
+    /// `NO_COLOR`, disables colors when set to a non-empty value
+    pub no_color: Option<String>,
+
+    /// `CLICOLOR_FORCE`, enables colors when set to anything but `0`
+    pub clicolor_force: Option<String>,
+}
+
+impl ColorEnv {
+    pub fn from_env() -> ColorEnv {
+        ColorEnv {
+            no_color: env::var("NO_COLOR").ok(),
+            clicolor_force: env::var("CLICOLOR_FORCE").ok(),
+        }
+    }
+}
+
+/// Whether each stream is colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamColors {
+    pub stdout: bool,
+
+    pub stderr: bool,
+}
+
+/// Decides which streams are colored.
+///
+/// `always` and `never` (from the command line or `NANSI_OPTS`) win over the
+/// environment. With `auto` a non-empty `NO_COLOR` disables colors, then a
+/// `CLICOLOR_FORCE` other than `0` enables them, and otherwise each stream is
+/// colored if it is a terminal.
+pub fn get_stream_colors(
+    choice: ColorChoice,
+    env: &ColorEnv,
+    stdout_tty: bool,
+    stderr_tty: bool,
+) -> StreamColors {
+    let forced = match choice {
+        ColorChoice::Always => Some(true),
+        ColorChoice::Never => Some(false),
+        ColorChoice::Auto => {
+            if env.no_color.as_deref().is_some_and(|v| !v.is_empty()) {
+                Some(false)
+            } else if env.clicolor_force.as_deref().is_some_and(|v| v != "0") {
+                Some(true)
+            } else {
+                None
+            }
+        }
+    };
+
+    StreamColors {
+        stdout: forced.unwrap_or(stdout_tty),
+        stderr: forced.unwrap_or(stderr_tty),
+    }
+}
+
+/// Applies the decision of `get_stream_colors` to everything printed afterwards.
+pub fn set_stream_colors(colors: StreamColors) {
+    STDOUT_COLORS.store(colors.stdout, Ordering::Relaxed);
+    STDERR_COLORS.store(colors.stderr, Ordering::Relaxed);
+}
+
+/// Returns `styled` with its colors if `stream` is colored, its bare content
+/// otherwise.
+pub(crate) fn paint<D: Display>(stream: OutputStream, styled: StyledContent<D>) -> String {
+    let colored = match stream {
+        OutputStream::Stdout => STDOUT_COLORS.load(Ordering::Relaxed),
+        OutputStream::Stderr => STDERR_COLORS.load(Ordering::Relaxed),
+    };
+
+    if colored {
+        styled.to_string()
+    } else {
+        styled.content().to_string()
+    }
+}
+
+/// Removes ANSI escape sequences (`ESC [ ... letter`).
+pub(crate) fn strip_ansi(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            chars.next();
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+#[test]
+fn strip_ansi_test() {
+    assert_eq!(
+        strip_ansi("[\u{1b}[38;5;9mFAIL\u{1b}[39m] ls\n\u{1b}[1mbold"),
+        "[FAIL] ls\nbold"
+    );
+}
+
+#[test]
+fn get_stream_colors_test() {
+    use ColorChoice::{Always, Auto, Never};
+
+    // every (choice, NO_COLOR, CLICOLOR_FORCE) => colored if a terminal, colored if not
+    #[rustfmt::skip]
+    let table = [
+        (Auto,   None,      None,      true,  false),
+        (Auto,   None,      Some("0"), true,  false),
+        (Auto,   None,      Some(""),  true,  true),
+        (Auto,   None,      Some("1"), true,  true),
+        (Auto,   Some(""),  None,      true,  false),
+        (Auto,   Some(""),  Some("0"), true,  false),
+        (Auto,   Some(""),  Some(""),  true,  true),
+        (Auto,   Some(""),  Some("1"), true,  true),
+        (Auto,   Some("1"), None,      false, false),
+        (Auto,   Some("1"), Some("0"), false, false),
+        (Auto,   Some("1"), Some(""),  false, false),
+        (Auto,   Some("1"), Some("1"), false, false),
+        (Always, None,      None,      true,  true),
+        (Always, None,      Some("0"), true,  true),
+        (Always, None,      Some(""),  true,  true),
+        (Always, None,      Some("1"), true,  true),
+        (Always, Some(""),  None,      true,  true),
+        (Always, Some(""),  Some("0"), true,  true),
+        (Always, Some(""),  Some(""),  true,  true),
+        (Always, Some(""),  Some("1"), true,  true),
+        (Always, Some("1"), None,      true,  true),
+        (Always, Some("1"), Some("0"), true,  true),
+        (Always, Some("1"), Some(""),  true,  true),
+        (Always, Some("1"), Some("1"), true,  true),
+        (Never,  None,      None,      false, false),
+        (Never,  None,      Some("0"), false, false),
+        (Never,  None,      Some(""),  false, false),
+        (Never,  None,      Some("1"), false, false),
+        (Never,  Some(""),  None,      false, false),
+        (Never,  Some(""),  Some("0"), false, false),
+        (Never,  Some(""),  Some(""),  false, false),
+        (Never,  Some(""),  Some("1"), false, false),
+        (Never,  Some("1"), None,      false, false),
+        (Never,  Some("1"), Some("0"), false, false),
+        (Never,  Some("1"), Some(""),  false, false),
+        (Never,  Some("1"), Some("1"), false, false),
+    ];
+
+    for (choice, no_color, clicolor_force, tty, not_tty) in table {
+        let env = ColorEnv {
+            no_color: no_color.map(String::from),
+            clicolor_force: clicolor_force.map(String::from),
+        };
+        for (stdout_tty, stderr_tty) in [(true, true), (true, false), (false, true), (false, false)]
+        {
+            let expected = StreamColors {
+                stdout: if stdout_tty { tty } else { not_tty },
+                stderr: if stderr_tty { tty } else { not_tty },
+            };
+            assert_eq!(
+                get_stream_colors(choice, &env, stdout_tty, stderr_tty),
+                expected,
+                "{:?} NO_COLOR={:?} CLICOLOR_FORCE={:?} tty=({}, {})",
+                choice,
+                no_color,
+                clicolor_force,
+                stdout_tty,
+                stderr_tty
+            );
+        }
+    }
+}
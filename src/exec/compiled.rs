@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::{sha256_hex, NansiFile};
+
+/// Extension of compiled `NansiFile`s; `setup.json` is compiled next to it
+/// as `setup.nansic.json` (see `get_cache_path`)
+pub const COMPILED_FILE_EXTENSION: &str = "nansic.json";
+
+/// File a compiled `NansiFile` was made from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceHash {
+    /// Canonical path of the file
+    pub path: String,
+
+    /// Hex SHA-256 digest of the content of the file
+    pub sha256: String,
+}
+
+/// `NansiFile` with its includes resolved, written by `--compile` and loaded
+/// instead of its sources as long as none of them changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompiledNansiFile {
+    /// Version of nansi which compiled the file; other versions ignore it
+    pub nansi_version: String,
+
+    /// Files the `NansiFile` was compiled from, the root file first
+    pub sources: Vec<SourceHash>,
+
+    pub nansi_file: NansiFile,
+}
+
+impl CompiledNansiFile {
+    pub fn new(nansi_file: NansiFile, sources: Vec<SourceHash>) -> CompiledNansiFile {
+        CompiledNansiFile {
+            nansi_version: String::from(env!("CARGO_PKG_VERSION")),
+            sources,
+            nansi_file,
+        }
+    }
+
+    pub fn from(file_path: &str) -> Result<CompiledNansiFile, io::Error> {
+        match fs::read_to_string(file_path) {
+            Ok(v) => CompiledNansiFile::from_json(&v, file_path),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+        }
+    }
+
+    /// Parses the content of a compiled `NansiFile` loaded from `file_path`.
+    pub fn from_json(file_str: &str, file_path: &str) -> Result<CompiledNansiFile, io::Error> {
+        let compiled: CompiledNansiFile = match serde_json::from_str(file_str) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", file_path, e),
+                ));
+            }
+        };
+
+        if compiled.sources.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: no source files recorded", file_path),
+            ));
+        }
+
+        Ok(compiled)
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), io::Error> {
+        let compiled_str = serde_json::to_string_pretty(self)?;
+        match fs::write(file_path, compiled_str) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+        }
+    }
+
+    /// Returns why the compiled `NansiFile` cannot be used: it was compiled by
+    /// another version or one of its sources is missing or has another digest.
+    pub fn get_stale_reason(&self) -> Option<String> {
+        if self.nansi_version != env!("CARGO_PKG_VERSION") {
+            return Some(format!("compiled by nansi {}", self.nansi_version));
+        }
+
+        for source in &self.sources {
+            match fs::read(&source.path) {
+                Ok(content) if sha256_hex(&content) == source.sha256 => {}
+                Ok(_) => return Some(format!("{} changed", source.path)),
+                Err(_) => return Some(format!("{} is missing", source.path)),
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns the path of the compiled `NansiFile` used for the file at
+/// `file_path` when it is up to date.
+pub fn get_cache_path(file_path: &str) -> PathBuf {
+    Path::new(file_path).with_extension(COMPILED_FILE_EXTENSION)
+}
+
+/// Returns `true` if `file_path` is the path of a compiled `NansiFile`.
+pub fn is_compiled_path(file_path: &str) -> bool {
+    file_path.ends_with(&format!(".{}", COMPILED_FILE_EXTENSION))
+}
+
+#[test]
+fn get_cache_path_test() {
+    assert_eq!(
+        get_cache_path("setup/site.json"),
+        PathBuf::from("setup/site.nansic.json")
+    );
+    assert_eq!(
+        get_cache_path("site.yml"),
+        PathBuf::from("site.nansic.json")
+    );
+    assert_eq!(get_cache_path("site"), PathBuf::from("site.nansic.json"));
+
+    assert!(is_compiled_path("setup/site.nansic.json"));
+    assert!(!is_compiled_path("setup/site.json"));
+}
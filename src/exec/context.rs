@@ -0,0 +1,359 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Elevation, FeatureFlags};
+
+/// Source of values for `{}` tags.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum VarSource {
+    /// Variables passed on the command line
+    Cli,
+
+    /// Values registered by previously executed items
+    Registers,
+
+    /// Variables defined in the `NansiFile`
+    Vars,
+
+    /// Environment of the `nansi` process
+    Env,
+}
+
+/// Order in which the sources are consulted unless overridden.
+pub const DEFAULT_VAR_PRECEDENCE: [VarSource; 4] = [
+    VarSource::Cli,
+    VarSource::Registers,
+    VarSource::Vars,
+    VarSource::Env,
+];
+
+/// Name of the tag expanding to the temporary directory of the run
+pub const TMP_DIR_VAR: &str = "nansi.tmp_dir";
+
+/// Holds the state needed while compiling and running `exec` items.
+///
+/// Values of `{}` tags are resolved by consulting a chain of sources in the
+/// order given by `precedence`; the first source defining a variable wins.
+#[derive(Debug, Clone)]
+pub struct ExecContext {
+    /// Variables of each source
+    vars: HashMap<VarSource, HashMap<String, String>>,
+
+    /// Order in which the sources are consulted
+    precedence: Vec<VarSource>,
+
+    /// Whether the `Env` source reads the process environment
+    use_env: bool,
+
+    /// Directory relative paths are resolved against
+    base_dir: Option<PathBuf>,
+
+    /// URL the `NansiFile` was fetched from; relative paths are an error then
+    source_url: Option<String>,
+
+    /// Toggles of built-in behaviors of the `NansiFile`
+    features: FeatureFlags,
+
+    /// Names of the only environment variables passed to commands by default
+    env_passthrough: Option<Vec<String>>,
+
+    /// How items marked `elevate` run with elevated privileges
+    elevation: Elevation,
+
+    /// Temporary directory of the run, created on first reference and shared
+    /// by all clones of the context
+    tmp_dir: Rc<RefCell<Option<PathBuf>>>,
+}
+
+impl ExecContext {
+    /// Creates a context resolving the `Env` source from the process environment.
+    pub fn new() -> ExecContext {
+        ExecContext {
+            vars: HashMap::new(),
+            precedence: DEFAULT_VAR_PRECEDENCE.to_vec(),
+            use_env: true,
+            base_dir: None,
+            source_url: None,
+            features: FeatureFlags::default(),
+            env_passthrough: None,
+            elevation: Elevation::default(),
+            tmp_dir: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Creates a context whose `Env` source is the given map instead of the
+    /// process environment.
+    pub fn from_map(vars: HashMap<String, String>) -> ExecContext {
+        let mut ctx = ExecContext {
+            vars: HashMap::new(),
+            precedence: DEFAULT_VAR_PRECEDENCE.to_vec(),
+            use_env: false,
+            base_dir: None,
+            source_url: None,
+            features: FeatureFlags::default(),
+            env_passthrough: None,
+            elevation: Elevation::default(),
+            tmp_dir: Rc::new(RefCell::new(None)),
+        };
+        ctx.vars.insert(VarSource::Env, vars);
+
+        ctx
+    }
+
+    /// Sets a variable of the given source.
+    pub fn set_var(&mut self, source: VarSource, name: &str, value: &str) {
+        self.vars
+            .entry(source)
+            .or_default()
+            .insert(String::from(name), String::from(value));
+    }
+
+    /// Overrides the order in which the sources are consulted.
+    ///
+    /// Sources missing from `order` are consulted afterwards in their default order.
+    pub fn set_precedence(&mut self, order: &[VarSource]) {
+        let mut precedence: Vec<VarSource> = Vec::new();
+        for source in order.iter().chain(DEFAULT_VAR_PRECEDENCE.iter()) {
+            if !precedence.contains(source) {
+                precedence.push(*source);
+            }
+        }
+
+        self.precedence = precedence;
+    }
+
+    /// Returns the order in which the sources are consulted.
+    pub fn precedence(&self) -> &[VarSource] {
+        &self.precedence
+    }
+
+    /// Sets the directory relative paths are resolved against (see `resolve_path`).
+    pub fn set_base_dir(&mut self, dir: &Path) {
+        self.base_dir = Some(dir.to_path_buf());
+    }
+
+    /// Returns the directory relative paths are resolved against.
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    /// Records that the `NansiFile` was fetched from `url` (see `resolve_path`).
+    pub fn set_source_url(&mut self, url: &str) {
+        self.source_url = Some(String::from(url));
+    }
+
+    /// Returns the URL the `NansiFile` was fetched from.
+    pub fn source_url(&self) -> Option<&str> {
+        self.source_url.as_deref()
+    }
+
+    /// Sets the toggles of built-in behaviors.
+    pub fn set_features(&mut self, features: &FeatureFlags) {
+        self.features = features.clone();
+    }
+
+    /// Returns the toggles of built-in behaviors.
+    pub fn features(&self) -> &FeatureFlags {
+        &self.features
+    }
+
+    /// Restricts the environment of commands to the variables `names`.
+    pub fn set_env_passthrough(&mut self, names: &[String]) {
+        self.env_passthrough = Some(names.to_vec());
+    }
+
+    /// Returns the names of the only environment variables passed to commands,
+    /// `None` if all are passed.
+    pub fn env_passthrough(&self) -> Option<&Vec<String>> {
+        self.env_passthrough.as_ref()
+    }
+
+    /// Sets how items marked `elevate` run with elevated privileges.
+    pub fn set_elevation(&mut self, elevation: &Elevation) {
+        self.elevation = elevation.clone();
+    }
+
+    /// Returns how items marked `elevate` run with elevated privileges.
+    pub fn elevation(&self) -> &Elevation {
+        &self.elevation
+    }
+
+    /// Returns the value of the variable `name` from the first source defining it.
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        if name == TMP_DIR_VAR {
+            return self
+                .get_tmp_dir()
+                .ok()
+                .map(|dir| dir.to_string_lossy().to_string());
+        }
+
+        for source in &self.precedence {
+            if let Some(value) = self.get_source_var(*source, name) {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the temporary directory of the run, creating it on first use.
+    pub fn get_tmp_dir(&self) -> Result<PathBuf, io::Error> {
+        let mut tmp_dir = self.tmp_dir.borrow_mut();
+        if tmp_dir.is_none() {
+            *tmp_dir = Some(create_tmp_dir()?);
+        }
+
+        Ok(tmp_dir.clone().unwrap_or_default())
+    }
+
+    /// Returns the temporary directory of the run if it was created.
+    pub fn created_tmp_dir(&self) -> Option<PathBuf> {
+        self.tmp_dir.borrow().clone()
+    }
+
+    /// Returns the value of the variable `name` of the given source only.
+    pub fn get_source_var(&self, source: VarSource, name: &str) -> Option<String> {
+        if source == VarSource::Env && self.use_env {
+            return env::var(name).ok();
+        }
+
+        match self.vars.get(&source) {
+            Some(vars) => vars.get(name).cloned(),
+            None => None,
+        }
+    }
+}
+
+/// Creates a new directory only accessible by the current user in the
+/// temporary directory of the system.
+pub(crate) fn create_tmp_dir() -> Result<PathBuf, io::Error> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    let mut attempt = 0;
+    loop {
+        let dir = env::temp_dir().join(format!(
+            "nansi-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            attempt
+        ));
+
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", dir.display(), e),
+                ));
+            }
+        }
+    }
+}
+
+/// Returns `true` if the name of a variable suggests that its value is a secret
+/// which must not end up in reports or traces.
+pub fn is_secret_name(name: &str) -> bool {
+    let name = name.to_uppercase();
+    ["SECRET", "TOKEN", "PASSWORD", "PASSWD", "KEY", "CREDENTIAL"]
+        .iter()
+        .any(|pattern| name.contains(pattern))
+}
+
+impl Default for ExecContext {
+    fn default() -> Self {
+        ExecContext::new()
+    }
+}
+
+#[cfg(test)]
+fn layered_context() -> ExecContext {
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    for source in DEFAULT_VAR_PRECEDENCE {
+        ctx.set_var(source, "NAME", format!("{:?}", source).as_str());
+    }
+    ctx.set_var(VarSource::Vars, "ONLY_VARS", "vars");
+    ctx.set_var(VarSource::Env, "ONLY_ENV", "env");
+
+    ctx
+}
+
+#[test]
+fn default_precedence_test() {
+    let mut ctx = layered_context();
+    assert_eq!(ctx.get_var("NAME").unwrap(), "Cli");
+
+    ctx.vars.remove(&VarSource::Cli);
+    assert_eq!(ctx.get_var("NAME").unwrap(), "Registers");
+
+    ctx.vars.remove(&VarSource::Registers);
+    assert_eq!(ctx.get_var("NAME").unwrap(), "Vars");
+
+    ctx.vars.remove(&VarSource::Vars);
+    assert_eq!(ctx.get_var("NAME").unwrap(), "Env");
+
+    assert_eq!(ctx.get_var("ONLY_ENV").unwrap(), "env");
+    assert_eq!(ctx.get_var("UNDEFINED"), None);
+}
+
+#[test]
+fn overridden_precedence_test() {
+    let mut ctx = layered_context();
+    ctx.set_precedence(&[VarSource::Env, VarSource::Vars]);
+
+    assert_eq!(
+        ctx.precedence(),
+        &[
+            VarSource::Env,
+            VarSource::Vars,
+            VarSource::Cli,
+            VarSource::Registers
+        ]
+    );
+    assert_eq!(ctx.get_var("NAME").unwrap(), "Env");
+    assert_eq!(ctx.get_var("ONLY_VARS").unwrap(), "vars");
+}
+
+#[test]
+fn is_secret_name_test() {
+    assert!(is_secret_name("GITHUB_TOKEN"));
+    assert!(is_secret_name("db_password"));
+    assert!(is_secret_name("AWS_SECRET_ACCESS_KEY"));
+    assert!(!is_secret_name("HOME"));
+    assert!(!is_secret_name("USER"));
+}
+
+#[test]
+fn tmp_dir_test() {
+    let ctx = ExecContext::from_map(HashMap::new());
+    assert_eq!(ctx.created_tmp_dir(), None);
+
+    let item_ctx = ctx.clone();
+    let tmp_dir = item_ctx.get_var(TMP_DIR_VAR).unwrap();
+    assert!(Path::new(&tmp_dir).is_dir());
+    assert_eq!(ctx.get_var(TMP_DIR_VAR), Some(tmp_dir.clone()));
+    assert_eq!(ctx.created_tmp_dir(), Some(PathBuf::from(&tmp_dir)));
+
+    let other_ctx = ExecContext::from_map(HashMap::new());
+    assert_ne!(other_ctx.get_var(TMP_DIR_VAR), Some(tmp_dir.clone()));
+
+    fs::remove_dir(&tmp_dir).unwrap();
+    fs::remove_dir(other_ctx.created_tmp_dir().unwrap()).unwrap();
+}
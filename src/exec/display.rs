@@ -0,0 +1,150 @@
+use std::fmt;
+
+use super::ExecItem;
+
+/// Canonical one-line rendering of an item: its 1-based index and label, e.g.
+/// `[2][build]`, optionally followed by its command, e.g. `[2][build] make all`
+pub struct ItemDisplay<'a> {
+    index: Option<usize>,
+
+    label: &'a str,
+
+    item: Option<&'a ExecItem>,
+
+    command: Option<String>,
+
+    max_width: Option<usize>,
+}
+
+impl<'a> ItemDisplay<'a> {
+    /// Renders the item at the 1-based `index` of the `exec_list`.
+    pub fn new(exec_item: &'a ExecItem, index: usize) -> ItemDisplay<'a> {
+        ItemDisplay {
+            index: Some(index),
+            label: &exec_item.label,
+            item: Some(exec_item),
+            command: None,
+            max_width: None,
+        }
+    }
+
+    /// Renders an item known only by its index and label (e.g. in a trace).
+    pub fn from_label(index: Option<usize>, label: &'a str) -> ItemDisplay<'a> {
+        ItemDisplay {
+            index,
+            label,
+            item: None,
+            command: None,
+            max_width: None,
+        }
+    }
+
+    /// Leaves out the index, e.g. `[build] make all`.
+    pub fn without_index(mut self) -> ItemDisplay<'a> {
+        self.index = None;
+        self
+    }
+
+    /// Appends the command as written in the `NansiFile` (see
+    /// `ExecItem::command_line`).
+    pub fn with_command(mut self) -> ItemDisplay<'a> {
+        self.command = self.item.map(|item| item.command_line());
+        self
+    }
+
+    /// Appends the command with the compiled `args` instead of the written
+    /// ones; `assert` items keep their checks.
+    pub fn with_args(mut self, args: &[String]) -> ItemDisplay<'a> {
+        self.command = self.item.map(|item| match &item.assert {
+            Some(_) => item.command_line(),
+            None => {
+                let mut command = vec![item.exec.clone()];
+                command.extend(args.iter().cloned());
+                command.join(" ")
+            }
+        });
+        self
+    }
+
+    /// Shortens the rendering to `width` characters, ending it with `…`.
+    pub fn max_width(mut self, width: usize) -> ItemDisplay<'a> {
+        self.max_width = Some(width);
+        self
+    }
+}
+
+impl fmt::Display for ItemDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rendered = String::new();
+        if let Some(index) = self.index {
+            rendered.push_str(&format!("[{}]", index));
+        }
+        if !self.label.is_empty() {
+            rendered.push_str(&format!("[{}]", self.label));
+        }
+        if let Some(command) = &self.command {
+            if !rendered.is_empty() {
+                rendered.push(' ');
+            }
+            rendered.push_str(command);
+        }
+
+        match self.max_width {
+            Some(width) if rendered.chars().count() > width => {
+                let shortened: String = rendered.chars().take(width.saturating_sub(1)).collect();
+                write!(f, "{}\u{2026}", shortened)
+            }
+            _ => f.write_str(&rendered),
+        }
+    }
+}
+
+#[test]
+fn item_display_test() {
+    let item = |s: &str| -> ExecItem { serde_json::from_str(s).unwrap() };
+    let labeled = item("{\"label\": \"build\", \"exec\": \"make\", \"args\": [\"{TARGET}\"]}");
+    let unlabeled = item("{\"exec\": \"ls\"}");
+    let assertion = item("{\"label\": \"env\", \"assert\": {\"env_set\": \"HOME\"}}");
+    let args = vec![String::from("all")];
+
+    let cases = [
+        (ItemDisplay::new(&labeled, 2), "[2][build]"),
+        (ItemDisplay::new(&unlabeled, 3), "[3]"),
+        (
+            ItemDisplay::new(&labeled, 2).with_command(),
+            "[2][build] make {TARGET}",
+        ),
+        // the separator of the (missing) arguments is kept, as in status lines
+        (ItemDisplay::new(&unlabeled, 3).with_command(), "[3] ls "),
+        (
+            ItemDisplay::new(&labeled, 2).with_args(&args),
+            "[2][build] make all",
+        ),
+        (ItemDisplay::new(&unlabeled, 3).with_args(&[]), "[3] ls"),
+        (
+            ItemDisplay::new(&assertion, 1).with_args(&args),
+            "[1][env] assert env_set=HOME",
+        ),
+        (
+            ItemDisplay::new(&labeled, 2).without_index().with_command(),
+            "[build] make {TARGET}",
+        ),
+        (
+            ItemDisplay::new(&unlabeled, 3)
+                .without_index()
+                .with_command(),
+            "ls ",
+        ),
+        (
+            ItemDisplay::new(&labeled, 2).with_command().max_width(12),
+            "[2][build] \u{2026}",
+        ),
+        (ItemDisplay::new(&labeled, 2).max_width(10), "[2][build]"),
+        (ItemDisplay::from_label(Some(4), "up"), "[4][up]"),
+        (ItemDisplay::from_label(None, "up").with_command(), "[up]"),
+    ];
+
+    for (display, expected) in cases {
+        assert_eq!(display.to_string(), expected);
+    }
+}
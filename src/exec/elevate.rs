@@ -0,0 +1,133 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Elevation command used when neither `--elevation-command` nor
+/// `elevation_command` of the `NansiFile` is set
+pub const DEFAULT_ELEVATION_COMMAND: &str = "sudo";
+
+/// How the items marked `elevate` run with elevated privileges: prefixed with
+/// the elevation command (`sudo`, `doas`, `pkexec`, ...), which never prompts
+/// for a password unless `interactive`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elevation {
+    pub command: String,
+
+    /// Let the elevation command prompt for a password on the terminal
+    pub interactive: bool,
+}
+
+impl Default for Elevation {
+    fn default() -> Elevation {
+        Elevation {
+            command: String::from(DEFAULT_ELEVATION_COMMAND),
+            interactive: false,
+        }
+    }
+}
+
+impl Elevation {
+    pub fn new(command: &str, interactive: bool) -> Elevation {
+        Elevation {
+            command: String::from(command),
+            interactive,
+        }
+    }
+
+    /// Returns the file name of the elevation command, e.g. `sudo` for
+    /// `/usr/bin/sudo`.
+    fn name(&self) -> &str {
+        Path::new(&self.command)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&self.command)
+    }
+
+    /// Whether the elevation command fails instead of prompting with `-n`
+    fn has_non_interactive_flag(&self) -> bool {
+        matches!(self.name(), "sudo" | "doas")
+    }
+
+    /// Whether `authenticate` runs before each elevated item rather than only
+    /// before the first: the credentials an interactive `sudo` caches expire
+    /// (after 15 minutes by default), `sudo -v` extends them or prompts again.
+    pub fn authenticates_each_item(&self) -> bool {
+        self.interactive && self.name() == "sudo"
+    }
+
+    /// Returns the elevation command and its arguments, which the command of
+    /// an elevated item is appended to. `sudo` always gets `-n` since an
+    /// interactive run prompts before each elevated item (see `authenticate`).
+    pub fn prefix(&self) -> Vec<String> {
+        let mut prefix = vec![self.command.clone()];
+        if self.has_non_interactive_flag() && (!self.interactive || self.name() == "sudo") {
+            prefix.push(String::from("-n"));
+        }
+
+        prefix
+    }
+
+    /// Makes sure elevated commands can run: an interactive `sudo` prompts for
+    /// the password with the stdio of `nansi` if its credentials are not
+    /// cached (`sudo -v`), otherwise `-n true` must succeed without a password.
+    pub fn authenticate(&self) -> Result<(), String> {
+        if self.interactive {
+            if self.name() != "sudo" {
+                return Ok(());
+            }
+
+            return match Command::new(&self.command).arg("-v").status() {
+                Ok(status) if status.success() => Ok(()),
+                Ok(_) => Err(format!("{} -v failed", self.command)),
+                Err(e) => Err(format!("{}: {}", self.command, e)),
+            };
+        }
+        if !self.has_non_interactive_flag() {
+            return Ok(());
+        }
+
+        let status = Command::new(&self.command)
+            .args(["-n", "true"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(format!(
+                "passwordless {} required or run with --interactive-elevation",
+                self.name()
+            )),
+            Err(e) => Err(format!("{}: {}", self.command, e)),
+        }
+    }
+}
+
+#[test]
+fn elevation_prefix_test() {
+    assert_eq!(Elevation::default().prefix(), vec!["sudo", "-n"]);
+    assert_eq!(
+        Elevation::new("/usr/bin/sudo", true).prefix(),
+        vec!["/usr/bin/sudo", "-n"]
+    );
+    assert_eq!(Elevation::new("doas", false).prefix(), vec!["doas", "-n"]);
+    assert_eq!(Elevation::new("doas", true).prefix(), vec!["doas"]);
+    assert_eq!(Elevation::new("pkexec", false).prefix(), vec!["pkexec"]);
+
+    assert!(Elevation::new("/usr/bin/sudo", true).authenticates_each_item());
+    assert!(!Elevation::new("sudo", false).authenticates_each_item());
+    assert!(!Elevation::new("doas", true).authenticates_each_item());
+}
+
+#[cfg(unix)]
+#[test]
+fn elevation_authenticate_test() {
+    assert_eq!(Elevation::new("pkexec", false).authenticate(), Ok(()));
+    assert_eq!(
+        Elevation::new("nansi_surely_missing_sudo", true).authenticate(),
+        Ok(())
+    );
+    assert!(Elevation::new("/nansi/surely/missing/sudo", false)
+        .authenticate()
+        .unwrap_err()
+        .starts_with("/nansi/surely/missing/sudo: "));
+}
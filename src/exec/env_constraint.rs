@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Diagnostic, DiagnosticKind, ExecContext, NansiFile, Severity, VarSource};
+
+/// Entry of `require_env` or `forbid_env`: the name of a variable, or a name
+/// with a condition its value must meet when it is set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvConstraint {
+    Name(String),
+
+    Value {
+        name: String,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        equals: Option<String>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        not_equals: Option<String>,
+    },
+}
+
+/// List an `EnvConstraint` belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvRule {
+    /// `require_env`: the variable must be set (and its value meet the
+    /// condition)
+    Require,
+
+    /// `forbid_env`: the variable must not be set, or with a condition, its
+    /// value must meet it if it is
+    Forbid,
+}
+
+impl EnvConstraint {
+    pub fn name(&self) -> &str {
+        match self {
+            EnvConstraint::Name(name) => name,
+            EnvConstraint::Value { name, .. } => name,
+        }
+    }
+}
+
+/// Returns why `value` (`None` if the variable is not set) violates the
+/// constraint, `None` if it does not.
+pub fn check_env_constraint(
+    rule: EnvRule,
+    constraint: &EnvConstraint,
+    value: Option<&str>,
+) -> Option<String> {
+    let name = constraint.name();
+    let value = match (rule, value) {
+        (EnvRule::Require, None) => return Some(format!("{} is required but not set", name)),
+        (EnvRule::Forbid, None) => return None,
+        (_, Some(value)) => value,
+    };
+
+    match constraint {
+        EnvConstraint::Name(_) if rule == EnvRule::Forbid => {
+            Some(format!("{} is forbidden but set", name))
+        }
+        EnvConstraint::Name(_) => None,
+        EnvConstraint::Value {
+            equals, not_equals, ..
+        } => {
+            if let Some(expected) = equals.as_deref().filter(|expected| *expected != value) {
+                Some(format!("{} must be {} but is {}", name, expected, value))
+            } else {
+                not_equals
+                    .as_deref()
+                    .filter(|forbidden| *forbidden == value)
+                    .map(|forbidden| format!("{} must not be {}", name, forbidden))
+            }
+        }
+    }
+}
+
+/// Checks `require_env` and `forbid_env` of the file against the environment
+/// of `ctx`, reporting each violated constraint with `severity`.
+pub fn validate_env_constraints(
+    nansi_file: &NansiFile,
+    ctx: &ExecContext,
+    severity: Severity,
+) -> Vec<Diagnostic> {
+    let constraints = nansi_file
+        .require_env
+        .iter()
+        .map(|constraint| (EnvRule::Require, constraint))
+        .chain(
+            nansi_file
+                .forbid_env
+                .iter()
+                .map(|constraint| (EnvRule::Forbid, constraint)),
+        );
+
+    constraints
+        .filter_map(|(rule, constraint)| {
+            let value = ctx.get_source_var(VarSource::Env, constraint.name());
+            let message = check_env_constraint(rule, constraint, value.as_deref())?;
+            Some(Diagnostic {
+                severity,
+                index: 0,
+                label: String::new(),
+                kind: DiagnosticKind::EnvConstraint {
+                    name: String::from(constraint.name()),
+                },
+                message,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn check_env_constraint_test() {
+    let name = EnvConstraint::Name(String::from("CI"));
+    let equals = EnvConstraint::Value {
+        name: String::from("STAGE"),
+        equals: Some(String::from("test")),
+        not_equals: None,
+    };
+    let not_equals = EnvConstraint::Value {
+        name: String::from("AWS_PROFILE"),
+        equals: None,
+        not_equals: Some(String::from("prod")),
+    };
+
+    let cases: Vec<(EnvRule, &EnvConstraint, Option<&str>, Option<&str>)> = vec![
+        (EnvRule::Require, &name, Some("1"), None),
+        (EnvRule::Require, &name, Some(""), None),
+        (
+            EnvRule::Require,
+            &name,
+            None,
+            Some("CI is required but not set"),
+        ),
+        (EnvRule::Require, &equals, Some("test"), None),
+        (
+            EnvRule::Require,
+            &equals,
+            Some("prod"),
+            Some("STAGE must be test but is prod"),
+        ),
+        (
+            EnvRule::Require,
+            &equals,
+            None,
+            Some("STAGE is required but not set"),
+        ),
+        (EnvRule::Require, &not_equals, Some("dev"), None),
+        (
+            EnvRule::Require,
+            &not_equals,
+            Some("prod"),
+            Some("AWS_PROFILE must not be prod"),
+        ),
+        (EnvRule::Forbid, &name, None, None),
+        (
+            EnvRule::Forbid,
+            &name,
+            Some(""),
+            Some("CI is forbidden but set"),
+        ),
+        (EnvRule::Forbid, &equals, None, None),
+        (
+            EnvRule::Forbid,
+            &equals,
+            Some("prod"),
+            Some("STAGE must be test but is prod"),
+        ),
+        (EnvRule::Forbid, &not_equals, None, None),
+        (EnvRule::Forbid, &not_equals, Some("dev"), None),
+        (
+            EnvRule::Forbid,
+            &not_equals,
+            Some("prod"),
+            Some("AWS_PROFILE must not be prod"),
+        ),
+    ];
+
+    for (rule, constraint, value, expected) in cases {
+        assert_eq!(
+            check_env_constraint(rule, constraint, value).as_deref(),
+            expected,
+            "{:?} {:?} with {:?}",
+            rule,
+            constraint,
+            value
+        );
+    }
+}
+
+#[test]
+fn validate_env_constraints_test() {
+    let nansi_file: NansiFile = serde_json::from_str(
+        "{\"require_env\": [\"CI\", {\"name\": \"STAGE\", \"equals\": \"test\"}], \"forbid_env\": [{\"name\": \"AWS_PROFILE\", \"not_equals\": \"prod\"}, \"KUBECONFIG\"], \"exec_list\": [{\"exec\": \"ls\"}]}",
+    )
+    .unwrap();
+    let mut ctx = ExecContext::from_map(std::collections::HashMap::new());
+    ctx.set_var(VarSource::Env, "STAGE", "test");
+    ctx.set_var(VarSource::Env, "AWS_PROFILE", "prod");
+
+    let messages: Vec<(Severity, String)> =
+        validate_env_constraints(&nansi_file, &ctx, Severity::Info)
+            .into_iter()
+            .map(|d| (d.severity, d.message))
+            .collect();
+
+    assert_eq!(
+        messages,
+        vec![
+            (Severity::Info, String::from("CI is required but not set")),
+            (Severity::Info, String::from("AWS_PROFILE must not be prod")),
+        ]
+    );
+}
@@ -0,0 +1,170 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use super::FailureReason;
+
+/// Machine readable event emitted while a `NansiFile` is executed.
+///
+/// Events are serialized as one `json` object per line (ndjson) with the
+/// event name stored in the `event` field.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    RunStarted {
+        file: String,
+        items: usize,
+    },
+    ItemStarted {
+        index: usize,
+        label: String,
+        exec: String,
+        args: Vec<String>,
+    },
+    ItemOutput {
+        index: usize,
+        label: String,
+        data: String,
+    },
+    ItemFinished {
+        index: usize,
+        label: String,
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        failure_reason: Option<FailureReason>,
+    },
+    Warning {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
+        message: String,
+    },
+    TimerFinished {
+        name: String,
+        duration_ms: u128,
+    },
+    RunFinished {
+        ok: usize,
+        err: usize,
+        warn: usize,
+        skip: usize,
+        changed: usize,
+    },
+}
+
+/// Writes events to every registered writer, one line per event.
+#[derive(Default)]
+pub struct EventSink {
+    writers: Vec<Box<dyn Write + Send>>,
+}
+
+impl EventSink {
+    pub fn new() -> EventSink {
+        EventSink {
+            writers: Vec::new(),
+        }
+    }
+
+    /// Registers a writer that receives every subsequently emitted event.
+    pub fn add_writer(&mut self, writer: Box<dyn Write + Send>) {
+        self.writers.push(writer);
+    }
+
+    /// Registers an already open file descriptor (e.g. `--status-fd 3`).
+    #[cfg(unix)]
+    pub fn add_fd(&mut self, fd: i32) -> Result<(), io::Error> {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        if fd < 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("status fd {}: standard streams cannot be used", fd),
+            ));
+        }
+
+        // The descriptor is handed over by the parent process for the sole use of
+        // the event stream, so taking ownership of it is sound.
+        let file = unsafe { File::from_raw_fd(fd) };
+        self.add_writer(Box::new(file));
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn add_fd(&mut self, fd: i32) -> Result<(), io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "status fd {}: not supported on this platform, use --status-pipe",
+                fd
+            ),
+        ))
+    }
+
+    /// Registers a named pipe (or any writable path) the events are written to.
+    pub fn add_pipe(&mut self, path: &str) -> Result<(), io::Error> {
+        let file = match OpenOptions::new().write(true).open(path) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(e.kind(), format!("{}: {}", path, e)));
+            }
+        };
+        self.add_writer(Box::new(file));
+
+        Ok(())
+    }
+
+    /// Returns `true` if at least one writer is registered.
+    pub fn is_active(&self) -> bool {
+        !self.writers.is_empty()
+    }
+
+    /// Writes the event to all writers and flushes them, so consumers see it immediately.
+    pub fn emit(&mut self, event: &Event) -> Result<(), io::Error> {
+        if self.writers.is_empty() {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+
+        for writer in &mut self.writers {
+            writer.write_all(line.as_bytes())?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn event_serialization_test() {
+    let event = Event::ItemFinished {
+        index: 2,
+        label: String::from("build"),
+        status: String::from("ok"),
+        message: None,
+        failure_reason: None,
+    };
+
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        "{\"event\":\"item_finished\",\"index\":2,\"label\":\"build\",\"status\":\"ok\"}"
+    );
+
+    let event = Event::ItemFinished {
+        index: 3,
+        label: String::from("test"),
+        status: String::from("err"),
+        message: None,
+        failure_reason: Some(FailureReason::NonZeroExit { code: 101 }),
+    };
+
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        "{\"event\":\"item_finished\",\"index\":3,\"label\":\"test\",\"status\":\"err\",\"failure_reason\":{\"reason\":\"non_zero_exit\",\"code\":101}}"
+    );
+}
@@ -1,330 +1,5565 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
 use std::error::Error;
-use std::process::Command;
-use std::{fs, io};
+use std::ffi::OsString;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::string::FromUtf8Error;
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
 
 use crossterm::style::Stylize;
-use serde::{Deserialize, Serialize};
+use crossterm::terminal;
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::{
+    apply_args_overrides, apply_label_matching, ask, deserialize_duration_secs, expand_arg_tilde,
+    expand_tilde, get_injected_items, get_selected_items, get_shuffled_order, get_skip_root_cause,
+    get_skip_root_cause_lines, get_timings_str, get_unused_labels, is_secret_name, is_url,
+    output_with_usage, paint, parse_yaml, pick_labels, resolve_includes, resolve_label_patterns,
+    resolve_path, resolve_vars, run_assertions, sha256_hex, split_reference, strip_ansi, validate,
+    validate_env, validate_env_constraints, Answers, ArgsOverride, Assertions, CancelToken,
+    CiFolding, Diagnostic, DiagnosticKind, Elevation, EnvConstraint, Event, EventSink, ExecContext,
+    ExecResult, FeatureFlags, ItemDisplay, LabelMatching, LabelResults, LabelState, LineSink,
+    NansiFormat, Notification, Prerequisite, Progress, ProgressMode, Redactor, RefState,
+    ReportFormat, ResourceUsage, RunLog, RunReport, Satisfaction, Severity, State, StdinInput,
+    TimerSpan, Trace, TraceItem, VarSource, NOT_RUN_STATUS, STDIN_ITEM_VAR,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ExecItem {
+    /// Name other items refer to in `prerequisites`; may be shared by
+    /// several items
     #[serde(default = "default_as_empty_string")]
     pub label: String,
 
+    /// Command to run, looked up on `PATH` unless it is a path
+    #[serde(default = "default_as_empty_string")]
     pub exec: String,
 
+    /// Arguments of the command; `{}` tags are expanded
     #[serde(default = "default_as_empty_vec_string")]
     pub args: Vec<String>,
 
+    /// Flags appended after `args` in sorted order, see `FlagValue`
+    #[serde(default = "default_as_empty_flags")]
+    pub flags: BTreeMap<String, FlagValue>,
+
+    /// Checks evaluated natively instead of running `exec`
+    #[serde(default = "default_as_none_assertions")]
+    pub assert: Option<Assertions>,
+
+    /// Starts the timer of the given name; items with `timer_start` or
+    /// `timer_stop` are markers and execute nothing
+    #[serde(default = "default_as_none_string")]
+    pub timer_start: Option<String>,
+
+    /// Stops the timer of the given name; the span is printed in the summary
+    #[serde(default = "default_as_none_string")]
+    pub timer_stop: Option<String>,
+
+    /// Print the status line of the item; a failure is still reported by a
+    /// shorter line unless `quiet` is set
     #[serde(default = "default_as_true")]
     pub print_status: bool,
 
+    /// Print the output of the command, see `PrintOutput`
+    #[serde(default = "default_as_print_output_none")]
+    pub print_output: PrintOutput,
+
+    /// Print `stderr` in addition to `stdout` when the item succeeds
     #[serde(default = "default_as_false")]
-    pub print_output: bool,
+    pub print_stderr: bool,
 
-    #[serde(default = "default_as_empty_vec_string")]
-    pub prerequisites: Vec<String>,
-}
+    /// How much of the output of the command is kept in memory, see `Capture`
+    #[serde(default = "default_as_capture_full")]
+    pub capture: Capture,
 
-/// Describes the structure and content of `NansiFile` file
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct NansiFile {
-    /// List of `exec` items
-    pub exec_list: Vec<ExecItem>,
+    /// Working directory of the command, resolved like other paths (see
+    /// `resolve_path`); the current directory if not set
+    #[serde(default = "default_as_none_string")]
+    pub cwd: Option<String>,
 
-    /// Path to the `json` file based on which this struct was parsed
-    #[serde(default = "default_as_empty_string")]
-    pub file_path: String,
-}
+    /// Environment variables set for the command on top of the inherited ones
+    /// (tags are expanded); empty values are set, not removed
+    #[serde(default = "default_as_empty_env")]
+    pub env: BTreeMap<String, String>,
 
-#[allow(dead_code)]
-#[derive(PartialEq)]
-enum ExecStatus {
-    OK,
-    ERR,
-    WARN,
-    SKIP,
-}
+    /// Written to the standard input of the command, which is then closed
+    /// (tags are expanded); without it or `stdin_file`, the input follows the
+    /// `null_stdin` feature
+    #[serde(default = "default_as_none_string")]
+    pub stdin: Option<String>,
 
-impl NansiFile {
-    pub fn from(file_path: &str) -> Result<NansiFile, io::Error> {
-        let file_str = match fs::read_to_string(file_path) {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(io::Error::new(
-                    e.kind(),
-                    format!("{}: {}", file_path, e.to_string()),
-                ));
-            }
-        };
+    /// File streamed to the standard input of the command, resolved like
+    /// other paths (see `resolve_path`)
+    #[serde(default = "default_as_none_string")]
+    pub stdin_file: Option<String>,
 
-        let mut file: NansiFile = match serde_json::from_str(file_str.as_str()) {
-            Ok(v) => v,
-            Err(e) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("{}: {}", file_path, e.to_string()),
-                ));
-            }
-        };
+    /// Capture `stderr` into the same pipe as `stdout`, keeping the order in
+    /// which the lines of both were written; the output is then shown as is
+    /// whether the item succeeds or fails
+    #[serde(default = "default_as_false")]
+    pub merge_streams: bool,
 
-        file.file_path = String::from(file_path);
+    /// Stream the output of the item is printed to, `--output-stream` if not set
+    #[serde(default = "default_as_none_output_stream")]
+    pub output_stream: Option<OutputStream>,
 
-        Ok(file)
-    }
-}
+    /// Labels or `#N` indices of items which must have succeeded (`!label` or
+    /// `label:failed`: failed, `label:skipped`: not run) for this item to run,
+    /// see `Prerequisite`
+    #[serde(default = "default_as_empty_vec_prerequisite")]
+    pub prerequisites: Vec<Prerequisite>,
 
-pub fn execute(nansi_file: &NansiFile) -> Result<(), Box<dyn Error>> {
-    print_nominal(
-        format!("Using NansiFile: {}", nansi_file.file_path)
-            .to_string()
-            .as_str(),
-    );
+    /// Overrides the file level `var_precedence` for this item
+    #[serde(default = "default_as_empty_vec_var_source")]
+    pub var_precedence: Vec<VarSource>,
 
-    let duplicates = get_label_duplicates(&nansi_file.exec_list);
+    /// Variables resolving `{}` tags (`VarSource::Vars`); their values may
+    /// refer to other variables and to the environment
+    #[serde(default = "default_as_empty_vars")]
+    pub vars: BTreeMap<String, String>,
 
-    if duplicates.len() > 0 {
-        let msg = format!(
-            "{}\n{:?}",
-            "The following aliases are duplicated which may cause issues with conditional execution:",
-            duplicates
-        )
-        .to_string();
+    /// Key recorded after a successful run; while it stays unchanged, later runs
+    /// skip the item as unchanged (it still satisfies prerequisites)
+    #[serde(default = "default_as_none_string")]
+    pub idempotence_key: Option<String>,
 
-        print_warning(&msg);
-    }
+    /// The item fails if it takes longer than this many seconds, even if the
+    /// command succeeded (it is not killed); also accepts a duration string
+    /// such as `2m30s`, see `parse_duration`
+    #[serde(
+        default = "default_as_none_u64",
+        deserialize_with = "deserialize_expect_duration_under_secs"
+    )]
+    pub expect_duration_under_secs: Option<u64>,
 
-    let mut succ_label_list: Vec<&str> = Vec::new();
+    /// The command (and the processes it started) is killed and the item
+    /// fails if it runs longer than this many seconds (or a duration string,
+    /// see `parse_duration`); unlimited if not set
+    #[serde(
+        default = "default_as_none_u64",
+        deserialize_with = "deserialize_timeout_secs"
+    )]
+    pub timeout_secs: Option<u64>,
 
-    for (idx, exec_item) in nansi_file.exec_list.iter().enumerate() {
-        if !exec_meets_prerequisites(&exec_item, &succ_label_list) {
-            let exec_status = ExecStatus::SKIP;
-            if exec_item.print_status {
-                print_status(&exec_item, idx + 1, exec_status);
-            }
+    /// Failures of this item do not count toward `max_failures`
+    #[serde(default = "default_as_false")]
+    pub ignore_errors: bool,
 
-            let item_str = get_item_str(exec_item, idx);
+    /// Consecutive parallel items form a batch whose commands run at the same
+    /// time (up to `ExecOptions::jobs`); items with `assert` or `chunk_args`
+    /// still run one at a time, see `get_batches`
+    #[serde(default = "default_as_false")]
+    pub parallel: bool,
 
-            print_nominal(format!("Prerequisites for item {} are not met.", item_str).as_str());
-            continue;
-        }
+    /// Number of the `ExecOptions::jobs` slots the item takes while it runs
+    /// in a batch, e.g. the number of jobs for an item which needs the
+    /// machine to itself; clamped to the jobs limit, 0 counts as 1
+    #[serde(default = "default_as_weight")]
+    pub weight: u32,
 
-        let (exec_status, output) = run_exec(&exec_item)?;
+    /// Environment variables that must be set (and not empty unless
+    /// `allow_empty` is set) before anything is run
+    #[serde(default = "default_as_empty_vec_string")]
+    pub requires_env: Vec<String>,
 
-        if exec_status == ExecStatus::OK {
-            if !exec_item.label.is_empty() && !succ_label_list.contains(&exec_item.label.as_str()) {
-                succ_label_list.push(exec_item.label.as_str());
-            }
-        }
+    /// Accept empty values of the variables in `requires_env`
+    #[serde(default = "default_as_false")]
+    pub allow_empty: bool,
 
-        if exec_item.print_status {
-            print_status(&exec_item, idx + 1, exec_status);
-        }
+    /// Send a desktop notification as soon as the item fails
+    #[serde(default = "default_as_false")]
+    pub notify_on_failure: bool,
 
-        if exec_item.print_output {
-            print_nominal(&output);
-        }
-    }
+    /// The label is used outside of the `NansiFile` (e.g. by external tooling),
+    /// so it is never reported as unused
+    #[serde(default = "default_as_false")]
+    pub keep: bool,
 
-    Ok(())
+    /// Runs the command once per chunk of `args` instead of once with all of them
+    #[serde(default = "default_as_none_chunk_args")]
+    pub chunk_args: Option<ChunkArgs>,
+
+    /// Printed after the status line when the item succeeds (tags are expanded)
+    #[serde(default = "default_as_none_string")]
+    pub success_message: Option<String>,
+
+    /// Printed after the status line when the item fails, e.g. what to check
+    /// before retrying (tags are expanded)
+    #[serde(default = "default_as_none_string")]
+    pub failure_hint: Option<String>,
+
+    /// Runs alone, in its original position relative to all other items, even
+    /// if `parallel` is set: the items before it finish first and those after
+    /// it start once it is done (see `get_batches`)
+    #[serde(default = "default_as_false")]
+    pub serial: bool,
+
+    /// Names of the only environment variables passed to the command,
+    /// overrides the top-level `env_passthrough`
+    #[serde(default = "default_as_none_vec_string")]
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// Decides whether the item changed anything when it succeeded, see
+    /// `ChangedWhen` (changed if unset)
+    #[serde(default = "default_as_none_changed_when")]
+    pub changed_when: Option<ChangedWhen>,
+
+    /// Run even if an argument containing tags expands to an empty string or
+    /// to `/` (e.g. `{TARGET_DIR}/` with `TARGET_DIR` empty), which fails the
+    /// item otherwise
+    #[serde(default = "default_as_false")]
+    pub allow_empty_expansion: bool,
+
+    /// Patterns whose matches are replaced with `***` in the captured output,
+    /// in addition to `redact` of the `NansiFile` (see `Redactor`)
+    #[serde(default = "default_as_empty_vec_string")]
+    pub redact: Vec<String>,
+
+    /// Run the command with elevated privileges through the elevation command
+    /// (see `Elevation`)
+    #[serde(default = "default_as_false")]
+    pub elevate: bool,
+
+    /// Ask on the terminal whether to run the item, showing its compiled
+    /// command; anything but `y` or `yes` skips it (see `ExecOptions::yes`)
+    #[serde(default = "default_as_false")]
+    pub confirm: bool,
+
+    /// Path of a `NansiFile` whose items replace this item when loaded,
+    /// relative to the including file (see `resolve_includes`)
+    #[serde(default = "default_as_none_string")]
+    pub include: Option<String>,
+
+    /// Line of the standard input this copy of the item runs with, expanding
+    /// `{item}` (see `expand_stdin_each`)
+    #[serde(skip)]
+    pub stdin_item: Option<String>,
+
+    /// Fields unknown to this version, reported as warnings
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
 }
 
-pub fn compile_arg(arg: &String) -> Result<String, Box<dyn Error>> {
-    let mut compiled_arg = String::from(arg);
+impl ExecItem {
+    /// Returns `true` if the item is a `timer_start`/`timer_stop` marker.
+    pub fn is_timer(&self) -> bool {
+        self.timer_start.is_some() || self.timer_stop.is_some()
+    }
 
-    let mut record = false;
-    let mut tag = String::from("");
-    let mut tags: Vec<String> = Vec::new();
+    /// Returns the unexpanded command of the item, e.g. `ls -la` or `assert ...`.
+    pub fn command_line(&self) -> String {
+        match &self.assert {
+            Some(assertions) => format!("assert {}", assertions.describe().join(" ")),
+            None => format!("{} {}", self.exec, self.get_args().join(" ")),
+        }
+    }
 
-    for (i, c) in arg.chars().enumerate() {
-        match c {
-            '{' => {
-                if (i == 0)
-                    || (i > 0
-                        && arg.chars().nth(i - 1).unwrap() != '\\'
-                        && arg.chars().nth(i - 1).unwrap() != '$')
-                {
-                    if record {
-                        return Err("Incorrect number of environment variable tags '{{'")?;
-                    }
-                    record = true;
-                }
-            }
-            '}' => {
-                if (i == 0) || (i > 0 && arg.chars().nth(i - 1).unwrap() != '\\') {
-                    if record {
-                        record = false;
-                        tags.push(tag.clone());
-                        tag.clear();
+    /// Returns `args` followed by the expanded `flags` (tags not compiled).
+    pub fn get_args(&self) -> Vec<String> {
+        let mut args = self.args.clone();
+        for (flag, value) in &self.flags {
+            match value {
+                FlagValue::Bool(true) => args.push(flag.clone()),
+                FlagValue::Bool(false) => {}
+                FlagValue::Number(n) => args.extend([flag.clone(), n.to_string()]),
+                FlagValue::String(v) => args.extend([flag.clone(), v.clone()]),
+                FlagValue::List(values) => {
+                    for v in values {
+                        args.extend([flag.clone(), v.clone()]);
                     }
                 }
             }
-            _ => {
-                if record {
-                    tag.push(c);
-                }
-            }
         }
-    }
 
-    for t in tags {
-        let tag = format!("{{{t}}}");
-        compiled_arg = compiled_arg.replace(tag.as_str(), env::var(t.as_str()).unwrap().as_str());
+        args
     }
-
-    Ok(compiled_arg)
 }
 
-fn run_exec(exec_item: &ExecItem) -> Result<(ExecStatus, String), Box<dyn Error>> {
-    let mut exec_status = ExecStatus::ERR;
-    let output: String;
+/// Condition under which a succeeded item counts as having changed something
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ChangedWhen {
+    /// `always`, `never` or `exit_code:N`
+    Condition(String),
 
-    let mut args: Vec<String> = Vec::new();
-    for arg in &exec_item.args {
-        args.push(compile_arg(arg)?);
+    /// Changed if one of the `|`-separated strings occurs in the output
+    OutputMatches { output_matches: String },
+}
+
+impl ChangedWhen {
+    /// Returns whether an item which succeeded with `exit_code` and printed
+    /// `stdout` changed something.
+    pub fn is_changed(&self, exit_code: Option<i32>, stdout: &str) -> Result<bool, String> {
+        match self {
+            ChangedWhen::Condition(condition) => match condition.as_str() {
+                "always" => Ok(true),
+                "never" => Ok(false),
+                _ => match condition.strip_prefix("exit_code:").map(|c| c.parse::<i32>()) {
+                    Some(Ok(code)) => Ok(exit_code == Some(code)),
+                    _ => Err(format!(
+                        "invalid changed_when \"{}\" (expected always, never, exit_code:N or {{\"output_matches\": ...}})",
+                        condition
+                    )),
+                },
+            },
+            ChangedWhen::OutputMatches { output_matches } => Ok(output_matches
+                .split('|')
+                .any(|pattern| !pattern.is_empty() && stdout.contains(pattern))),
+        }
     }
+}
 
-    match Command::new(&exec_item.exec).args(&args).output() {
-        Ok(result) => {
-            if result.status.success() {
-                exec_status = ExecStatus::OK;
-            }
+/// Value of an entry in `flags`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FlagValue {
+    /// `true` passes the bare flag, `false` omits it
+    Bool(bool),
 
-            output = if result.status.success() {
-                String::from_utf8(result.stdout)?
-            } else {
-                String::from_utf8(result.stderr)?
-            };
-        }
-        Err(e) => {
-            exec_status = ExecStatus::ERR;
-            output = e.to_string();
-        }
-    };
+    Number(serde_json::Number),
+
+    String(String),
 
-    Ok((exec_status, output))
+    /// The flag is repeated for each value
+    List(Vec<String>),
 }
 
-fn get_label_duplicates(exec_list: &Vec<ExecItem>) -> Vec<&str> {
-    let mut exec_map: HashMap<&str, u16> = HashMap::new();
-    for exec in exec_list {
-        if !exec.label.is_empty() {
-            match exec_map.get(&exec.label.as_str()) {
-                Some(count) => {
-                    exec_map.insert(exec.label.as_str(), count + 1);
-                }
-                None => {
-                    exec_map.insert(exec.label.as_str(), 1);
-                }
-            }
+/// Stream `nansi` prints to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+
+    Stderr,
+}
+
+impl FromStr for OutputStream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputStream, String> {
+        match s {
+            "stdout" => Ok(OutputStream::Stdout),
+            "stderr" => Ok(OutputStream::Stderr),
+            _ => Err(format!("unknown stream: {} (expected stdout or stderr)", s)),
         }
     }
-    exec_map.retain(|_, v| *v > 1);
+}
+
+/// Value of `print_output`: `false`, `true` or `"stream"`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "FlagValue", into = "FlagValue")]
+pub enum PrintOutput {
+    /// `false`: the output is not printed
+    #[default]
+    None,
 
-    let mut keys: Vec<&str> = exec_map.keys().cloned().collect();
-    keys.sort_by(|a, b| a.cmp(&b));
+    /// `true`: the output is printed under the status line once the item
+    /// finished
+    Buffered,
 
-    keys
+    /// `"stream"`: the lines of `stdout` and `stderr` are printed as they are
+    /// written, prefixed with the item; items with `assert` or `chunk_args`
+    /// print their output once finished instead
+    Stream,
 }
 
-fn exec_meets_prerequisites(exec_item: &ExecItem, succ_label_list: &Vec<&str>) -> bool {
-    for prereq in &exec_item.prerequisites {
-        if !succ_label_list.contains(&prereq.as_str()) {
-            return false;
+impl TryFrom<FlagValue> for PrintOutput {
+    type Error = String;
+
+    fn try_from(value: FlagValue) -> Result<PrintOutput, String> {
+        match value {
+            FlagValue::Bool(false) => Ok(PrintOutput::None),
+            FlagValue::Bool(true) => Ok(PrintOutput::Buffered),
+            FlagValue::String(s) if s == "stream" => Ok(PrintOutput::Stream),
+            _ => Err(String::from(
+                "print_output: expected true, false or \"stream\"",
+            )),
         }
     }
+}
 
-    true
+impl From<PrintOutput> for FlagValue {
+    fn from(print_output: PrintOutput) -> FlagValue {
+        match print_output {
+            PrintOutput::None => FlagValue::Bool(false),
+            PrintOutput::Buffered => FlagValue::Bool(true),
+            PrintOutput::Stream => FlagValue::String(String::from("stream")),
+        }
+    }
 }
 
-fn get_item_str(exec_item: &ExecItem, idx: usize) -> String {
-    let item_str = if exec_item.label.is_empty() {
-        String::from(format!("[{}]", idx.to_string()))
-    } else {
-        String::from(format!("[{}][{}]", idx.to_string(), &exec_item.label))
-    };
+/// Value of `capture`: how much of the output of an item is kept
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Capture {
+    /// The whole output is kept
+    #[default]
+    Full,
+
+    /// Only the last `MAX_TRUNCATED_OUTPUT_BYTES` of each stream are kept
+    Truncated,
 
-    item_str
+    /// The output goes to the null device; traces and reports hold
+    /// `OUTPUT_NOT_CAPTURED` instead
+    None,
 }
 
-fn print_status(exec_item: &ExecItem, idx: usize, exec_status: ExecStatus) {
-    let status = match exec_status {
-        ExecStatus::OK => String::from("OK").green().to_string(),
-        ExecStatus::ERR => String::from("FAIL".red().to_string()),
-        ExecStatus::WARN => String::from("WARN".yellow().to_string()),
-        ExecStatus::SKIP => String::from("SKIP".dark_yellow().to_string()),
-    };
+/// Number of bytes kept of each stream of an item with `"capture":
+/// "truncated"`
+pub const MAX_TRUNCATED_OUTPUT_BYTES: usize = 64 * 1024;
 
-    let item_str = get_item_str(exec_item, idx);
+/// Recorded as the output of items with `"capture": "none"`
+pub const OUTPUT_NOT_CAPTURED: &str = "<not captured>";
 
-    println!(
-        "[{}] {} {} {}",
-        status,
-        item_str,
-        exec_item.exec,
-        exec_item.args.join(" ")
-    );
+/// Format of listings printed instead of running the `NansiFile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListFormat {
+    #[default]
+    Text,
+
+    Json,
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ListFormat, String> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(format!(
+                "unknown output format: {} (expected text or json)",
+                s
+            )),
+        }
+    }
 }
 
-#[allow(dead_code)]
-fn print_nominal(msg: &str) {
-    println!("{}", msg);
+/// Splits the `args` of an item into chunks (like `xargs`) to stay below the
+/// argument list limit of the OS
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChunkArgs {
+    /// Maximum number of `args` passed to a single run
+    pub size: usize,
+
+    /// Arguments passed verbatim before every chunk
+    #[serde(default = "default_as_empty_vec_string")]
+    pub fixed: Vec<String>,
 }
 
-#[allow(dead_code)]
-fn print_ok(msg: &str) {
-    println!("{} {}", "[OK]", msg);
+/// Describes the structure and content of `NansiFile` file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NansiFile {
+    /// List of `exec` items
+    #[serde(deserialize_with = "deserialize_exec_list")]
+    pub exec_list: Vec<ExecItem>,
+
+    /// Path to the `json` file based on which this struct was parsed
+    #[serde(default = "default_as_empty_string")]
+    pub file_path: String,
+
+    /// Order in which `{}` tags are resolved from variable sources
+    /// (`cli`, `registers`, `vars`, `env`); omitted sources follow in default order
+    #[serde(default = "default_as_empty_vec_var_source")]
+    pub var_precedence: Vec<VarSource>,
+
+    /// Variables resolving `{}` tags (`VarSource::Vars`); their values may
+    /// refer to other variables and to the environment
+    #[serde(default = "default_as_empty_vars")]
+    pub vars: BTreeMap<String, String>,
+
+    /// Number of failed items after which the remaining items are skipped
+    #[serde(default = "default_as_none_u32")]
+    pub max_failures: Option<u32>,
+
+    /// Skip the remaining items once an item failed and make the run fail,
+    /// see `ExecOptions::fail_fast`
+    #[serde(default = "default_as_false")]
+    pub fail_fast: bool,
+
+    /// Toggles of built-in behaviors, see `FeatureFlags`
+    #[serde(default)]
+    pub features: FeatureFlags,
+
+    /// Skip items identical to an earlier item after expansion, see
+    /// `get_item_fingerprint`
+    #[serde(default = "default_as_false")]
+    pub dedupe_identical: bool,
+
+    /// Keep the temporary directory of the run (`{nansi.tmp_dir}`) if an item
+    /// failed
+    #[serde(default = "default_as_false")]
+    pub keep_tmp_on_failure: bool,
+
+    /// Names of the only environment variables passed to the commands of all
+    /// items (all variables are passed if unset)
+    #[serde(default = "default_as_none_vec_string")]
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// Variables which must be set before any item runs, optionally with a
+    /// condition on their value (`{"name", "equals"|"not_equals"}`)
+    #[serde(default)]
+    pub require_env: Vec<EnvConstraint>,
+
+    /// Variables which must not be set before any item runs, or with a
+    /// condition, whose value must meet it if they are
+    #[serde(default)]
+    pub forbid_env: Vec<EnvConstraint>,
+
+    /// Patterns whose matches are replaced with `***` in the captured output
+    /// of all items (see `Redactor`)
+    #[serde(default = "default_as_empty_vec_string")]
+    pub redact: Vec<String>,
+
+    /// Command running the items marked `elevate`, `sudo` if not set
+    #[serde(default = "default_as_none_string")]
+    pub elevation_command: Option<String>,
+
+    /// How items sharing a label decide whether the label is met as a
+    /// prerequisite
+    #[serde(default = "default_as_label_state")]
+    pub label_state: LabelState,
+
+    /// Whether prerequisites refer to labels by their exact spelling or
+    /// whatever their case, see `LabelMatching`
+    #[serde(default = "default_as_label_matching")]
+    pub label_matching: LabelMatching,
+
+    /// Hex SHA-256 digest of the bytes this struct was parsed from
+    #[serde(default = "default_as_empty_string")]
+    pub sha256: String,
+
+    /// Fields unknown to this version, reported as warnings
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
 }
 
-#[allow(dead_code)]
-fn print_warning(msg: &str) {
-    println!("{} {}", "[WARN]".yellow().to_string(), msg);
+/// `exec_list` of a `NansiFile` given as a bare array
+#[derive(Deserialize)]
+struct BareExecList(#[serde(deserialize_with = "deserialize_exec_list")] Vec<ExecItem>);
+
+/// Maximum number of lines of the failure preview at the end of a run
+pub const MAX_FAILURE_PREVIEW_LINES: usize = 50;
+
+/// Options controlling how a `NansiFile` is executed
+///
+/// Options missing from traces written by older versions take their default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ExecOptions {
+    /// Treat warnings about suspicious items (e.g. unexpanded tags) as failures
+    /// and return an error if an item failed (see `RunFailed`)
+    pub strict: bool,
+
+    /// In strict mode, also return an error if an item was skipped because of
+    /// unmet prerequisites
+    pub strict_skips: bool,
+
+    /// Return an error before running anything if a prerequisite refers to a
+    /// label no item has or which is first used by the item itself or a later
+    /// one, instead of warning about it
+    pub strict_prereqs: bool,
+
+    /// Warn about violated `require_env` and `forbid_env` constraints and run
+    /// anyway, instead of refusing to run
+    pub force_env_checks: bool,
+
+    /// File descriptor the ndjson event stream is written to
+    pub status_fd: Option<i32>,
+
+    /// Named pipe (or file) the ndjson event stream is written to
+    pub status_pipe: Option<String>,
+
+    /// Run items even if their `idempotence_key` is unchanged
+    pub force: bool,
+
+    /// Location of the state file, `State::default_path()` if not set
+    pub state_file: Option<String>,
+
+    /// Overrides `max_failures` of the `NansiFile`
+    pub max_failures: Option<u32>,
+
+    /// Skip the remaining items after the first failure (items with
+    /// `ignore_errors` and skipped items do not count) and return an error,
+    /// also enabled by `fail_fast` of the `NansiFile`
+    pub fail_fast: bool,
+
+    /// Print only failed items (see `Verbosity::Quiet`)
+    pub quiet: bool,
+
+    /// Print a `get_porcelain_line` per item to stdout, which nothing else is
+    /// printed to
+    pub porcelain: bool,
+
+    /// Print the compiled arguments and exit code of every item and
+    /// additional details, such as the number of redacted matches and the
+    /// commands of elevated items
+    pub verbose: bool,
+
+    /// Path of the trace file recording the run for bug reports
+    pub trace: Option<String>,
+
+    /// Path of the plain-text log the run is appended to (see `RunLog`)
+    pub log_file: Option<String>,
+
+    /// Path of the report of the results of all items (see `RunReport`)
+    pub report: Option<String>,
+
+    pub report_format: ReportFormat,
+
+    /// Print the resolved definition of failed items
+    pub echo_item_on_failure: bool,
+
+    /// Send a desktop notification when the run completes
+    pub notify_desktop: bool,
+
+    /// Path of the file answering prompts instead of the terminal
+    pub answers: Option<String>,
+
+    /// Show the duration and peak memory of each item after its status line
+    pub timings: bool,
+
+    /// Stream the output of items is printed to unless set by the item
+    pub output_stream: OutputStream,
+
+    /// Stream status lines and all other messages are printed to
+    pub status_stream: OutputStream,
+
+    /// Keep the temporary directory of the run (`{nansi.tmp_dir}`)
+    pub keep_tmp: bool,
+
+    /// Markers wrapping the printed output of items for CI logs
+    pub ci_folding: CiFolding,
+
+    /// Trace of an earlier run of the same `NansiFile` whose succeeded items
+    /// are not run again and satisfy prerequisites
+    pub import_state: Option<String>,
+
+    /// Run the items in a random order determined by the seed (see
+    /// `get_shuffled_order`)
+    pub shuffle_seed: Option<u64>,
+
+    /// Exit code of a run in which an item changed something (see
+    /// `changed_when`)
+    pub changed_exit_code: Option<i32>,
+
+    /// Number of trailing lines of the output of each failed item repeated
+    /// at the end of the run, 0 to disable
+    pub failure_preview_lines: usize,
+
+    /// How the item being run is shown; `Auto` is only resolved by
+    /// `detect_progress_mode` and runs as `Plain`
+    pub progress: ProgressMode,
+
+    /// Print the items that would run, with their compiled arguments, without
+    /// running anything (see `dry_run`)
+    pub dry_run: bool,
+
+    /// Arguments added to the items with a label, by label (see
+    /// `apply_args_overrides`)
+    pub args_overrides: BTreeMap<String, ArgsOverride>,
+
+    /// Labels of the only items run (see `get_selected_items`), or globs and
+    /// prefixes of them (see `match_label_pattern`)
+    pub only: Vec<String>,
+
+    /// Labels of the only items run along with their prerequisites
+    pub only_with_deps: Vec<String>,
+
+    /// Labels of the items not run
+    pub skip: Vec<String>,
+
+    /// Labels of the items which fail without running their command, to
+    /// exercise how failures are handled (see `FailureReason::Injected`)
+    pub inject_failure: Vec<String>,
+
+    /// Number of `parallel` items run at the same time, items run one at a
+    /// time if not greater than 1
+    pub jobs: usize,
+
+    /// Overrides `elevation_command` of the `NansiFile`
+    pub elevation_command: Option<String>,
+
+    /// Let the elevation command prompt for a password (see `Elevation`)
+    pub interactive_elevation: bool,
+
+    /// Run the items marked `confirm` without asking
+    pub yes: bool,
+
+    /// Skips the remaining items once cancelled (not recorded in traces)
+    #[serde(skip)]
+    pub cancel: CancelToken,
 }
 
-#[allow(dead_code)]
-fn print_error(msg: &str) {
-    println!("{} {}", "[ERR]".red().to_string(), msg);
+impl ExecOptions {
+    /// Returns the level of the messages printed, `verbose` winning over
+    /// `quiet`.
+    pub(crate) fn verbosity(&self) -> Verbosity {
+        if self.verbose {
+            Verbosity::Verbose
+        } else if self.quiet {
+            Verbosity::Quiet
+        } else {
+            Verbosity::Normal
+        }
+    }
 }
 
-fn default_as_false() -> bool {
-    false
+/// Number of items of a run by outcome
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub ok: usize,
+
+    pub err: usize,
+
+    pub warn: usize,
+
+    pub skip: usize,
+
+    /// Items which succeeded and changed something, see `changed_when`
+    pub changed: usize,
+
+    /// Kept to `u64` so that summaries convert to `serde_json::Value`
+    pub duration_ms: u64,
 }
 
-fn default_as_true() -> bool {
-    true
+impl RunSummary {
+    /// Returns the line printed last by `--summary-line`, e.g.
+    /// `nansi: ok=12 err=2 warn=1 skip=3 duration_ms=251034 exit=1`.
+    ///
+    /// Scripts parse this line: fields may be appended but are never
+    /// reordered or removed.
+    pub fn summary_line(&self, exit_code: i32) -> String {
+        format!(
+            "nansi: ok={} err={} warn={} skip={} duration_ms={} exit={}",
+            self.ok, self.err, self.warn, self.skip, self.duration_ms, exit_code
+        )
+    }
 }
 
-fn default_as_empty_vec_string() -> Vec<String> {
-    vec![]
+/// Error returned by `execute` when `fail_fast` stopped the run, with the
+/// summary of what ran
+#[derive(Debug)]
+pub struct RunAborted {
+    /// The item whose failure stopped the run, as shown in messages
+    pub failed_item: String,
+
+    pub summary: RunSummary,
 }
 
-fn default_as_empty_string() -> String {
-    String::from("")
+impl fmt::Display for RunAborted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Execution aborted: item {} failed", self.failed_item)
+    }
 }
 
-#[test]
-fn compile_arg_envvar_test() {
-    let arg = String::from("cat Cargo.toml | grep \"version = \\\"${TEST}\\\"\"");
+impl Error for RunAborted {}
 
-    env::set_var("TEST", "XYZ");
+/// Error returned by `execute` in strict mode when items failed (or were
+/// skipped for unmet prerequisites with `strict_skips`), with the summary of
+/// the run
+#[derive(Debug)]
+pub struct RunFailed {
+    /// Number of failed items, not counting items with `ignore_errors`
+    pub failed: usize,
 
-    let compiled_arg = compile_arg(&arg).unwrap();
-    assert_eq!(
-        compiled_arg.as_str(),
-        "cat Cargo.toml | grep \"version = \\\"${TEST}\\\"\""
-    );
+    /// Number of items skipped for unmet prerequisites, 0 without
+    /// `strict_skips`
+    pub skipped: usize,
+
+    pub summary: RunSummary,
 }
 
-#[test]
-fn compile_arg_var_test() {
-    let arg = String::from("cat Cargo.toml | grep \"version = \\\"{TEST}\\\"\"");
+impl fmt::Display for RunFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        if self.failed > 0 {
+            parts.push(format!("{} item(s) failed", self.failed));
+        }
+        if self.skipped > 0 {
+            parts.push(format!(
+                "{} item(s) skipped for unmet prerequisites",
+                self.skipped
+            ));
+        }
 
-    env::set_var("TEST", "XYZ");
+        write!(f, "{}", parts.join(", "))
+    }
+}
 
-    let compiled_arg = compile_arg(&arg).unwrap();
-    assert_eq!(
-        compiled_arg.as_str(),
-        "cat Cargo.toml | grep \"version = \\\"XYZ\\\"\""
+impl Error for RunFailed {}
+
+/// Cause of a failed item, recorded in traces and events
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum FailureReason {
+    /// The command could not be started
+    SpawnFailed {
+        message: String,
+    },
+
+    NonZeroExit {
+        code: i32,
+    },
+
+    /// The command was terminated by a signal (Unix)
+    Signal {
+        signal: i32,
+    },
+
+    /// A tag could not be expanded in strict mode
+    MissingVariable {
+        name: String,
+    },
+
+    /// A check of an `assert` item failed
+    AssertionFailed,
+
+    /// An argument containing tags expanded to an empty string or to `/`;
+    /// `name` is the tag with an empty value, if any
+    EmptyExpansion {
+        argument: usize,
+        name: Option<String>,
+    },
+
+    /// The command succeeded but took longer than `expect_duration_under_secs`
+    DurationExceeded {
+        limit_secs: u64,
+    },
+
+    /// The command was killed after running for `timeout_secs`
+    TimedOut {
+        limit_secs: u64,
+    },
+
+    /// The elevation command of an `elevate` item refused to run without a
+    /// password, or could not be run
+    ElevationFailed,
+
+    /// The item was made to fail with `--inject-failure`, its command was not
+    /// run
+    Injected,
+
+    /// The command succeeded but its `changed_when` could not be evaluated
+    InvalidChangedWhen {
+        message: String,
+    },
+}
+
+impl FailureReason {
+    /// Returns the reason as shown after the status line, e.g. `exit 2`.
+    pub fn short_str(&self) -> String {
+        match self {
+            FailureReason::SpawnFailed { .. } => String::from("spawn failed"),
+            FailureReason::NonZeroExit { code } => format!("exit {}", code),
+            FailureReason::Signal { signal } => format!("signal {}", signal),
+            FailureReason::MissingVariable { name } => format!("missing variable {}", name),
+            FailureReason::AssertionFailed => String::from("assertion failed"),
+            FailureReason::EmptyExpansion {
+                name: Some(name), ..
+            } => format!("empty variable {}", name),
+            FailureReason::EmptyExpansion { argument, .. } => {
+                format!("empty argument {}", argument)
+            }
+            FailureReason::DurationExceeded { limit_secs } => format!("over {}s", limit_secs),
+            FailureReason::TimedOut { limit_secs } => {
+                format!("timed out after {}s", limit_secs)
+            }
+            FailureReason::ElevationFailed => String::from("elevation failed"),
+            FailureReason::Injected => String::from("failure injected"),
+            FailureReason::InvalidChangedWhen { .. } => String::from("invalid changed_when"),
+        }
+    }
+
+    /// Returns the reason of a command which did not exit successfully.
+    fn from_exit_status(status: &ExitStatus) -> FailureReason {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            if let Some(signal) = status.signal() {
+                return FailureReason::Signal { signal };
+            }
+        }
+
+        FailureReason::NonZeroExit {
+            code: status.code().unwrap_or(-1),
+        }
+    }
+}
+
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub(crate) enum ExecStatus {
+    OK,
+    ERR,
+    WARN,
+    SKIP,
+    DRY,
+}
+
+impl ExecStatus {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ExecStatus::OK => "ok",
+            ExecStatus::ERR => "err",
+            ExecStatus::WARN => "warn",
+            ExecStatus::SKIP => "skip",
+            ExecStatus::DRY => "dry",
+        }
+    }
+}
+
+impl NansiFile {
+    pub fn from(file_path: &str) -> Result<NansiFile, io::Error> {
+        let file_str = match fs::read_to_string(file_path) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", file_path, e.to_string()),
+                ));
+            }
+        };
+
+        NansiFile::parse(&file_str, file_path, NansiFormat::Auto.resolve(file_path))
+    }
+
+    /// Parses the content of a `NansiFile` loaded from `file_path` as
+    /// `format` (`Auto` is parsed as json), splicing the included files.
+    pub fn parse(
+        file_str: &str,
+        file_path: &str,
+        format: NansiFormat,
+    ) -> Result<NansiFile, io::Error> {
+        let mut file = match format {
+            NansiFormat::Yaml => NansiFile::from_yaml(file_str, file_path)?,
+            _ => NansiFile::from_json(file_str, file_path)?,
+        };
+        resolve_includes(&mut file)?;
+
+        Ok(file)
+    }
+
+    /// Parses the yaml content of a `NansiFile` loaded from `file_path` (see
+    /// `parse_yaml`).
+    pub fn from_yaml(file_str: &str, file_path: &str) -> Result<NansiFile, io::Error> {
+        let value = match parse_yaml(file_str) {
+            Ok(v) => v,
+            Err(e) => {
+                // `file:line:column: message` when the position is known
+                let separator = if e.position.is_some() { ":" } else { ": " };
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}{}{}", file_path, separator, e),
+                ));
+            }
+        };
+        let mut file: NansiFile = match serde_json::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", file_path, e),
+                ));
+            }
+        };
+
+        file.file_path = String::from(file_path);
+        file.sha256 = sha256_hex(file_str.as_bytes());
+
+        Ok(file)
+    }
+
+    /// Parses the content of a `NansiFile` loaded from `file_path`, either an
+    /// object or a bare array of items (its `exec_list`).
+    pub fn from_json(file_str: &str, file_path: &str) -> Result<NansiFile, io::Error> {
+        let parsed = if file_str.trim_start().starts_with('[') {
+            serde_json::from_str::<BareExecList>(file_str).and_then(|bare| {
+                let mut file: NansiFile = serde_json::from_str("{\"exec_list\": []}")?;
+                file.exec_list = bare.0;
+                Ok(file)
+            })
+        } else {
+            serde_json::from_str(file_str)
+        };
+        let mut file: NansiFile = match parsed {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", file_path, e.to_string()),
+                ));
+            }
+        };
+
+        file.file_path = String::from(file_path);
+        file.sha256 = sha256_hex(file_str.as_bytes());
+
+        Ok(file)
+    }
+}
+
+pub fn execute(
+    nansi_file: &NansiFile,
+    options: &ExecOptions,
+) -> Result<RunSummary, Box<dyn Error>> {
+    let matched = apply_label_matching(nansi_file);
+    let nansi_file: &NansiFile = &matched;
+    let mut results: Vec<ExecResult> = Vec::new();
+    let run_result = execute_items(nansi_file, options, &mut results);
+
+    // written whatever stopped the run, the items it did not get to being
+    // `not_run`
+    if let Some(report_path) = &options.report {
+        let saved = RunReport::new(nansi_file, &results).save(report_path, options.report_format);
+        if let (Ok(_), Err(e)) = (&run_result, saved) {
+            return Err(e.into());
+        }
+    }
+
+    run_result
+}
+
+/// Runs the items of the `NansiFile`, collecting the result of every item it
+/// gets to in `results`.
+fn execute_items(
+    nansi_file: &NansiFile,
+    options: &ExecOptions,
+    results: &mut Vec<ExecResult>,
+) -> Result<RunSummary, Box<dyn Error>> {
+    let run_start = Instant::now();
+    let nansi_file_hash = get_file_hash(&nansi_file.file_path);
+
+    let mut events = EventSink::new();
+    if let Some(fd) = options.status_fd {
+        events.add_fd(fd)?;
+    }
+    if let Some(pipe) = &options.status_pipe {
+        events.add_pipe(pipe)?;
+    }
+
+    let log = match &options.log_file {
+        Some(file_path) => {
+            let mut log = RunLog::open(file_path)?;
+            log.start(&nansi_file.file_path)?;
+            Some(log)
+        }
+        None => None,
+    };
+
+    let answers = match &options.answers {
+        Some(file_path) => Some(Answers::from(file_path)?),
+        None => None,
+    };
+
+    let trace = match &options.trace {
+        Some(_) => {
+            let mut env_names: Vec<String> = env::vars_os()
+                .map(|(name, _)| name.to_string_lossy().to_string())
+                .collect();
+            env_names.sort();
+            Some(Trace::new(nansi_file, options, env_names))
+        }
+        None => None,
+    };
+
+    let overridden = apply_args_overrides(nansi_file, &options.args_overrides)?;
+    let nansi_file = overridden.as_ref();
+    let only = resolve_label_patterns(&nansi_file.exec_list, &options.only, pick_labels)?;
+    let selected = get_selected_items(
+        &nansi_file.exec_list,
+        &only,
+        &options.only_with_deps,
+        &options.skip,
+    )?;
+    let injected = get_injected_items(&nansi_file.exec_list, &options.inject_failure)?;
+
+    let printer = Printer::new(options.status_stream, options.verbosity());
+    printer.print(
+        Verbosity::Normal,
+        &vec![format!("Using NansiFile: {}", nansi_file.file_path)],
+    )?;
+    events.emit(&Event::RunStarted {
+        file: nansi_file.file_path.clone(),
+        items: nansi_file.exec_list.len(),
+    })?;
+
+    let mut run_state = RunState::new(
+        events,
+        trace,
+        log,
+        results,
+        answers,
+        printer,
+        nansi_file.label_state,
+        options.porcelain,
+    );
+
+    let diagnostics = validate(nansi_file);
+    if let Some(diagnostic) = get_run_error(&diagnostics, options.jobs) {
+        return Err(diagnostic.message.clone().into());
+    }
+
+    let duplicates = get_label_duplicates(&diagnostics);
+
+    if duplicates.len() > 0 {
+        let msg = format!(
+            "{}\n{:?}",
+            "The following aliases are duplicated which may cause issues with conditional execution:",
+            duplicates
+        )
+        .to_string();
+
+        run_state.warn(msg)?;
+    }
+
+    for diagnostic in &diagnostics {
+        if let DiagnosticKind::UnknownFeature { .. } | DiagnosticKind::UnknownField { .. } =
+            diagnostic.kind
+        {
+            run_state.warn(diagnostic.message.clone())?;
+        }
+    }
+
+    let unmeetable = get_unmeetable_prerequisites(&diagnostics);
+    if options.strict_prereqs && !unmeetable.is_empty() {
+        return Err(format!(
+            "Prerequisites which can never be met (--strict-prereqs):\n{}",
+            unmeetable.join("\n")
+        )
+        .into());
+    }
+    for msg in unmeetable {
+        run_state.warn(msg)?;
+    }
+
+    if !options.inject_failure.is_empty() {
+        let msg = format!(
+            "Failure drill: items labeled {} fail without being run (--inject-failure)",
+            options.inject_failure.join(", ")
+        );
+        run_state.warn(msg)?;
+    }
+
+    let mut ctx = get_exec_context(nansi_file);
+    ctx.set_elevation(&Elevation::new(
+        options
+            .elevation_command
+            .as_deref()
+            .unwrap_or(&ctx.elevation().command),
+        options.interactive_elevation,
+    ));
+
+    let missing_env = validate_env(nansi_file, &ctx, false);
+    if !missing_env.is_empty() {
+        let msgs: Vec<&str> = missing_env.iter().map(|d| d.message.as_str()).collect();
+        return Err(format!(
+            "Required environment variables are missing:\n{}",
+            msgs.join("\n")
+        )
+        .into());
+    }
+
+    let env_violations = validate_env_constraints(nansi_file, &ctx, Severity::Error);
+    if !options.force_env_checks && !env_violations.is_empty() {
+        let msgs: Vec<&str> = env_violations.iter().map(|d| d.message.as_str()).collect();
+        return Err(format!(
+            "Environment checks failed (use --force-env-checks to run anyway):\n{}",
+            msgs.join("\n")
+        )
+        .into());
+    }
+    for diagnostic in env_violations {
+        run_state.warn(format!("Environment check failed: {}", diagnostic.message))?;
+    }
+
+    if options.dry_run {
+        return dry_run(
+            nansi_file,
+            &selected,
+            &injected,
+            &ctx,
+            options,
+            &mut run_state,
+        );
+    }
+
+    let mut state = load_state(nansi_file, options)?;
+    let state_file_key = get_state_file_key(nansi_file);
+    let mut unchanged_list: Vec<String> = Vec::new();
+
+    let max_failures = match options.max_failures {
+        Some(v) => Some(v),
+        None => nansi_file.max_failures,
+    };
+    let fail_fast = options.fail_fast || nansi_file.fail_fast;
+    let redactors = nansi_file
+        .exec_list
+        .iter()
+        .map(|exec_item| Redactor::new(nansi_file.redact.iter().chain(&exec_item.redact)))
+        .collect::<Result<Vec<Redactor>, String>>()?;
+    let mut threshold_skip_count = 0;
+    let mut cancel_skip_count = 0;
+    let mut prerequisite_skip_count: usize = 0;
+    let mut aborted_by: Option<String> = None;
+    // whether the elevation command can run, checked before the first
+    // elevated item (before each of them while it can, see
+    // `Elevation::authenticates_each_item`)
+    let mut elevation: Option<Result<(), String>> = None;
+    let mut abort_skip_count = 0;
+    let mut fingerprints: HashMap<String, (usize, ExecStatus)> = HashMap::new();
+    let mut changed_count = 0;
+    let mut failures: Vec<(String, String)> = Vec::new();
+
+    let imported = match &options.import_state {
+        Some(path) => load_imported_state(nansi_file, path)?,
+        None => Vec::new(),
+    };
+    for idx in &imported {
+        run_state.mark_success(&nansi_file.exec_list[idx - 1], *idx);
+    }
+
+    let order: Vec<usize> = match options.shuffle_seed {
+        Some(seed) => {
+            printer.print(
+                Verbosity::Normal,
+                &vec![format!(
+                    "Items shuffled with seed {} (reproduce with --shuffle={})",
+                    seed, seed
+                )],
+            )?;
+            get_shuffled_order(&nansi_file.exec_list, seed)
+        }
+        None => (0..nansi_file.exec_list.len()).collect(),
+    };
+
+    let mut progress = Progress::new(options.status_stream, options.progress, order.len());
+    for batch in get_batches(&order, &nansi_file.exec_list, options.jobs) {
+        if let [(_, idx)] = batch[..] {
+            let exec_item = &nansi_file.exec_list[idx];
+            if options.jobs > 1 && exec_item.parallel && exec_item.serial {
+                printer.print(
+                    Verbosity::Verbose,
+                    &vec![format!(
+                        "[INFO] Item {} is serial: it runs alone, the items before it are done and those after it wait",
+                        ItemDisplay::new(exec_item, idx + 1)
+                    )],
+                )?;
+            }
+        }
+
+        // the items of a batch are all prepared (and their prerequisites
+        // checked) before any of them runs, then finish in any order
+        let mut pending: Vec<(PendingItem, ItemJob)> = Vec::new();
+        for (position, idx) in batch {
+            let exec_item = &nansi_file.exec_list[idx];
+            let mut block: Vec<String> = Vec::new();
+
+            if exec_item.is_timer() {
+                run_state.update_timers(exec_item)?;
+                continue;
+            }
+
+            if !selected[idx] {
+                run_state.skip(exec_item, idx + 1, get_not_selected_msg(exec_item, idx + 1))?;
+                continue;
+            }
+
+            if imported.contains(&(idx + 1)) {
+                let msg = format!(
+                    "Item {} is satisfied by imported state.",
+                    ItemDisplay::new(exec_item, idx + 1)
+                );
+                run_state.skip(exec_item, idx + 1, msg)?;
+                continue;
+            }
+
+            if let Some(max) = max_failures {
+                if run_state.failure_count >= max {
+                    let msg = format!(
+                        "Item {} skipped: failure threshold reached.",
+                        ItemDisplay::new(exec_item, idx + 1)
+                    );
+                    run_state.skip_not_run(exec_item, idx + 1, msg)?;
+                    threshold_skip_count += 1;
+                    continue;
+                }
+            }
+
+            if let Some(failed) = &aborted_by {
+                let msg = format!(
+                    "Item {} skipped: execution aborted after item {} failed.",
+                    ItemDisplay::new(exec_item, idx + 1),
+                    failed
+                );
+                run_state.skip_not_run(exec_item, idx + 1, msg)?;
+                abort_skip_count += 1;
+                continue;
+            }
+
+            if options.cancel.is_cancelled() {
+                let msg = format!(
+                    "Item {} skipped: the run was cancelled.",
+                    ItemDisplay::new(exec_item, idx + 1)
+                );
+                run_state.skip_not_run(exec_item, idx + 1, msg)?;
+                cancel_skip_count += 1;
+                continue;
+            }
+
+            let unmet = get_unmet_prerequisites(exec_item, &run_state);
+            if !unmet.is_empty() {
+                let item_str = ItemDisplay::new(exec_item, idx + 1).to_string();
+
+                let unmet_str: Vec<String> = unmet
+                    .iter()
+                    .map(|prereq| get_prerequisite_str(prereq, &nansi_file.exec_list, &run_state))
+                    .collect();
+                let msg = format!(
+                    "Prerequisites for item {} are not met: {}.",
+                    item_str,
+                    unmet_str.join(", ")
+                );
+                let root_cause =
+                    get_skip_root_cause(&nansi_file.exec_list, idx + 1, &unmet, run_state.results);
+                run_state.skip(exec_item, idx + 1, msg)?;
+                if let Some(result) = run_state.results.last_mut() {
+                    result.skip_root_cause = root_cause;
+                }
+                prerequisite_skip_count += 1;
+                continue;
+            }
+
+            // an item whose key, arguments or env do not compile fails without
+            // running, the rest of the run goes on
+            let (idempotence_key, key_error) = match &exec_item.idempotence_key {
+                Some(key) => match compile_arg(key, &ctx) {
+                    Ok(key) => (Some(key), None),
+                    Err(e) => (None, Some(e)),
+                },
+                None => (None, None),
+            };
+            let item_id = get_item_id(exec_item, idx + 1);
+
+            if let (Some(key), Some((_, state))) = (&idempotence_key, &state) {
+                if !options.force
+                    && !injected[idx]
+                    && state.get_idempotence_key(&state_file_key, &item_id) == Some(key)
+                {
+                    let item_str = ItemDisplay::new(exec_item, idx + 1).to_string();
+
+                    let msg = format!("Item {} is unchanged since last successful run.", item_str);
+                    run_state.skip(exec_item, idx + 1, msg)?;
+                    run_state.mark_success(exec_item, idx + 1);
+                    unchanged_list.push(item_str);
+                    continue;
+                }
+            }
+
+            let (args, compile_error) = match compile_args(exec_item, &ctx) {
+                Ok(args) => (
+                    args,
+                    key_error.or_else(|| get_item_env(exec_item, &ctx).err()),
+                ),
+                Err(e) => (exec_item.get_args(), Some(e)),
+            };
+            let fingerprint = if nansi_file.dedupe_identical && compile_error.is_none() {
+                get_item_fingerprint(exec_item, &args, &ctx)
+            } else {
+                None
+            };
+            if let Some((first_idx, first_status)) = fingerprint
+                .as_ref()
+                .filter(|_| !injected[idx])
+                .and_then(|fingerprint| fingerprints.get(fingerprint))
+                .copied()
+            {
+                let msg = format!(
+                    "Item {} is a duplicate of item {}.",
+                    ItemDisplay::new(exec_item, idx + 1),
+                    first_idx
+                );
+                run_state.skip(exec_item, idx + 1, msg)?;
+                if first_status == ExecStatus::OK {
+                    run_state.mark_success(exec_item, idx + 1);
+                }
+                continue;
+            }
+
+            if exec_item.confirm && !options.yes && compile_error.is_none() && !injected[idx] {
+                progress.clear()?;
+                let command = ItemDisplay::new(exec_item, idx + 1)
+                    .with_args(&mask_secret_args(exec_item, &args, &ctx))
+                    .to_string();
+                if let Some(msg) = run_state.confirm(exec_item, idx + 1, &command)? {
+                    run_state.skip(exec_item, idx + 1, msg)?;
+                    continue;
+                }
+            }
+
+            let cwd = get_item_cwd(exec_item, &ctx).unwrap_or_default();
+            for arg in get_self_references(&args, &nansi_file.file_path, cwd.as_deref()) {
+                block.push(format!(
+                    "[INFO] Item {} references the NansiFile itself: {}",
+                    ItemDisplay::new(exec_item, idx + 1),
+                    arg
+                ));
+            }
+            block.extend(get_args_override_msg(exec_item, idx + 1, options));
+            run_state.events.emit(&Event::ItemStarted {
+                index: idx + 1,
+                label: exec_item.label.clone(),
+                exec: exec_item.exec.clone(),
+                args: args.clone(),
+            })?;
+
+            let unexpanded = if compile_error.is_none() {
+                get_unexpanded_tags_msgs(exec_item, idx + 1, &args)
+            } else {
+                Vec::new()
+            };
+            for msg in &unexpanded {
+                run_state.events.emit(&Event::Warning {
+                    index: Some(idx + 1),
+                    message: msg.clone(),
+                })?;
+            }
+
+            progress.show(
+                position + 1,
+                ItemDisplay::new(exec_item, idx + 1)
+                    .with_command()
+                    .to_string()
+                    .trim_end(),
+            )?;
+            let empty_expansions = if exec_item.allow_empty_expansion || compile_error.is_some() {
+                Vec::new()
+            } else {
+                get_empty_expansions(exec_item, idx + 1, &args, &ctx)?
+            };
+
+            let mut detail_lines: Vec<String> = Vec::new();
+            let mut usage: Option<ResourceUsage> = None;
+            let mut streamed = false;
+            let elevation_error =
+                if exec_item.elevate && exec_item.assert.is_none() && !injected[idx] {
+                    progress.clear()?;
+                    let result = match &elevation {
+                        Some(Ok(())) if !ctx.elevation().authenticates_each_item() => Ok(()),
+                        Some(Err(e)) => Err(e.clone()),
+                        _ => ctx.elevation().authenticate(),
+                    };
+                    elevation = Some(result.clone());
+                    result.err()
+                } else {
+                    None
+                };
+            let job = if injected[idx] {
+                block.push(get_warning_str(
+                    options.status_stream,
+                    &format!(
+                        "Failure of item {} is injected, its command was not run",
+                        ItemDisplay::new(exec_item, idx + 1)
+                    ),
+                ));
+                ItemJob::Done(RunOutcome::failed(
+                    "failure injected",
+                    Some(FailureReason::Injected),
+                ))
+            } else if let Some(e) = &compile_error {
+                let msg = format!("item {}: {}", ItemDisplay::new(exec_item, idx + 1), e);
+                block.push(get_error_str(options.status_stream, &msg));
+                ItemJob::Done(RunOutcome::failed(&msg, e.failure_reason()))
+            } else if !unexpanded.is_empty() && options.strict {
+                block.extend(
+                    unexpanded
+                        .iter()
+                        .map(|msg| get_error_str(options.status_stream, msg)),
+                );
+                ItemJob::Done(RunOutcome::failed(
+                    "Unexpanded tags are not allowed in strict mode",
+                    get_unexpanded_tags(exec_item, &args)
+                        .into_iter()
+                        .next()
+                        .map(|name| FailureReason::MissingVariable { name }),
+                ))
+            } else if let Some((reason, _)) = empty_expansions.first() {
+                block.extend(
+                    empty_expansions
+                        .iter()
+                        .map(|(_, msg)| get_error_str(options.status_stream, msg)),
+                );
+                ItemJob::Done(RunOutcome::failed(
+                    "Arguments expanding to an empty string or / are not run (see allow_empty_expansion)",
+                    Some(reason.clone()),
+                ))
+            } else if let Some(message) = elevation_error {
+                block.push(get_error_str(options.status_stream, &message));
+                ItemJob::Done(RunOutcome::failed(
+                    &message,
+                    Some(FailureReason::ElevationFailed),
+                ))
+            } else {
+                if exec_item.assert.is_none() {
+                    let mut argv = vec![exec_item.exec.clone()];
+                    argv.extend(mask_secret_args(exec_item, &args, &ctx));
+                    printer.print(
+                        Verbosity::Verbose,
+                        &vec![format!(
+                            "[INFO] Running item {}: {:?}",
+                            ItemDisplay::new(exec_item, idx + 1),
+                            argv
+                        )],
+                    )?;
+                }
+                if printer.shows(Verbosity::Verbose)
+                    && exec_item.elevate
+                    && exec_item.assert.is_none()
+                {
+                    let mut command = ctx.elevation().prefix();
+                    command.push(exec_item.exec.clone());
+                    command.extend(mask_secret_args(exec_item, &args, &ctx));
+                    block.push(format!(
+                        "[INFO] Elevated command of item {}: {}",
+                        ItemDisplay::new(exec_item, idx + 1),
+                        command.join(" ")
+                    ));
+                }
+                block.extend(
+                    unexpanded
+                        .iter()
+                        .map(|msg| get_warning_str(options.status_stream, msg)),
+                );
+                match (&exec_item.assert, &exec_item.chunk_args) {
+                    (Some(assertions), _) => {
+                        ItemJob::Done(run_assertions(assertions, &ctx, &mut detail_lines)?)
+                    }
+                    (None, Some(chunk_args)) => ItemJob::Done(run_chunked_exec(
+                        exec_item,
+                        &args,
+                        chunk_args,
+                        &ctx,
+                        &mut detail_lines,
+                        &mut usage,
+                    )?),
+                    (None, None) => match prepare_exec(exec_item, &args, &ctx)? {
+                        ItemJob::Command(mut command)
+                            if exec_item.print_output == PrintOutput::Stream =>
+                        {
+                            let output_stream = get_item_output_stream(exec_item, options);
+                            command.on_line = get_line_sink(
+                                ItemDisplay::new(exec_item, idx + 1).to_string(),
+                                redactors[idx].clone(),
+                                printer,
+                                output_stream,
+                            );
+                            streamed = command.on_line.is_some();
+                            ItemJob::Command(command)
+                        }
+                        job => job,
+                    },
+                }
+            };
+
+            if let Some(msg) = get_weight_warning(exec_item, idx + 1, options.jobs) {
+                block.push(get_warning_str(options.status_stream, &msg));
+            }
+
+            let pending_item = PendingItem {
+                idx,
+                exec_item,
+                block,
+                args,
+                unexpanded,
+                fingerprint,
+                idempotence_key,
+                item_id,
+                detail_lines,
+                usage,
+                streamed,
+            };
+            pending.push((pending_item, job));
+        }
+
+        for (pending_item, mut outcome) in run_batch(pending, options.jobs) {
+            let PendingItem {
+                idx,
+                exec_item,
+                mut block,
+                args,
+                unexpanded,
+                fingerprint,
+                idempotence_key,
+                item_id,
+                mut detail_lines,
+                usage,
+                streamed,
+            } = pending_item;
+            let duration_ms = outcome.duration.as_millis();
+            progress.clear()?;
+
+            if outcome.status == ExecStatus::OK {
+                if let Some(msg) = get_duration_failure(exec_item, duration_ms) {
+                    outcome.status = ExecStatus::ERR;
+                    outcome.failure_reason = exec_item
+                        .expect_duration_under_secs
+                        .map(|limit_secs| FailureReason::DurationExceeded { limit_secs });
+                    detail_lines.push(msg);
+                }
+            }
+
+            // the output shown is stdout on success and stderr on failure, unless
+            // both streams were merged into stdout
+            let mut exec_status = outcome.status;
+            let exit_code = outcome.exit_code;
+            let mut failure_reason = outcome.failure_reason.clone();
+            let (stdout, stdout_redacted) = redactors[idx].redact(&outcome.stdout_str()?);
+            let (stderr, stderr_redacted) = redactors[idx].redact(&outcome.stderr_str()?);
+            if printer.shows(Verbosity::Verbose) && stdout_redacted + stderr_redacted > 0 {
+                block.push(format!(
+                    "[INFO] {} match(es) redacted from the output of item {}",
+                    stdout_redacted + stderr_redacted,
+                    ItemDisplay::new(exec_item, idx + 1)
+                ));
+            }
+            let (output, other_output) = if exec_status == ExecStatus::OK || outcome.merged {
+                (stdout.clone(), stderr.clone())
+            } else {
+                (stderr.clone(), stdout.clone())
+            };
+
+            // problems with changed_when or the messages fail the item, which
+            // already ran, instead of the run
+            let mut changed = match get_changed(exec_item, exec_status, exit_code, &output) {
+                Ok(changed) => changed,
+                Err(e) => {
+                    let msg = format!("item {}: {}", ItemDisplay::new(exec_item, idx + 1), e);
+                    detail_lines.push(get_error_str(options.status_stream, &msg));
+                    exec_status = ExecStatus::ERR;
+                    failure_reason = Some(FailureReason::InvalidChangedWhen { message: e });
+                    None
+                }
+            };
+
+            let message = match exec_status {
+                ExecStatus::OK => compile_opt_arg(&exec_item.success_message, &ctx),
+                ExecStatus::ERR => compile_opt_arg(&exec_item.failure_hint, &ctx),
+                _ => Ok(None),
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    let msg = format!("item {}: {}", ItemDisplay::new(exec_item, idx + 1), e);
+                    detail_lines.push(get_error_str(options.status_stream, &msg));
+                    if exec_status == ExecStatus::OK {
+                        exec_status = ExecStatus::ERR;
+                        failure_reason = e.failure_reason();
+                        changed = None;
+                    }
+                    None
+                }
+            };
+            let (success_message, failure_hint) = match exec_status {
+                ExecStatus::OK => (message, None),
+                _ => (None, message),
+            };
+            if changed == Some(true) {
+                changed_count += 1;
+            }
+
+            let (stdout, stderr) = match exec_item.capture {
+                Capture::None => (
+                    String::from(OUTPUT_NOT_CAPTURED),
+                    String::from(OUTPUT_NOT_CAPTURED),
+                ),
+                _ => (stdout, stderr),
+            };
+            run_state.record_item(TraceItem {
+                index: idx + 1,
+                label: exec_item.label.clone(),
+                exec: exec_item.exec.clone(),
+                args: mask_secret_args(exec_item, &args, &ctx),
+                status: String::from(exec_status.as_str()),
+                exit_code,
+                duration_ms,
+                stdout,
+                stderr,
+                failure_reason: failure_reason.clone(),
+                message: None,
+                warnings: unexpanded.clone(),
+                success_message: success_message.clone(),
+                failure_hint: failure_hint.clone(),
+                resource_usage: usage,
+                changed,
+                merged_streams: outcome.merged,
+                args_override: options.args_overrides.get(&exec_item.label).cloned(),
+            })?;
+
+            if exec_status == ExecStatus::OK {
+                run_state.mark_success(exec_item, idx + 1);
+            } else {
+                run_state.mark_failure(exec_item, idx + 1);
+            }
+
+            if let Some(fingerprint) = fingerprint {
+                fingerprints.insert(fingerprint, (idx + 1, exec_status));
+            }
+
+            if exec_status == ExecStatus::ERR && !exec_item.ignore_errors {
+                run_state.failure_count += 1;
+                if fail_fast {
+                    aborted_by = Some(ItemDisplay::new(exec_item, idx + 1).to_string());
+                }
+            }
+
+            // a drill leaves the state of the item as it is
+            if let (Some(key), Some((_, state)), false) =
+                (&idempotence_key, &mut state, injected[idx])
+            {
+                if exec_status == ExecStatus::OK {
+                    state.set_idempotence_key(&state_file_key, &item_id, key);
+                } else {
+                    state.remove_idempotence_key(&state_file_key, &item_id);
+                }
+            }
+
+            if exec_item.print_status {
+                let mut status_str =
+                    get_status_str(options.status_stream, exec_item, idx + 1, exec_status);
+                if let Some(reason) = &failure_reason {
+                    status_str.push_str(&format!(" ({})", reason.short_str()));
+                }
+                if changed == Some(false) {
+                    status_str.push_str(" (unchanged)");
+                }
+                if options.timings {
+                    status_str.push(' ');
+                    status_str.push_str(&get_timings_str(duration_ms, usage.as_ref()));
+                }
+                block.push(status_str);
+            } else if exec_status == ExecStatus::ERR && printer.shows(Verbosity::Normal) {
+                // a failure is never silent, even if the status line is disabled
+                block.push(format!(
+                    "{} {} (status output suppressed)",
+                    get_status_tag(options.status_stream, exec_status),
+                    ItemDisplay::new(exec_item, idx + 1)
+                ));
+            }
+            block.extend(detail_lines);
+            if let Some(msg) = &success_message {
+                block.extend(get_item_message_lines(options.status_stream, "[NOTE]", msg));
+            }
+            if let Some(hint) = &failure_hint {
+                block.extend(get_item_message_lines(
+                    options.status_stream,
+                    "[HINT]",
+                    hint,
+                ));
+            }
+
+            if exec_status == ExecStatus::ERR {
+                failures.push((
+                    ItemDisplay::new(exec_item, idx + 1)
+                        .with_command()
+                        .to_string()
+                        .trim_end()
+                        .to_string(),
+                    output.clone(),
+                ));
+            }
+
+            // output printed to the status stream stays in the block of the item
+            let output_stream = get_item_output_stream(exec_item, options);
+            let mut output_block: Vec<String> = Vec::new();
+            if exec_item.print_output != PrintOutput::None {
+                let output = get_output_str(
+                    exec_item,
+                    exec_status,
+                    output,
+                    &other_output,
+                    !printer.shows(Verbosity::Normal),
+                );
+                if let Some(output) = output {
+                    run_state.events.emit(&Event::ItemOutput {
+                        index: idx + 1,
+                        label: exec_item.label.clone(),
+                        data: output.clone(),
+                    })?;
+                    let lines = if streamed {
+                        Vec::new()
+                    } else {
+                        options
+                            .ci_folding
+                            .wrap(&ItemDisplay::new(exec_item, idx + 1).to_string(), output)
+                    };
+                    if output_stream == options.status_stream {
+                        block.extend(lines);
+                    } else {
+                        output_block.extend(lines);
+                    }
+                }
+            }
+
+            if exec_status == ExecStatus::ERR && options.echo_item_on_failure {
+                block.push(get_resolved_item_str(exec_item, idx + 1, &args, &ctx)?);
+            }
+            if printer.shows(Verbosity::Verbose) && exec_item.assert.is_none() {
+                block.push(get_exit_code_str(exec_item, idx + 1, exit_code));
+            }
+
+            // only failures are printed with `--quiet`
+            let level = if exec_status == ExecStatus::ERR {
+                Verbosity::Quiet
+            } else {
+                Verbosity::Normal
+            };
+            printer.print(level, &block)?;
+            printer.print_to(output_stream, level, &output_block)?;
+
+            if exec_status == ExecStatus::ERR && exec_item.notify_on_failure {
+                let notification = Notification::item_failed(
+                    &nansi_file.file_path,
+                    &ItemDisplay::new(exec_item, idx + 1).to_string(),
+                );
+                run_state.notify(notification);
+            }
+
+            run_state.finish(
+                exec_item,
+                idx + 1,
+                exec_status,
+                None,
+                failure_reason,
+                exit_code,
+                duration_ms,
+            )?;
+        }
+    }
+
+    if let Some((state_path, state)) = &state {
+        state.save(state_path)?;
+    }
+
+    if !unchanged_list.is_empty() {
+        printer.print(
+            Verbosity::Normal,
+            &vec![format!(
+                "{} item(s) unchanged since last successful run:\n{:?}",
+                unchanged_list.len(),
+                unchanged_list
+            )],
+        )?;
+    }
+
+    if threshold_skip_count > 0 {
+        let msg = format!(
+            "Failure threshold of {} reached, {} remaining item(s) skipped.",
+            run_state.failure_count, threshold_skip_count
+        );
+        run_state.warn(msg)?;
+    }
+
+    if cancel_skip_count > 0 {
+        let msg = format!(
+            "Run cancelled, {} remaining item(s) skipped.",
+            cancel_skip_count
+        );
+        run_state.warn(msg)?;
+    }
+
+    if let Some(failed) = &aborted_by {
+        let msg = format!(
+            "Fail fast: execution aborted after item {} failed, {} remaining item(s) skipped.",
+            failed, abort_skip_count
+        );
+        run_state.warn(msg)?;
+    }
+
+    for msg in get_duplicate_label_msgs(&duplicates, run_state.results) {
+        run_state.warn(msg)?;
+    }
+
+    printer.print(
+        Verbosity::Normal,
+        &get_skip_root_cause_lines(&nansi_file.exec_list, run_state.results),
+    )?;
+
+    if nansi_file_hash.is_some() && get_file_hash(&nansi_file.file_path) != nansi_file_hash {
+        let msg = format!(
+            "NansiFile {} was modified during the run, the items executed are those loaded at the start.",
+            nansi_file.file_path
+        );
+        run_state.warn(msg)?;
+    }
+
+    if !run_state.timer_spans.is_empty() {
+        let mut lines = vec![String::from("Timers:")];
+        lines.extend(
+            run_state
+                .timer_spans
+                .iter()
+                .map(|span| format!("  {}: {} ms", span.name, span.duration_ms)),
+        );
+        printer.print(Verbosity::Normal, &lines)?;
+    }
+
+    if let Some(tmp_dir) = ctx.created_tmp_dir() {
+        if options.keep_tmp || (nansi_file.keep_tmp_on_failure && run_state.failure_count > 0) {
+            printer.print(
+                Verbosity::Normal,
+                &vec![format!("Temporary directory kept: {}", tmp_dir.display())],
+            )?;
+        } else if let Err(e) = fs::remove_dir_all(&tmp_dir) {
+            let msg = format!(
+                "Temporary directory {} could not be removed: {}",
+                tmp_dir.display(),
+                e
+            );
+            run_state.warn(msg)?;
+        }
+    }
+
+    let status_counts = run_state.status_counts.clone();
+    let count = |status: ExecStatus| *status_counts.get(&status).unwrap_or(&0);
+    let summary = RunSummary {
+        ok: count(ExecStatus::OK),
+        err: count(ExecStatus::ERR),
+        warn: count(ExecStatus::WARN),
+        skip: count(ExecStatus::SKIP),
+        changed: changed_count,
+        duration_ms: run_start.elapsed().as_millis() as u64,
+    };
+
+    if options.changed_exit_code.is_some()
+        || nansi_file
+            .exec_list
+            .iter()
+            .any(|item| item.changed_when.is_some())
+    {
+        printer.print(
+            Verbosity::Normal,
+            &vec![format!(
+                "{} item(s) changed, {} unchanged.",
+                summary.changed,
+                summary.ok - summary.changed
+            )],
+        )?;
+    }
+
+    printer.print(
+        Verbosity::Quiet,
+        &get_failure_preview(&failures, options.failure_preview_lines),
+    )?;
+
+    printer.print(
+        Verbosity::Normal,
+        &vec![format!("NansiFile sha256: {}", nansi_file.sha256)],
+    )?;
+
+    if options.notify_desktop {
+        let notification = Notification::run_finished(
+            &nansi_file.file_path,
+            summary.ok,
+            summary.err,
+            summary.skip,
+            run_start.elapsed().as_millis(),
+        );
+        run_state.notify(notification);
+    }
+    run_state.wait_for_notifications()?;
+
+    run_state.events.emit(&Event::RunFinished {
+        ok: summary.ok,
+        err: summary.err,
+        warn: summary.warn,
+        skip: summary.skip,
+        changed: summary.changed,
+    })?;
+    if let Some(log) = &mut run_state.log {
+        log.finish(&summary)?;
+    }
+
+    if let (Some(trace), Some(trace_path)) = (&mut run_state.trace, &options.trace) {
+        trace.duration_ms = run_start.elapsed().as_millis();
+        trace.save(trace_path)?;
+    }
+
+    if let Some(failed_item) = aborted_by {
+        return Err(Box::new(RunAborted {
+            failed_item,
+            summary,
+        }));
+    }
+
+    let skipped = if options.strict_skips {
+        prerequisite_skip_count
+    } else {
+        0
+    };
+    if options.strict && (run_state.failure_count > 0 || skipped > 0) {
+        return Err(Box::new(RunFailed {
+            failed: run_state.failure_count as usize,
+            skipped,
+            summary,
+        }));
+    }
+
+    Ok(summary)
+}
+
+/// Prints the plan of `execute` without running anything: each item that
+/// would run with its compiled (and masked) arguments and the status `DRY`,
+/// assuming every item succeeds, and the items whose prerequisites would not
+/// be met. Fails if arguments cannot be compiled or would fail the item
+/// (empty expansions, unexpanded tags in strict mode).
+fn dry_run<'a>(
+    nansi_file: &'a NansiFile,
+    selected: &[bool],
+    injected: &[bool],
+    ctx: &ExecContext,
+    options: &ExecOptions,
+    run_state: &mut RunState<'a>,
+) -> Result<RunSummary, Box<dyn Error>> {
+    let start = Instant::now();
+    let mut error_count = 0;
+
+    for (idx, exec_item) in nansi_file.exec_list.iter().enumerate() {
+        if exec_item.is_timer() {
+            continue;
+        }
+
+        if !selected[idx] {
+            run_state.skip(exec_item, idx + 1, get_not_selected_msg(exec_item, idx + 1))?;
+            continue;
+        }
+
+        let unmet = get_unmet_prerequisites(exec_item, &*run_state);
+        if !unmet.is_empty() {
+            let unmet_str: Vec<String> = unmet
+                .iter()
+                .map(|prereq| get_prerequisite_str(prereq, &nansi_file.exec_list, &*run_state))
+                .collect();
+            let msg = format!(
+                "Prerequisites for item {} would not be met: {}.",
+                ItemDisplay::new(exec_item, idx + 1),
+                unmet_str.join(", ")
+            );
+            run_state.skip(exec_item, idx + 1, msg)?;
+            continue;
+        }
+
+        // simulates the failure so that the items depending on it show what
+        // would happen
+        if injected[idx] {
+            let mut status_str =
+                get_status_str(options.status_stream, exec_item, idx + 1, ExecStatus::ERR);
+            status_str.push_str(&format!(" ({})", FailureReason::Injected.short_str()));
+            run_state
+                .printer
+                .print(Verbosity::Quiet, &vec![status_str])?;
+            run_state.mark_failure(exec_item, idx + 1);
+            run_state.finish(
+                exec_item,
+                idx + 1,
+                ExecStatus::ERR,
+                None,
+                Some(FailureReason::Injected),
+                None,
+                0,
+            )?;
+            continue;
+        }
+
+        let mut item_errors = get_undefined_tags_msgs(exec_item, idx + 1, ctx)?;
+        let mut block: Vec<String> = Vec::new();
+        let compiled = if item_errors.is_empty() {
+            Some(compile_args(exec_item, ctx))
+        } else {
+            None
+        };
+        match compiled {
+            Some(Ok(args)) => {
+                let masked_args = mask_secret_args(exec_item, &args, ctx);
+                block.push(format!(
+                    "[{}] {}",
+                    paint(options.status_stream, "DRY".cyan()),
+                    ItemDisplay::new(exec_item, idx + 1).with_args(&masked_args)
+                ));
+                block.extend(get_args_override_msg(exec_item, idx + 1, options));
+
+                let unexpanded = get_unexpanded_tags_msgs(exec_item, idx + 1, &args);
+                if options.strict {
+                    item_errors.extend(unexpanded);
+                } else {
+                    block.extend(
+                        unexpanded
+                            .iter()
+                            .map(|msg| get_warning_str(options.status_stream, msg)),
+                    );
+                }
+                if !exec_item.allow_empty_expansion {
+                    item_errors.extend(
+                        get_empty_expansions(exec_item, idx + 1, &args, ctx)?
+                            .into_iter()
+                            .map(|(_, msg)| msg),
+                    );
+                }
+            }
+            None => block.push(format!(
+                "[{}] {}",
+                paint(options.status_stream, "DRY".cyan()),
+                ItemDisplay::new(exec_item, idx + 1)
+                    .with_command()
+                    .to_string()
+                    .trim_end()
+            )),
+            Some(Err(e)) => item_errors.push(format!(
+                "Arguments of item {} cannot be compiled: {}",
+                ItemDisplay::new(exec_item, idx + 1),
+                e
+            )),
+        }
+
+        block.extend(
+            item_errors
+                .iter()
+                .map(|msg| get_error_str(options.status_stream, msg)),
+        );
+        let level = if item_errors.is_empty() {
+            Verbosity::Normal
+        } else {
+            Verbosity::Quiet
+        };
+        run_state.printer.print(level, &block)?;
+
+        if item_errors.is_empty() {
+            run_state.mark_success(exec_item, idx + 1);
+            run_state.finish(exec_item, idx + 1, ExecStatus::DRY, None, None, None, 0)?;
+        } else {
+            run_state.mark_failure(exec_item, idx + 1);
+            run_state.finish(exec_item, idx + 1, ExecStatus::ERR, None, None, None, 0)?;
+            error_count += item_errors.len();
+        }
+    }
+
+    // `{nansi.tmp_dir}` creates the directory when compiled
+    if let Some(tmp_dir) = ctx.created_tmp_dir() {
+        let _ = fs::remove_dir_all(tmp_dir);
+    }
+
+    let count = |status: ExecStatus| *run_state.status_counts.get(&status).unwrap_or(&0);
+    let summary = RunSummary {
+        ok: count(ExecStatus::DRY),
+        err: count(ExecStatus::ERR),
+        warn: 0,
+        skip: count(ExecStatus::SKIP),
+        changed: 0,
+        duration_ms: start.elapsed().as_millis() as u64,
+    };
+    let injected_count = injected.iter().filter(|injected| **injected).count();
+    let mut summary_str = format!(
+        "Dry run: {} item(s) would run, {} would be skipped",
+        summary.ok, summary.skip
+    );
+    if injected_count > 0 {
+        summary_str.push_str(&format!(", {} would fail (injected)", injected_count));
+    }
+    run_state
+        .printer
+        .print(Verbosity::Normal, &vec![format!("{}.", summary_str)])?;
+    if let Some(log) = &mut run_state.log {
+        log.finish(&summary)?;
+    }
+
+    if error_count > 0 {
+        return Err(format!("The plan is invalid, {} error(s) found", error_count).into());
+    }
+
+    Ok(summary)
+}
+
+/// Returns the context items of the `NansiFile` are compiled and run in.
+pub(crate) fn get_exec_context(nansi_file: &NansiFile) -> ExecContext {
+    let mut ctx = ExecContext::new();
+    if !nansi_file.var_precedence.is_empty() {
+        ctx.set_precedence(&nansi_file.var_precedence);
+    }
+    if is_url(&nansi_file.file_path) {
+        ctx.set_source_url(&nansi_file.file_path);
+    } else if let Some(dir) = Path::new(&nansi_file.file_path).parent() {
+        if !dir.as_os_str().is_empty() {
+            ctx.set_base_dir(dir);
+        }
+    }
+    ctx.set_features(&nansi_file.features);
+    if let Some(names) = &nansi_file.env_passthrough {
+        ctx.set_env_passthrough(names);
+    }
+    if let Some(command) = &nansi_file.elevation_command {
+        ctx.set_elevation(&Elevation::new(command, false));
+    }
+    for (name, value) in resolve_vars(&nansi_file.vars, &ctx) {
+        ctx.set_var(VarSource::Vars, &name, &value);
+    }
+
+    ctx
+}
+
+/// Prints the items of a `NansiFile` (`nansi list`), or only the items whose
+/// label is not referenced by any other item.
+pub fn list(nansi_file: &NansiFile, unused_labels: bool) -> Result<(), Box<dyn Error>> {
+    let items: Vec<(usize, &ExecItem)> = if unused_labels {
+        get_unused_labels(nansi_file)
+    } else {
+        nansi_file
+            .exec_list
+            .iter()
+            .enumerate()
+            .map(|(idx, exec_item)| (idx + 1, exec_item))
+            .collect()
+    };
+
+    let lines: Vec<String> = items
+        .iter()
+        .map(|(idx, exec_item)| ItemDisplay::new(exec_item, *idx).with_command().to_string())
+        .collect();
+
+    print_block(OutputStream::Stdout, &lines)?;
+
+    Ok(())
+}
+
+/// Prints the labeled items with their index and command.
+pub fn list_labels(nansi_file: &NansiFile, format: ListFormat) -> Result<(), Box<dyn Error>> {
+    print_block(OutputStream::Stdout, &get_label_lines(nansi_file, format)?)?;
+
+    Ok(())
+}
+
+/// Returns the listing printed by `list_labels`: one line per labeled item, or
+/// a single json array of `{index, label, command}` objects.
+fn get_label_lines(
+    nansi_file: &NansiFile,
+    format: ListFormat,
+) -> Result<Vec<String>, serde_json::Error> {
+    let items = nansi_file
+        .exec_list
+        .iter()
+        .enumerate()
+        .filter(|(_, exec_item)| !exec_item.label.is_empty() && !exec_item.is_timer());
+
+    match format {
+        ListFormat::Text => Ok(items
+            .map(|(idx, exec_item)| {
+                ItemDisplay::new(exec_item, idx + 1)
+                    .with_command()
+                    .to_string()
+            })
+            .collect()),
+        ListFormat::Json => {
+            let entries: Vec<serde_json::Value> = items
+                .map(|(idx, exec_item)| {
+                    serde_json::json!({
+                        "index": idx + 1,
+                        "label": exec_item.label,
+                        "command": exec_item.command_line().trim(),
+                    })
+                })
+                .collect();
+
+            Ok(vec![serde_json::to_string_pretty(&entries)?])
+        }
+    }
+}
+
+/// Problem found while compiling the tags of an argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    /// A tag refers to a variable which is not set
+    UndefinedVariable { name: String },
+
+    /// A `{` opens a tag inside another one; `position` is the 1-based
+    /// position of the character in the argument
+    UnbalancedBrace { position: usize },
+}
+
+/// Error of `compile_arg`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// Argument as written in the `NansiFile`
+    pub arg: String,
+
+    /// 1-based position of the argument in `args`, set by `compile_args`
+    pub arg_index: Option<usize>,
+
+    pub kind: CompileErrorKind,
+}
+
+impl CompileError {
+    /// Returns the reason an item fails with because of this error.
+    pub fn failure_reason(&self) -> Option<FailureReason> {
+        match &self.kind {
+            CompileErrorKind::UndefinedVariable { name } => {
+                Some(FailureReason::MissingVariable { name: name.clone() })
+            }
+            CompileErrorKind::UnbalancedBrace { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            CompileErrorKind::UndefinedVariable { name } => {
+                write!(f, "undefined variable '{}'", name)?
+            }
+            CompileErrorKind::UnbalancedBrace { position } => {
+                write!(f, "unbalanced '{{' at position {}", position)?
+            }
+        }
+        match self.arg_index {
+            Some(arg_index) => write!(f, " in arg {}", arg_index),
+            None => write!(f, " in {:?}", self.arg),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// Returns `arg` with its `{}` tags replaced by the values of the variables
+/// they name in `ctx`.
+pub fn compile_arg(arg: &str, ctx: &ExecContext) -> Result<String, CompileError> {
+    let mut compiled_arg = String::new();
+
+    for part in scan_tags(arg)?.parts {
+        match part {
+            ArgPart::Text(text) => compiled_arg.push_str(&text),
+            ArgPart::Tag(t) => match get_tag_value(&t, ctx) {
+                Some(value) => compiled_arg.push_str(&value),
+                None => {
+                    return Err(CompileError {
+                        arg: String::from(arg),
+                        arg_index: None,
+                        kind: CompileErrorKind::UndefinedVariable {
+                            name: String::from(split_tag(&t).0),
+                        },
+                    })
+                }
+            },
+        }
+    }
+
+    Ok(compiled_arg)
+}
+
+/// Returns the names of tags that are still present in `compiled_arg` after all
+/// expansion took place, ignoring the ones escaped in the original `arg`
+/// (`${VAR}` and `\{VAR}`).
+pub fn find_unexpanded_tags(arg: &str, compiled_arg: &str) -> Vec<String> {
+    let escaped = match scan_tags(arg) {
+        Ok(scan) => scan.escaped,
+        Err(_) => vec![],
+    };
+
+    match scan_tags(compiled_arg) {
+        Ok(scan) => scan
+            .tags
+            .into_iter()
+            .filter(|t| !escaped.contains(t))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Returns the compiled arguments of `exec_item` with secrets replaced by `***`:
+/// the values of tags that look like secrets (see `is_secret_name`) and the
+/// values of flags named like secrets (`--password=...`, `--token ...`).
+fn mask_secret_args(exec_item: &ExecItem, args: &[String], ctx: &ExecContext) -> Vec<String> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    let mut masked_args: Vec<String> = Vec::new();
+    let mut mask_next = false;
+    for (arg, compiled_arg) in exec_item.get_args().iter().zip(args) {
+        if mask_next {
+            mask_next = false;
+            masked_args.push(String::from("***"));
+            continue;
+        }
+
+        let mut masked_arg = compiled_arg.clone();
+        if let Ok(scan) = scan_tags(arg) {
+            for tag in scan.tags.iter().filter(|t| is_secret_name(split_tag(t).0)) {
+                if let Some(value) = get_tag_value(tag, &ctx) {
+                    if !value.is_empty() {
+                        masked_arg = masked_arg.replace(value.as_str(), "***");
+                    }
+                }
+            }
+        }
+
+        if masked_arg.starts_with('-') {
+            match masked_arg.split_once('=') {
+                Some((flag, _)) if is_secret_name(flag.trim_start_matches('-')) => {
+                    masked_arg = format!("{}=***", flag);
+                }
+                Some(_) => {}
+                None => mask_next = is_secret_name(masked_arg.trim_start_matches('-')),
+            }
+        }
+
+        masked_args.push(masked_arg);
+    }
+
+    masked_args
+}
+
+/// Fully resolved definition of an item, printed by `--echo-item-on-failure`
+#[derive(Serialize)]
+struct ResolvedItem<'a> {
+    index: usize,
+
+    #[serde(skip_serializing_if = "str::is_empty")]
+    label: &'a str,
+
+    exec: &'a str,
+
+    /// Compiled arguments with secrets masked
+    args: Vec<String>,
+
+    cwd: String,
+
+    /// Names of the variables of `env`, never their values
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    env: Vec<&'a str>,
+
+    /// Names of the variables referenced by `{}` tags
+    vars: Vec<String>,
+
+    var_precedence: Vec<VarSource>,
+
+    #[serde(skip_serializing_if = "<[Prerequisite]>::is_empty")]
+    prerequisites: &'a [Prerequisite],
+
+    ignore_errors: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk_args: Option<&'a ChunkArgs>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assert: Option<&'a Assertions>,
+}
+
+/// Returns the resolved definition of an item as a compact `json` object.
+fn get_resolved_item_str(
+    exec_item: &ExecItem,
+    idx: usize,
+    args: &[String],
+    ctx: &ExecContext,
+) -> Result<String, Box<dyn Error>> {
+    let mut vars: Vec<String> = Vec::new();
+    for arg in &exec_item.get_args() {
+        if let Ok(scan) = scan_tags(arg) {
+            for tag in scan.tags {
+                let name = String::from(split_tag(&tag).0);
+                if !vars.contains(&name) {
+                    vars.push(name);
+                }
+            }
+        }
+    }
+
+    let mut item_ctx = ctx.clone();
+    item_ctx.set_precedence(&exec_item.var_precedence);
+
+    let resolved_item = ResolvedItem {
+        index: idx,
+        label: &exec_item.label,
+        exec: &exec_item.exec,
+        args: mask_secret_args(exec_item, args, ctx),
+        cwd: match get_item_cwd(exec_item, ctx)? {
+            Some(cwd) => cwd.display().to_string(),
+            None => env::current_dir()?.display().to_string(),
+        },
+        env: exec_item.env.keys().map(|name| name.as_str()).collect(),
+        vars,
+        var_precedence: item_ctx.precedence().to_vec(),
+        prerequisites: &exec_item.prerequisites,
+        ignore_errors: exec_item.ignore_errors,
+        chunk_args: exec_item.chunk_args.as_ref(),
+        assert: exec_item.assert.as_ref(),
+    };
+
+    Ok(serde_json::to_string(&resolved_item)?)
+}
+
+/// Result of scanning an argument for `{}` tags
+pub(crate) struct TagScan {
+    /// Names of tags to be expanded
+    pub(crate) tags: Vec<String>,
+
+    /// Names of tags that are escaped and must be left untouched
+    escaped: Vec<String>,
+
+    /// The argument split into text, escapes removed, and tags
+    parts: Vec<ArgPart>,
+}
+
+/// Piece of an argument, see `scan_tags`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArgPart {
+    Text(String),
+
+    /// Tag to be expanded, without its braces
+    Tag(String),
+}
+
+/// Splits a tag into the name of its variable and its default, the text after
+/// the first `:` (`{PORT:8080}`), if any.
+pub(crate) fn split_tag(tag: &str) -> (&str, Option<&str>) {
+    match tag.split_once(':') {
+        Some((name, default)) => (name, Some(default)),
+        None => (tag, None),
+    }
+}
+
+/// Returns the value a tag expands to: the value of its variable, or its
+/// default if the variable is not set.
+pub(crate) fn get_tag_value(tag: &str, ctx: &ExecContext) -> Option<String> {
+    let (name, default) = split_tag(tag);
+    ctx.get_var(name).or_else(|| default.map(String::from))
+}
+
+/// Splits `arg` into text and tags.
+///
+/// A tag is escaped by a backslash (`\{VAR}` passes `{VAR}`, as does
+/// `\{VAR\}`) or by a leading `$` (`${VAR}` is passed as is, for the shell);
+/// `\\{VAR}` passes a backslash followed by the value of `VAR`. Other
+/// backslashes, including a trailing one, are passed as they are.
+pub(crate) fn scan_tags(arg: &str) -> Result<TagScan, CompileError> {
+    let chars: Vec<char> = arg.chars().collect();
+    let mut scan = TagScan {
+        tags: Vec::new(),
+        escaped: Vec::new(),
+        parts: Vec::new(),
+    };
+    let mut text = String::new();
+    // Tag being read: its name, whether it is escaped, and where its text
+    // starts in `text` (dropped once the tag is closed)
+    let mut tag: Option<(String, bool, usize)> = None;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match (c, next) {
+            ('\\', Some('\\')) if matches!(chars.get(i + 2), Some('{') | Some('}')) => {
+                text.push('\\');
+                if let Some((name, _, _)) = &mut tag {
+                    name.push('\\');
+                }
+                i += 2;
+            }
+            ('\\', Some('{')) | ('$', Some('{')) => {
+                text.push_str(if c == '$' { "${" } else { "{" });
+                match &mut tag {
+                    Some((name, false, _)) if c == '$' => name.push('$'),
+                    Some(_) => {}
+                    None => tag = Some((String::new(), true, text.len())),
+                }
+                i += 2;
+            }
+            ('\\', Some('}')) => {
+                text.push('}');
+                if let Some((name, true, _)) = &tag {
+                    scan.escaped.push(name.clone());
+                    tag = None;
+                }
+                i += 2;
+            }
+            ('{', _) => {
+                if let Some((_, false, _)) = tag {
+                    return Err(CompileError {
+                        arg: String::from(arg),
+                        arg_index: None,
+                        kind: CompileErrorKind::UnbalancedBrace { position: i + 1 },
+                    });
+                }
+                tag = Some((String::new(), false, text.len()));
+                text.push('{');
+                i += 1;
+            }
+            ('}', _) => {
+                match tag.take() {
+                    Some((name, false, start)) => {
+                        text.truncate(start);
+                        if !text.is_empty() {
+                            scan.parts.push(ArgPart::Text(text.clone()));
+                            text.clear();
+                        }
+                        scan.tags.push(name.clone());
+                        scan.parts.push(ArgPart::Tag(name));
+                    }
+                    Some((name, true, _)) => {
+                        scan.escaped.push(name);
+                        text.push('}');
+                    }
+                    None => text.push('}'),
+                }
+                i += 1;
+            }
+            _ => {
+                text.push(c);
+                if let Some((name, _, _)) = &mut tag {
+                    name.push(c);
+                }
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        scan.parts.push(ArgPart::Text(text));
+    }
+
+    Ok(scan)
+}
+
+fn compile_opt_arg(
+    arg: &Option<String>,
+    ctx: &ExecContext,
+) -> Result<Option<String>, CompileError> {
+    match arg {
+        Some(arg) => Ok(Some(compile_arg(arg, ctx)?)),
+        None => Ok(None),
+    }
+}
+
+fn compile_args(exec_item: &ExecItem, ctx: &ExecContext) -> Result<Vec<String>, CompileError> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    let mut args: Vec<String> = Vec::new();
+    for (arg_idx, arg) in exec_item.get_args().iter().enumerate() {
+        match compile_arg(arg, &ctx) {
+            Ok(compiled_arg) => args.push(compiled_arg),
+            Err(e) => {
+                return Err(CompileError {
+                    arg_index: Some(arg_idx + 1),
+                    ..e
+                })
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Returns the resolved working directory of the item, if it sets `cwd`.
+fn get_item_cwd(
+    exec_item: &ExecItem,
+    ctx: &ExecContext,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    match &exec_item.cwd {
+        Some(cwd) => Ok(Some(resolve_path(cwd, &get_item_context(exec_item, ctx))?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the `env` of the item with its tags expanded.
+fn get_item_env(
+    exec_item: &ExecItem,
+    ctx: &ExecContext,
+) -> Result<BTreeMap<String, String>, CompileError> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    let mut item_env: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in &exec_item.env {
+        item_env.insert(name.clone(), compile_arg(value, &ctx)?);
+    }
+
+    Ok(item_env)
+}
+
+/// Returns the input of the item: `stdin` with its tags expanded, or
+/// `stdin_file` resolved and opened.
+fn get_item_stdin(
+    exec_item: &ExecItem,
+    ctx: &ExecContext,
+) -> Result<Option<StdinInput>, Box<dyn Error>> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    if let Some(stdin) = &exec_item.stdin {
+        return Ok(Some(StdinInput::Data(
+            compile_arg(stdin, &ctx)?.into_bytes(),
+        )));
+    }
+    match &exec_item.stdin_file {
+        Some(stdin_file) => {
+            let path = resolve_path(stdin_file, &ctx)?;
+            match fs::File::open(&path) {
+                Ok(file) => Ok(Some(StdinInput::File(file))),
+                Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns the context the tags of the item are expanded in, which differs
+/// from `ctx` if the item has its own `var_precedence` or a `stdin_item`.
+fn get_item_context<'a>(exec_item: &ExecItem, ctx: &'a ExecContext) -> Cow<'a, ExecContext> {
+    if exec_item.var_precedence.is_empty() && exec_item.stdin_item.is_none() {
+        return Cow::Borrowed(ctx);
+    }
+
+    let mut item_ctx = ctx.clone();
+    if !exec_item.var_precedence.is_empty() {
+        item_ctx.set_precedence(&exec_item.var_precedence);
+    }
+    if let Some(line) = &exec_item.stdin_item {
+        item_ctx.set_var(VarSource::Vars, STDIN_ITEM_VAR, line);
+    }
+
+    Cow::Owned(item_ctx)
+}
+
+/// Returns the arguments containing tags which expanded (`args`) to an empty
+/// string or to `/`, with the reason and a message naming the tags whose value
+/// is empty.
+fn get_empty_expansions(
+    exec_item: &ExecItem,
+    idx: usize,
+    args: &[String],
+    ctx: &ExecContext,
+) -> Result<Vec<(FailureReason, String)>, Box<dyn Error>> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    let mut expansions: Vec<(FailureReason, String)> = Vec::new();
+    for (arg_idx, (arg, compiled_arg)) in exec_item.get_args().iter().zip(args).enumerate() {
+        let tags = scan_tags(arg)?.tags;
+        let resolved = compiled_arg.trim();
+        if tags.is_empty() || !(resolved.is_empty() || resolved == "/") {
+            continue;
+        }
+
+        let empty_tags: Vec<&String> = tags
+            .iter()
+            .filter(|tag| get_tag_value(tag, &ctx).is_some_and(|v| v.trim().is_empty()))
+            .collect();
+        let cause = if empty_tags.is_empty() {
+            format!("from {}", arg)
+        } else {
+            let names: Vec<String> = empty_tags
+                .iter()
+                .map(|tag| format!("{{{}}}", tag))
+                .collect();
+            format!("because {} is empty", names.join(", "))
+        };
+
+        expansions.push((
+            FailureReason::EmptyExpansion {
+                argument: arg_idx + 1,
+                name: empty_tags.first().map(|tag| tag.to_string()),
+            },
+            format!(
+                "Argument {} of item {} expands to {:?} {}",
+                arg_idx + 1,
+                ItemDisplay::new(exec_item, idx),
+                compiled_arg,
+                cause
+            ),
+        ));
+    }
+
+    Ok(expansions)
+}
+
+/// Returns the names of the tags of the item which were left unexpanded.
+fn get_unexpanded_tags(exec_item: &ExecItem, args: &[String]) -> Vec<String> {
+    exec_item
+        .get_args()
+        .iter()
+        .zip(args)
+        .flat_map(|(arg, compiled_arg)| find_unexpanded_tags(arg, compiled_arg))
+        .collect()
+}
+
+/// Returns a message for each tag in the arguments of the item referring to a
+/// variable which is not set.
+fn get_undefined_tags_msgs(
+    exec_item: &ExecItem,
+    idx: usize,
+    ctx: &ExecContext,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let ctx = get_item_context(exec_item, ctx);
+
+    let mut msgs: Vec<String> = Vec::new();
+    for (arg_idx, arg) in exec_item.get_args().iter().enumerate() {
+        let scan = match scan_tags(arg) {
+            Ok(scan) => scan,
+            Err(_) => continue,
+        };
+        for tag in scan.tags {
+            if get_tag_value(&tag, &ctx).is_none() {
+                msgs.push(format!(
+                    "Argument {} of item {} refers to a variable which is not set: {{{}}}",
+                    arg_idx + 1,
+                    ItemDisplay::new(exec_item, idx),
+                    tag
+                ));
+            }
+        }
+    }
+
+    Ok(msgs)
+}
+
+fn get_unexpanded_tags_msgs(exec_item: &ExecItem, idx: usize, args: &[String]) -> Vec<String> {
+    let mut msgs: Vec<String> = Vec::new();
+    for (arg_idx, (arg, compiled_arg)) in exec_item.get_args().iter().zip(args).enumerate() {
+        for tag in find_unexpanded_tags(arg, compiled_arg) {
+            msgs.push(format!(
+                "Argument {} of item {} contains an unexpanded tag: {{{}}}",
+                arg_idx + 1,
+                ItemDisplay::new(exec_item, idx),
+                tag
+            ));
+        }
+    }
+
+    msgs
+}
+
+/// Returns whether an item which finished with `exec_status` changed
+/// something, `None` unless it succeeded; an item without `changed_when`
+/// always changes something.
+fn get_changed(
+    exec_item: &ExecItem,
+    exec_status: ExecStatus,
+    exit_code: Option<i32>,
+    output: &str,
+) -> Result<Option<bool>, String> {
+    match (exec_status, &exec_item.changed_when) {
+        (ExecStatus::OK, Some(changed_when)) => {
+            changed_when.is_changed(exit_code, output).map(Some)
+        }
+        (ExecStatus::OK, None) => Ok(Some(true)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the reason an item which succeeded fails anyway because it took
+/// longer than `expect_duration_under_secs`.
+fn get_duration_failure(exec_item: &ExecItem, duration_ms: u128) -> Option<String> {
+    let limit = exec_item.expect_duration_under_secs?;
+    if duration_ms <= limit as u128 * 1000 {
+        return None;
+    }
+
+    Some(format!(
+        "Completed in {:.1}s, limit {}s",
+        duration_ms as f64 / 1000.0,
+        limit
+    ))
+}
+
+/// Returns the hash of the content of the file, `None` if it cannot be read.
+fn get_file_hash(file_path: &str) -> Option<u64> {
+    let content = fs::read(file_path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+
+    Some(hasher.finish())
+}
+
+/// Returns what identifies the behavior of `exec_item` run with the expanded
+/// `args`: the item without its label and prerequisites, with its `cwd`,
+/// `env`, `stdin` and `stdin_file` expanded, and whether `stdin` is closed.
+/// Returns `None` if one of them does not expand, the item failing anyway.
+fn get_item_fingerprint(
+    exec_item: &ExecItem,
+    args: &[String],
+    ctx: &ExecContext,
+) -> Option<String> {
+    let item_ctx = get_item_context(exec_item, ctx);
+
+    let mut item = exec_item.clone();
+    item.label = String::new();
+    item.prerequisites = Vec::new();
+    item.args = args.to_vec();
+    item.flags = BTreeMap::new();
+    item.cwd = get_item_cwd(exec_item, ctx)
+        .ok()?
+        .map(|cwd| cwd.display().to_string());
+    item.env = get_item_env(exec_item, ctx).ok()?;
+    item.stdin = match &exec_item.stdin {
+        Some(stdin) => Some(compile_arg(stdin, &item_ctx).ok()?),
+        None => None,
+    };
+    item.stdin_file = match &exec_item.stdin_file {
+        Some(stdin_file) => Some(
+            resolve_path(stdin_file, &item_ctx)
+                .ok()?
+                .display()
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    serde_json::to_string(&(item, ctx.features().null_stdin)).ok()
+}
+
+/// Returns the arguments (or the values of `--flag=value` arguments) that refer
+/// to the `NansiFile` at `file_path`.
+///
+/// Relative arguments are resolved against the working directory of the
+/// command, `cwd` or else the current directory, rather than against the
+/// directory of the `NansiFile`.
+fn get_self_references(args: &[String], file_path: &str, cwd: Option<&Path>) -> Vec<String> {
+    let nansi_path = match fs::canonicalize(file_path) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+
+    args.iter()
+        .filter(|arg| {
+            let value = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with('-') => value,
+                _ => arg.as_str(),
+            };
+            let path = match (expand_tilde(value), cwd) {
+                (path, Some(cwd)) if path.is_relative() => cwd.join(path),
+                (path, _) => path,
+            };
+            !value.is_empty() && fs::canonicalize(path).is_ok_and(|path| path == nansi_path)
+        })
+        .cloned()
+        .collect()
+}
+
+/// What running an item produced; which stream is shown is decided by the
+/// caller (see `RunOutcome::stdout_str`, `RunOutcome::stderr_str`)
+#[derive(Debug)]
+pub(crate) struct RunOutcome {
+    pub status: ExecStatus,
+
+    /// Exit code of the command, `None` if it did not run or was killed
+    pub exit_code: Option<i32>,
+
+    pub stdout: Vec<u8>,
+
+    pub stderr: Vec<u8>,
+
+    pub duration: Duration,
+
+    /// Error starting the command, shown in place of its stderr
+    pub spawn_error: Option<io::Error>,
+
+    /// Whether stderr was captured into `stdout` (see `merge_streams`)
+    pub merged: bool,
+
+    pub failure_reason: Option<FailureReason>,
+}
+
+impl RunOutcome {
+    /// Returns the outcome of a command which could not be started; `message`
+    /// is recorded as the failure reason, `spawn_error` is shown.
+    fn spawn_failed(spawn_error: io::Error, message: String, duration: Duration) -> RunOutcome {
+        RunOutcome {
+            status: ExecStatus::ERR,
+            exit_code: None,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            duration,
+            spawn_error: Some(spawn_error),
+            merged: false,
+            failure_reason: Some(FailureReason::SpawnFailed { message }),
+        }
+    }
+
+    /// Returns the outcome of an item failed by nansi before running it, with
+    /// `message` as its stderr.
+    fn failed(message: &str, failure_reason: Option<FailureReason>) -> RunOutcome {
+        RunOutcome {
+            status: ExecStatus::ERR,
+            exit_code: None,
+            stdout: Vec::new(),
+            stderr: message.as_bytes().to_vec(),
+            duration: Duration::ZERO,
+            spawn_error: None,
+            merged: false,
+            failure_reason,
+        }
+    }
+
+    pub(crate) fn stdout_str(&self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.stdout.clone())
+    }
+
+    /// Returns stderr, or the spawn error if the command could not be started.
+    pub(crate) fn stderr_str(&self) -> Result<String, FromUtf8Error> {
+        match &self.spawn_error {
+            Some(e) => Ok(e.to_string()),
+            None => String::from_utf8(self.stderr.clone()),
+        }
+    }
+}
+
+/// Runs the item; the resources used by the command are stored in `usage`
+/// where they can be measured.
+fn run_exec(
+    exec_item: &ExecItem,
+    args: &[String],
+    ctx: &ExecContext,
+    usage: &mut Option<ResourceUsage>,
+) -> Result<RunOutcome, Box<dyn Error>> {
+    let outcome = match prepare_exec(exec_item, args, ctx)? {
+        ItemJob::Done(outcome) => outcome,
+        ItemJob::Command(command) => {
+            let (outcome, command_usage) = command.run();
+            *usage = command_usage;
+            outcome
+        }
+    };
+
+    Ok(outcome)
+}
+
+/// Builds the command of the item, or the failed outcome of an item whose
+/// command cannot be built (e.g. a missing working directory).
+fn prepare_exec(
+    exec_item: &ExecItem,
+    args: &[String],
+    ctx: &ExecContext,
+) -> Result<ItemJob, Box<dyn Error>> {
+    let start = Instant::now();
+    let (exec, args): (OsString, Vec<OsString>) = if ctx.features().expand_tilde {
+        (
+            expand_arg_tilde(&exec_item.exec),
+            args.iter().map(|arg| expand_arg_tilde(arg)).collect(),
+        )
+    } else {
+        (
+            OsString::from(&exec_item.exec),
+            args.iter().map(OsString::from).collect(),
+        )
+    };
+    let mut command = if exec_item.elevate {
+        let prefix = ctx.elevation().prefix();
+        let mut command = Command::new(&prefix[0]);
+        command.args(&prefix[1..]).arg(exec);
+        command
+    } else {
+        Command::new(exec)
+    };
+    command.args(&args);
+    if ctx.features().null_stdin {
+        command.stdin(Stdio::null());
+    }
+    if exec_item.cwd.is_some() {
+        let cwd = match get_item_cwd(exec_item, ctx) {
+            Ok(Some(cwd)) if cwd.is_dir() => cwd,
+            Ok(cwd) => {
+                let message = format!(
+                    "working directory {} does not exist",
+                    cwd.unwrap_or_default().display()
+                );
+                let e = io::Error::new(io::ErrorKind::NotFound, message.clone());
+                let outcome = RunOutcome::spawn_failed(e, message, start.elapsed());
+                return Ok(ItemJob::Done(outcome));
+            }
+            Err(e) => {
+                let message = format!("working directory {}", e);
+                let e = io::Error::other(message.clone());
+                let outcome = RunOutcome::spawn_failed(e, message, start.elapsed());
+                return Ok(ItemJob::Done(outcome));
+            }
+        };
+        command.current_dir(cwd);
+    }
+    if let Some(names) = exec_item.env_passthrough.as_ref().or(ctx.env_passthrough()) {
+        command.env_clear();
+        for name in names {
+            if let Some(value) = env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
+    match get_item_env(exec_item, ctx) {
+        Ok(item_env) => {
+            command.envs(item_env);
+        }
+        Err(e) => {
+            let message = format!("env {}", e);
+            return Ok(ItemJob::Done(RunOutcome::failed(
+                &message,
+                e.failure_reason(),
+            )));
+        }
+    }
+    let stdin = match get_item_stdin(exec_item, ctx) {
+        Ok(stdin) => stdin,
+        Err(e) => {
+            let message = format!("stdin {}", e);
+            let e = io::Error::other(message.clone());
+            let outcome = RunOutcome::spawn_failed(e, message, start.elapsed());
+            return Ok(ItemJob::Done(outcome));
+        }
+    };
+
+    Ok(ItemJob::Command(PreparedCommand {
+        command,
+        stdin,
+        capture: exec_item.capture,
+        merge_streams: exec_item.merge_streams,
+        timeout_secs: exec_item.timeout_secs,
+        arg_count: args.len(),
+        on_line: None,
+    }))
+}
+
+/// What is left to do to get the outcome of an item prepared by `execute`
+enum ItemJob {
+    /// The item already has its outcome (failed before running, assertions,
+    /// chunked runs)
+    Done(RunOutcome),
+
+    Command(PreparedCommand),
+}
+
+/// Command of an item ready to run, possibly on another thread
+struct PreparedCommand {
+    command: Command,
+
+    /// Written to the standard input of the command while it runs
+    stdin: Option<StdinInput>,
+
+    capture: Capture,
+
+    merge_streams: bool,
+    timeout_secs: Option<u64>,
+    arg_count: usize,
+
+    /// Receives the lines of the output while the command runs
+    on_line: Option<LineSink>,
+}
+
+impl PreparedCommand {
+    /// Runs the command and returns its outcome and the resources it used.
+    fn run(mut self) -> (RunOutcome, Option<ResourceUsage>) {
+        let start = Instant::now();
+        let timeout = self.timeout_secs.map(Duration::from_secs);
+        match output_with_usage(
+            &mut self.command,
+            self.stdin.take(),
+            self.capture,
+            self.merge_streams,
+            timeout,
+            self.on_line.as_ref(),
+        ) {
+            Ok(captured) => {
+                let result = captured.output;
+                let (status, failure_reason) =
+                    if let Some(limit_secs) = self.timeout_secs.filter(|_| captured.timed_out) {
+                        (
+                            ExecStatus::ERR,
+                            Some(FailureReason::TimedOut { limit_secs }),
+                        )
+                    } else if result.status.success() {
+                        (ExecStatus::OK, None)
+                    } else {
+                        (
+                            ExecStatus::ERR,
+                            Some(FailureReason::from_exit_status(&result.status)),
+                        )
+                    };
+
+                let outcome = RunOutcome {
+                    status,
+                    exit_code: result.status.code(),
+                    stdout: result.stdout,
+                    stderr: result.stderr,
+                    duration: start.elapsed(),
+                    spawn_error: None,
+                    merged: self.merge_streams,
+                    failure_reason,
+                };
+
+                (outcome, captured.usage)
+            }
+            Err(e) => {
+                let message = e.to_string();
+                let spawn_error = if e.kind() == io::ErrorKind::ArgumentListTooLong {
+                    io::Error::new(
+                        e.kind(),
+                        format!(
+                            "{} ({} argument(s), use chunk_args to split them into several runs)",
+                            e, self.arg_count
+                        ),
+                    )
+                } else {
+                    e
+                };
+
+                let outcome = RunOutcome::spawn_failed(spawn_error, message, start.elapsed());
+
+                (outcome, None)
+            }
+        }
+    }
+}
+
+/// Item prepared by `execute` whose outcome is not known yet, with what is
+/// needed to report it
+struct PendingItem<'a> {
+    idx: usize,
+    exec_item: &'a ExecItem,
+    block: Vec<String>,
+    args: Vec<String>,
+    unexpanded: Vec<String>,
+    fingerprint: Option<String>,
+    idempotence_key: Option<String>,
+    item_id: String,
+    detail_lines: Vec<String>,
+    usage: Option<ResourceUsage>,
+
+    /// The output of the command is printed while it runs
+    streamed: bool,
+}
+
+/// Splits the execution `order` into batches of (position, index): when more
+/// than one job is allowed, consecutive `parallel` items form a batch, every
+/// other item (`serial` ones included) is a batch of its own.
+fn get_batches(order: &[usize], exec_list: &[ExecItem], jobs: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut batches: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut in_batch = false;
+    for (position, idx) in order.iter().copied().enumerate() {
+        let parallel = runs_in_batch(&exec_list[idx], jobs);
+        match batches.last_mut() {
+            Some(batch) if parallel && in_batch => batch.push((position, idx)),
+            _ => batches.push(vec![(position, idx)]),
+        }
+        in_batch = parallel;
+    }
+
+    batches
+}
+
+/// Returns whether the item joins the batch of the `parallel` items before
+/// it when `jobs` jobs are allowed.
+fn runs_in_batch(exec_item: &ExecItem, jobs: usize) -> bool {
+    jobs > 1 && exec_item.parallel && !exec_item.serial
+}
+
+/// Returns whether the consecutive `items` run in the same batch when `jobs`
+/// jobs are allowed.
+pub(crate) fn share_batch(items: &[ExecItem], jobs: usize) -> bool {
+    items.iter().all(|exec_item| runs_in_batch(exec_item, jobs))
+}
+
+/// Returns the warning about an item whose `weight` exceeds the jobs limit of
+/// a run in which it may run in parallel, `None` if it fits.
+fn get_weight_warning(exec_item: &ExecItem, idx: usize, jobs: usize) -> Option<String> {
+    if jobs <= 1 || !exec_item.parallel || exec_item.weight as usize <= jobs {
+        return None;
+    }
+
+    Some(format!(
+        "Weight {} of item {} exceeds the jobs limit, it takes all {} slots",
+        exec_item.weight,
+        ItemDisplay::new(exec_item, idx),
+        jobs
+    ))
+}
+
+/// Queue of jobs started in order as long as the sum of the weights of the
+/// running ones stays within the capacity; a job heavier than the capacity
+/// takes all of it.
+struct JobQueue<T> {
+    queue: VecDeque<(usize, T)>,
+    capacity: usize,
+    used: usize,
+}
+
+impl<T> JobQueue<T> {
+    fn new(capacity: usize) -> JobQueue<T> {
+        JobQueue {
+            queue: VecDeque::new(),
+            capacity: capacity.max(1),
+            used: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns the weight the job takes, `weight` clamped to the capacity.
+    fn push(&mut self, weight: u32, job: T) -> usize {
+        let weight = (weight as usize).clamp(1, self.capacity);
+        self.queue.push_back((weight, job));
+
+        weight
+    }
+
+    /// Takes the next job if its weight fits, jobs behind one that does not fit
+    /// waiting for it to start first.
+    fn start_next(&mut self) -> Option<T> {
+        let (weight, _) = self.queue.front()?;
+        if self.used + weight > self.capacity {
+            return None;
+        }
+
+        let (weight, job) = self.queue.pop_front()?;
+        self.used += weight;
+        Some(job)
+    }
+
+    /// Frees the slots of a finished job of the given weight.
+    fn finish(&mut self, weight: usize) {
+        self.used -= weight;
+    }
+}
+
+/// Runs the commands of the `pending` items, as many at a time as their
+/// `weight` fits the `jobs` limit, and returns the items with their outcome
+/// in the order they finish.
+fn run_batch(pending: Vec<(PendingItem, ItemJob)>, jobs: usize) -> BatchRun {
+    let mut batch_run = BatchRun {
+        items: Vec::new(),
+        weights: Vec::new(),
+        finished: VecDeque::new(),
+        queue: JobQueue::new(jobs),
+        running: 0,
+        channel: mpsc::channel(),
+    };
+    for (slot, (item, job)) in pending.into_iter().enumerate() {
+        let weight = match job {
+            ItemJob::Done(outcome) => {
+                batch_run.finished.push_back((slot, outcome, None));
+                0
+            }
+            ItemJob::Command(command) => {
+                batch_run.queue.push(item.exec_item.weight, (slot, command))
+            }
+        };
+        batch_run.items.push(Some(item));
+        batch_run.weights.push(weight);
+    }
+
+    batch_run
+}
+
+/// Iterator over the items of a batch as they finish, see `run_batch`
+struct BatchRun<'a> {
+    items: Vec<Option<PendingItem<'a>>>,
+    /// Slots taken by the command of each item while it runs
+    weights: Vec<usize>,
+    finished: VecDeque<BatchResult>,
+    queue: JobQueue<(usize, PreparedCommand)>,
+    running: usize,
+    channel: (mpsc::Sender<BatchResult>, mpsc::Receiver<BatchResult>),
+}
+
+/// Slot of the item in its batch, its outcome and the resources it used
+type BatchResult = (usize, RunOutcome, Option<ResourceUsage>);
+
+impl<'a> Iterator for BatchRun<'a> {
+    type Item = (PendingItem<'a>, RunOutcome);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slot, outcome, usage) = match self.finished.pop_front() {
+            Some(result) => result,
+            None if self.running == 0 && self.queue.len() == 1 => {
+                // a single command runs on the current thread
+                let (slot, command) = self.queue.start_next()?;
+                self.queue.finish(self.weights[slot]);
+                let (outcome, usage) = command.run();
+                (slot, outcome, usage)
+            }
+            None => {
+                while let Some((slot, command)) = self.queue.start_next() {
+                    let sender = self.channel.0.clone();
+                    thread::spawn(move || {
+                        let (outcome, usage) = command.run();
+                        let _ = sender.send((slot, outcome, usage));
+                    });
+                    self.running += 1;
+                }
+                if self.running == 0 {
+                    return None;
+                }
+
+                let result = self.channel.1.recv().ok()?;
+                self.running -= 1;
+                self.queue.finish(self.weights[result.0]);
+                result
+            }
+        };
+
+        let mut item = self.items[slot].take()?;
+        if usage.is_some() {
+            item.usage = usage;
+        }
+
+        Some((item, outcome))
+    }
+}
+
+/// Runs the item once per chunk of `args` and aggregates the results: the item
+/// fails if any chunk fails, outputs are concatenated in order and the exit
+/// code is the one of the first failed chunk. A line describing the result of
+/// each chunk is appended to `chunk_lines`.
+fn run_chunked_exec(
+    exec_item: &ExecItem,
+    args: &[String],
+    chunk_args: &ChunkArgs,
+    ctx: &ExecContext,
+    chunk_lines: &mut Vec<String>,
+    usage: &mut Option<ResourceUsage>,
+) -> Result<RunOutcome, Box<dyn Error>> {
+    let chunks = get_arg_chunks(args, chunk_args);
+
+    let mut outcome = RunOutcome {
+        status: ExecStatus::OK,
+        exit_code: None,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        duration: Duration::ZERO,
+        spawn_error: None,
+        merged: exec_item.merge_streams,
+        failure_reason: None,
+    };
+
+    for (chunk_idx, chunk) in chunks.iter().enumerate() {
+        let mut chunk_usage: Option<ResourceUsage> = None;
+        let chunk_outcome = run_exec(exec_item, chunk, ctx, &mut chunk_usage)?;
+
+        if let Some(chunk_usage) = chunk_usage {
+            *usage = Some(match usage {
+                Some(total) => total.merge(&chunk_usage),
+                None => chunk_usage,
+            });
+        }
+
+        if outcome.status == ExecStatus::OK {
+            outcome.exit_code = chunk_outcome.exit_code;
+            outcome.failure_reason = chunk_outcome.failure_reason.clone();
+        }
+        if chunk_outcome.status != ExecStatus::OK {
+            outcome.status = ExecStatus::ERR;
+        }
+
+        outcome.stdout.extend(&chunk_outcome.stdout);
+        match &chunk_outcome.spawn_error {
+            Some(e) => outcome.stderr.extend(e.to_string().as_bytes()),
+            None => outcome.stderr.extend(&chunk_outcome.stderr),
+        }
+        outcome.duration += chunk_outcome.duration;
+
+        chunk_lines.push(format!(
+            "Chunk {}/{} ({} arg(s)): {}",
+            chunk_idx + 1,
+            chunks.len(),
+            chunk.len() - chunk_args.fixed.len(),
+            chunk_outcome.status.as_str()
+        ));
+    }
+
+    Ok(outcome)
+}
+
+/// Splits `args` into the argument lists of the chunked runs, each starting
+/// with the `fixed` arguments. Without `args` the command still runs once.
+fn get_arg_chunks(args: &[String], chunk_args: &ChunkArgs) -> Vec<Vec<String>> {
+    if args.is_empty() || chunk_args.size == 0 {
+        let mut chunk = chunk_args.fixed.clone();
+        chunk.extend(args.iter().cloned());
+        return vec![chunk];
+    }
+
+    args.chunks(chunk_args.size)
+        .map(|args_chunk| {
+            let mut chunk = chunk_args.fixed.clone();
+            chunk.extend(args_chunk.iter().cloned());
+            chunk
+        })
+        .collect()
+}
+
+/// Returns the message telling that arguments of the item were added from the
+/// command line, if any were.
+fn get_args_override_msg(
+    exec_item: &ExecItem,
+    idx: usize,
+    options: &ExecOptions,
+) -> Option<String> {
+    options
+        .args_overrides
+        .get(&exec_item.label)
+        .map(|args_override| {
+            format!(
+                "[INFO] Arguments of item {} overridden from the command line: {}",
+                ItemDisplay::new(exec_item, idx),
+                args_override.describe()
+            )
+        })
+}
+
+/// Returns the text printed for an item with `print_output` set.
+///
+/// Empty output is replaced by a note, which also points out when the output
+/// ended up on the stream that is not printed.
+fn get_output_str(
+    exec_item: &ExecItem,
+    exec_status: ExecStatus,
+    output: String,
+    other_output: &str,
+    quiet: bool,
+) -> Option<String> {
+    let print_stderr = exec_item.print_stderr && exec_status == ExecStatus::OK;
+    if print_stderr && !other_output.is_empty() {
+        return Some(output + other_output);
+    }
+
+    if !output.is_empty() {
+        return Some(output);
+    }
+
+    if !other_output.is_empty() {
+        let lines = other_output.lines().count();
+        let note = if exec_status == ExecStatus::OK {
+            format!(
+                "(stdout empty; {} line(s) on stderr \u{2014} use print_stderr)",
+                lines
+            )
+        } else {
+            format!("(stderr empty; {} line(s) on stdout)", lines)
+        };
+        return Some(note);
+    }
+
+    if quiet {
+        None
+    } else {
+        Some(String::from("(no output)"))
+    }
+}
+
+/// Returns the last `lines` lines of the output of each failed item,
+/// stripped of colors and indented under the item. Failures beyond
+/// `MAX_FAILURE_PREVIEW_LINES` lines in total are only counted.
+fn get_failure_preview(failures: &[(String, String)], lines: usize) -> Vec<String> {
+    if failures.is_empty() || lines == 0 {
+        return Vec::new();
+    }
+
+    let mut preview = vec![String::from("Failures:")];
+    let mut shown = 0;
+    for (item_str, output) in failures {
+        let output = strip_ansi(output);
+        let output_lines: Vec<&str> = output.lines().filter(|l| !l.trim().is_empty()).collect();
+        let tail = &output_lines[output_lines.len().saturating_sub(lines)..];
+
+        if shown > 0 && preview.len() + 1 + tail.len() > MAX_FAILURE_PREVIEW_LINES {
+            break;
+        }
+        preview.push(format!("  {}", item_str));
+        preview.extend(tail.iter().map(|line| format!("    {}", line)));
+        shown += 1;
+    }
+
+    if shown < failures.len() {
+        preview.push(format!(
+            "  ... {} more failure(s) omitted",
+            failures.len() - shown
+        ));
+    }
+
+    preview
+}
+
+/// Returns the diagnostic refusing a run with `jobs` jobs: an error, or an
+/// index prerequisite on an item of the same parallel batch, which only
+/// shares it when more than one job is allowed.
+fn get_run_error(diagnostics: &[Diagnostic], jobs: usize) -> Option<&Diagnostic> {
+    diagnostics.iter().find(|d| match &d.kind {
+        DiagnosticKind::ParallelPrerequisite { prerequisite } => {
+            let (target, _) = split_reference(prerequisite);
+            jobs > 1 && parse_index_prerequisite(target).is_some()
+        }
+        _ => d.severity == Severity::Error,
+    })
+}
+
+/// Returns the sorted labels used by more than one item.
+fn get_label_duplicates(diagnostics: &[Diagnostic]) -> Vec<&str> {
+    let mut labels: Vec<&str> = diagnostics
+        .iter()
+        .filter(|d| matches!(d.kind, DiagnosticKind::DuplicateLabel { .. }))
+        .map(|d| d.label.as_str())
+        .collect();
+    labels.sort();
+    labels.dedup();
+
+    labels
+}
+
+/// Returns the messages of the prerequisites on a label which no item has or
+/// which is first used by the item itself or a later one, as items run from top
+/// to bottom (those on an index are errors of `validate`).
+fn get_unmeetable_prerequisites(diagnostics: &[Diagnostic]) -> Vec<String> {
+    diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Warning)
+        .filter(|d| {
+            matches!(
+                d.kind,
+                DiagnosticKind::UnknownPrerequisite { .. }
+                    | DiagnosticKind::LabelCaseMismatch { .. }
+                    | DiagnosticKind::SelfPrerequisite { .. }
+                    | DiagnosticKind::ForwardPrerequisite { .. }
+            )
+        })
+        .map(|d| d.message.clone())
+        .collect()
+}
+
+/// Returns a message per duplicated label with the status recorded for each of
+/// its items, e.g. `Duplicate label build recorded: #1 ok, #4 err`, as they
+/// decide which `label`, `!label` and `label:skipped` prerequisites were met.
+fn get_duplicate_label_msgs(duplicates: &[&str], results: &[ExecResult]) -> Vec<String> {
+    duplicates
+        .iter()
+        .map(|label| {
+            let statuses: Vec<String> = results
+                .iter()
+                .filter(|result| result.label == *label)
+                .map(|result| format!("#{} {}", result.index, result.status))
+                .collect();
+            format!(
+                "Duplicate label {} recorded: {}",
+                label,
+                match statuses.is_empty() {
+                    true => String::from("nothing"),
+                    false => statuses.join(", "),
+                }
+            )
+        })
+        .collect()
+}
+
+/// Bookkeeping of a single run of a `NansiFile`
+struct RunState<'a> {
+    events: EventSink,
+    trace: Option<Trace>,
+    log: Option<RunLog>,
+    results: &'a mut Vec<ExecResult>,
+    label_results: LabelResults<'a>,
+    succ_idx_list: Vec<usize>,
+    fail_idx_list: Vec<usize>,
+    status_counts: HashMap<ExecStatus, usize>,
+    failure_count: u32,
+    timers: HashMap<String, Instant>,
+    timer_spans: Vec<TimerSpan>,
+    answers: Option<Answers>,
+    printer: Printer,
+    porcelain: bool,
+    /// Desktop notifications being sent, see `RunState::notify`
+    notifications: Vec<JoinHandle<Result<(), io::Error>>>,
+}
+
+impl<'a> RunState<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        events: EventSink,
+        trace: Option<Trace>,
+        log: Option<RunLog>,
+        results: &'a mut Vec<ExecResult>,
+        answers: Option<Answers>,
+        printer: Printer,
+        label_state: LabelState,
+        porcelain: bool,
+    ) -> RunState<'a> {
+        RunState {
+            events,
+            trace,
+            log,
+            results,
+            label_results: LabelResults::new(label_state),
+            succ_idx_list: Vec::new(),
+            fail_idx_list: Vec::new(),
+            status_counts: HashMap::new(),
+            failure_count: 0,
+            timers: HashMap::new(),
+            timer_spans: Vec::new(),
+            answers,
+            printer,
+            porcelain,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Asks `question`, answered from the `--answers` file if one is given.
+    fn ask(&self, key: &str, question: &str) -> Result<String, io::Error> {
+        ask(self.answers.as_ref(), key, question)
+    }
+
+    /// Asks whether to run an item marked `confirm`, answered from the
+    /// `--answers` file by its label (or `#N`) if one is given; returns why the
+    /// item is skipped unless confirmed. Without a terminal to ask on, the item
+    /// is skipped with a warning instead of waiting for input.
+    fn confirm(
+        &mut self,
+        exec_item: &ExecItem,
+        idx: usize,
+        command: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let item_str = ItemDisplay::new(exec_item, idx).to_string();
+        if self.answers.is_none() && !io::stdin().is_terminal() {
+            self.warn(format!(
+                "Item {} needs a confirmation but stdin is not a terminal, use --yes to run it",
+                item_str
+            ))?;
+            return Ok(Some(format!("Item {} skipped: not confirmed.", item_str)));
+        }
+
+        let response = self.ask(
+            &get_item_id(exec_item, idx),
+            &format!("{}\nRun? [y/N]", command),
+        )?;
+        match response.trim().to_lowercase().as_str() {
+            "y" | "yes" => Ok(None),
+            _ => Ok(Some(format!(
+                "Item {} skipped: declined by user.",
+                item_str
+            ))),
+        }
+    }
+
+    /// Sends a desktop notification in the background, the run going on
+    /// meanwhile (see `wait_for_notifications`).
+    fn notify(&mut self, notification: Notification) {
+        self.notifications.push(notification.send_in_background());
+    }
+
+    /// Waits for the desktop notifications to be sent; failing to send one is
+    /// only a warning.
+    fn wait_for_notifications(&mut self) -> Result<(), Box<dyn Error>> {
+        for notification in std::mem::take(&mut self.notifications) {
+            let result = notification
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("sending it panicked")));
+            if let Err(e) = result {
+                self.warn(format!("Desktop notification could not be sent: {}", e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops and starts the timers of a timer marker item.
+    ///
+    /// A marker ends any batch of parallel items, so a span lasts from its
+    /// start marker until all items between the markers have finished. Stopping a timer which is not
+    /// running is ignored (`validate` reports it).
+    fn update_timers(&mut self, exec_item: &ExecItem) -> Result<(), Box<dyn Error>> {
+        if let Some(name) = &exec_item.timer_stop {
+            if let Some(start) = self.timers.remove(name) {
+                let span = TimerSpan {
+                    name: name.clone(),
+                    duration_ms: start.elapsed().as_millis(),
+                };
+                self.events.emit(&Event::TimerFinished {
+                    name: span.name.clone(),
+                    duration_ms: span.duration_ms,
+                })?;
+                if let Some(trace) = &mut self.trace {
+                    trace.timers.push(span.clone());
+                }
+                self.timer_spans.push(span);
+            }
+        }
+
+        if let Some(name) = &exec_item.timer_start {
+            self.timers.insert(name.clone(), Instant::now());
+        }
+
+        Ok(())
+    }
+
+    /// Prints a warning which is not related to a particular item.
+    fn warn(&mut self, msg: String) -> Result<(), Box<dyn Error>> {
+        self.printer.print(
+            Verbosity::Normal,
+            &vec![get_warning_str(self.printer.stream, &msg)],
+        )?;
+        if let Some(trace) = &mut self.trace {
+            trace.warnings.push(msg.clone());
+        }
+        if let Some(log) = &mut self.log {
+            log.warn(&msg)?;
+        }
+        self.events.emit(&Event::Warning {
+            index: None,
+            message: msg,
+        })?;
+
+        Ok(())
+    }
+
+    /// Records a finished or skipped item in the results, the trace and the
+    /// log.
+    fn record_item(&mut self, item: TraceItem) -> Result<(), io::Error> {
+        self.results.push(ExecResult::from(&item));
+        if let Some(log) = &mut self.log {
+            log.item(&item)?;
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.items.push(item);
+        }
+
+        Ok(())
+    }
+
+    /// Records that the item succeeded so that it satisfies prerequisites.
+    fn mark_success(&mut self, exec_item: &'a ExecItem, idx: usize) {
+        self.label_results.record(&exec_item.label, true);
+        self.succ_idx_list.push(idx);
+    }
+
+    /// Records that the item failed, which may leave its label unmet (see
+    /// `LabelState`).
+    fn mark_failure(&mut self, exec_item: &'a ExecItem, idx: usize) {
+        self.label_results.record(&exec_item.label, false);
+        self.fail_idx_list.push(idx);
+    }
+
+    /// Prints the item as skipped with the given reason.
+    fn skip(
+        &mut self,
+        exec_item: &ExecItem,
+        idx: usize,
+        msg: String,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut block: Vec<String> = Vec::new();
+        if exec_item.print_status {
+            block.push(get_status_str(
+                self.printer.stream,
+                exec_item,
+                idx,
+                ExecStatus::SKIP,
+            ));
+        }
+        block.push(msg.clone());
+        self.printer.print(Verbosity::Normal, &block)?;
+
+        self.record_item(TraceItem {
+            index: idx,
+            label: exec_item.label.clone(),
+            exec: exec_item.exec.clone(),
+            args: exec_item.get_args(),
+            status: String::from(ExecStatus::SKIP.as_str()),
+            exit_code: None,
+            duration_ms: 0,
+            stdout: String::new(),
+            stderr: String::new(),
+            failure_reason: None,
+            message: Some(msg.clone()),
+            warnings: vec![],
+            success_message: None,
+            failure_hint: None,
+            resource_usage: None,
+            changed: None,
+            merged_streams: false,
+            args_override: None,
+        })?;
+
+        self.finish(exec_item, idx, ExecStatus::SKIP, Some(msg), None, None, 0)
+    }
+
+    /// Prints the item as skipped like `skip`, its result being `not_run` as
+    /// the run stopped before it.
+    fn skip_not_run(
+        &mut self,
+        exec_item: &ExecItem,
+        idx: usize,
+        msg: String,
+    ) -> Result<(), Box<dyn Error>> {
+        self.skip(exec_item, idx, msg)?;
+        if let Some(result) = self.results.last_mut() {
+            result.status = String::from(NOT_RUN_STATUS);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the final status of the item, emits the corresponding event and
+    /// prints its porcelain line.
+    #[allow(clippy::too_many_arguments)]
+    fn finish(
+        &mut self,
+        exec_item: &ExecItem,
+        idx: usize,
+        exec_status: ExecStatus,
+        msg: Option<String>,
+        failure_reason: Option<FailureReason>,
+        exit_code: Option<i32>,
+        duration_ms: u128,
+    ) -> Result<(), Box<dyn Error>> {
+        *self.status_counts.entry(exec_status).or_insert(0) += 1;
+        if self.porcelain {
+            print_block(
+                OutputStream::Stdout,
+                &vec![get_porcelain_line(
+                    idx,
+                    &exec_item.label,
+                    exec_status,
+                    exit_code,
+                    duration_ms,
+                )],
+            )?;
+        }
+        self.events.emit(&Event::ItemFinished {
+            index: idx,
+            label: exec_item.label.clone(),
+            status: String::from(exec_status.as_str()),
+            message: msg,
+            failure_reason,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Returns the line printed for an item with `--porcelain`, e.g.
+/// `3\tbuild\tok\t0\t2140ms`: index, label, status, exit code and duration
+/// separated by tabs, fields being empty if they do not apply.
+///
+/// Scripts parse this line: fields may be appended but are never reordered
+/// or removed, and it is never colored.
+pub(crate) fn get_porcelain_line(
+    idx: usize,
+    label: &str,
+    exec_status: ExecStatus,
+    exit_code: Option<i32>,
+    duration_ms: u128,
+) -> String {
+    let exit_code = match (exec_status, exit_code) {
+        (ExecStatus::SKIP, _) | (_, None) => String::new(),
+        (_, Some(code)) => code.to_string(),
+    };
+
+    format!(
+        "{}\t{}\t{}\t{}\t{}ms",
+        idx,
+        label,
+        exec_status.as_str(),
+        exit_code,
+        duration_ms
+    )
+}
+
+/// Returns the stream the output of the item is printed to, ignoring its
+/// `output_stream` with `porcelain` as stdout is reserved for the porcelain
+/// lines.
+fn get_item_output_stream(exec_item: &ExecItem, options: &ExecOptions) -> OutputStream {
+    match exec_item.output_stream {
+        Some(stream) if !options.porcelain => stream,
+        _ => options.output_stream,
+    }
+}
+
+impl Satisfaction for RunState<'_> {
+    fn get_state(&self, reference: &str) -> RefState {
+        match parse_index_prerequisite(reference) {
+            Some(idx) if self.succ_idx_list.contains(&idx) => RefState::Ok,
+            Some(idx) if self.fail_idx_list.contains(&idx) => RefState::Failed,
+            Some(_) => RefState::Skipped,
+            None if self.label_results.is_met(reference) => RefState::Ok,
+            None if self.label_results.has_result(reference) => RefState::Failed,
+            None => RefState::Skipped,
+        }
+    }
+}
+
+/// Returns the prerequisites of `exec_item` that are not met yet (see
+/// `Prerequisite`).
+fn get_unmet_prerequisites<'a>(
+    exec_item: &'a ExecItem,
+    satisfaction: &dyn Satisfaction,
+) -> Vec<&'a Prerequisite> {
+    exec_item
+        .prerequisites
+        .iter()
+        .filter(|prereq| !prereq.is_met(satisfaction))
+        .collect()
+}
+
+/// Returns the message of an item skipped because `--only`, `--only-with-deps`
+/// or `--skip` did not select it.
+fn get_not_selected_msg(exec_item: &ExecItem, idx: usize) -> String {
+    format!(
+        "Item {} skipped: not selected.",
+        ItemDisplay::new(exec_item, idx)
+    )
+}
+
+/// Returns the index referenced by a prerequisite of the form `#N`.
+pub(crate) fn parse_index_prerequisite(prereq: &str) -> Option<usize> {
+    match prereq.strip_prefix('#') {
+        Some(idx) if !idx.is_empty() && idx.chars().all(|c| c.is_ascii_digit()) => idx.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns an unmet prerequisite in a human readable form; index references
+/// are followed by the command of the referenced item, e.g. `#2 (curl -O ...)`,
+/// and `any_of` prerequisites by the outcome of each reference.
+fn get_prerequisite_str(
+    prereq: &Prerequisite,
+    exec_list: &[ExecItem],
+    satisfaction: &dyn Satisfaction,
+) -> String {
+    let reference = match prereq {
+        Prerequisite::Ref(reference) => reference,
+        Prerequisite::AnyOf { .. } => return prereq.get_unmet_str(satisfaction),
+    };
+
+    match parse_index_prerequisite(reference) {
+        Some(prereq_idx) if prereq_idx > 0 && prereq_idx <= exec_list.len() => {
+            let prereq_item = &exec_list[prereq_idx - 1];
+            let mut command = vec![prereq_item.exec.clone()];
+            command.extend(prereq_item.get_args());
+            format!("{} ({})", reference, command.join(" "))
+        }
+        _ => reference.clone(),
+    }
+}
+
+/// Loads the state file if any item of the `NansiFile` depends on it.
+fn load_state(
+    nansi_file: &NansiFile,
+    options: &ExecOptions,
+) -> Result<Option<(PathBuf, State)>, Box<dyn Error>> {
+    if !nansi_file
+        .exec_list
+        .iter()
+        .any(|item| item.idempotence_key.is_some())
+    {
+        return Ok(None);
+    }
+
+    let state_path = match &options.state_file {
+        Some(path) => PathBuf::from(path),
+        None => match State::default_path() {
+            Some(path) => path,
+            None => {
+                return Err("Unable to determine the location of the state file, use --state-file")?
+            }
+        },
+    };
+
+    let state = State::load(&state_path)?;
+
+    Ok(Some((state_path, state)))
+}
+
+/// Returns the indices of the items which succeeded in the trace at `path`,
+/// which must come from a run of the same `NansiFile` content.
+fn load_imported_state(nansi_file: &NansiFile, path: &str) -> Result<Vec<usize>, io::Error> {
+    let trace = Trace::from(path)?;
+
+    if trace.nansi_file.sha256 != nansi_file.sha256 {
+        let imported_sha256 = if trace.nansi_file.sha256.is_empty() {
+            String::from("unknown")
+        } else {
+            trace.nansi_file.sha256.clone()
+        };
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: imported state comes from a different NansiFile (sha256 {}, expected {})",
+                path, imported_sha256, nansi_file.sha256
+            ),
+        ));
+    }
+
+    Ok(trace
+        .get_succeeded_indices()
+        .into_iter()
+        .filter(|idx| *idx >= 1 && *idx <= nansi_file.exec_list.len())
+        .collect())
+}
+
+fn get_state_file_key(nansi_file: &NansiFile) -> String {
+    match fs::canonicalize(&nansi_file.file_path) {
+        Ok(path) => path.display().to_string(),
+        Err(_) => nansi_file.file_path.clone(),
+    }
+}
+
+/// Returns the identifier of an item in the state file: its label, or its index
+/// if the item has no label.
+fn get_item_id(exec_item: &ExecItem, idx: usize) -> String {
+    if exec_item.label.is_empty() {
+        format!("#{}", idx)
+    } else {
+        exec_item.label.clone()
+    }
+}
+
+fn get_status_str(
+    stream: OutputStream,
+    exec_item: &ExecItem,
+    idx: usize,
+    exec_status: ExecStatus,
+) -> String {
+    format!(
+        "{} {}",
+        get_status_tag(stream, exec_status),
+        ItemDisplay::new(exec_item, idx).with_command()
+    )
+}
+
+/// Returns the status at the start of status lines, e.g. `[FAIL]`.
+fn get_status_tag(stream: OutputStream, exec_status: ExecStatus) -> String {
+    let status = match exec_status {
+        ExecStatus::OK => paint(stream, "OK".green()),
+        ExecStatus::ERR => paint(stream, "FAIL".red()),
+        ExecStatus::WARN => paint(stream, "WARN".yellow()),
+        ExecStatus::SKIP => paint(stream, "SKIP".dark_yellow()),
+        ExecStatus::DRY => paint(stream, "DRY".cyan()),
+    };
+
+    format!("[{}]", status)
+}
+
+/// Returns the sink printing the lines of an item streaming its output, each
+/// prefixed with the item and redacted.
+///
+/// Returns `None` if `printer` leaves out the output of items which succeed
+/// (`--quiet`): whether the item fails is only known once it is done, so its
+/// output is then buffered and printed with its status line like any other.
+fn get_line_sink(
+    prefix: String,
+    redactor: Redactor,
+    printer: Printer,
+    stream: OutputStream,
+) -> Option<LineSink> {
+    if !printer.shows(Verbosity::Normal) {
+        return None;
+    }
+
+    Some(Arc::new(move |line| {
+        let (line, _) = redactor.redact(line);
+        // a closed stream must not stop the command
+        let _ = print_block(stream, &vec![format!("{} {}", prefix, line)]);
+    }))
+}
+
+/// Prints all lines belonging to a single item (status line, messages, output)
+/// to `stream` while holding its lock, so the lines of one item are never
+/// interleaved with the lines of another item finishing at the same time.
+pub(crate) fn print_block(stream: OutputStream, lines: &Vec<String>) -> Result<(), io::Error> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    match stream {
+        OutputStream::Stdout => write_block(&mut io::stdout().lock(), lines),
+        OutputStream::Stderr => write_block(&mut io::stderr().lock(), lines),
+    }
+}
+
+fn write_block<W: Write>(writer: &mut W, lines: &Vec<String>) -> Result<(), io::Error> {
+    let mut block = String::new();
+    for line in lines {
+        block.push_str(line);
+        block.push('\n');
+    }
+
+    writer.write_all(block.as_bytes())?;
+    writer.flush()
+}
+
+/// Level of the messages printed while executing, set with `--quiet` and
+/// `--verbose`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Verbosity {
+    /// Failures only
+    Quiet,
+
+    Normal,
+
+    /// Also the commands as run and their exit codes
+    Verbose,
+}
+
+/// Prints blocks of messages (see `print_block`) to the status stream, leaving
+/// out those of a level above the verbosity of the run
+#[derive(Debug, Clone, Copy)]
+struct Printer {
+    stream: OutputStream,
+    verbosity: Verbosity,
+}
+
+impl Printer {
+    fn new(stream: OutputStream, verbosity: Verbosity) -> Printer {
+        Printer { stream, verbosity }
+    }
+
+    /// Returns whether messages of `level` are printed: `Quiet` ones always,
+    /// `Verbose` ones only with `--verbose`.
+    fn shows(&self, level: Verbosity) -> bool {
+        level <= self.verbosity
+    }
+
+    fn print(&self, level: Verbosity, lines: &Vec<String>) -> Result<(), io::Error> {
+        self.print_to(self.stream, level, lines)
+    }
+
+    /// Prints to `stream` instead of the status stream, e.g. the output of an
+    /// item.
+    fn print_to(
+        &self,
+        stream: OutputStream,
+        level: Verbosity,
+        lines: &Vec<String>,
+    ) -> Result<(), io::Error> {
+        if !self.shows(level) {
+            return Ok(());
+        }
+
+        print_block(stream, lines)
+    }
+}
+
+/// Returns the line telling the exit code of the command of an item.
+fn get_exit_code_str(exec_item: &ExecItem, idx: usize, exit_code: Option<i32>) -> String {
+    match exit_code {
+        Some(code) => format!(
+            "[INFO] Item {} exited with code {}",
+            ItemDisplay::new(exec_item, idx),
+            code
+        ),
+        None => format!(
+            "[INFO] Item {} exited without an exit code",
+            ItemDisplay::new(exec_item, idx)
+        ),
+    }
+}
+
+/// Returns the lines of a `success_message` or `failure_hint`, wrapped at the
+/// terminal width when `stdout` is a terminal.
+fn get_item_message_lines(stream: OutputStream, prefix: &str, msg: &str) -> Vec<String> {
+    let width = if io::stdout().is_terminal() {
+        match terminal::size() {
+            Ok((columns, _)) => Some(columns as usize),
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+
+    let indent = " ".repeat(prefix.chars().count() + 1);
+    let text_width = width.map(|w| w.saturating_sub(indent.len()).max(20));
+
+    wrap_text(msg, text_width)
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                format!("{} {}", paint(stream, prefix.cyan()), line)
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect()
+}
+
+/// Wraps `text` at word boundaries so no line exceeds `width` characters
+/// (unless a single word does); line breaks in `text` are kept.
+fn wrap_text(text: &str, width: Option<usize>) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for text_line in text.lines() {
+        let width = match width {
+            Some(v) => v,
+            None => {
+                lines.push(String::from(text_line));
+                continue;
+            }
+        };
+
+        let mut line = String::new();
+        for word in text_line.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(line);
+                line = String::new();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+pub(crate) fn get_warning_str(stream: OutputStream, msg: &str) -> String {
+    format!("{} {}", paint(stream, "[WARN]".yellow()), msg)
+}
+
+pub(crate) fn get_error_str(stream: OutputStream, msg: &str) -> String {
+    format!("{} {}", paint(stream, "[ERR]".red()), msg)
+}
+
+fn default_as_false() -> bool {
+    false
+}
+
+fn default_as_true() -> bool {
+    true
+}
+
+fn default_as_weight() -> u32 {
+    1
+}
+
+fn default_as_empty_vec_string() -> Vec<String> {
+    vec![]
+}
+
+fn default_as_empty_vec_var_source() -> Vec<VarSource> {
+    vec![]
+}
+
+fn default_as_none_u64() -> Option<u64> {
+    None
+}
+
+fn deserialize_expect_duration_under_secs<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration_secs(deserializer, "expect_duration_under_secs")
+}
+
+fn deserialize_timeout_secs<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration_secs(deserializer, "timeout_secs")
+}
+
+/// Deserializes the items one by one so that an invalid item is reported with
+/// its index and label, e.g. `[2][build] timeout_secs: ...`.
+fn deserialize_exec_list<'de, D>(deserializer: D) -> Result<Vec<ExecItem>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values: Vec<serde_json::Value> = Vec::deserialize(deserializer)?;
+    let mut exec_list: Vec<ExecItem> = Vec::with_capacity(values.len());
+    for (idx, value) in values.into_iter().enumerate() {
+        let label = match value.get("label") {
+            Some(serde_json::Value::String(label)) => label.clone(),
+            _ => String::new(),
+        };
+        match serde_json::from_value(value) {
+            Ok(exec_item) => exec_list.push(exec_item),
+            Err(e) => {
+                return Err(de::Error::custom(format!(
+                    "{} {}",
+                    ItemDisplay::from_label(Some(idx + 1), &label),
+                    e
+                )))
+            }
+        }
+    }
+
+    Ok(exec_list)
+}
+
+fn default_as_none_u32() -> Option<u32> {
+    None
+}
+
+fn default_as_none_assertions() -> Option<Assertions> {
+    None
+}
+
+fn default_as_none_chunk_args() -> Option<ChunkArgs> {
+    None
+}
+
+fn default_as_empty_flags() -> BTreeMap<String, FlagValue> {
+    BTreeMap::new()
+}
+
+fn default_as_empty_env() -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+fn default_as_empty_vec_prerequisite() -> Vec<Prerequisite> {
+    Vec::new()
+}
+
+fn default_as_empty_vars() -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+fn default_as_none_output_stream() -> Option<OutputStream> {
+    None
+}
+
+fn default_as_print_output_none() -> PrintOutput {
+    PrintOutput::None
+}
+
+fn default_as_capture_full() -> Capture {
+    Capture::Full
+}
+
+fn default_as_label_state() -> LabelState {
+    LabelState::StickySuccess
+}
+
+fn default_as_label_matching() -> LabelMatching {
+    LabelMatching::Exact
+}
+
+fn default_as_none_changed_when() -> Option<ChangedWhen> {
+    None
+}
+
+fn default_as_none_vec_string() -> Option<Vec<String>> {
+    None
+}
+
+fn default_as_none_string() -> Option<String> {
+    None
+}
+
+fn default_as_empty_string() -> String {
+    String::from("")
+}
+
+#[test]
+fn compile_arg_envvar_test() {
+    let arg = String::from("cat Cargo.toml | grep \"version = \\\"${TEST}\\\"\"");
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "TEST", "XYZ");
+
+    let compiled_arg = compile_arg(&arg, &ctx).unwrap();
+    assert_eq!(
+        compiled_arg.as_str(),
+        "cat Cargo.toml | grep \"version = \\\"${TEST}\\\"\""
+    );
+}
+
+#[test]
+fn compile_arg_var_test() {
+    let arg = String::from("cat Cargo.toml | grep \"version = \\\"{TEST}\\\"\"");
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "TEST", "XYZ");
+
+    let compiled_arg = compile_arg(&arg, &ctx).unwrap();
+    assert_eq!(
+        compiled_arg.as_str(),
+        "cat Cargo.toml | grep \"version = \\\"XYZ\\\"\""
+    );
+}
+
+#[test]
+fn compile_arg_default_test() {
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Vars, "HOST", "example.com");
+    let compile = |arg: &str| compile_arg(&String::from(arg), &ctx).unwrap();
+
+    assert_eq!(compile("--port={PORT:8080}"), "--port=8080");
+    assert_eq!(compile("{HOST:localhost}:{PORT:}"), "example.com:");
+    assert_eq!(
+        compile("{URL:http://localhost:8080/a}"),
+        "http://localhost:8080/a"
+    );
+    assert_eq!(
+        compile("${PORT:8080} \\{PORT:8080}"),
+        "${PORT:8080} {PORT:8080}"
+    );
+
+    ctx.set_var(VarSource::Env, "PORT", "9090");
+    assert_eq!(
+        compile_arg(&String::from("{PORT:8080}"), &ctx).unwrap(),
+        "9090"
+    );
+
+    let e = compile_arg(
+        &String::from("{PORT}{MISSING}"),
+        &ExecContext::from_map(HashMap::new()),
+    )
+    .unwrap_err();
+    assert_eq!(
+        e.kind,
+        CompileErrorKind::UndefinedVariable {
+            name: String::from("PORT")
+        }
+    );
+}
+
+#[test]
+fn compile_arg_escape_test() {
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "FOO", "foo");
+    let compile = |arg: &str| compile_arg(&String::from(arg), &ctx).unwrap();
+
+    assert_eq!(compile("\\{FOO}"), "{FOO}");
+    assert_eq!(compile("\\{FOO\\}"), "{FOO}");
+    assert_eq!(compile("a\\}b"), "a}b");
+    assert_eq!(compile("\\\\{FOO}"), "\\foo");
+    assert_eq!(compile("C:\\dir\\\\{FOO}\\x"), "C:\\dir\\foo\\x");
+    assert_eq!(compile("{FOO}\\"), "foo\\");
+    assert_eq!(compile("\\"), "\\");
+    assert_eq!(compile("{FOO} \\{FOO} {FOO"), "foo {FOO} {FOO");
+
+    let scan = scan_tags("\\{A} ${B} \\\\{C}").unwrap();
+    assert_eq!(scan.tags, vec!["C"]);
+    assert_eq!(scan.escaped, vec!["A", "B"]);
+}
+
+#[test]
+fn compile_arg_undefined_var_test() {
+    let ctx = ExecContext::from_map(HashMap::new());
+
+    let e = compile_arg(&String::from("--token={NANSI_TEST_UNSET}"), &ctx).unwrap_err();
+    assert_eq!(
+        e.kind,
+        CompileErrorKind::UndefinedVariable {
+            name: String::from("NANSI_TEST_UNSET")
+        }
+    );
+    assert_eq!(
+        e.to_string(),
+        "undefined variable 'NANSI_TEST_UNSET' in \"--token={NANSI_TEST_UNSET}\""
+    );
+    assert_eq!(
+        e.failure_reason(),
+        Some(FailureReason::MissingVariable {
+            name: String::from("NANSI_TEST_UNSET")
+        })
+    );
+
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"label\": \"deploy\", \"exec\": \"echo\", \"args\": [\"a\", \"{TOKEN}\"]}",
+    )
+    .unwrap();
+    let e = compile_args(&exec_item, &ctx).unwrap_err();
+    assert_eq!(e.arg_index, Some(2));
+    assert_eq!(e.to_string(), "undefined variable 'TOKEN' in arg 2");
+}
+
+#[test]
+fn compile_arg_unbalanced_brace_test() {
+    let ctx = ExecContext::from_map(HashMap::new());
+
+    let e = compile_arg(&String::from("{A{B}}"), &ctx).unwrap_err();
+    assert_eq!(e.arg, "{A{B}}");
+    assert_eq!(e.kind, CompileErrorKind::UnbalancedBrace { position: 3 });
+    assert_eq!(e.to_string(), "unbalanced '{' at position 3 in \"{A{B}}\"");
+    assert_eq!(e.failure_reason(), None);
+}
+
+#[test]
+fn find_unexpanded_tags_test() {
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "TEST", "{NESTED}");
+
+    let arg = String::from("{TEST} ${SHELL_VAR} \\{ESCAPED}");
+    let compiled_arg = compile_arg(&arg, &ctx).unwrap();
+
+    assert_eq!(compiled_arg.as_str(), "{NESTED} ${SHELL_VAR} {ESCAPED}");
+    assert_eq!(find_unexpanded_tags(&arg, &compiled_arg), vec!["NESTED"]);
+}
+
+#[test]
+fn parse_index_prerequisite_test() {
+    assert_eq!(parse_index_prerequisite("#2"), Some(2));
+    assert_eq!(parse_index_prerequisite("#12"), Some(12));
+    assert_eq!(parse_index_prerequisite("#"), None);
+    assert_eq!(parse_index_prerequisite("#two"), None);
+    assert_eq!(parse_index_prerequisite("2"), None);
+    assert_eq!(parse_index_prerequisite("label"), None);
+}
+
+#[test]
+fn get_failure_preview_test() {
+    let failures = vec![
+        (
+            String::from("[2][build] make"),
+            String::from("a\nb\n\n\u{1b}[31mc\u{1b}[0m\n"),
+        ),
+        (
+            String::from("[3] ls -12345"),
+            String::from("invalid option\n"),
+        ),
+    ];
+
+    assert!(get_failure_preview(&failures, 0).is_empty());
+    assert!(get_failure_preview(&[], 5).is_empty());
+    assert_eq!(
+        get_failure_preview(&failures, 2),
+        vec![
+            "Failures:",
+            "  [2][build] make",
+            "    b",
+            "    c",
+            "  [3] ls -12345",
+            "    invalid option"
+        ]
+    );
+
+    let many: Vec<(String, String)> = (1..=30)
+        .map(|i| (format!("[{}]", i), String::from("x\ny\nz\n")))
+        .collect();
+    let preview = get_failure_preview(&many, 3);
+    assert!(preview.len() <= MAX_FAILURE_PREVIEW_LINES + 1);
+    assert_eq!(preview.last().unwrap(), "  ... 18 more failure(s) omitted");
+}
+
+#[test]
+fn nansi_file_serialization_test() {
+    let content = "{\"features\": {\"zeta\": 1, \"null_stdin\": false, \"alpha\": true}, \"exec_list\": [{\"exec\": \"ls\", \"flags\": {\"--recursive\": true, \"--depth\": 2, \"--all\": false}}]}";
+
+    let first = serde_json::to_string(&NansiFile::from_json(content, "f.json").unwrap()).unwrap();
+    let second = serde_json::to_string(&NansiFile::from_json(content, "f.json").unwrap()).unwrap();
+
+    assert_eq!(first.as_bytes(), second.as_bytes());
+    assert!(first.contains("\"flags\":{\"--all\":false,\"--depth\":2,\"--recursive\":true}"));
+    assert!(first.contains("\"alpha\":true,\"zeta\":1"));
+}
+
+#[test]
+fn nansi_file_units_test() {
+    let nansi_file = NansiFile::from_yaml(
+        "exec_list:\n  - exec: ls\n    timeout_secs: 2m30s\n    expect_duration_under_secs: 90\n    assert:\n      min_free_space_mb: 1.5GiB\n",
+        "f.yaml",
+    )
+    .unwrap();
+    let exec_item = &nansi_file.exec_list[0];
+    assert_eq!(exec_item.timeout_secs, Some(150));
+    assert_eq!(exec_item.expect_duration_under_secs, Some(90));
+    assert_eq!(
+        exec_item.assert.as_ref().unwrap().min_free_space_mb,
+        Some(1536)
+    );
+
+    let err = NansiFile::from_json(
+        "{\"exec_list\": [{\"exec\": \"ls\"}, {\"label\": \"build\", \"exec\": \"make\", \"timeout_secs\": \"5 minutes\"}]}",
+        "f.json",
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("f.json: [2][build] timeout_secs: unknown unit \"minutes\" in \"5 minutes\""));
+}
+
+#[test]
+fn nansi_file_bare_array_test() {
+    let nansi_file = NansiFile::from_json(
+        "  [{\"exec\": \"mkdir\", \"args\": [\"-p\", \"build\"]}, {\"exec\": \"cargo\"}]",
+        "f.json",
+    )
+    .unwrap();
+    assert_eq!(nansi_file.exec_list.len(), 2);
+    assert_eq!(nansi_file.exec_list[1].exec, "cargo");
+    assert_eq!(nansi_file.file_path, "f.json");
+    assert_eq!(nansi_file.label_state, LabelState::StickySuccess);
+
+    let err = NansiFile::from_json("[{\"exec\": \"ls\", \"args\": 3}]", "f.json").unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("f.json: [1] invalid type: integer `3`"));
+}
+
+#[test]
+fn get_empty_expansions_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"label\": \"rm\", \"exec\": \"rm\", \"args\": [\"-rf\", \"{TARGET_DIR}/\", \"{EMPTY}\", \"{ROOT}\", \"{TARGET_DIR}{ROOT}x\"]}",
+    )
+    .unwrap();
+    let ctx = ExecContext::from_map(HashMap::from([
+        (String::from("TARGET_DIR"), String::new()),
+        (String::from("EMPTY"), String::from(" ")),
+        (String::from("ROOT"), String::from("/")),
+    ]));
+    let args = compile_args(&exec_item, &ctx).unwrap();
+
+    let expansions = get_empty_expansions(&exec_item, 1, &args, &ctx).unwrap();
+    assert_eq!(
+        expansions,
+        vec![
+            (
+                FailureReason::EmptyExpansion {
+                    argument: 2,
+                    name: Some(String::from("TARGET_DIR"))
+                },
+                String::from(
+                    "Argument 2 of item [1][rm] expands to \"/\" because {TARGET_DIR} is empty"
+                )
+            ),
+            (
+                FailureReason::EmptyExpansion {
+                    argument: 3,
+                    name: Some(String::from("EMPTY"))
+                },
+                String::from(
+                    "Argument 3 of item [1][rm] expands to \" \" because {EMPTY} is empty"
+                )
+            ),
+            (
+                FailureReason::EmptyExpansion {
+                    argument: 4,
+                    name: None
+                },
+                String::from("Argument 4 of item [1][rm] expands to \"/\" from {ROOT}")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn print_output_test() {
+    let parse = |s: &str| {
+        serde_json::from_str::<ExecItem>(&format!("{{\"exec\": \"ls\", \"print_output\": {}}}", s))
+            .map(|exec_item| exec_item.print_output)
+            .map_err(|e| e.to_string())
+    };
+
+    assert_eq!(parse("false"), Ok(PrintOutput::None));
+    assert_eq!(parse("true"), Ok(PrintOutput::Buffered));
+    assert_eq!(parse("\"stream\""), Ok(PrintOutput::Stream));
+    assert!(parse("\"live\"")
+        .unwrap_err()
+        .starts_with("print_output: expected true, false or \"stream\""));
+
+    let exec_item: ExecItem = serde_json::from_str("{\"exec\": \"ls\"}").unwrap();
+    assert_eq!(exec_item.print_output, PrintOutput::None);
+    assert_eq!(
+        serde_json::to_value(PrintOutput::Stream).unwrap(),
+        serde_json::json!("stream")
+    );
+    assert_eq!(
+        serde_json::to_value(PrintOutput::Buffered).unwrap(),
+        serde_json::json!(true)
+    );
+}
+
+#[test]
+fn get_output_str_test() {
+    let mut exec_item: ExecItem =
+        serde_json::from_str("{\"exec\": \"ls\", \"print_output\": true}").unwrap();
+
+    let output = get_output_str(&exec_item, ExecStatus::OK, String::from("out\n"), "", false);
+    assert_eq!(output.unwrap(), "out\n");
+
+    let output = get_output_str(&exec_item, ExecStatus::OK, String::new(), "a\nb\n", false);
+    assert_eq!(
+        output.unwrap(),
+        "(stdout empty; 2 line(s) on stderr \u{2014} use print_stderr)"
+    );
+
+    let output = get_output_str(&exec_item, ExecStatus::ERR, String::new(), "a\n", false);
+    assert_eq!(output.unwrap(), "(stderr empty; 1 line(s) on stdout)");
+
+    let output = get_output_str(&exec_item, ExecStatus::OK, String::new(), "", false);
+    assert_eq!(output.unwrap(), "(no output)");
+
+    let output = get_output_str(&exec_item, ExecStatus::OK, String::new(), "", true);
+    assert_eq!(output, None);
+
+    exec_item.print_stderr = true;
+    let output = get_output_str(
+        &exec_item,
+        ExecStatus::OK,
+        String::from("out\n"),
+        "err\n",
+        false,
+    );
+    assert_eq!(output.unwrap(), "out\nerr\n");
+}
+
+#[test]
+fn mask_secret_args_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"exec\": \"curl\", \"args\": [\"-H\", \"Token: {API_TOKEN}\", \"{HOST}\"]}",
+    )
+    .unwrap();
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "API_TOKEN", "s3cr3t");
+    ctx.set_var(VarSource::Env, "HOST", "example.com");
+
+    let args = compile_args(&exec_item, &ctx).unwrap();
+    assert_eq!(args[1], "Token: s3cr3t");
+    assert_eq!(
+        mask_secret_args(&exec_item, &args, &ctx),
+        vec!["-H", "Token: ***", "example.com"]
+    );
+}
+
+#[test]
+fn mask_secret_args_flags_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"exec\": \"login\", \"args\": [\"--user\", \"andy\", \"--password\", \"hunter2\", \"--api-token=abc\", \"-v\", \"{DB_SECRET}-{DB_SECRET}\", \"--\"]}",
+    )
+    .unwrap();
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "DB_SECRET", "xyz");
+
+    let args = compile_args(&exec_item, &ctx).unwrap();
+    assert_eq!(
+        mask_secret_args(&exec_item, &args, &ctx),
+        vec![
+            "--user",
+            "andy",
+            "--password",
+            "***",
+            "--api-token=***",
+            "-v",
+            "***-***",
+            "--"
+        ]
+    );
+}
+
+#[test]
+fn mask_secret_args_item_precedence_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"exec\": \"echo\", \"args\": [\"{TOKEN}\"], \"var_precedence\": [\"env\"]}",
+    )
+    .unwrap();
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Cli, "TOKEN", "from-cli");
+    ctx.set_var(VarSource::Env, "TOKEN", "from-env");
+
+    let args = compile_args(&exec_item, &ctx).unwrap();
+    assert_eq!(args, vec!["from-env"]);
+    assert_eq!(mask_secret_args(&exec_item, &args, &ctx), vec!["***"]);
+}
+
+#[test]
+fn run_failed_test() {
+    let run_failed = |failed: usize, skipped: usize| {
+        RunFailed {
+            failed,
+            skipped,
+            summary: RunSummary::default(),
+        }
+        .to_string()
+    };
+
+    assert_eq!(run_failed(2, 0), "2 item(s) failed");
+    assert_eq!(
+        run_failed(0, 1),
+        "1 item(s) skipped for unmet prerequisites"
+    );
+    assert_eq!(
+        run_failed(2, 1),
+        "2 item(s) failed, 1 item(s) skipped for unmet prerequisites"
+    );
+}
+
+#[test]
+fn porcelain_line_test() {
+    assert_eq!(
+        get_porcelain_line(3, "build", ExecStatus::OK, Some(0), 2140),
+        "3\tbuild\tok\t0\t2140ms"
+    );
+    assert_eq!(
+        get_porcelain_line(4, "", ExecStatus::ERR, Some(2), 15),
+        "4\t\terr\t2\t15ms"
+    );
+    assert_eq!(
+        get_porcelain_line(5, "deploy", ExecStatus::SKIP, None, 0),
+        "5\tdeploy\tskip\t\t0ms"
+    );
+}
+
+#[test]
+fn summary_line_test() {
+    let summary = RunSummary {
+        ok: 12,
+        err: 2,
+        warn: 1,
+        skip: 3,
+        changed: 4,
+        duration_ms: 251034,
+    };
+
+    assert_eq!(
+        summary.summary_line(1),
+        "nansi: ok=12 err=2 warn=1 skip=3 duration_ms=251034 exit=1"
+    );
+}
+
+#[test]
+fn get_batches_test() {
+    let exec_list: Vec<ExecItem> = [false, true, true, false, true, true, true]
+        .iter()
+        .map(|parallel| {
+            serde_json::from_str(&format!("{{\"exec\": \"ls\", \"parallel\": {}}}", parallel))
+                .unwrap()
+        })
+        .collect();
+    let order: Vec<usize> = (0..exec_list.len()).collect();
+
+    assert_eq!(
+        get_batches(&order, &exec_list, 2),
+        vec![
+            vec![(0, 0)],
+            vec![(1, 1), (2, 2)],
+            vec![(3, 3)],
+            vec![(4, 4), (5, 5), (6, 6)],
+        ]
+    );
+    assert_eq!(get_batches(&order, &exec_list, 1).len(), 7);
+    assert_eq!(
+        get_batches(&[2, 0, 1], &exec_list, 2),
+        vec![vec![(0, 2)], vec![(1, 0)], vec![(2, 1)]]
+    );
+}
+
+#[test]
+fn get_batches_serial_test() {
+    // the migration is parallel like its neighbours, but serial
+    let exec_list: Vec<ExecItem> = [false, false, true, false, false]
+        .iter()
+        .map(|serial| {
+            serde_json::from_str(&format!(
+                "{{\"exec\": \"ls\", \"parallel\": true, \"serial\": {}}}",
+                serial
+            ))
+            .unwrap()
+        })
+        .collect();
+    let order: Vec<usize> = (0..exec_list.len()).collect();
+
+    assert_eq!(
+        get_batches(&order, &exec_list, 8),
+        vec![vec![(0, 0), (1, 1)], vec![(2, 2)], vec![(3, 3), (4, 4)]]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn execute_serial_test() {
+    // each item logs when it starts and ends; with every item parallel they
+    // would all run at once, the appends giving the order of the events
+    let log = env::temp_dir().join(format!("nansi_serial_{}.log", std::process::id()));
+    let _ = fs::remove_file(&log);
+    let item = |label: &str, serial: bool| {
+        format!(
+            "{{\"label\": \"{0}\", \"exec\": \"sh\", \"args\": [\"-c\", \"echo start {0} >> {1}; sleep 0.3; echo end {0} >> {1}\"], \"parallel\": true, \"serial\": {2}}}",
+            label,
+            log.display(),
+            serial
+        )
+    };
+    let nansi_file: NansiFile = serde_json::from_str(&format!(
+        "{{\"exec_list\": [{}, {}, {}, {}, {}]}}",
+        item("a", false),
+        item("b", false),
+        item("migrate", true),
+        item("c", false),
+        item("d", false)
+    ))
+    .unwrap();
+    let options = ExecOptions {
+        jobs: 8,
+        quiet: true,
+        ..ExecOptions::default()
+    };
+
+    let summary = execute(&nansi_file, &options).unwrap();
+    let events = fs::read_to_string(&log).unwrap();
+    fs::remove_file(&log).unwrap();
+
+    assert_eq!(summary.ok, 5);
+    let events: Vec<&str> = events.lines().collect();
+    let position = |event: &str| events.iter().position(|e| *e == event).unwrap();
+    let (start, end) = (position("start migrate"), position("end migrate"));
+    assert_eq!(end, start + 1, "{:?}", events);
+    for label in ["a", "b"] {
+        assert!(position(&format!("end {}", label)) < start, "{:?}", events);
+    }
+    for label in ["c", "d"] {
+        assert!(position(&format!("start {}", label)) > end, "{:?}", events);
+    }
+    // the items around it still run at the same time
+    assert!(position("start b") < position("end a"), "{:?}", events);
+    assert!(position("start d") < position("end c"), "{:?}", events);
+}
+
+#[test]
+fn get_run_error_test() {
+    let nansi_file: NansiFile = serde_json::from_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\", \"parallel\": true}, {\"exec\": \"ls\", \"parallel\": true}, {\"exec\": \"ls\", \"parallel\": true, \"prerequisites\": [\"a\", \"#2\"]}]}",
+    )
+    .unwrap();
+    let diagnostics = validate(&nansi_file);
+
+    // with a single job every item runs on its own
+    assert!(get_run_error(&diagnostics, 1).is_none());
+    // a label prerequisite in the batch only causes the item to be skipped
+    assert_eq!(
+        get_run_error(&diagnostics, 8).map(|d| d.message.as_str()),
+        Some("Prerequisite #2 of item [3] refers to an item of the same parallel batch when run with more than one job")
+    );
+
+    let mut nansi_file = nansi_file;
+    nansi_file.exec_list[1].serial = true;
+    assert!(get_run_error(&validate(&nansi_file), 8).is_none());
+}
+
+/// Runs jobs given as `(weight, duration)` through a `JobQueue` with a mock
+/// runner advancing a clock, and returns the jobs running together after each
+/// round of starts.
+#[cfg(test)]
+fn simulate_job_queue(capacity: usize, jobs: &[(u32, u32)]) -> Vec<Vec<usize>> {
+    let mut queue: JobQueue<usize> = JobQueue::new(capacity);
+    let weights: Vec<usize> = jobs
+        .iter()
+        .enumerate()
+        .map(|(job, (weight, _))| queue.push(*weight, job))
+        .collect();
+
+    let mut clock = 0;
+    // (end time, job) of the running jobs
+    let mut running: Vec<(u32, usize)> = Vec::new();
+    let mut occupancy: Vec<Vec<usize>> = Vec::new();
+    loop {
+        let mut started = false;
+        while let Some(job) = queue.start_next() {
+            running.push((clock + jobs[job].1, job));
+            started = true;
+        }
+        if started {
+            let mut jobs_running: Vec<usize> = running.iter().map(|(_, job)| *job).collect();
+            jobs_running.sort();
+            let used: usize = jobs_running.iter().map(|job| weights[*job]).sum();
+            assert!(used <= queue.capacity, "{:?} over capacity", jobs_running);
+            occupancy.push(jobs_running);
+        }
+
+        // the jobs ending first all finish before the next round of starts
+        let Some(end) = running.iter().map(|(end, _)| *end).min() else {
+            break;
+        };
+        clock = end;
+        for (_, job) in running.iter().filter(|(end, _)| *end == clock) {
+            queue.finish(weights[*job]);
+        }
+        running.retain(|(end, _)| *end != clock);
+    }
+    assert_eq!(queue.len(), 0);
+
+    occupancy
+}
+
+#[test]
+fn job_queue_test() {
+    // unweighted items run `jobs` at a time
+    assert_eq!(
+        simulate_job_queue(2, &[(1, 1), (1, 1), (1, 1)]),
+        vec![vec![0, 1], vec![2]]
+    );
+
+    // a heavy item waits for the slots to free up, runs alone and holds back
+    // the items behind it
+    assert_eq!(
+        simulate_job_queue(8, &[(1, 2), (1, 1), (8, 1), (1, 1), (4, 3), (4, 1)]),
+        vec![vec![0, 1], vec![2], vec![3, 4], vec![4, 5]]
+    );
+
+    // light items pack around a medium one
+    assert_eq!(
+        simulate_job_queue(4, &[(2, 3), (1, 1), (1, 2), (1, 1)]),
+        vec![vec![0, 1, 2], vec![0, 2, 3]]
+    );
+
+    // weights over the capacity clamp to it, 0 counts as 1
+    assert_eq!(
+        simulate_job_queue(4, &[(10, 1), (0, 1), (3, 1)]),
+        vec![vec![0], vec![1, 2]]
+    );
+}
+
+#[test]
+fn get_weight_warning_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"label\": \"link\", \"exec\": \"ld\", \"parallel\": true, \"weight\": 16}",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_weight_warning(&exec_item, 3, 8),
+        Some(String::from(
+            "Weight 16 of item [3][link] exceeds the jobs limit, it takes all 8 slots"
+        ))
+    );
+    assert_eq!(get_weight_warning(&exec_item, 3, 16), None);
+    assert_eq!(get_weight_warning(&exec_item, 3, 1), None);
+}
+
+#[test]
+fn verbosity_test() {
+    let mut options = ExecOptions::default();
+    assert_eq!(options.verbosity(), Verbosity::Normal);
+    options.quiet = true;
+    assert_eq!(options.verbosity(), Verbosity::Quiet);
+
+    let printer = Printer::new(OutputStream::Stdout, options.verbosity());
+    assert!(printer.shows(Verbosity::Quiet));
+    assert!(!printer.shows(Verbosity::Normal));
+
+    options.quiet = false;
+    options.verbose = true;
+    let printer = Printer::new(OutputStream::Stdout, options.verbosity());
+    assert!(printer.shows(Verbosity::Normal));
+    assert!(printer.shows(Verbosity::Verbose));
+}
+
+#[test]
+fn get_arg_chunks_test() {
+    let args: Vec<String> = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+    let chunk_args = ChunkArgs {
+        size: 2,
+        fixed: vec![String::from("-f")],
+    };
+
+    assert_eq!(
+        get_arg_chunks(&args, &chunk_args),
+        vec![vec!["-f", "a", "b"], vec!["-f", "c", "d"], vec!["-f", "e"]]
+    );
+    assert_eq!(get_arg_chunks(&[], &chunk_args), vec![vec!["-f"]]);
+}
+
+#[test]
+fn wrap_text_test() {
+    assert_eq!(
+        wrap_text("Is the VPN connected? Run 'vpnctl up' and retry", Some(20)),
+        vec!["Is the VPN", "connected? Run", "'vpnctl up' and", "retry"]
+    );
+    assert_eq!(
+        wrap_text("first line\nsecond", Some(40)),
+        vec!["first line", "second"]
+    );
+    assert_eq!(
+        wrap_text("a_single_word_longer_than_width", Some(10)),
+        vec!["a_single_word_longer_than_width"]
+    );
+    assert_eq!(
+        wrap_text("not   wrapped  at all", None),
+        vec!["not   wrapped  at all"]
+    );
+}
+
+#[test]
+fn get_args_flags_test() {
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"exec\": \"aws\", \"args\": [\"s3\", \"ls\"], \"flags\": {\"--region\": \"eu-west-1\", \"--profile\": \"{PROFILE}\", \"--force\": true, \"--dry-run\": false, \"--page-size\": 100, \"--exclude\": [\"*.tmp\", \"*.log\"]}}",
+    )
+    .unwrap();
+
+    assert_eq!(
+        exec_item.get_args(),
+        vec![
+            "s3",
+            "ls",
+            "--exclude",
+            "*.tmp",
+            "--exclude",
+            "*.log",
+            "--force",
+            "--page-size",
+            "100",
+            "--profile",
+            "{PROFILE}",
+            "--region",
+            "eu-west-1",
+        ]
+    );
+}
+
+#[test]
+fn get_self_references_test() {
+    let file_path = "testdata/nansifile_linux.json";
+    let abs_path = fs::canonicalize(file_path).unwrap();
+    let args: Vec<String> = vec![
+        String::from("-f"),
+        String::from(file_path),
+        format!("--config={}", abs_path.display()),
+        String::from("testdata/./nansifile_linux.json"),
+        String::from("testdata"),
+        String::from("--config=testdata/variable.json"),
+    ];
+
+    assert_eq!(
+        get_self_references(&args, file_path, None),
+        vec![
+            String::from(file_path),
+            format!("--config={}", abs_path.display()),
+            String::from("testdata/./nansifile_linux.json"),
+        ]
+    );
+    assert!(get_self_references(&args, "surely_missing_file.json", None).is_empty());
+
+    let args = vec![
+        String::from("nansifile_linux.json"),
+        String::from(file_path),
+    ];
+    assert_eq!(
+        get_self_references(&args, file_path, Some(Path::new("testdata"))),
+        vec![String::from("nansifile_linux.json")]
+    );
+}
+
+#[test]
+fn get_changed_test() {
+    let mut exec_item: ExecItem = serde_json::from_str("{\"exec\": \"ls\"}").unwrap();
+    assert_eq!(
+        get_changed(&exec_item, ExecStatus::OK, Some(0), ""),
+        Ok(Some(true))
+    );
+    assert_eq!(
+        get_changed(&exec_item, ExecStatus::ERR, Some(1), ""),
+        Ok(None)
+    );
+
+    exec_item.changed_when = Some(ChangedWhen::Condition(String::from("exit_code:2")));
+    assert_eq!(
+        get_changed(&exec_item, ExecStatus::OK, Some(0), ""),
+        Ok(Some(false))
+    );
+
+    // the error becomes the reason the item fails, as written to the report
+    exec_item.changed_when = Some(ChangedWhen::Condition(String::from("sometimes")));
+    let message = get_changed(&exec_item, ExecStatus::OK, Some(0), "").unwrap_err();
+    let reason = FailureReason::InvalidChangedWhen { message };
+    assert_eq!(reason.short_str(), "invalid changed_when");
+    assert_eq!(
+        serde_json::to_string(&reason).unwrap(),
+        "{\"reason\":\"invalid_changed_when\",\"message\":\"invalid changed_when \\\"sometimes\\\" (expected always, never, exit_code:N or {\\\"output_matches\\\": ...})\"}"
+    );
+}
+
+#[test]
+fn get_duration_failure_test() {
+    let mut exec_item: ExecItem = serde_json::from_str("{\"exec\": \"ls\"}").unwrap();
+    assert_eq!(get_duration_failure(&exec_item, 93_400), None);
+
+    exec_item.expect_duration_under_secs = Some(60);
+    assert_eq!(get_duration_failure(&exec_item, 60_000), None);
+    assert_eq!(
+        get_duration_failure(&exec_item, 93_400),
+        Some(String::from("Completed in 93.4s, limit 60s"))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exec_failure_reason_test() {
+    let run = |exec: &str, args: &[&str]| {
+        let exec_item: ExecItem =
+            serde_json::from_str(&format!("{{\"exec\": \"{}\"}}", exec)).unwrap();
+        let args: Vec<String> = args.iter().map(|arg| String::from(*arg)).collect();
+        run_exec(&exec_item, &args, &ExecContext::new(), &mut None)
+            .unwrap()
+            .failure_reason
+    };
+
+    assert_eq!(run("sh", &["-c", "exit 0"]), None);
+    assert_eq!(
+        run("sh", &["-c", "exit 3"]),
+        Some(FailureReason::NonZeroExit { code: 3 })
+    );
+    assert_eq!(
+        run("sh", &["-c", "kill -9 $$"]),
+        Some(FailureReason::Signal { signal: 9 })
+    );
+    assert!(matches!(
+        run("nansi_surely_missing_command", &[]),
+        Some(FailureReason::SpawnFailed { .. })
+    ));
+}
+
+#[test]
+fn run_exec_outcome_test() {
+    let exec_item: ExecItem = serde_json::from_str("{\"exec\": \"sh\"}").unwrap();
+    let args = vec![
+        String::from("-c"),
+        String::from("echo out; echo err >&2; exit 1"),
+    ];
+
+    let outcome = run_exec(&exec_item, &args, &ExecContext::new(), &mut None).unwrap();
+    assert_eq!(outcome.status, ExecStatus::ERR);
+    assert_eq!(outcome.exit_code, Some(1));
+    assert_eq!(outcome.stdout, b"out\n");
+    assert_eq!(outcome.stderr_str().unwrap(), "err\n");
+    assert!(outcome.spawn_error.is_none());
+
+    let exec_item: ExecItem =
+        serde_json::from_str("{\"exec\": \"nansi_surely_missing_command\"}").unwrap();
+    let outcome = run_exec(&exec_item, &[], &ExecContext::new(), &mut None).unwrap();
+    assert_eq!(outcome.exit_code, None);
+    assert_eq!(
+        outcome.stderr_str().unwrap(),
+        outcome.spawn_error.unwrap().to_string()
+    );
+}
+
+#[test]
+fn get_item_fingerprint_test() {
+    let item = |s: &str| -> ExecItem { serde_json::from_str(s).unwrap() };
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "STAGE", "test");
+    let args = vec![String::from("-la")];
+
+    let first = item("{\"label\": \"a\", \"exec\": \"ls\", \"args\": [\"{dir}\"]}");
+    let second = item("{\"label\": \"b\", \"exec\": \"ls\", \"prerequisites\": [\"a\"]}");
+    let third = item("{\"exec\": \"ls\", \"print_output\": true}");
+
+    let fingerprint = get_item_fingerprint(&first, &args, &ctx).unwrap();
+    assert_eq!(
+        get_item_fingerprint(&second, &args, &ctx).unwrap(),
+        fingerprint
+    );
+    assert_ne!(
+        get_item_fingerprint(&third, &args, &ctx).unwrap(),
+        fingerprint
+    );
+    assert_ne!(
+        get_item_fingerprint(&second, &[], &ctx).unwrap(),
+        fingerprint
+    );
+
+    let mut inherit_stdin = ctx.clone();
+    inherit_stdin.set_features(&FeatureFlags {
+        null_stdin: false,
+        ..FeatureFlags::default()
+    });
+    assert_ne!(
+        get_item_fingerprint(&first, &args, &inherit_stdin).unwrap(),
+        fingerprint
+    );
+
+    // cwd, env and stdin count as expanded: the same template expanding
+    // differently (e.g. `{item}` of --stdin-each) differs, the same value
+    // written differently does not
+    for field in [
+        "\"cwd\": \"/srv/{X}\"",
+        "\"env\": {\"HOST\": \"{X}\"}",
+        "\"stdin\": \"{X}\"",
+    ] {
+        let mut with_line = item(&format!(
+            "{{\"exec\": \"ls\", {}}}",
+            field.replace("{X}", "{item}")
+        ));
+        with_line.stdin_item = Some(String::from("host1"));
+        let host1 = get_item_fingerprint(&with_line, &args, &ctx).unwrap();
+        with_line.stdin_item = Some(String::from("host2"));
+        let host2 = get_item_fingerprint(&with_line, &args, &ctx).unwrap();
+        assert_ne!(host1, host2, "{}", field);
+
+        let templated = item(&format!(
+            "{{\"exec\": \"ls\", {}}}",
+            field.replace("{X}", "{STAGE}")
+        ));
+        let literal = item(&format!(
+            "{{\"exec\": \"ls\", {}}}",
+            field.replace("{X}", "test")
+        ));
+        let other = item(&format!(
+            "{{\"exec\": \"ls\", {}}}",
+            field.replace("{X}", "prod")
+        ));
+        assert_eq!(
+            get_item_fingerprint(&templated, &args, &ctx),
+            get_item_fingerprint(&literal, &args, &ctx),
+            "{}",
+            field
+        );
+        assert_ne!(
+            get_item_fingerprint(&templated, &args, &ctx),
+            get_item_fingerprint(&other, &args, &ctx),
+            "{}",
+            field
+        );
+    }
+}
+
+#[test]
+fn get_label_lines_test() {
+    let nansi_file = NansiFile::from_json(
+        "{\"exec_list\": [{\"label\": \"ls\", \"exec\": \"ls\", \"args\": [\"-la\"]}, {\"exec\": \"pwd\"}, {\"label\": \"up\", \"exec\": \"uptime\"}]}",
+        "setup.json",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_label_lines(&nansi_file, ListFormat::Text).unwrap(),
+        vec!["[1][ls] ls -la", "[3][up] uptime "]
+    );
+
+    let json: serde_json::Value =
+        serde_json::from_str(&get_label_lines(&nansi_file, ListFormat::Json).unwrap()[0]).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([
+            {"index": 1, "label": "ls", "command": "ls -la"},
+            {"index": 3, "label": "up", "command": "uptime"},
+        ])
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exec_env_passthrough_test() {
+    env::set_var("NANSI_TEST_PASSTHROUGH_CANARY", "canary");
+    env::set_var("NANSI_TEST_PASSTHROUGH_KEPT", "kept");
+
+    let run = |exec_item: &str, ctx: &ExecContext| {
+        let exec_item: ExecItem = serde_json::from_str(exec_item).unwrap();
+        run_exec(&exec_item, &[], ctx, &mut None)
+            .unwrap()
+            .stdout_str()
+            .unwrap()
+    };
+
+    let output = run("{\"exec\": \"/usr/bin/env\"}", &ExecContext::new());
+    assert!(output.contains("NANSI_TEST_PASSTHROUGH_CANARY=canary\n"));
+
+    let mut ctx = ExecContext::new();
+    ctx.set_env_passthrough(&[String::from("NANSI_TEST_PASSTHROUGH_KEPT")]);
+    let output = run("{\"exec\": \"/usr/bin/env\"}", &ctx);
+    assert_eq!(output, "NANSI_TEST_PASSTHROUGH_KEPT=kept\n");
+
+    let output = run(
+        "{\"exec\": \"/usr/bin/env\", \"env_passthrough\": []}",
+        &ctx,
+    );
+    assert_eq!(output, "");
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exec_expand_tilde_test() {
+    let home = dirs::home_dir().unwrap();
+    let exec_item: ExecItem = serde_json::from_str("{\"exec\": \"echo\"}").unwrap();
+    let args: Vec<String> = ["~/testdev", "\\~/testdev", "file~backup", "~"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+
+    let mut ctx = ExecContext::new();
+    let output = run_exec(&exec_item, &args, &ctx, &mut None)
+        .unwrap()
+        .stdout_str()
+        .unwrap();
+    assert_eq!(
+        output,
+        format!(
+            "{} ~/testdev file~backup {}\n",
+            home.join("testdev").display(),
+            home.display()
+        )
+    );
+
+    ctx.set_features(&FeatureFlags {
+        expand_tilde: false,
+        ..Default::default()
+    });
+    let output = run_exec(&exec_item, &args, &ctx, &mut None)
+        .unwrap()
+        .stdout_str()
+        .unwrap();
+    assert_eq!(output, "~/testdev \\~/testdev file~backup ~\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exec_env_test() {
+    env::set_var("NANSI_TEST_ENV_BASE", "base");
+    env::set_var("NANSI_TEST_ENV_EMPTIED", "set");
+
+    let mut ctx = ExecContext::new();
+    ctx.set_env_passthrough(&[
+        String::from("NANSI_TEST_ENV_BASE"),
+        String::from("NANSI_TEST_ENV_EMPTIED"),
+    ]);
+    let exec_item: ExecItem = serde_json::from_str(
+        "{\"exec\": \"/usr/bin/env\", \"env\": {\"NANSI_TEST_ENV_COMPOSED\": \"{NANSI_TEST_ENV_BASE}/x\", \"NANSI_TEST_ENV_EMPTIED\": \"\"}}",
+    )
+    .unwrap();
+
+    let output = run_exec(&exec_item, &[], &ctx, &mut None)
+        .unwrap()
+        .stdout_str()
+        .unwrap();
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort();
+
+    assert_eq!(
+        lines,
+        vec![
+            "NANSI_TEST_ENV_BASE=base",
+            "NANSI_TEST_ENV_COMPOSED=base/x",
+            "NANSI_TEST_ENV_EMPTIED=",
+        ]
+    );
+}
+
+#[test]
+fn changed_when_test() {
+    let changed_when = |s: &str| -> ChangedWhen { serde_json::from_str(s).unwrap() };
+
+    assert_eq!(changed_when("\"always\"").is_changed(Some(0), ""), Ok(true));
+    assert_eq!(changed_when("\"never\"").is_changed(Some(0), ""), Ok(false));
+    assert_eq!(
+        changed_when("\"exit_code:0\"").is_changed(Some(0), ""),
+        Ok(true)
+    );
+    assert_eq!(
+        changed_when("\"exit_code:2\"").is_changed(Some(0), ""),
+        Ok(false)
+    );
+    assert!(changed_when("\"sometimes\"")
+        .is_changed(Some(0), "")
+        .is_err());
+
+    let output_matches = changed_when("{\"output_matches\": \"installed|upgraded\"}");
+    assert_eq!(
+        output_matches.is_changed(Some(0), "1 package upgraded\n"),
+        Ok(true)
+    );
+    assert_eq!(
+        output_matches.is_changed(Some(0), "nothing to do\n"),
+        Ok(false)
     );
 }
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Toggles of built-in behaviors (`features` of a `NansiFile`), letting a file
+/// keep the behavior it was written for when a default changes.
+///
+/// Each toggle documents its default and the version that introduced it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeatureFlags {
+    /// Replace a leading `~` in paths, `exec` and `args` with the home
+    /// directory (see `resolve_path`, `expand_arg_tilde`); default `true`
+    /// since 0.2.0
+    #[serde(default = "default_as_true")]
+    pub expand_tilde: bool,
+
+    /// Connect `stdin` of commands to the null device instead of inheriting it
+    /// from `nansi`; default `true` since 0.2.0
+    #[serde(default = "default_as_true")]
+    pub null_stdin: bool,
+
+    /// Toggles unknown to this version, reported as warnings
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> FeatureFlags {
+        FeatureFlags {
+            expand_tilde: true,
+            null_stdin: true,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_as_true() -> bool {
+    true
+}
+
+#[test]
+fn feature_flags_test() {
+    let features: FeatureFlags =
+        serde_json::from_str("{\"expand_tilde\": false, \"colour\": true}").unwrap();
+
+    assert!(!features.expand_tilde);
+    assert!(features.null_stdin);
+    assert_eq!(features.unknown.keys().collect::<Vec<_>>(), vec!["colour"]);
+
+    let features: FeatureFlags = serde_json::from_str("{}").unwrap();
+    assert_eq!(features, FeatureFlags::default());
+}
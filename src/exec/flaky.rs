@@ -0,0 +1,277 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::{print_block, ListFormat, NansiFile, OutputStream};
+
+/// Item of a report as read back by `nansi flaky`; `attempts` is missing from
+/// reports of versions which did not record it, counting as a single attempt
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct ReportEntry {
+    index: usize,
+
+    #[serde(default)]
+    label: String,
+
+    status: String,
+
+    #[serde(default = "default_as_one")]
+    attempts: u32,
+}
+
+/// Report of a run (`--report`) as read back by `nansi flaky`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct RunHistory {
+    items: Vec<ReportEntry>,
+}
+
+/// Outcome of an item over the runs of a set of reports
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FlakyItem {
+    /// Label of the item, `#N` if it has none
+    pub item: String,
+
+    /// Number of runs in which the item ran (skipped or not run excluded)
+    pub runs: usize,
+
+    pub failures: usize,
+
+    /// Number of runs in which the item needed more than one attempt
+    pub retried: usize,
+
+    /// Share of the runs after the first whose outcome differs from the run
+    /// before, from 0 to 1
+    pub flake_rate: f64,
+
+    pub median_attempts: f64,
+}
+
+impl FlakyItem {
+    /// Returns `true` if the item needed retries or alternated between success
+    /// and failure.
+    pub fn is_flaky(&self) -> bool {
+        self.retried > 0 || self.flake_rate > 0.0
+    }
+}
+
+/// Prints the items of `nansi_file` which were flaky over the runs of the
+/// reports at `report_paths`, oldest first (`nansi flaky`).
+pub fn flaky(
+    nansi_file: &NansiFile,
+    report_paths: &[String],
+    format: ListFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut history: Vec<RunHistory> = Vec::new();
+    for path in report_paths {
+        history.push(load_history(path)?);
+    }
+
+    let items: Vec<FlakyItem> = get_flaky_items(nansi_file, &history)
+        .into_iter()
+        .filter(|item| item.is_flaky())
+        .collect();
+
+    let lines = match format {
+        ListFormat::Json => vec![serde_json::to_string_pretty(&items)?],
+        ListFormat::Text if items.is_empty() => {
+            vec![format!("No flaky items in {} run(s).", history.len())]
+        }
+        ListFormat::Text => get_flaky_table(&items),
+    };
+    print_block(OutputStream::Stdout, &lines)?;
+
+    Ok(())
+}
+
+fn load_history(path: &str) -> Result<RunHistory, io::Error> {
+    let report_str = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path, e))),
+    };
+
+    serde_json::from_str(&report_str)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+/// Returns the outcome over `history` of each item of `nansi_file`, labeled
+/// items once per label, in the order of the `exec_list`.
+fn get_flaky_items(nansi_file: &NansiFile, history: &[RunHistory]) -> Vec<FlakyItem> {
+    let mut ids: Vec<String> = Vec::new();
+    for (idx, exec_item) in nansi_file.exec_list.iter().enumerate() {
+        if exec_item.is_timer() {
+            continue;
+        }
+        let id = get_entry_id(&exec_item.label, idx + 1);
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    ids.into_iter()
+        .map(|id| {
+            // (succeeded, attempts) of each run the item ran in
+            let outcomes: Vec<(bool, u32)> = history
+                .iter()
+                .filter_map(|run| get_run_outcome(run, &id))
+                .collect();
+            let flips = outcomes.windows(2).filter(|w| w[0].0 != w[1].0).count();
+            let mut attempts: Vec<u32> = outcomes.iter().map(|(_, a)| *a).collect();
+            attempts.sort();
+
+            FlakyItem {
+                item: id,
+                runs: outcomes.len(),
+                failures: outcomes.iter().filter(|(ok, _)| !ok).count(),
+                retried: attempts.iter().filter(|a| **a > 1).count(),
+                flake_rate: match outcomes.len() {
+                    0 | 1 => 0.0,
+                    n => flips as f64 / (n - 1) as f64,
+                },
+                median_attempts: get_median(&attempts),
+            }
+        })
+        .collect()
+}
+
+/// Returns whether the items of the run identified by `id` all succeeded and
+/// the most attempts one of them needed, `None` if none of them ran.
+fn get_run_outcome(run: &RunHistory, id: &str) -> Option<(bool, u32)> {
+    let entries: Vec<&ReportEntry> = run
+        .items
+        .iter()
+        .filter(|entry| get_entry_id(&entry.label, entry.index) == id)
+        .filter(|entry| matches!(entry.status.as_str(), "ok" | "err" | "warn"))
+        .collect();
+    if entries.is_empty() {
+        return None;
+    }
+
+    Some((
+        entries.iter().all(|entry| entry.status == "ok"),
+        entries
+            .iter()
+            .map(|entry| entry.attempts)
+            .max()
+            .unwrap_or(1),
+    ))
+}
+
+fn get_entry_id(label: &str, index: usize) -> String {
+    if label.is_empty() {
+        format!("#{}", index)
+    } else {
+        String::from(label)
+    }
+}
+
+fn get_median(sorted: &[u32]) -> f64 {
+    match sorted.len() {
+        0 => 0.0,
+        n if n % 2 == 1 => sorted[n / 2] as f64,
+        n => (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.0,
+    }
+}
+
+/// Returns the lines of the table of flaky items, columns aligned.
+fn get_flaky_table(items: &[FlakyItem]) -> Vec<String> {
+    let mut rows: Vec<[String; 6]> = vec![[
+        String::from("ITEM"),
+        String::from("RUNS"),
+        String::from("FAILURES"),
+        String::from("RETRIED"),
+        String::from("FLAKE RATE"),
+        String::from("MEDIAN ATTEMPTS"),
+    ]];
+    rows.extend(items.iter().map(|item| {
+        [
+            item.item.clone(),
+            item.runs.to_string(),
+            item.failures.to_string(),
+            item.retried.to_string(),
+            format!("{:.0}%", item.flake_rate * 100.0),
+            item.median_attempts.to_string(),
+        ]
+    }));
+
+    let widths: Vec<usize> = (0..6)
+        .map(|col| rows.iter().map(|row| row[col].len()).max().unwrap_or(0))
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            String::from(cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+fn default_as_one() -> u32 {
+    1
+}
+
+#[test]
+fn get_flaky_items_test() {
+    let nansi_file: NansiFile = serde_json::from_str(
+        "{\"exec_list\": [{\"label\": \"fetch\", \"exec\": \"curl\"}, {\"exec\": \"make\"}, {\"label\": \"deploy\", \"exec\": \"ssh\"}]}",
+    )
+    .unwrap();
+    let run = |items: &str| -> RunHistory {
+        serde_json::from_str(&format!(
+            "{{\"nansi_file\": \"f.json\", \"items\": [{}]}}",
+            items
+        ))
+        .unwrap()
+    };
+    let history = vec![
+        run("{\"index\": 1, \"label\": \"fetch\", \"status\": \"ok\"}, {\"index\": 2, \"label\": \"\", \"status\": \"ok\"}, {\"index\": 3, \"label\": \"deploy\", \"status\": \"ok\", \"attempts\": 3}"),
+        run("{\"index\": 1, \"label\": \"fetch\", \"status\": \"err\"}, {\"index\": 2, \"label\": \"\", \"status\": \"ok\"}, {\"index\": 3, \"label\": \"deploy\", \"status\": \"skip\"}"),
+        run("{\"index\": 1, \"label\": \"fetch\", \"status\": \"ok\"}, {\"index\": 2, \"label\": \"\", \"status\": \"ok\"}, {\"index\": 3, \"label\": \"deploy\", \"status\": \"ok\"}"),
+    ];
+
+    let items = get_flaky_items(&nansi_file, &history);
+
+    assert_eq!(
+        items,
+        vec![
+            FlakyItem {
+                item: String::from("fetch"),
+                runs: 3,
+                failures: 1,
+                retried: 0,
+                flake_rate: 1.0,
+                median_attempts: 1.0,
+            },
+            FlakyItem {
+                item: String::from("#2"),
+                runs: 3,
+                failures: 0,
+                retried: 0,
+                flake_rate: 0.0,
+                median_attempts: 1.0,
+            },
+            FlakyItem {
+                item: String::from("deploy"),
+                runs: 2,
+                failures: 0,
+                retried: 1,
+                flake_rate: 0.0,
+                median_attempts: 2.0,
+            },
+        ]
+    );
+    assert!(items[0].is_flaky() && !items[1].is_flaky() && items[2].is_flaky());
+
+    assert_eq!(
+        get_flaky_table(&items[..1]),
+        vec![
+            "ITEM   RUNS  FAILURES  RETRIED  FLAKE RATE  MEDIAN ATTEMPTS",
+            "fetch  3     1         0        100%        1",
+        ]
+    );
+}
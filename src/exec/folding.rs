@@ -0,0 +1,134 @@
+use std::env;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Markers wrapping the output of items so CI logs render it as collapsible
+/// sections
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CiFolding {
+    #[default]
+    None,
+
+    /// `::group::` / `::endgroup::` of GitHub Actions
+    Github,
+
+    /// `section_start` / `section_end` of GitLab CI
+    Gitlab,
+}
+
+impl CiFolding {
+    /// Returns the flavor of the CI system `nansi` runs in, based on the
+    /// variables GitHub Actions and GitLab CI set.
+    pub fn detect() -> CiFolding {
+        if env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true") {
+            CiFolding::Github
+        } else if env::var_os("GITLAB_CI").is_some() {
+            CiFolding::Gitlab
+        } else {
+            CiFolding::None
+        }
+    }
+
+    /// Returns `output` wrapped in the start and end markers of a section named
+    /// by `title` (e.g. `[2][build]`, unique within the run).
+    pub fn wrap(&self, title: &str, output: String) -> Vec<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        match self.get_markers(title, timestamp) {
+            Some((start, end)) => vec![start, output, end],
+            None => vec![output],
+        }
+    }
+
+    /// Returns the start and end markers of a section, `None` without folding.
+    fn get_markers(&self, title: &str, timestamp: u64) -> Option<(String, String)> {
+        match self {
+            CiFolding::None => None,
+            CiFolding::Github => {
+                Some((format!("::group::{}", title), String::from("::endgroup::")))
+            }
+            CiFolding::Gitlab => {
+                let name = get_section_name(title);
+                Some((
+                    format!(
+                        "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+                        timestamp, name, title
+                    ),
+                    format!("\x1b[0Ksection_end:{}:{}\r\x1b[0K", timestamp, name),
+                ))
+            }
+        }
+    }
+}
+
+impl FromStr for CiFolding {
+    type Err = String;
+
+    /// Parses `github`, `gitlab`, `none` or `auto` (see `detect`).
+    fn from_str(s: &str) -> Result<CiFolding, String> {
+        match s {
+            "auto" => Ok(CiFolding::detect()),
+            "github" => Ok(CiFolding::Github),
+            "gitlab" => Ok(CiFolding::Gitlab),
+            "none" => Ok(CiFolding::None),
+            _ => Err(format!(
+                "unknown CI folding: {} (expected auto, github, gitlab or none)",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns a GitLab section name (letters, digits, `_`, `.` and `-`) derived
+/// from the title, e.g. `nansi_2_run_tests` for `[2][run tests]`.
+fn get_section_name(title: &str) -> String {
+    let mut name = String::from("nansi");
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+
+    String::from(name.trim_end_matches('_'))
+}
+
+#[test]
+fn get_markers_test() {
+    assert_eq!(CiFolding::None.get_markers("[1][build]", 1700000000), None);
+    assert_eq!(
+        CiFolding::Github.get_markers("[1][build]", 1700000000),
+        Some((
+            String::from("::group::[1][build]"),
+            String::from("::endgroup::")
+        ))
+    );
+    assert_eq!(
+        CiFolding::Gitlab.get_markers("[2][run tests]", 1700000000),
+        Some((
+            String::from(
+                "\x1b[0Ksection_start:1700000000:nansi_2_run_tests[collapsed=true]\r\x1b[0K[2][run tests]"
+            ),
+            String::from("\x1b[0Ksection_end:1700000000:nansi_2_run_tests\r\x1b[0K")
+        ))
+    );
+}
+
+#[test]
+fn wrap_test() {
+    assert_eq!(
+        CiFolding::None.wrap("[1]", String::from("out\n")),
+        vec!["out\n"]
+    );
+    assert_eq!(
+        CiFolding::Github.wrap("[1]", String::from("out\n")),
+        vec!["::group::[1]", "out\n", "::endgroup::"]
+    );
+}
@@ -0,0 +1,458 @@
+use std::fmt::Write as _;
+
+/// Field of a `NansiFile` or of its items, as listed by `nansi help-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldDoc {
+    pub name: &'static str,
+
+    /// Type of the value as written in the file
+    pub kind: &'static str,
+
+    /// Value used when the field is omitted, `required` if it cannot be
+    pub default: &'static str,
+
+    /// What the field does, in terms of the file and the command line
+    pub description: &'static str,
+}
+
+/// Top-level fields of a `NansiFile`, in declaration order; checked against
+/// the struct by `format_help_sync_test`
+pub const NANSI_FILE_FIELDS: &[FieldDoc] = &[
+    FieldDoc {
+        name: "exec_list",
+        kind: "array of items",
+        default: "required",
+        description: "List of `exec` items",
+    },
+    FieldDoc {
+        name: "var_precedence",
+        kind: "array of strings",
+        default: "[]",
+        description: "Order in which `{}` tags are resolved from variable sources (`cli`, `registers`, `vars`, `env`); omitted sources follow in default order",
+    },
+    FieldDoc {
+        name: "vars",
+        kind: "object of strings",
+        default: "{}",
+        description: "Variables resolving `{}` tags; their values may refer to other variables and to the environment",
+    },
+    FieldDoc {
+        name: "max_failures",
+        kind: "integer",
+        default: "none",
+        description: "Number of failed items after which the remaining items are skipped",
+    },
+    FieldDoc {
+        name: "fail_fast",
+        kind: "bool",
+        default: "false",
+        description: "Skip the remaining items once an item failed and make the run fail, like `--fail-fast`",
+    },
+    FieldDoc {
+        name: "features",
+        kind: "object",
+        default: "{}",
+        description: "Toggles of built-in behaviors: `expand_tilde` (a leading `~` in paths, `exec` and `args` is the home directory) and `null_stdin` (commands read from the null device instead of the input of `nansi`), both `true` by default",
+    },
+    FieldDoc {
+        name: "dedupe_identical",
+        kind: "bool",
+        default: "false",
+        description: "Skip items which would run the same command as an earlier item once their tags are expanded (same `exec`, `args`, `cwd`, `env` and input, whatever their label and prerequisites)",
+    },
+    FieldDoc {
+        name: "keep_tmp_on_failure",
+        kind: "bool",
+        default: "false",
+        description: "Keep the temporary directory of the run (`{nansi.tmp_dir}`) if an item failed",
+    },
+    FieldDoc {
+        name: "env_passthrough",
+        kind: "array of strings",
+        default: "none",
+        description: "Names of the only environment variables passed to the commands of all items (all variables are passed if unset)",
+    },
+    FieldDoc {
+        name: "require_env",
+        kind: "array of strings or objects",
+        default: "[]",
+        description: "Variables which must be set before any item runs, optionally with a condition on their value (`{\"name\", \"equals\"|\"not_equals\"}`)",
+    },
+    FieldDoc {
+        name: "forbid_env",
+        kind: "array of strings or objects",
+        default: "[]",
+        description: "Variables which must not be set before any item runs, or with a condition, whose value must meet it if they are",
+    },
+    FieldDoc {
+        name: "redact",
+        kind: "array of strings",
+        default: "[]",
+        description: "Regular expressions whose matches are replaced with `***` in the captured output of all items",
+    },
+    FieldDoc {
+        name: "elevation_command",
+        kind: "string",
+        default: "none",
+        description: "Command running the items marked `elevate`, `sudo` if not set",
+    },
+    FieldDoc {
+        name: "label_state",
+        kind: "string",
+        default: "\"sticky_success\"",
+        description: "How items sharing a label decide whether the label is met as a prerequisite: once one of them succeeded (`sticky_success`), if the last one to run succeeded (`last_result`), or if one succeeded and none failed (`all_must_succeed`)",
+    },
+    FieldDoc {
+        name: "label_matching",
+        kind: "string",
+        default: "\"exact\"",
+        description: "Whether prerequisites refer to labels by their exact spelling (`exact`) or whatever their case (`case_insensitive`)",
+    },
+];
+
+/// Fields of the items of `exec_list`, in declaration order; checked against
+/// `ExecItem` by `format_help_sync_test`
+pub const EXEC_ITEM_FIELDS: &[FieldDoc] = &[
+    FieldDoc {
+        name: "label",
+        kind: "string",
+        default: "\"\"",
+        description: "Name other items refer to in `prerequisites`; may be shared by several items",
+    },
+    FieldDoc {
+        name: "exec",
+        kind: "string",
+        default: "\"\"",
+        description: "Command to run, looked up on `PATH` unless it is a path",
+    },
+    FieldDoc {
+        name: "args",
+        kind: "array of strings",
+        default: "[]",
+        description: "Arguments of the command; `{}` tags are expanded",
+    },
+    FieldDoc {
+        name: "flags",
+        kind: "object",
+        default: "{}",
+        description: "Flags appended after `args` in sorted order: `true` passes the bare flag, `false` omits it, a list repeats the flag for each value",
+    },
+    FieldDoc {
+        name: "assert",
+        kind: "object",
+        default: "none",
+        description: "Checks evaluated natively instead of running `exec`",
+    },
+    FieldDoc {
+        name: "timer_start",
+        kind: "string",
+        default: "none",
+        description: "Starts the timer of the given name; items with `timer_start` or `timer_stop` are markers and execute nothing",
+    },
+    FieldDoc {
+        name: "timer_stop",
+        kind: "string",
+        default: "none",
+        description: "Stops the timer of the given name; the span is printed in the summary",
+    },
+    FieldDoc {
+        name: "print_status",
+        kind: "bool",
+        default: "true",
+        description: "Print the status line of the item; a failure is still reported by a shorter line unless `quiet` is set",
+    },
+    FieldDoc {
+        name: "print_output",
+        kind: "bool or \"stream\"",
+        default: "false",
+        description: "Print the output of the command: `true` under the status line once the item finished, `\"stream\"` line by line while it runs",
+    },
+    FieldDoc {
+        name: "print_stderr",
+        kind: "bool",
+        default: "false",
+        description: "Print `stderr` in addition to `stdout` when the item succeeds",
+    },
+    FieldDoc {
+        name: "capture",
+        kind: "string",
+        default: "\"full\"",
+        description: "How much of the output of the command is kept in memory: all of it (`full`), the last 64 KiB of each stream (`truncated`) or nothing (`none`)",
+    },
+    FieldDoc {
+        name: "cwd",
+        kind: "string",
+        default: "none",
+        description: "Working directory of the command, relative to the directory of the `NansiFile` (tags and a leading `~` are expanded); the current directory if not set",
+    },
+    FieldDoc {
+        name: "env",
+        kind: "object of strings",
+        default: "{}",
+        description: "Environment variables set for the command on top of the inherited ones (tags are expanded); empty values are set, not removed",
+    },
+    FieldDoc {
+        name: "stdin",
+        kind: "string",
+        default: "none",
+        description: "Written to the standard input of the command, which is then closed (tags are expanded); without it or `stdin_file`, the input follows the `null_stdin` feature",
+    },
+    FieldDoc {
+        name: "stdin_file",
+        kind: "string",
+        default: "none",
+        description: "File streamed to the standard input of the command, relative to the directory of the `NansiFile` (tags and a leading `~` are expanded)",
+    },
+    FieldDoc {
+        name: "merge_streams",
+        kind: "bool",
+        default: "false",
+        description: "Capture `stderr` into the same pipe as `stdout`, keeping the order in which the lines of both were written; the output is then shown as is whether the item succeeds or fails",
+    },
+    FieldDoc {
+        name: "output_stream",
+        kind: "\"stdout\" or \"stderr\"",
+        default: "none",
+        description: "Stream the output of the item is printed to, `--output-stream` if not set",
+    },
+    FieldDoc {
+        name: "prerequisites",
+        kind: "array",
+        default: "[]",
+        description: "Labels or `#N` indices of items which must have succeeded (`!label` or `label:failed`: failed, `label:skipped`: not run) for this item to run; `{\"any_of\": [...], \"min\": N}` is met by at least `min` of its references",
+    },
+    FieldDoc {
+        name: "var_precedence",
+        kind: "array of strings",
+        default: "[]",
+        description: "Overrides the file level `var_precedence` for this item",
+    },
+    FieldDoc {
+        name: "vars",
+        kind: "object of strings",
+        default: "{}",
+        description: "Variables resolving `{}` tags; their values may refer to other variables and to the environment",
+    },
+    FieldDoc {
+        name: "idempotence_key",
+        kind: "string",
+        default: "none",
+        description: "Key recorded after a successful run; while it stays unchanged, later runs skip the item as unchanged (it still satisfies prerequisites)",
+    },
+    FieldDoc {
+        name: "expect_duration_under_secs",
+        kind: "integer or duration",
+        default: "none",
+        description: "The item fails if it takes longer than this many seconds, even if the command succeeded (it is not killed); also accepts a duration with the units `ms`, `s`, `m`, `h` and `d` such as `2m30s` or `1.5h`",
+    },
+    FieldDoc {
+        name: "timeout_secs",
+        kind: "integer or duration",
+        default: "none",
+        description: "The command (and the processes it started) is killed and the item fails if it runs longer than this many seconds, or a duration such as `2m30s` or `1.5h`; unlimited if not set",
+    },
+    FieldDoc {
+        name: "ignore_errors",
+        kind: "bool",
+        default: "false",
+        description: "Failures of this item do not count toward `max_failures`",
+    },
+    FieldDoc {
+        name: "parallel",
+        kind: "bool",
+        default: "false",
+        description: "Consecutive parallel items form a batch whose commands run at the same time, up to `--jobs` of them (one at a time by default); items with `assert` or `chunk_args` still run one at a time",
+    },
+    FieldDoc {
+        name: "weight",
+        kind: "integer",
+        default: "1",
+        description: "Number of the `--jobs` slots the item takes while it runs in a batch, e.g. the number of jobs for an item which needs the machine to itself; clamped to `--jobs`, 0 counts as 1",
+    },
+    FieldDoc {
+        name: "requires_env",
+        kind: "array of strings",
+        default: "[]",
+        description: "Environment variables that must be set (and not empty unless `allow_empty` is set) before anything is run",
+    },
+    FieldDoc {
+        name: "allow_empty",
+        kind: "bool",
+        default: "false",
+        description: "Accept empty values of the variables in `requires_env`",
+    },
+    FieldDoc {
+        name: "notify_on_failure",
+        kind: "bool",
+        default: "false",
+        description: "Send a desktop notification as soon as the item fails",
+    },
+    FieldDoc {
+        name: "keep",
+        kind: "bool",
+        default: "false",
+        description: "The label is used outside of the `NansiFile` (e.g. by external tooling), so it is never reported as unused",
+    },
+    FieldDoc {
+        name: "chunk_args",
+        kind: "object",
+        default: "none",
+        description: "Runs the command once per chunk of `args` instead of once with all of them",
+    },
+    FieldDoc {
+        name: "success_message",
+        kind: "string",
+        default: "none",
+        description: "Printed after the status line when the item succeeds (tags are expanded)",
+    },
+    FieldDoc {
+        name: "failure_hint",
+        kind: "string",
+        default: "none",
+        description: "Printed after the status line when the item fails, e.g. what to check before retrying (tags are expanded)",
+    },
+    FieldDoc {
+        name: "serial",
+        kind: "bool",
+        default: "false",
+        description: "Runs alone, in its original position relative to all other items, even if `parallel` is set: the items before it finish first and those after it start once it is done",
+    },
+    FieldDoc {
+        name: "env_passthrough",
+        kind: "array of strings",
+        default: "none",
+        description: "Names of the only environment variables passed to the command, overrides the top-level `env_passthrough`",
+    },
+    FieldDoc {
+        name: "changed_when",
+        kind: "string or object",
+        default: "none",
+        description: "Decides whether the item changed anything when it succeeded: `always`, `never`, `exit_code:N`, or `{\"output_matches\": \"a|b\"}` if one of the `|`-separated strings occurs in the output (changed if unset)",
+    },
+    FieldDoc {
+        name: "allow_empty_expansion",
+        kind: "bool",
+        default: "false",
+        description: "Run even if an argument containing tags expands to an empty string or to `/` (e.g. `{TARGET_DIR}/` with `TARGET_DIR` empty), which fails the item otherwise",
+    },
+    FieldDoc {
+        name: "redact",
+        kind: "array of strings",
+        default: "[]",
+        description: "Regular expressions whose matches are replaced with `***` in the captured output, in addition to `redact` of the `NansiFile`",
+    },
+    FieldDoc {
+        name: "elevate",
+        kind: "bool",
+        default: "false",
+        description: "Run the command with elevated privileges through the elevation command (`--elevation-command`, `elevation_command` or `sudo`); it fails instead of prompting for a password unless `--interactive-elevation` is set",
+    },
+    FieldDoc {
+        name: "confirm",
+        kind: "bool",
+        default: "false",
+        description: "Ask on the terminal whether to run the item, showing its compiled command; anything but `y` or `yes` skips it, `--yes` runs it without asking",
+    },
+    FieldDoc {
+        name: "include",
+        kind: "string",
+        default: "none",
+        description: "Path of a `NansiFile` whose items replace this item when loaded, relative to the including file",
+    },
+];
+
+/// Returns the reference of the `NansiFile` format printed by `nansi
+/// help-format`.
+pub fn get_format_help() -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "NansiFile fields:");
+    write_fields(&mut out, NANSI_FILE_FIELDS);
+    let _ = writeln!(out, "\nItem fields (entries of `exec_list`):");
+    write_fields(&mut out, EXEC_ITEM_FIELDS);
+
+    out
+}
+
+fn write_fields(out: &mut String, fields: &[FieldDoc]) {
+    for field in fields {
+        let _ = writeln!(
+            out,
+            "  {}: {} (default: {})\n      {}",
+            field.name, field.kind, field.default, field.description
+        );
+    }
+}
+
+/// Returns the fields of `pub struct {name}` in `source`, flattened fields
+/// excluded.
+#[cfg(test)]
+fn get_struct_fields(source: &str, name: &str) -> Vec<String> {
+    let start = source.find(&format!("pub struct {} {{", name)).unwrap();
+    let body = &source[start..];
+    let body = &body[..body.find("\n}").unwrap()];
+
+    let mut fields = Vec::new();
+    let mut flatten = false;
+    for line in body.lines().skip(1).map(|line| line.trim()) {
+        if let Some(field) = line.strip_prefix("pub ") {
+            if !flatten {
+                fields.push(String::from(&field[..field.find(':').unwrap()]));
+            }
+            flatten = false;
+        } else if line.contains("flatten") {
+            flatten = true;
+        }
+    }
+
+    fields
+}
+
+#[test]
+fn format_help_sync_test() {
+    let source = include_str!("exec.rs");
+    let internal = ["file_path", "sha256", "stdin_item"];
+
+    for (name, table) in [
+        ("NansiFile", NANSI_FILE_FIELDS),
+        ("ExecItem", EXEC_ITEM_FIELDS),
+    ] {
+        // the descriptions are written for users, not copied from the docs
+        let fields: Vec<String> = get_struct_fields(source, name)
+            .into_iter()
+            .filter(|field| !internal.contains(&field.as_str()))
+            .collect();
+        let listed: Vec<&str> = table.iter().map(|field| field.name).collect();
+        assert_eq!(listed, fields, "fields of {} are out of date", name);
+        assert!(table.iter().all(|field| !field.description.is_empty()));
+    }
+
+    let nansi_file: super::NansiFile =
+        serde_json::from_str("{\"exec_list\": [{\"exec\": \"ls\"}]}").unwrap();
+    let serialized = serde_json::to_value(&nansi_file).unwrap();
+    let mut file_keys: Vec<&str> = serialized
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|key| key.as_str())
+        .filter(|key| !internal.contains(key))
+        .collect();
+    let mut item_keys: Vec<&str> = serialized["exec_list"][0]
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|key| key.as_str())
+        .collect();
+    let mut file_names: Vec<&str> = NANSI_FILE_FIELDS.iter().map(|field| field.name).collect();
+    let mut item_names: Vec<&str> = EXEC_ITEM_FIELDS.iter().map(|field| field.name).collect();
+    file_keys.sort();
+    item_keys.sort();
+    file_names.sort();
+    item_names.sort();
+    assert_eq!(file_names, file_keys);
+    assert_eq!(item_names, item_keys);
+
+    let help = get_format_help();
+    assert!(help.starts_with("NansiFile fields:\n  exec_list: array of items (default: required)\n      List of `exec` items\n"));
+    assert!(help.contains("\n  weight: integer (default: 1)\n"));
+}
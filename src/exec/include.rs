@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use super::{
+    is_url, parse_index_prerequisite, replace_reference_target, sha256_hex, split_reference,
+    ExecItem, ItemDisplay, NansiFile, NansiFormat, SourceHash,
+};
+
+/// Replaces the items of `nansi_file` marked `include` with the `exec_list` of
+/// the files they point at, recursively.
+///
+/// Paths are relative to the including file. `#N` prerequisites are
+/// renumbered to keep pointing at the same items; a prerequisite on an
+/// `include` item points at the last item it was replaced with (or the item
+/// before it if the included file has no items).
+///
+/// Returns the canonical path and digest of every file read, in the order they
+/// were included.
+pub fn resolve_includes(nansi_file: &mut NansiFile) -> Result<Vec<SourceHash>, io::Error> {
+    if !nansi_file
+        .exec_list
+        .iter()
+        .any(|item| item.include.is_some())
+    {
+        return Ok(Vec::new());
+    }
+    if is_url(&nansi_file.file_path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: include is not supported in a NansiFile fetched from a URL",
+                nansi_file.file_path
+            ),
+        ));
+    }
+
+    let mut chain = vec![(
+        nansi_file.file_path.clone(),
+        canonicalize(&nansi_file.file_path)?,
+    )];
+    let mut sources: Vec<SourceHash> = Vec::new();
+    let exec_list = std::mem::take(&mut nansi_file.exec_list);
+    nansi_file.exec_list =
+        splice_includes(exec_list, &nansi_file.file_path, &mut chain, &mut sources)?;
+
+    Ok(sources)
+}
+
+/// Returns `exec_list` of the file at `file_path` with its `include` items
+/// replaced; `chain` holds the files being included, from the outermost one,
+/// and the files read are added to `sources`.
+fn splice_includes(
+    exec_list: Vec<ExecItem>,
+    file_path: &str,
+    chain: &mut Vec<(String, PathBuf)>,
+    sources: &mut Vec<SourceHash>,
+) -> Result<Vec<ExecItem>, io::Error> {
+    let mut spliced: Vec<ExecItem> = Vec::new();
+    // 1-based index of each item of `exec_list` once spliced
+    let mut new_indexes: Vec<usize> = Vec::with_capacity(exec_list.len());
+    let mut is_included: Vec<bool> = Vec::new();
+
+    for (idx, exec_item) in exec_list.into_iter().enumerate() {
+        let Some(include) = &exec_item.include else {
+            spliced.push(exec_item);
+            is_included.push(false);
+            new_indexes.push(spliced.len());
+            continue;
+        };
+        if !exec_item.exec.is_empty() || exec_item.assert.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: item {} cannot have include and exec or assert",
+                    file_path,
+                    ItemDisplay::new(&exec_item, idx + 1)
+                ),
+            ));
+        }
+
+        let offset = spliced.len();
+        for mut included_item in load_included(include, file_path, chain, sources)? {
+            shift_index_prerequisites(&mut included_item, |prereq_idx| prereq_idx + offset);
+            spliced.push(included_item);
+            is_included.push(true);
+        }
+        new_indexes.push(spliced.len());
+    }
+
+    for (exec_item, included) in spliced.iter_mut().zip(is_included) {
+        if !included {
+            shift_index_prerequisites(exec_item, |prereq_idx| {
+                new_indexes
+                    .get(prereq_idx - 1)
+                    .copied()
+                    .unwrap_or(prereq_idx)
+            });
+        }
+    }
+
+    Ok(spliced)
+}
+
+/// Loads the `exec_list` of the file included as `include` by the file at
+/// `file_path`, with its own includes spliced.
+fn load_included(
+    include: &str,
+    file_path: &str,
+    chain: &mut Vec<(String, PathBuf)>,
+    sources: &mut Vec<SourceHash>,
+) -> Result<Vec<ExecItem>, io::Error> {
+    let path = match Path::new(file_path).parent() {
+        Some(dir) => dir.join(include),
+        None => PathBuf::from(include),
+    };
+    let path_str = path.to_string_lossy().to_string();
+    let canonical = canonicalize(&path_str)?;
+
+    if let Some(start) = chain.iter().position(|(_, c)| *c == canonical) {
+        let mut names: Vec<&str> = chain[start..].iter().map(|(n, _)| n.as_str()).collect();
+        names.push(&path_str);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Include cycle: {}", names.join(" -> ")),
+        ));
+    }
+
+    let file_str = match fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path_str, e))),
+    };
+    let included = match NansiFormat::Auto.resolve(&path_str) {
+        NansiFormat::Yaml => NansiFile::from_yaml(&file_str, &path_str)?,
+        _ => NansiFile::from_json(&file_str, &path_str)?,
+    };
+
+    sources.push(SourceHash {
+        path: canonical.to_string_lossy().to_string(),
+        sha256: sha256_hex(file_str.as_bytes()),
+    });
+    chain.push((path_str.clone(), canonical));
+    let exec_list = splice_includes(included.exec_list, &path_str, chain, sources)?;
+    chain.pop();
+
+    Ok(exec_list)
+}
+
+fn canonicalize(path: &str) -> Result<PathBuf, io::Error> {
+    fs::canonicalize(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+/// Renumbers the `#N` prerequisites of the item (keeping their condition, e.g.
+/// `!#N`), leaving `#0` alone.
+pub(crate) fn shift_index_prerequisites<F: Fn(usize) -> usize>(
+    exec_item: &mut ExecItem,
+    new_index: F,
+) {
+    for prereq in exec_item
+        .prerequisites
+        .iter_mut()
+        .flat_map(|p| p.refs_mut().iter_mut())
+    {
+        if let Some(prereq_idx) = parse_index_prerequisite(split_reference(prereq).0) {
+            if prereq_idx > 0 {
+                *prereq = replace_reference_target(prereq, &format!("#{}", new_index(prereq_idx)));
+            }
+        }
+    }
+}
+
+#[test]
+fn resolve_includes_test() {
+    let nansi_file = NansiFile::from("testdata/include/nansifile_linux_include.json").unwrap();
+    let items: Vec<(&str, Vec<String>)> = nansi_file
+        .exec_list
+        .iter()
+        .map(|item| {
+            let prereqs: Vec<String> = item.prerequisites.iter().map(|p| p.to_string()).collect();
+            (item.label.as_str(), prereqs)
+        })
+        .collect();
+
+    assert_eq!(
+        items,
+        vec![
+            ("start", vec![]),
+            ("packages", vec![]),
+            ("configure", vec![String::from("#2")]),
+            (
+                "deploy",
+                vec![String::from("configure"), String::from("#3")]
+            ),
+        ]
+    );
+
+    let err = NansiFile::from("testdata/include/nansifile_linux_include_cycle.json").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Include cycle: testdata/include/nansifile_linux_include_cycle.json -> \
+         testdata/include/common/cycle.json -> \
+         testdata/include/common/../nansifile_linux_include_cycle.json"
+    );
+}
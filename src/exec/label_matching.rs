@@ -0,0 +1,112 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use super::{parse_index_prerequisite, replace_reference_target, split_reference, NansiFile};
+
+/// How labels referred to by prerequisites are matched against the labels of
+/// the items
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelMatching {
+    /// A reference must spell the label exactly; references differing from a
+    /// label only by case are reported by `validate`
+    #[default]
+    Exact,
+
+    /// A reference matches a label whatever the case, e.g. `Install-Docker`
+    /// refers to `install-docker`
+    CaseInsensitive,
+}
+
+/// Returns the first of `labels` equal to `label` ignoring case.
+pub(crate) fn find_label_ignoring_case<'a, I>(label: &str, labels: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let label = label.to_lowercase();
+    labels
+        .into_iter()
+        .find(|candidate| candidate.to_lowercase() == label)
+}
+
+/// Returns the file as matched by its `label_matching`: with
+/// `LabelMatching::CaseInsensitive`, labels and label references are spelled
+/// like the first label they match ignoring case, so the rest of the run can
+/// compare them exactly. The file is returned as is otherwise.
+pub fn apply_label_matching(nansi_file: &NansiFile) -> Cow<'_, NansiFile> {
+    if nansi_file.label_matching == LabelMatching::Exact {
+        return Cow::Borrowed(nansi_file);
+    }
+
+    let mut labels: Vec<String> = Vec::new();
+    for exec_item in &nansi_file.exec_list {
+        if !exec_item.label.is_empty()
+            && find_label_ignoring_case(&exec_item.label, labels.iter().map(|l| l.as_str()))
+                .is_none()
+        {
+            labels.push(exec_item.label.clone());
+        }
+    }
+    let get_spelling = |label: &str| -> Option<String> {
+        find_label_ignoring_case(label, labels.iter().map(|l| l.as_str())).map(String::from)
+    };
+
+    let mut matched = nansi_file.clone();
+    for exec_item in &mut matched.exec_list {
+        if let Some(label) = get_spelling(&exec_item.label) {
+            exec_item.label = label;
+        }
+        for reference in exec_item
+            .prerequisites
+            .iter_mut()
+            .flat_map(|p| p.refs_mut().iter_mut())
+        {
+            let (target, _) = split_reference(reference);
+            if parse_index_prerequisite(target).is_some() {
+                continue;
+            }
+            if let Some(label) = get_spelling(target).filter(|label| label != target) {
+                *reference = replace_reference_target(reference, &label);
+            }
+        }
+    }
+
+    Cow::Owned(matched)
+}
+
+#[test]
+fn apply_label_matching_test() {
+    let content = "{\"exec_list\": [{\"label\": \"install-docker\", \"exec\": \"apt\"}, {\"label\": \"Install-Docker\", \"exec\": \"apt\"}, {\"exec\": \"docker\", \"prerequisites\": [\"Install-Docker\", \"INSTALL-DOCKER:failed\", \"#1\", \"unknown\", {\"any_of\": [\"install-DOCKER\"]}]}]}";
+    let nansi_file: NansiFile = serde_json::from_str(content).unwrap();
+    assert!(matches!(
+        apply_label_matching(&nansi_file),
+        Cow::Borrowed(_)
+    ));
+
+    let mut nansi_file = nansi_file;
+    nansi_file.label_matching = LabelMatching::CaseInsensitive;
+    let matched = apply_label_matching(&nansi_file);
+
+    let labels: Vec<&str> = matched
+        .exec_list
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect();
+    assert_eq!(labels, vec!["install-docker", "install-docker", ""]);
+    let prereqs: Vec<String> = matched.exec_list[2]
+        .prerequisites
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    assert_eq!(
+        prereqs,
+        vec![
+            "install-docker",
+            "install-docker:failed",
+            "#1",
+            "unknown",
+            "any_of [install-docker] (min 1)"
+        ]
+    );
+}
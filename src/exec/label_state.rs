@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How the results of the items sharing a label decide whether the label is
+/// met as a prerequisite; items which did not run (skipped) leave the state of
+/// their label unchanged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelState {
+    /// Met once an item with the label succeeded, whatever happens after
+    #[default]
+    StickySuccess,
+
+    /// Met if the item with the label which ran last succeeded
+    LastResult,
+
+    /// Met if an item with the label succeeded and none failed so far
+    AllMustSucceed,
+}
+
+/// Results of the items with a label which ran so far
+#[derive(Debug, Clone, Copy, Default)]
+struct LabelResult {
+    any_success: bool,
+    any_failure: bool,
+    last_success: bool,
+}
+
+/// Tracks which labels are met as prerequisites according to a `LabelState`
+#[derive(Debug)]
+pub(crate) struct LabelResults<'a> {
+    policy: LabelState,
+    results: HashMap<&'a str, LabelResult>,
+}
+
+impl<'a> LabelResults<'a> {
+    pub(crate) fn new(policy: LabelState) -> LabelResults<'a> {
+        LabelResults {
+            policy,
+            results: HashMap::new(),
+        }
+    }
+
+    /// Records that an item with the label succeeded or failed; empty labels
+    /// are ignored.
+    pub(crate) fn record(&mut self, label: &'a str, succeeded: bool) {
+        if label.is_empty() {
+            return;
+        }
+
+        let result = self.results.entry(label).or_default();
+        result.any_success |= succeeded;
+        result.any_failure |= !succeeded;
+        result.last_success = succeeded;
+    }
+
+    /// Returns whether an item with the label succeeded or failed so far.
+    pub(crate) fn has_result(&self, label: &str) -> bool {
+        self.results.contains_key(label)
+    }
+
+    /// Returns whether the label is met as a prerequisite.
+    pub(crate) fn is_met(&self, label: &str) -> bool {
+        let Some(result) = self.results.get(label) else {
+            return false;
+        };
+
+        match self.policy {
+            LabelState::StickySuccess => result.any_success,
+            LabelState::LastResult => result.last_success,
+            LabelState::AllMustSucceed => result.any_success && !result.any_failure,
+        }
+    }
+}
+
+#[test]
+fn label_results_test() {
+    let run = |policy: LabelState, results: &[bool]| {
+        let mut label_results = LabelResults::new(policy);
+        for succeeded in results {
+            label_results.record("setup", *succeeded);
+        }
+        label_results.is_met("setup")
+    };
+
+    for policy in [
+        LabelState::StickySuccess,
+        LabelState::LastResult,
+        LabelState::AllMustSucceed,
+    ] {
+        assert!(!run(policy, &[]));
+        assert!(run(policy, &[true]));
+        assert!(!run(policy, &[false]));
+    }
+
+    assert!(run(LabelState::StickySuccess, &[true, false]));
+    assert!(!run(LabelState::LastResult, &[true, false]));
+    assert!(!run(LabelState::AllMustSucceed, &[true, false]));
+
+    assert!(run(LabelState::StickySuccess, &[true, false, true]));
+    assert!(run(LabelState::LastResult, &[true, false, true]));
+    assert!(!run(LabelState::AllMustSucceed, &[true, false, true]));
+
+    let mut label_results = LabelResults::new(LabelState::StickySuccess);
+    label_results.record("", true);
+    assert!(!label_results.is_met(""));
+}
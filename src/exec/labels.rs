@@ -0,0 +1,251 @@
+use std::error::Error;
+use std::fs;
+
+use super::{replace_reference_target, split_reference, NansiFile, NansiFormat};
+
+/// Step of the path to a value in a json document
+#[derive(Debug, Clone, PartialEq)]
+enum PathStep {
+    Key(String),
+    Index,
+}
+
+/// Container the scanner is inside of
+struct Frame {
+    is_object: bool,
+
+    /// Key of the value being read in an object
+    key: Option<String>,
+}
+
+/// Renames the label `old` to `new` in the `NansiFile` at `file_path`,
+/// updating every prerequisite referring to it. The file is only written with
+/// `write`, otherwise the changes are just reported.
+pub fn rename_label(
+    file_path: &str,
+    old: &str,
+    new: &str,
+    write: bool,
+) -> Result<(), Box<dyn Error>> {
+    let content = match fs::read_to_string(file_path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("{}: {}", file_path, e).into()),
+    };
+    if NansiFormat::Auto.resolve(file_path) == NansiFormat::Yaml {
+        return Err(format!(
+            "{}: renaming labels is only supported in json NansiFiles",
+            file_path
+        )
+        .into());
+    }
+    let nansi_file = NansiFile::from_json(&content, file_path)?;
+
+    if new.is_empty() {
+        return Err("The new label must not be empty".into());
+    }
+    if !nansi_file.exec_list.iter().any(|item| item.label == old) {
+        return Err(format!("{}: no item has the label {}", file_path, old).into());
+    }
+    if nansi_file.exec_list.iter().any(|item| item.label == new) {
+        return Err(format!("{}: the label {} already exists", file_path, new).into());
+    }
+
+    let (renamed, labels, references) = get_renamed_content(&content, old, new)?;
+
+    if write {
+        if let Err(e) = fs::write(file_path, renamed) {
+            return Err(format!("{}: {}", file_path, e).into());
+        }
+        println!(
+            "Renamed {} label(s) and {} reference(s) from {} to {}.",
+            labels, references, old, new
+        );
+    } else {
+        println!(
+            "Would rename {} label(s) and {} reference(s) from {} to {} (pass --write to save).",
+            labels, references, old, new
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns `content` with the labels `old` and the prerequisites referring to
+/// them replaced by `new`, and the number of labels and references replaced.
+///
+/// Only the affected string literals change; formatting, key order and
+/// unknown fields of the document are preserved.
+fn get_renamed_content(
+    content: &str,
+    old: &str,
+    new: &str,
+) -> Result<(String, usize, usize), String> {
+    let label_path = [
+        PathStep::Key(String::from("exec_list")),
+        PathStep::Index,
+        PathStep::Key(String::from("label")),
+    ];
+    let prerequisite_path = [
+        PathStep::Key(String::from("exec_list")),
+        PathStep::Index,
+        PathStep::Key(String::from("prerequisites")),
+        PathStep::Index,
+    ];
+    let any_of_path = [
+        PathStep::Key(String::from("exec_list")),
+        PathStep::Index,
+        PathStep::Key(String::from("prerequisites")),
+        PathStep::Index,
+        PathStep::Key(String::from("any_of")),
+        PathStep::Index,
+    ];
+    let replacement = match serde_json::to_string(new) {
+        Ok(v) => v,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut renamed = String::with_capacity(content.len());
+    let mut labels = 0;
+    let mut references = 0;
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut chars = content.char_indices();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut end = start + 1;
+                let mut escaped = false;
+                for (idx, c) in chars.by_ref() {
+                    end = idx + c.len_utf8();
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+
+                let literal = &content[start..end];
+                let value: String = match serde_json::from_str(literal) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                let is_key = match stack.last() {
+                    Some(frame) => frame.is_object && frame.key.is_none(),
+                    None => false,
+                };
+                if is_key {
+                    if let Some(frame) = stack.last_mut() {
+                        frame.key = Some(value);
+                    }
+                    renamed.push_str(literal);
+                    continue;
+                }
+
+                let path = get_path(&stack);
+                if value == old && path == label_path {
+                    labels += 1;
+                    renamed.push_str(&replacement);
+                } else if split_reference(&value).0 == old
+                    && (path == prerequisite_path || path == any_of_path)
+                {
+                    references += 1;
+                    match serde_json::to_string(&replace_reference_target(&value, new)) {
+                        Ok(v) => renamed.push_str(&v),
+                        Err(e) => return Err(e.to_string()),
+                    }
+                } else {
+                    renamed.push_str(literal);
+                }
+                continue;
+            }
+            '{' | '[' => stack.push(Frame {
+                is_object: c == '{',
+                key: None,
+            }),
+            '}' | ']' => {
+                stack.pop();
+            }
+            ',' => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.key = None;
+                }
+            }
+            _ => {}
+        }
+        renamed.push(c);
+    }
+
+    Ok((renamed, labels, references))
+}
+
+/// Returns the path to the value the scanner is at.
+fn get_path(stack: &[Frame]) -> Vec<PathStep> {
+    stack
+        .iter()
+        .map(|frame| match (&frame.key, frame.is_object) {
+            (Some(key), true) => PathStep::Key(key.clone()),
+            _ => PathStep::Index,
+        })
+        .collect()
+}
+
+#[test]
+fn get_renamed_content_test() {
+    let content = r##"{
+  "exec_list": [
+    {"label": "build", "exec": "make", "args": ["build"]},
+    {
+      "label": "test",
+      "exec": "make",
+      "args": ["test", "{build}"],
+      "prerequisites": ["build", "#1"],
+      "custom": {"label": "build"}
+    },
+    {"exec": "echo", "prerequisites": ["test", "build"], "success_message": "build"},
+    {"exec": "echo", "prerequisites": [{"any_of": ["build", "test"], "min": 1}]}
+  ]
+}
+"##;
+
+    let (renamed, labels, references) = get_renamed_content(content, "build", "compile").unwrap();
+
+    assert_eq!(labels, 1);
+    assert_eq!(references, 3);
+    assert_eq!(
+        renamed,
+        content
+            .replacen("\"label\": \"build\"", "\"label\": \"compile\"", 1)
+            .replace("[\"build\", \"#1\"]", "[\"compile\", \"#1\"]")
+            .replace("[\"test\", \"build\"]", "[\"test\", \"compile\"]")
+            .replace("[\"build\", \"test\"]", "[\"compile\", \"test\"]")
+    );
+}
+
+#[test]
+fn get_renamed_content_escapes_test() {
+    let content = "{\"exec_list\": [{\"label\": \"a\\\"b\", \"exec\": \"ls\"}, {\"prerequisites\": [\"a\\\"b\"]}]}";
+
+    let (renamed, labels, references) = get_renamed_content(content, "a\"b", "c\\d").unwrap();
+
+    assert_eq!((labels, references), (1, 1));
+    assert_eq!(
+        renamed,
+        "{\"exec_list\": [{\"label\": \"c\\\\d\", \"exec\": \"ls\"}, {\"prerequisites\": [\"c\\\\d\"]}]}"
+    );
+}
+
+#[test]
+fn get_renamed_content_conditions_test() {
+    let content = r#"{"exec_list": [{"label": "build", "exec": "make"}, {"exec": "echo", "prerequisites": ["!build", "build:skipped", "builder"]}]}"#;
+
+    let (renamed, labels, references) = get_renamed_content(content, "build", "!make").unwrap();
+
+    assert_eq!((labels, references), (1, 2));
+    assert_eq!(
+        renamed,
+        r#"{"exec_list": [{"label": "!make", "exec": "make"}, {"exec": "echo", "prerequisites": ["!\\!make", "\\!make:skipped", "builder"]}]}"#
+    );
+}
@@ -1,3 +1,83 @@
+pub mod answers;
+pub mod assertion;
+pub mod cancel;
+pub mod color;
+pub mod compiled;
+pub mod context;
+pub mod display;
+pub mod elevate;
+pub mod env_constraint;
+pub mod events;
 pub mod exec;
+pub mod features;
+pub mod flaky;
+pub mod folding;
+pub mod format_help;
+pub mod include;
+pub mod label_matching;
+pub mod label_state;
+pub mod labels;
+pub mod notify;
+pub mod overrides;
+pub mod path;
+pub mod picker;
+pub mod prerequisite;
+pub mod progress;
+pub mod redact;
+pub mod report;
+pub mod run_log;
+pub mod rusage;
+pub mod selection;
+pub mod sha256;
+pub mod shuffle;
+pub mod skip_cause;
+pub mod source;
+pub mod state;
+pub mod stdin_each;
+pub mod trace;
+pub mod units;
+pub mod validate;
+pub mod vars;
+pub mod yaml;
 
+pub use answers::*;
+pub use assertion::*;
+pub use cancel::*;
+pub use color::*;
+pub use compiled::*;
+pub use context::*;
+pub use display::*;
+pub use elevate::*;
+pub use env_constraint::*;
+pub use events::*;
 pub use exec::*;
+pub use features::*;
+pub use flaky::*;
+pub use folding::*;
+pub use format_help::*;
+pub use include::*;
+pub use label_matching::*;
+pub use label_state::*;
+pub use labels::*;
+pub use notify::*;
+pub use overrides::*;
+pub use path::*;
+pub use picker::*;
+pub use prerequisite::*;
+pub use progress::*;
+pub use redact::*;
+pub use report::*;
+pub use run_log::*;
+pub use rusage::*;
+pub use selection::*;
+pub use sha256::*;
+pub use shuffle::*;
+pub use skip_cause::*;
+pub use source::*;
+pub use state::*;
+pub use stdin_each::*;
+pub use trace::*;
+pub use units::*;
+pub use validate::*;
+pub use vars::*;
+pub use yaml::*;
@@ -0,0 +1,116 @@
+use std::io;
+use std::thread::{self, JoinHandle};
+
+/// Urgency of a desktop notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Normal,
+
+    /// Used for failures; shown with a different icon or kept visible longer
+    /// where the platform supports it
+    Critical,
+}
+
+/// Desktop notification sent by `--notify-desktop` and `notify_on_failure`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub summary: String,
+
+    pub body: String,
+
+    pub urgency: Urgency,
+}
+
+impl Notification {
+    /// Returns the notification sent when a run completes.
+    pub fn run_finished(
+        file_path: &str,
+        ok: usize,
+        err: usize,
+        skip: usize,
+        duration_ms: u128,
+    ) -> Notification {
+        Notification {
+            summary: format!(
+                "nansi {}: {}",
+                if err > 0 { "failed" } else { "finished" },
+                file_path
+            ),
+            body: format!(
+                "{} ok, {} failed, {} skipped in {:.1} s",
+                ok,
+                err,
+                skip,
+                duration_ms as f64 / 1000.0
+            ),
+            urgency: if err > 0 {
+                Urgency::Critical
+            } else {
+                Urgency::Normal
+            },
+        }
+    }
+
+    /// Returns the notification sent when an item with `notify_on_failure` fails.
+    pub fn item_failed(file_path: &str, item_str: &str) -> Notification {
+        Notification {
+            summary: format!("nansi item failed: {}", item_str),
+            body: String::from(file_path),
+            urgency: Urgency::Critical,
+        }
+    }
+
+    /// Shows the notification with the notification service of the platform
+    /// (D-Bus on Linux and BSD, the Notification Center on macOS, toasts on
+    /// Windows).
+    #[cfg(feature = "notifications")]
+    pub fn send(&self) -> Result<(), io::Error> {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .appname("nansi")
+            .summary(&self.summary)
+            .body(&self.body);
+        #[cfg(all(unix, not(target_os = "macos")))]
+        notification.urgency(match self.urgency {
+            Urgency::Normal => notify_rust::Urgency::Normal,
+            Urgency::Critical => notify_rust::Urgency::Critical,
+        });
+        #[cfg(target_os = "macos")]
+        if self.urgency == Urgency::Critical {
+            notification.sound_name("Basso");
+        }
+
+        match notification.show() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::other(e.to_string())),
+        }
+    }
+
+    #[cfg(not(feature = "notifications"))]
+    pub fn send(&self) -> Result<(), io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "nansi was built without the notifications feature",
+        ))
+    }
+
+    /// Sends the notification on a new thread, so that a slow notification
+    /// service does not hold up the run; joining the thread returns whether
+    /// it was sent.
+    pub fn send_in_background(self) -> JoinHandle<Result<(), io::Error>> {
+        thread::spawn(move || self.send())
+    }
+}
+
+#[test]
+fn run_finished_notification_test() {
+    let notification = Notification::run_finished("setup.json", 3, 1, 2, 12345);
+
+    assert_eq!(notification.summary, "nansi failed: setup.json");
+    assert_eq!(notification.body, "3 ok, 1 failed, 2 skipped in 12.3 s");
+    assert_eq!(notification.urgency, Urgency::Critical);
+
+    let notification = Notification::run_finished("setup.json", 3, 0, 0, 500);
+    assert_eq!(notification.summary, "nansi finished: setup.json");
+    assert_eq!(notification.urgency, Urgency::Normal);
+}
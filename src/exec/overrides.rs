@@ -0,0 +1,168 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use super::NansiFile;
+
+/// Arguments added from the command line (`--prepend-args`, `--append-args`)
+/// to the command of the items with a given label
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ArgsOverride {
+    /// Inserted before the arguments of the item
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prepend: Vec<String>,
+
+    /// Added after the arguments (and flags) of the item
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub append: Vec<String>,
+}
+
+impl ArgsOverride {
+    /// Returns the override as shown in messages, e.g.
+    /// `prepended -n, appended --verbose --timings`.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        if !self.prepend.is_empty() {
+            parts.push(format!("prepended {}", self.prepend.join(" ")));
+        }
+        if !self.append.is_empty() {
+            parts.push(format!("appended {}", self.append.join(" ")));
+        }
+
+        parts.join(", ")
+    }
+}
+
+/// Returns `nansi_file` with the arguments of `overrides` (by label) added to
+/// its items. The `flags` of an overridden item are turned into `args` so the
+/// appended arguments come last. Labels no item has are an error.
+pub(crate) fn apply_args_overrides<'a>(
+    nansi_file: &'a NansiFile,
+    overrides: &BTreeMap<String, ArgsOverride>,
+) -> Result<Cow<'a, NansiFile>, Box<dyn Error>> {
+    if overrides.is_empty() {
+        return Ok(Cow::Borrowed(nansi_file));
+    }
+
+    let labels: Vec<&str> = nansi_file
+        .exec_list
+        .iter()
+        .map(|item| item.label.as_str())
+        .filter(|label| !label.is_empty())
+        .collect();
+    for label in overrides.keys() {
+        if !labels.contains(&label.as_str()) {
+            let msg = match get_closest_label(label, &labels) {
+                Some(closest) => format!(
+                    "Cannot override the arguments of {}: no item has this label, did you mean {}?",
+                    label, closest
+                ),
+                None => format!(
+                    "Cannot override the arguments of {}: no item has this label",
+                    label
+                ),
+            };
+            return Err(msg.into());
+        }
+    }
+
+    let mut overridden = nansi_file.clone();
+    for exec_item in &mut overridden.exec_list {
+        if let Some(args_override) = overrides.get(&exec_item.label) {
+            let mut args = args_override.prepend.clone();
+            args.extend(exec_item.get_args());
+            args.extend(args_override.append.iter().cloned());
+            exec_item.args = args;
+            exec_item.flags = BTreeMap::new();
+        }
+    }
+
+    Ok(Cow::Owned(overridden))
+}
+
+/// Returns the label closest to `label` if it is likely a misspelling of it.
+pub(crate) fn get_closest_label<'a>(label: &str, labels: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (label.chars().count() / 3).max(2);
+
+    labels
+        .iter()
+        .map(|candidate| (get_edit_distance(label, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[test]
+fn apply_args_overrides_test() {
+    let nansi_file = NansiFile::from_json(
+        "{\"exec_list\": [{\"label\": \"build\", \"exec\": \"cargo\", \"args\": [\"build\"], \"flags\": {\"--jobs\": 2}}, {\"label\": \"test\", \"exec\": \"cargo\", \"args\": [\"test\"]}]}",
+        "nansi.json",
+    )
+    .unwrap();
+
+    let mut overrides: BTreeMap<String, ArgsOverride> = BTreeMap::new();
+    overrides.insert(
+        String::from("build"),
+        ArgsOverride {
+            prepend: vec![String::from("+nightly")],
+            append: vec![String::from("--verbose"), String::from("--timings")],
+        },
+    );
+
+    let overridden = apply_args_overrides(&nansi_file, &overrides).unwrap();
+    assert_eq!(
+        overridden.exec_list[0].get_args(),
+        vec!["+nightly", "build", "--jobs", "2", "--verbose", "--timings"]
+    );
+    assert_eq!(overridden.exec_list[1], nansi_file.exec_list[1]);
+    assert_eq!(
+        overrides["build"].describe(),
+        "prepended +nightly, appended --verbose --timings"
+    );
+
+    overrides.insert(String::from("tset"), ArgsOverride::default());
+    assert_eq!(
+        apply_args_overrides(&nansi_file, &overrides)
+            .unwrap_err()
+            .to_string(),
+        "Cannot override the arguments of tset: no item has this label, did you mean test?"
+    );
+
+    overrides.remove("tset");
+    overrides.insert(String::from("deploy"), ArgsOverride::default());
+    assert_eq!(
+        apply_args_overrides(&nansi_file, &overrides)
+            .unwrap_err()
+            .to_string(),
+        "Cannot override the arguments of deploy: no item has this label"
+    );
+}
+
+#[test]
+fn get_edit_distance_test() {
+    assert_eq!(get_edit_distance("", ""), 0);
+    assert_eq!(get_edit_distance("build", "build"), 0);
+    assert_eq!(get_edit_distance("biuld", "build"), 2);
+    assert_eq!(get_edit_distance("buil", "build"), 1);
+    assert_eq!(get_edit_distance("kitten", "sitting"), 3);
+}
@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use super::{compile_arg, ExecContext};
+
+/// Resolves a path given in a `NansiFile`; every field holding a path goes
+/// through this function so they all behave the same.
+///
+/// The steps are applied in order:
+/// 1. `{}` tags are expanded as in `args`,
+/// 2. a leading `~` (`~`, `~/...`) is replaced by the home directory of the
+///    current user (`~user` is not supported) unless the `expand_tilde`
+///    feature is disabled,
+/// 3. a path that is still relative is resolved against the base directory of
+///    the context (the directory containing the `NansiFile`), or left relative
+///    to the current directory if the context has none; it is an error if the
+///    `NansiFile` was fetched from a URL.
+pub fn resolve_path(raw: &str, ctx: &ExecContext) -> Result<PathBuf, Box<dyn Error>> {
+    let expanded = compile_arg(&String::from(raw), ctx)?;
+    let path = if ctx.features().expand_tilde {
+        expand_tilde(&expanded)
+    } else {
+        PathBuf::from(expanded)
+    };
+
+    if path.is_relative() {
+        if let Some(url) = ctx.source_url() {
+            return Err(format!(
+                "{}: relative paths cannot be resolved for a NansiFile fetched from {}",
+                raw, url
+            )
+            .into());
+        }
+        if let Some(base_dir) = ctx.base_dir() {
+            return Ok(base_dir.join(path));
+        }
+    }
+
+    Ok(path)
+}
+
+/// Replaces a leading `~` with the home directory of the current user.
+pub(crate) fn expand_tilde(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") || path.starts_with("~\\") {
+        if let Some(home) = dirs::home_dir() {
+            return match path[1..].trim_start_matches(['/', '\\']) {
+                "" => home,
+                rest => home.join(rest),
+            };
+        }
+    }
+
+    PathBuf::from(path)
+}
+
+/// Expands a leading `~` of the `exec` or of an argument of an item as
+/// `expand_tilde` does; a leading `\~` is passed on as a literal `~`.
+pub(crate) fn expand_arg_tilde(arg: &str) -> OsString {
+    match arg.strip_prefix('\\') {
+        Some(literal) if literal.starts_with('~') => OsString::from(literal),
+        _ => expand_tilde(arg).into_os_string(),
+    }
+}
+
+#[test]
+fn resolve_path_test() {
+    let home = dirs::home_dir().unwrap();
+
+    let mut ctx = ExecContext::from_map(std::collections::HashMap::new());
+    ctx.set_var(super::VarSource::Env, "DIR", "data");
+    ctx.set_var(super::VarSource::Env, "HOME_REL", "~/cache");
+    ctx.set_base_dir(std::path::Path::new("/srv/nansi"));
+
+    let cases = [
+        ("~", home.clone()),
+        ("~/x", home.join("x")),
+        ("~\\x", home.join("x")),
+        ("x/y", PathBuf::from("/srv/nansi/x/y")),
+        ("./x", PathBuf::from("/srv/nansi/./x")),
+        ("../x", PathBuf::from("/srv/nansi/../x")),
+        ("/abs/x", PathBuf::from("/abs/x")),
+        ("{DIR}/x", PathBuf::from("/srv/nansi/data/x")),
+        ("{HOME_REL}/x", home.join("cache/x")),
+        ("a/~/b", PathBuf::from("/srv/nansi/a/~/b")),
+        ("~user/x", PathBuf::from("/srv/nansi/~user/x")),
+    ];
+
+    for (raw, expected) in cases {
+        assert_eq!(resolve_path(raw, &ctx).unwrap(), expected, "{}", raw);
+    }
+
+    let ctx = ExecContext::from_map(std::collections::HashMap::new());
+    assert_eq!(resolve_path("x/y", &ctx).unwrap(), PathBuf::from("x/y"));
+}
+
+#[test]
+fn expand_arg_tilde_test() {
+    let home = dirs::home_dir().unwrap();
+
+    let cases = [
+        ("~", home.clone().into_os_string()),
+        ("~/testdev", home.join("testdev").into_os_string()),
+        (
+            "~/.cargo/bin/cargo",
+            home.join(".cargo/bin/cargo").into_os_string(),
+        ),
+        ("\\~/testdev", OsString::from("~/testdev")),
+        ("\\~", OsString::from("~")),
+        ("file~backup", OsString::from("file~backup")),
+        ("-p", OsString::from("-p")),
+        ("~user/x", OsString::from("~user/x")),
+        ("\\x", OsString::from("\\x")),
+    ];
+
+    for (arg, expected) in cases {
+        assert_eq!(expand_arg_tilde(arg), expected, "{}", arg);
+    }
+}
+
+#[test]
+fn resolve_path_expand_tilde_disabled_test() {
+    let mut ctx = ExecContext::from_map(std::collections::HashMap::new());
+    ctx.set_base_dir(std::path::Path::new("/srv/nansi"));
+    ctx.set_features(&super::FeatureFlags {
+        expand_tilde: false,
+        ..Default::default()
+    });
+
+    assert_eq!(
+        resolve_path("~/x", &ctx).unwrap(),
+        PathBuf::from("/srv/nansi/~/x")
+    );
+}
+
+#[test]
+fn resolve_path_source_url_test() {
+    let mut ctx = ExecContext::from_map(std::collections::HashMap::new());
+    ctx.set_source_url("https://example.com/setup.json");
+
+    assert_eq!(
+        resolve_path("/abs/x", &ctx).unwrap(),
+        PathBuf::from("/abs/x")
+    );
+    assert_eq!(
+        resolve_path("x", &ctx).unwrap_err().to_string(),
+        "x: relative paths cannot be resolved for a NansiFile fetched from https://example.com/setup.json"
+    );
+}
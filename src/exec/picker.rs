@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crossterm::style::Stylize;
+
+use super::{paint, OutputStream};
+
+/// Asks which of the labels starting with `prefix` to run (`--only` with a
+/// prefix matching several labels): one of them by number, all of them, or
+/// none, which cancels the run. Outside of an interactive terminal the
+/// ambiguity is an error listing the candidates.
+pub fn pick_labels(prefix: &str, candidates: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(format!(
+            "Cannot select {}: several labels start with it ({}), use one of them or a glob",
+            prefix,
+            candidates.join(", ")
+        )
+        .into());
+    }
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "Several labels start with {}:", prefix)?;
+    for (idx, label) in candidates.iter().enumerate() {
+        writeln!(
+            stdout,
+            "  {}) {}",
+            paint(OutputStream::Stdout, (idx + 1).to_string().bold()),
+            label
+        )?;
+    }
+
+    loop {
+        write!(
+            stdout,
+            "Run which? [1-{}, a for all, Enter to cancel] ",
+            candidates.len()
+        )?;
+        stdout.flush()?;
+
+        let mut response = String::new();
+        if io::stdin().lock().read_line(&mut response)? == 0 {
+            response.clear();
+        }
+        match get_picked_labels(&response, candidates) {
+            Some(labels) if labels.is_empty() => {
+                return Err(format!("Cannot select {}: cancelled", prefix).into())
+            }
+            Some(labels) => return Ok(labels),
+            None => continue,
+        }
+    }
+}
+
+/// Returns the labels picked by `response`: the candidate of the given
+/// number, all candidates for `a`, none for an empty response; `None` if the
+/// response is invalid.
+fn get_picked_labels(response: &str, candidates: &[String]) -> Option<Vec<String>> {
+    match response.trim() {
+        "" => Some(Vec::new()),
+        "a" | "A" => Some(candidates.to_vec()),
+        number => match number.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= candidates.len() => Some(vec![candidates[n - 1].clone()]),
+            _ => None,
+        },
+    }
+}
+
+#[test]
+fn get_picked_labels_test() {
+    let candidates = vec![String::from("deploy-api"), String::from("deploy-web")];
+
+    assert_eq!(
+        get_picked_labels("2\n", &candidates),
+        Some(vec![String::from("deploy-web")])
+    );
+    assert_eq!(
+        get_picked_labels(" a ", &candidates),
+        Some(candidates.clone())
+    );
+    assert_eq!(get_picked_labels("\n", &candidates), Some(vec![]));
+    assert_eq!(get_picked_labels("3", &candidates), None);
+    assert_eq!(get_picked_labels("0", &candidates), None);
+    assert_eq!(get_picked_labels("web", &candidates), None);
+}
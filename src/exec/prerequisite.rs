@@ -0,0 +1,306 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Prerequisite of an item: a label or the (1-based) index of an item in the
+/// form `#N` which must have succeeded, or a set of them of which at least
+/// `min` must be met (`{"any_of": ["a", "b", "c"], "min": 2}`)
+///
+/// A reference may require another outcome (see `split_reference`): `!a` or
+/// `a:failed` is met if `a` ran and failed, `a:skipped` if it did not run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Prerequisite {
+    Ref(String),
+
+    AnyOf {
+        any_of: Vec<String>,
+
+        /// Number of references which must be met, 1 if not set
+        #[serde(default = "default_as_min")]
+        min: usize,
+    },
+}
+
+/// Outcome so far of the item(s) a prerequisite reference points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RefState {
+    Ok,
+    Failed,
+
+    /// Skipped or not run (yet)
+    Skipped,
+}
+
+impl RefState {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            RefState::Ok => "ok",
+            RefState::Failed => "failed",
+            RefState::Skipped => "skipped",
+        }
+    }
+}
+
+/// Tells the outcome of the item(s) a prerequisite reference points at
+pub(crate) trait Satisfaction {
+    fn get_state(&self, reference: &str) -> RefState;
+}
+
+impl Prerequisite {
+    /// Returns the labels and `#N` references of the prerequisite.
+    pub fn refs(&self) -> &[String] {
+        match self {
+            Prerequisite::Ref(reference) => std::slice::from_ref(reference),
+            Prerequisite::AnyOf { any_of, .. } => any_of,
+        }
+    }
+
+    pub fn refs_mut(&mut self) -> &mut [String] {
+        match self {
+            Prerequisite::Ref(reference) => std::slice::from_mut(reference),
+            Prerequisite::AnyOf { any_of, .. } => any_of,
+        }
+    }
+
+    /// Returns the number of references which must be met.
+    pub fn min(&self) -> usize {
+        match self {
+            Prerequisite::Ref(_) => 1,
+            Prerequisite::AnyOf { min, .. } => *min,
+        }
+    }
+
+    /// Returns whether enough references of the prerequisite are met.
+    pub(crate) fn is_met(&self, satisfaction: &dyn Satisfaction) -> bool {
+        let met_count = self
+            .refs()
+            .iter()
+            .filter(|reference| is_reference_met(reference, satisfaction))
+            .count();
+
+        met_count >= self.min()
+    }
+
+    /// Returns why an `any_of` prerequisite is not met, e.g.
+    /// `1 of required 2 satisfied: [a ok, b failed, c skipped]`.
+    pub(crate) fn get_unmet_str(&self, satisfaction: &dyn Satisfaction) -> String {
+        let states: Vec<(&String, RefState)> = self
+            .refs()
+            .iter()
+            .map(|reference| {
+                (
+                    reference,
+                    satisfaction.get_state(split_reference(reference).0),
+                )
+            })
+            .collect();
+        let met_count = self
+            .refs()
+            .iter()
+            .filter(|reference| is_reference_met(reference, satisfaction))
+            .count();
+        let states_str: Vec<String> = states
+            .iter()
+            .map(|(reference, state)| format!("{} {}", reference, state.as_str()))
+            .collect();
+
+        format!(
+            "{} of required {} satisfied: [{}]",
+            met_count,
+            self.min(),
+            states_str.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for Prerequisite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prerequisite::Ref(reference) => write!(f, "{}", reference),
+            Prerequisite::AnyOf { any_of, min } => {
+                write!(f, "any_of [{}] (min {})", any_of.join(", "), min)
+            }
+        }
+    }
+}
+
+/// Splits a prerequisite reference into the label or `#N` it points at and the
+/// outcome required of it: `a` and `#2` must have succeeded, `!a` and
+/// `a:failed` must have failed and `a:skipped` must not have run.
+///
+/// A leading `\` is removed from the label, so `\!a` refers to the label `!a`
+/// (`!\!a` to its failure); a label ending in `:failed` or `:skipped` can only
+/// be referred to by its index.
+pub(crate) fn split_reference(reference: &str) -> (&str, RefState) {
+    if let Some(label) = reference.strip_prefix('!') {
+        return (unescape_label(label), RefState::Failed);
+    }
+    for (suffix, state) in CONDITION_SUFFIXES {
+        if let Some(label) = reference.strip_suffix(suffix) {
+            return (unescape_label(label), state);
+        }
+    }
+
+    (unescape_label(reference), RefState::Ok)
+}
+
+/// Returns `reference` pointing at `target` instead, with the same condition.
+pub(crate) fn replace_reference_target(reference: &str, target: &str) -> String {
+    let target = match target.starts_with(['!', '\\']) {
+        true => format!("\\{}", target),
+        false => String::from(target),
+    };
+
+    if reference.starts_with('!') {
+        return format!("!{}", target);
+    }
+    match CONDITION_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| reference.ends_with(suffix))
+    {
+        Some((suffix, _)) => format!("{}{}", target, suffix),
+        None => target,
+    }
+}
+
+fn unescape_label(label: &str) -> &str {
+    label.strip_prefix('\\').unwrap_or(label)
+}
+
+/// Suffixes of references requiring an outcome other than success
+const CONDITION_SUFFIXES: [(&str, RefState); 2] = [
+    (":failed", RefState::Failed),
+    (":skipped", RefState::Skipped),
+];
+
+/// Returns whether the item(s) the reference points at had the outcome it
+/// requires.
+fn is_reference_met(reference: &str, satisfaction: &dyn Satisfaction) -> bool {
+    let (target, expected) = split_reference(reference);
+
+    satisfaction.get_state(target) == expected
+}
+
+fn default_as_min() -> usize {
+    1
+}
+
+#[cfg(test)]
+impl Satisfaction for std::collections::HashMap<&str, RefState> {
+    fn get_state(&self, reference: &str) -> RefState {
+        self.get(reference).copied().unwrap_or(RefState::Skipped)
+    }
+}
+
+#[test]
+fn prerequisite_deserialize_test() {
+    let prereqs: Vec<Prerequisite> =
+        serde_json::from_str("[\"a\", \"#2\", {\"any_of\": [\"b\", \"c\"]}, {\"any_of\": [\"d\", \"e\", \"f\"], \"min\": 2}]")
+            .unwrap();
+
+    assert_eq!(
+        prereqs,
+        vec![
+            Prerequisite::Ref(String::from("a")),
+            Prerequisite::Ref(String::from("#2")),
+            Prerequisite::AnyOf {
+                any_of: vec![String::from("b"), String::from("c")],
+                min: 1
+            },
+            Prerequisite::AnyOf {
+                any_of: vec![String::from("d"), String::from("e"), String::from("f")],
+                min: 2
+            },
+        ]
+    );
+    assert_eq!(prereqs[3].to_string(), "any_of [d, e, f] (min 2)");
+}
+
+#[test]
+fn prerequisite_is_met_test() {
+    let satisfaction = std::collections::HashMap::from([
+        ("a", RefState::Ok),
+        ("b", RefState::Failed),
+        ("#3", RefState::Ok),
+    ]);
+    let any_of = |refs: &[&str], min: usize| Prerequisite::AnyOf {
+        any_of: refs.iter().map(|r| String::from(*r)).collect(),
+        min,
+    };
+
+    assert!(Prerequisite::Ref(String::from("a")).is_met(&satisfaction));
+    assert!(!Prerequisite::Ref(String::from("b")).is_met(&satisfaction));
+    assert!(!Prerequisite::Ref(String::from("c")).is_met(&satisfaction));
+
+    assert!(any_of(&["a", "b", "c"], 1).is_met(&satisfaction));
+    assert!(!any_of(&["b", "c"], 1).is_met(&satisfaction));
+    assert!(any_of(&["a", "#3", "b"], 2).is_met(&satisfaction));
+
+    let unmet = any_of(&["a", "b", "c"], 2);
+    assert!(!unmet.is_met(&satisfaction));
+    assert_eq!(
+        unmet.get_unmet_str(&satisfaction),
+        "1 of required 2 satisfied: [a ok, b failed, c skipped]"
+    );
+}
+
+#[test]
+fn split_reference_test() {
+    let cases = [
+        ("build", "build", RefState::Ok),
+        ("#2", "#2", RefState::Ok),
+        ("!build", "build", RefState::Failed),
+        ("!#2", "#2", RefState::Failed),
+        ("build:failed", "build", RefState::Failed),
+        ("#2:skipped", "#2", RefState::Skipped),
+        ("db:migrate", "db:migrate", RefState::Ok),
+        ("\\!important", "!important", RefState::Ok),
+        ("!\\!important", "!important", RefState::Failed),
+        ("\\!important:skipped", "!important", RefState::Skipped),
+        ("\\\\share", "\\share", RefState::Ok),
+    ];
+
+    for (reference, target, state) in cases {
+        assert_eq!(split_reference(reference), (target, state), "{}", reference);
+    }
+
+    assert_eq!(replace_reference_target("build", "make"), "make");
+    assert_eq!(replace_reference_target("!build", "make"), "!make");
+    assert_eq!(
+        replace_reference_target("build:skipped", "#3"),
+        "#3:skipped"
+    );
+    assert_eq!(replace_reference_target("build", "!make"), "\\!make");
+    assert_eq!(
+        replace_reference_target("build:failed", "!make"),
+        "\\!make:failed"
+    );
+}
+
+#[test]
+fn prerequisite_condition_is_met_test() {
+    let satisfaction = std::collections::HashMap::from([
+        ("build", RefState::Ok),
+        ("test", RefState::Failed),
+        ("!odd", RefState::Failed),
+    ]);
+    let is_met = |reference: &str| Prerequisite::Ref(String::from(reference)).is_met(&satisfaction);
+
+    assert!(!is_met("!build"));
+    assert!(is_met("!test"));
+    assert!(is_met("test:failed"));
+    assert!(!is_met("test:skipped"));
+    assert!(is_met("lint:skipped"));
+    assert!(is_met("!\\!odd"));
+    assert!(!is_met("\\!odd"));
+
+    let any_of = Prerequisite::AnyOf {
+        any_of: vec![String::from("!build"), String::from("test:failed")],
+        min: 2,
+    };
+    assert_eq!(
+        any_of.get_unmet_str(&satisfaction),
+        "1 of required 2 satisfied: [!build ok, test:failed failed]"
+    );
+}
@@ -0,0 +1,270 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::OutputStream;
+
+/// Moves to the start of the line and erases it
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// How the item being run is shown
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressMode {
+    /// `Fancy` if the terminal supports it, `Plain` otherwise
+    #[default]
+    Auto,
+
+    /// Only the sequential status lines
+    Plain,
+
+    /// A line showing the item being run, redrawn in place
+    Fancy,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProgressMode, String> {
+        match s {
+            "auto" => Ok(ProgressMode::Auto),
+            "plain" => Ok(ProgressMode::Plain),
+            "fancy" => Ok(ProgressMode::Fancy),
+            _ => Err(format!(
+                "unknown progress mode: {} (expected auto, plain or fancy)",
+                s
+            )),
+        }
+    }
+}
+
+/// Resolves `Auto` to `Fancy` if the stream is a terminal supporting ANSI
+/// sequences (see `crossterm::ansi_support` on Windows) whose `TERM` is not
+/// `dumb`, and to `Plain` otherwise.
+pub fn get_progress_mode(
+    mode: ProgressMode,
+    term: Option<&str>,
+    is_tty: bool,
+    supports_ansi: bool,
+) -> ProgressMode {
+    match mode {
+        ProgressMode::Auto => {
+            let dumb = matches!(term, Some("") | Some("dumb"));
+            if is_tty && supports_ansi && !dumb {
+                ProgressMode::Fancy
+            } else {
+                ProgressMode::Plain
+            }
+        }
+        _ => mode,
+    }
+}
+
+/// Resolves `mode` for the terminal `stream` goes to (see `get_progress_mode`).
+pub fn detect_progress_mode(mode: ProgressMode, stream: OutputStream) -> ProgressMode {
+    let is_tty = match stream {
+        OutputStream::Stdout => io::stdout().is_terminal(),
+        OutputStream::Stderr => io::stderr().is_terminal(),
+    };
+    #[cfg(windows)]
+    let supports_ansi = crossterm::ansi_support::supports_ansi();
+    #[cfg(not(windows))]
+    let supports_ansi = true;
+
+    get_progress_mode(
+        mode,
+        env::var("TERM").ok().as_deref(),
+        is_tty,
+        supports_ansi,
+    )
+}
+
+/// Line on the status stream showing the item being run, erased before the
+/// status line of the item is printed
+pub struct Progress {
+    stream: OutputStream,
+    fancy: bool,
+    total: usize,
+    drawn: bool,
+}
+
+impl Progress {
+    pub fn new(stream: OutputStream, mode: ProgressMode, total: usize) -> Progress {
+        let fancy = mode == ProgressMode::Fancy;
+        if fancy {
+            interrupt::install(stream);
+        }
+
+        Progress {
+            stream,
+            fancy,
+            total,
+            drawn: false,
+        }
+    }
+
+    /// Shows `item_str` as the `position`th (1-based) item being run.
+    pub fn show(&mut self, position: usize, item_str: &str) -> Result<(), io::Error> {
+        if !self.fancy {
+            return Ok(());
+        }
+
+        self.write(&format!(
+            "{}[{}/{}] {} ...",
+            CLEAR_LINE, position, self.total, item_str
+        ))?;
+        self.drawn = true;
+        interrupt::set_drawn(true);
+
+        Ok(())
+    }
+
+    /// Erases the progress line, if drawn.
+    pub fn clear(&mut self) -> Result<(), io::Error> {
+        if !self.drawn {
+            return Ok(());
+        }
+
+        interrupt::set_drawn(false);
+        self.drawn = false;
+        self.write(CLEAR_LINE)
+    }
+
+    fn write(&self, s: &str) -> Result<(), io::Error> {
+        match self.stream {
+            OutputStream::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(s.as_bytes())?;
+                stdout.flush()
+            }
+            OutputStream::Stderr => {
+                let mut stderr = io::stderr().lock();
+                stderr.write_all(s.as_bytes())?;
+                stderr.flush()
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let _ = self.clear();
+    }
+}
+
+/// Erasing of a drawn progress line when `nansi` is interrupted (Ctrl-C), so
+/// the shell prompt does not follow a half-drawn line
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+    use super::super::cancel_interrupted;
+    use super::{OutputStream, CLEAR_LINE};
+
+    /// File descriptor of the status stream, -1 until `install`
+    static FD: AtomicI32 = AtomicI32::new(-1);
+
+    static DRAWN: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_interrupt(signal: libc::c_int) {
+        let fd = FD.load(Ordering::SeqCst);
+        if fd >= 0 && DRAWN.load(Ordering::SeqCst) {
+            // write(2), signal(2) and raise(3) are async-signal-safe
+            unsafe {
+                libc::write(
+                    fd,
+                    CLEAR_LINE.as_ptr() as *const libc::c_void,
+                    CLEAR_LINE.len(),
+                );
+            }
+        }
+
+        // the run was cancelled instead, see `cancel_on_interrupt`
+        if cancel_interrupted(signal) {
+            DRAWN.store(false, Ordering::SeqCst);
+            return;
+        }
+
+        // terminate as if the handler was never installed
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub fn install(stream: OutputStream) {
+        let fd = match stream {
+            OutputStream::Stdout => libc::STDOUT_FILENO,
+            OutputStream::Stderr => libc::STDERR_FILENO,
+        };
+        if FD.swap(fd, Ordering::SeqCst) >= 0 {
+            return;
+        }
+
+        // `on_interrupt` only calls async-signal-safe functions
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+            libc::signal(
+                libc::SIGTERM,
+                on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+
+    pub fn set_drawn(drawn: bool) {
+        DRAWN.store(drawn, Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+    use super::OutputStream;
+
+    pub fn install(_stream: OutputStream) {}
+
+    pub fn set_drawn(_drawn: bool) {}
+}
+
+#[test]
+fn get_progress_mode_test() {
+    use ProgressMode::{Auto, Fancy, Plain};
+
+    // (mode, TERM, tty, ANSI support) => resolved mode
+    #[rustfmt::skip]
+    let table = [
+        (Auto,  Some("xterm-256color"), true,  true,  Fancy),
+        (Auto,  None,                   true,  true,  Fancy),
+        (Auto,  Some("dumb"),           true,  true,  Plain),
+        (Auto,  Some(""),               true,  true,  Plain),
+        (Auto,  Some("xterm"),          false, true,  Plain),
+        (Auto,  Some("xterm"),          true,  false, Plain),
+        (Plain, Some("xterm"),          true,  true,  Plain),
+        (Fancy, Some("dumb"),           false, false, Fancy),
+    ];
+
+    for (mode, term, is_tty, supports_ansi, expected) in table {
+        assert_eq!(
+            get_progress_mode(mode, term, is_tty, supports_ansi),
+            expected,
+            "{:?} TERM={:?} tty={} ansi={}",
+            mode,
+            term,
+            is_tty,
+            supports_ansi
+        );
+    }
+}
+
+#[test]
+fn progress_plain_test() {
+    let mut progress = Progress::new(OutputStream::Stdout, ProgressMode::Plain, 3);
+
+    progress.show(1, "[1][build] make").unwrap();
+    assert!(!progress.drawn);
+    progress.clear().unwrap();
+}
@@ -0,0 +1,91 @@
+use regex::Regex;
+
+/// Replacement of the matches of `redact` patterns
+const REDACTED: &str = "***";
+
+/// Patterns (`redact` of the `NansiFile` and of an item) whose matches are
+/// replaced with `***` in the captured output of the item.
+///
+/// Patterns use the syntax of the `regex` crate, which has no lookaround nor
+/// backreferences and matches in time linear in the size of the output, so no
+/// pattern can backtrack catastrophically.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new<'a, I>(patterns: I) -> Result<Redactor, String>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| compile_pattern(pattern))
+            .collect::<Result<Vec<Regex>, String>>()?;
+
+        Ok(Redactor { patterns })
+    }
+
+    /// Returns `text` with the matches of the patterns replaced, and the
+    /// number of replacements.
+    pub fn redact(&self, text: &str) -> (String, usize) {
+        let mut redacted = text.to_string();
+        let mut count = 0;
+        for pattern in &self.patterns {
+            let matches = pattern.find_iter(&redacted).count();
+            if matches > 0 {
+                count += matches;
+                redacted = pattern.replace_all(&redacted, REDACTED).into_owned();
+            }
+        }
+
+        (redacted, count)
+    }
+}
+
+/// Compiles a `redact` pattern; the error is the reason the pattern is
+/// invalid, e.g. `"(": unclosed group`.
+pub(crate) fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| {
+        // syntax errors draw the pattern over several lines, the reason is last
+        let e = e.to_string();
+        let reason = e.lines().last().unwrap_or_default();
+        format!(
+            "{:?}: {}",
+            pattern,
+            reason.strip_prefix("error: ").unwrap_or(reason)
+        )
+    })
+}
+
+#[test]
+fn redactor_test() {
+    let patterns = vec![
+        String::from("ghp_[A-Za-z0-9]+"),
+        String::from(r"password=\S+"),
+    ];
+    let redactor = Redactor::new(&patterns).unwrap();
+
+    assert_eq!(
+        redactor.redact("token ghp_abc123 and ghp_XYZ\npassword=hunter2 ok\n"),
+        (String::from("token *** and ***\n*** ok\n"), 3)
+    );
+    assert_eq!(
+        redactor.redact("nothing to hide"),
+        (String::from("nothing to hide"), 0)
+    );
+    assert_eq!(
+        Redactor::default().redact("ghp_abc"),
+        (String::from("ghp_abc"), 0)
+    );
+
+    assert_eq!(
+        Redactor::new(&vec![String::from("(")]).unwrap_err(),
+        "\"(\": unclosed group"
+    );
+    assert_eq!(
+        compile_pattern("(?=x)").unwrap_err(),
+        "\"(?=x)\": look-around, including look-ahead and look-behind, is not supported"
+    );
+}
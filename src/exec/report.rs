@@ -0,0 +1,187 @@
+use std::str::FromStr;
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ExecItem, FailureReason, NansiFile, TraceItem};
+
+/// Status of an item the run never got to, e.g. after `fail_fast` stopped it
+pub const NOT_RUN_STATUS: &str = "not_run";
+
+/// Format of the report written with `--report`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReportFormat, String> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format: {} (expected json)", s)),
+        }
+    }
+}
+
+/// Outcome of a single item of a run, as written to the report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExecResult {
+    pub index: usize,
+
+    pub label: String,
+
+    pub exec: String,
+
+    /// Arguments after tag compilation, with secret values masked
+    pub args: Vec<String>,
+
+    /// `ok`, `err`, `warn`, `skip`, `dry` or `not_run`
+    pub status: String,
+
+    pub exit_code: Option<i32>,
+
+    /// Why the item failed, `injected` for failures of `--inject-failure`
+    #[serde(default)]
+    pub failure_reason: Option<FailureReason>,
+
+    /// Index of the failed item a skip for unmet prerequisites traces back
+    /// to, through the prerequisites of skipped prerequisites
+    #[serde(default)]
+    pub skip_root_cause: Option<usize>,
+
+    pub duration_ms: u128,
+
+    pub stdout: String,
+
+    pub stderr: String,
+}
+
+impl ExecResult {
+    /// Returns the result of an item the run never got to.
+    pub fn not_run(exec_item: &ExecItem, idx: usize) -> ExecResult {
+        ExecResult {
+            index: idx,
+            label: exec_item.label.clone(),
+            exec: exec_item.exec.clone(),
+            args: exec_item.get_args(),
+            status: String::from(NOT_RUN_STATUS),
+            exit_code: None,
+            failure_reason: None,
+            skip_root_cause: None,
+            duration_ms: 0,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+}
+
+impl From<&TraceItem> for ExecResult {
+    fn from(item: &TraceItem) -> ExecResult {
+        ExecResult {
+            index: item.index,
+            label: item.label.clone(),
+            exec: item.exec.clone(),
+            args: item.args.clone(),
+            status: item.status.clone(),
+            exit_code: item.exit_code,
+            failure_reason: item.failure_reason.clone(),
+            skip_root_cause: None,
+            duration_ms: item.duration_ms,
+            stdout: item.stdout.clone(),
+            stderr: item.stderr.clone(),
+        }
+    }
+}
+
+/// Results of a run written with `--report` once `execute` returns, also if
+/// the run was aborted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub nansi_file: String,
+
+    /// One result per item in `exec_list` order, timer markers excluded
+    pub items: Vec<ExecResult>,
+}
+
+impl RunReport {
+    /// Returns the report of `results`, items missing from them (the run
+    /// ended before reaching them) being `not_run`.
+    pub fn new(nansi_file: &NansiFile, results: &[ExecResult]) -> RunReport {
+        let items = nansi_file
+            .exec_list
+            .iter()
+            .enumerate()
+            .filter(|(_, exec_item)| !exec_item.is_timer())
+            .map(
+                |(idx, exec_item)| match results.iter().find(|r| r.index == idx + 1) {
+                    Some(result) => result.clone(),
+                    None => ExecResult::not_run(exec_item, idx + 1),
+                },
+            )
+            .collect();
+
+        RunReport {
+            nansi_file: nansi_file.file_path.clone(),
+            items,
+        }
+    }
+
+    pub fn save(&self, file_path: &str, format: ReportFormat) -> Result<(), io::Error> {
+        let report_str = match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self)?,
+        };
+        match fs::write(file_path, report_str) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+        }
+    }
+}
+
+#[test]
+fn run_report_test() {
+    let nansi_file: NansiFile = serde_json::from_str(
+        "{\"exec_list\": [{\"label\": \"ls\", \"exec\": \"ls\"}, {\"timer_start\": \"t\"}, {\"exec\": \"echo\", \"args\": [\"{X}\"]}]}",
+    )
+    .unwrap();
+    let trace = super::sample_trace();
+
+    let report = RunReport::new(&nansi_file, &[ExecResult::from(&trace.items[0])]);
+    assert_eq!(
+        report.items,
+        vec![
+            ExecResult {
+                index: 1,
+                label: String::from("ls"),
+                exec: String::from("ls"),
+                args: vec![String::from("-l")],
+                status: String::from("ok"),
+                exit_code: Some(0),
+                failure_reason: None,
+                skip_root_cause: None,
+                duration_ms: 3,
+                stdout: String::from("Cargo.toml\nsrc\n"),
+                stderr: String::new(),
+            },
+            ExecResult {
+                index: 3,
+                label: String::new(),
+                exec: String::from("echo"),
+                args: vec![String::from("{X}")],
+                status: String::from("not_run"),
+                exit_code: None,
+                failure_reason: None,
+                skip_root_cause: None,
+                duration_ms: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            },
+        ]
+    );
+
+    assert_eq!("json".parse::<ReportFormat>(), Ok(ReportFormat::Json));
+    assert!("xml".parse::<ReportFormat>().is_err());
+}
@@ -0,0 +1,140 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+use super::{get_utc_now, strip_ansi, write_stream, ItemDisplay, RunSummary, TraceItem};
+
+/// Plain-text record of runs written with `--log-file`, appended to so that
+/// the file keeps the history of several runs.
+///
+/// Every record is written as soon as it is known, so the log survives the
+/// terminal (e.g. a closed SSH session) and reflects an interrupted run up to
+/// the item being executed.
+#[derive(Debug)]
+pub struct RunLog {
+    file: File,
+    file_path: String,
+}
+
+impl RunLog {
+    /// Opens the log at `file_path` for appending, creating it if missing.
+    pub fn open(file_path: &str) -> Result<RunLog, io::Error> {
+        match OpenOptions::new().create(true).append(true).open(file_path) {
+            Ok(file) => Ok(RunLog {
+                file,
+                file_path: String::from(file_path),
+            }),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+        }
+    }
+
+    pub fn start(&mut self, nansi_file_path: &str) -> Result<(), io::Error> {
+        self.write(&format!(
+            "[{}] nansi {} using NansiFile: {}\n",
+            get_utc_now(),
+            env!("CARGO_PKG_VERSION"),
+            nansi_file_path
+        ))
+    }
+
+    pub fn warn(&mut self, msg: &str) -> Result<(), io::Error> {
+        self.write(&format!("[{}] Warning: {}\n", get_utc_now(), msg))
+    }
+
+    pub fn item(&mut self, item: &TraceItem) -> Result<(), io::Error> {
+        self.write(&get_item_record(&get_utc_now(), item))
+    }
+
+    pub fn finish(&mut self, summary: &RunSummary) -> Result<(), io::Error> {
+        self.write(&format!(
+            "[{}] Finished: ok={} err={} warn={} skip={} in {} ms\n\n",
+            get_utc_now(),
+            summary.ok,
+            summary.err,
+            summary.warn,
+            summary.skip,
+            summary.duration_ms
+        ))
+    }
+
+    fn write(&mut self, record: &str) -> Result<(), io::Error> {
+        match self.file.write_all(strip_ansi(record).as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", self.file_path, e),
+            )),
+        }
+    }
+}
+
+/// Returns the lines logged for a finished or skipped item, stamped with
+/// `timestamp`.
+fn get_item_record(timestamp: &str, item: &TraceItem) -> String {
+    let mut out = String::new();
+
+    let exit_code = match item.exit_code {
+        Some(code) => code.to_string(),
+        None => String::from("-"),
+    };
+    let reason = match &item.failure_reason {
+        Some(reason) => format!(" ({})", reason.short_str()),
+        None => String::new(),
+    };
+
+    let _ = writeln!(
+        out,
+        "[{}] {} {} exit={} {} ms{}",
+        timestamp,
+        ItemDisplay::from_label(Some(item.index), &item.label),
+        item.status,
+        exit_code,
+        item.duration_ms,
+        reason
+    );
+    let mut command = vec![item.exec.as_str()];
+    command.extend(item.args.iter().map(|arg| arg.as_str()));
+    let _ = writeln!(out, "  command: {}", command.join(" "));
+    if let Some(message) = &item.message {
+        let _ = writeln!(out, "  message: {}", message);
+    }
+    for warning in &item.warnings {
+        let _ = writeln!(out, "  warning: {}", warning);
+    }
+    if item.merged_streams {
+        write_stream(&mut out, "stdout and stderr (merged)", &item.stdout);
+    } else {
+        write_stream(&mut out, "stdout", &item.stdout);
+        write_stream(&mut out, "stderr", &item.stderr);
+    }
+
+    out
+}
+
+#[test]
+fn run_log_test() {
+    let mut item = super::sample_trace().items.remove(0);
+    item.stderr = String::from("\u{1b}[31mwarning\u{1b}[0m: colored\n");
+
+    assert_eq!(
+        get_item_record("2024-03-01T12:00:00Z", &item),
+        "[2024-03-01T12:00:00Z] [1][ls] ok exit=0 3 ms\n  command: ls -l\n  stdout:\n    Cargo.toml\n    src\n  stderr:\n    \u{1b}[31mwarning\u{1b}[0m: colored\n"
+    );
+
+    let file_path = std::env::temp_dir().join(format!("nansi_run_log_{}.log", std::process::id()));
+    let file_path = file_path.to_str().unwrap();
+    std::fs::write(file_path, "earlier run\n").unwrap();
+
+    let mut log = RunLog::open(file_path).unwrap();
+    log.item(&item).unwrap();
+    let logged = std::fs::read_to_string(file_path).unwrap();
+    std::fs::remove_file(file_path).unwrap();
+
+    assert!(logged.starts_with("earlier run\n["));
+    assert!(logged.ends_with("  stderr:\n    warning: colored\n"));
+
+    assert!(RunLog::open("/nansi/surely/missing/run.log")
+        .unwrap_err()
+        .to_string()
+        .starts_with("/nansi/surely/missing/run.log: "));
+}
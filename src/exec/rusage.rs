@@ -0,0 +1,635 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, PipeReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Output, Stdio};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Capture, MAX_TRUNCATED_OUTPUT_BYTES};
+
+/// Resources used by the process of an item (and the processes it waited for)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    /// Peak resident set size in KiB
+    pub max_rss_kib: u64,
+
+    /// CPU time spent in user mode
+    pub user_ms: u128,
+
+    /// CPU time spent in the kernel
+    pub system_ms: u128,
+}
+
+impl ResourceUsage {
+    /// Combines the usage of several runs of an item (e.g. with `chunk_args`):
+    /// CPU times add up, the peak is the highest of the runs.
+    pub fn merge(&self, other: &ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            max_rss_kib: self.max_rss_kib.max(other.max_rss_kib),
+            user_ms: self.user_ms + other.user_ms,
+            system_ms: self.system_ms + other.system_ms,
+        }
+    }
+}
+
+/// How often a child with a timeout is checked for having exited
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Spawns the command with `stdout` and `stderr` captured, or sent to the
+/// null device with `Capture::None`. With `merge_streams` both go to the same
+/// pipe, whose reader is returned, so their lines keep the order they were
+/// written in.
+fn spawn_captured(
+    command: &mut Command,
+    capture: Capture,
+    merge_streams: bool,
+) -> Result<(Child, Option<PipeReader>), io::Error> {
+    if capture == Capture::None {
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        return Ok((child, None));
+    }
+    if !merge_streams {
+        let child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        return Ok((child, None));
+    }
+
+    let (reader, writer) = io::pipe()?;
+    command.stdout(writer.try_clone()?).stderr(writer);
+    let child = command.spawn();
+    // the command holds the write ends, reading would never reach the end
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+
+    Ok((child?, Some(reader)))
+}
+
+/// Output of a command run by `output_with_usage`
+pub(crate) struct CapturedOutput {
+    pub output: Output,
+
+    /// Resources used by the child, `None` where they cannot be measured
+    pub usage: Option<ResourceUsage>,
+
+    /// The child was killed because it ran longer than the timeout
+    pub timed_out: bool,
+}
+
+/// Input written to the `stdin` of the child by `output_with_usage`
+pub(crate) enum StdinInput {
+    Data(Vec<u8>),
+
+    /// Streamed from the file without reading it into memory first
+    File(File),
+}
+
+/// Called by `output_with_usage` with each line of the output of the child
+/// (without its line ending) as soon as it is read
+pub(crate) type LineSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Reads `reader` to the end on a new thread, passing each line to `on_line`;
+/// with a `limit`, only the last `limit` bytes (or a little less, to start on a
+/// character) are kept.
+fn read_in_background(
+    reader: impl Read + Send + 'static,
+    on_line: Option<LineSink>,
+    limit: Option<usize>,
+) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf: Vec<u8> = Vec::new();
+        let mut reader = BufReader::new(reader);
+        loop {
+            let start = buf.len();
+            let read = match &on_line {
+                Some(_) => reader.read_until(b'\n', &mut buf)?,
+                None => {
+                    let chunk = reader.fill_buf()?;
+                    buf.extend_from_slice(chunk);
+                    let read = chunk.len();
+                    reader.consume(read);
+                    read
+                }
+            };
+            if read == 0 {
+                if let Some(limit) = limit {
+                    keep_tail(&mut buf, limit);
+                }
+                return Ok(buf);
+            }
+            if let Some(on_line) = &on_line {
+                let line = String::from_utf8_lossy(&buf[start..]);
+                on_line(line.trim_end_matches(['\n', '\r']));
+            }
+            // trimmed in batches rather than on every read
+            if let Some(limit) = limit.filter(|limit| buf.len() > 2 * limit) {
+                keep_tail(&mut buf, limit);
+            }
+        }
+    })
+}
+
+/// Drops the start of `buf` to keep at most its last `limit` bytes, and the
+/// continuation bytes of a character cut in the middle.
+fn keep_tail(buf: &mut Vec<u8>, limit: usize) {
+    if buf.len() <= limit {
+        return;
+    }
+
+    let mut start = buf.len() - limit;
+    while start < buf.len() && buf[start] & 0b1100_0000 == 0b1000_0000 {
+        start += 1;
+    }
+    buf.drain(..start);
+}
+
+/// Writes `input` to `stdin` on a new thread, so a child filling its output
+/// pipes before reading all of its input cannot block on `nansi`; `stdin` is
+/// closed once done.
+fn write_in_background(input: StdinInput, mut stdin: ChildStdin) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let result = match input {
+            StdinInput::Data(data) => stdin.write_all(&data),
+            StdinInput::File(mut file) => io::copy(&mut file, &mut stdin).map(|_| ()),
+        };
+        match result {
+            // the child exited without reading all of its input
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    })
+}
+
+fn join_writer(writer: Option<JoinHandle<io::Result<()>>>) -> Result<(), io::Error> {
+    match writer {
+        Some(writer) => match writer.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("writing the input of the child failed")),
+        },
+        None => Ok(()),
+    }
+}
+
+fn join_reader(reader: Option<JoinHandle<io::Result<Vec<u8>>>>) -> Result<Vec<u8>, io::Error> {
+    match reader {
+        Some(reader) => match reader.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("reading the output of the child failed")),
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Runs the command like `Command::output` and returns the resources used by
+/// the child, `None` where they cannot be measured.
+///
+/// `stdout` and `stderr` are captured as set by `capture`, with
+/// `merge_streams` together in `stdout` (`stderr` is then empty); `stdin` is
+/// fed from `stdin` if given, left as configured on `command` (inherited
+/// unless set) otherwise.
+///
+/// With a `timeout`, the child runs in its own process group which is killed
+/// once the timeout elapses; what it wrote until then is kept. The interrupts
+/// `nansi` receives are forwarded to the group (see `forward_interrupts`).
+///
+/// With `on_line`, the lines of both streams are also passed to it while the
+/// child runs.
+#[cfg(unix)]
+pub(crate) fn output_with_usage(
+    command: &mut Command,
+    stdin: Option<StdinInput>,
+    capture: Capture,
+    merge_streams: bool,
+    timeout: Option<Duration>,
+    on_line: Option<&LineSink>,
+) -> Result<CapturedOutput, io::Error> {
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    use super::forward_interrupts;
+
+    if timeout.is_some() {
+        // grandchildren holding the pipes open are killed with the child
+        command.process_group(0);
+    }
+    if stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut forwarding = timeout.map(|_| forward_interrupts());
+    let (mut child, merged) = spawn_captured(command, capture, merge_streams)?;
+    if let Some(forwarding) = forwarding.as_mut() {
+        forwarding.attach(child.id());
+    }
+    let stdin_writer = stdin
+        .zip(child.stdin.take())
+        .map(|(input, child_stdin)| write_in_background(input, child_stdin));
+
+    let limit = match capture {
+        Capture::Truncated => Some(MAX_TRUNCATED_OUTPUT_BYTES),
+        _ => None,
+    };
+    let stdout_reader = match merged {
+        Some(merged) => Some(read_in_background(merged, on_line.cloned(), limit)),
+        None => child
+            .stdout
+            .take()
+            .map(|stdout| read_in_background(stdout, on_line.cloned(), limit)),
+    };
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|stderr| read_in_background(stderr, on_line.cloned(), limit));
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let mut status: libc::c_int = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let options = match deadline {
+            Some(_) if !timed_out => libc::WNOHANG,
+            _ => 0,
+        };
+        // `status` and `rusage` are writable buffers of the expected types and
+        // the child has not been waited for yet.
+        let pid =
+            unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut rusage) };
+        if pid > 0 {
+            // the id of the reaped child may be reused
+            drop(forwarding);
+            break;
+        }
+        if pid == 0 {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                // the child is the leader of its process group, which has not
+                // been reaped yet
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                timed_out = true;
+            } else {
+                thread::sleep(WAIT_POLL_INTERVAL);
+            }
+            continue;
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    join_writer(stdin_writer)?;
+    let stdout = join_reader(stdout_reader)?;
+    let stderr = join_reader(stderr_reader)?;
+
+    // `ru_maxrss` is in bytes on macOS and in KiB elsewhere
+    #[allow(clippy::unnecessary_cast)]
+    let max_rss_kib = if cfg!(target_os = "macos") {
+        rusage.ru_maxrss as u64 / 1024
+    } else {
+        rusage.ru_maxrss as u64
+    };
+
+    let usage = ResourceUsage {
+        max_rss_kib,
+        user_ms: get_timeval_ms(&rusage.ru_utime),
+        system_ms: get_timeval_ms(&rusage.ru_stime),
+    };
+
+    Ok(CapturedOutput {
+        output: Output {
+            status: ExitStatusExt::from_raw(status),
+            stdout,
+            stderr,
+        },
+        usage: Some(usage),
+        timed_out,
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn output_with_usage(
+    command: &mut Command,
+    stdin: Option<StdinInput>,
+    capture: Capture,
+    merge_streams: bool,
+    timeout: Option<Duration>,
+    on_line: Option<&LineSink>,
+) -> Result<CapturedOutput, io::Error> {
+    if stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let (mut child, merged) = spawn_captured(command, capture, merge_streams)?;
+    let stdin_writer = stdin
+        .zip(child.stdin.take())
+        .map(|(input, child_stdin)| write_in_background(input, child_stdin));
+
+    let limit = match capture {
+        Capture::Truncated => Some(MAX_TRUNCATED_OUTPUT_BYTES),
+        _ => None,
+    };
+    let stdout_reader = match merged {
+        Some(merged) => Some(read_in_background(merged, on_line.cloned(), limit)),
+        None => child
+            .stdout
+            .take()
+            .map(|stdout| read_in_background(stdout, on_line.cloned(), limit)),
+    };
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|stderr| read_in_background(stderr, on_line.cloned(), limit));
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                child.kill()?;
+                timed_out = true;
+                break child.wait()?;
+            }
+            _ => thread::sleep(WAIT_POLL_INTERVAL),
+        }
+    };
+
+    join_writer(stdin_writer)?;
+    Ok(CapturedOutput {
+        output: Output {
+            status,
+            stdout: join_reader(stdout_reader)?,
+            stderr: join_reader(stderr_reader)?,
+        },
+        usage: None,
+        timed_out,
+    })
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn get_timeval_ms(tv: &libc::timeval) -> u128 {
+    tv.tv_sec as u128 * 1000 + tv.tv_usec as u128 / 1000
+}
+
+/// Formats a duration for humans, e.g. `850ms`, `4.2s`, `2m14s`, `1h05m`.
+pub fn format_duration_ms(ms: u128) -> String {
+    if ms < 1000 {
+        return format!("{}ms", ms);
+    }
+    if ms < 60 * 1000 {
+        return format!("{:.1}s", ms as f64 / 1000.0);
+    }
+
+    let secs = ms / 1000;
+    if secs < 60 * 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Formats a size given in KiB using binary units, e.g. `512 KiB`, `3.1 GiB`.
+pub fn format_kib(kib: u64) -> String {
+    let units = ["MiB", "GiB", "TiB"];
+    if kib < 1024 {
+        return format!("{} KiB", kib);
+    }
+
+    let mut size = kib as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Returns the timing shown after the status line with `--timings`, e.g.
+/// `(2m14s, peak 3.1 GiB)`.
+pub fn get_timings_str(duration_ms: u128, usage: Option<&ResourceUsage>) -> String {
+    match usage {
+        Some(usage) => format!(
+            "({}, peak {})",
+            format_duration_ms(duration_ms),
+            format_kib(usage.max_rss_kib)
+        ),
+        None => format!("({})", format_duration_ms(duration_ms)),
+    }
+}
+
+#[test]
+fn format_duration_ms_test() {
+    assert_eq!(format_duration_ms(850), "850ms");
+    assert_eq!(format_duration_ms(4210), "4.2s");
+    assert_eq!(format_duration_ms(134_000), "2m14s");
+    assert_eq!(format_duration_ms(3_900_000), "1h05m");
+}
+
+#[test]
+fn format_kib_test() {
+    assert_eq!(format_kib(512), "512 KiB");
+    assert_eq!(format_kib(1536), "1.5 MiB");
+    assert_eq!(format_kib(3_250_586), "3.1 GiB");
+    assert_eq!(format_kib(5 * 1024 * 1024 * 1024), "5.0 TiB");
+}
+
+#[test]
+fn get_timings_str_test() {
+    let usage = ResourceUsage {
+        max_rss_kib: 3_250_586,
+        user_ms: 0,
+        system_ms: 0,
+    };
+
+    assert_eq!(
+        get_timings_str(134_000, Some(&usage)),
+        "(2m14s, peak 3.1 GiB)"
+    );
+    assert_eq!(get_timings_str(850, None), "(850ms)");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn output_with_usage_test() {
+    let mut command = Command::new("sh");
+    command
+        .args([
+            "-c",
+            "x=$(head -c 67108864 /dev/zero | tr '\\0' a); echo done; exit 3",
+        ])
+        .stdin(std::process::Stdio::null());
+
+    let captured = output_with_usage(&mut command, None, Capture::Full, false, None, None).unwrap();
+    let (output, usage) = (captured.output, captured.usage.unwrap());
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"done\n");
+    assert!(usage.max_rss_kib >= 64 * 1024, "{:?}", usage);
+}
+
+#[cfg(unix)]
+#[test]
+fn output_with_usage_merge_streams_test() {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "echo 1; echo 2 >&2; echo 3; echo 4 >&2"])
+        .stdin(Stdio::null());
+
+    let output = output_with_usage(&mut command, None, Capture::Full, true, None, None)
+        .unwrap()
+        .output;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1\n2\n3\n4\n");
+    assert_eq!(output.stderr, b"");
+}
+
+#[cfg(unix)]
+#[test]
+fn output_with_usage_timeout_test() {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "echo started; echo warn >&2; sleep 10; echo never"])
+        .stdin(Stdio::null());
+
+    let start = Instant::now();
+    let captured = output_with_usage(
+        &mut command,
+        None,
+        Capture::Full,
+        false,
+        Some(Duration::from_millis(200)),
+        None,
+    )
+    .unwrap();
+
+    assert!(captured.timed_out);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!captured.output.status.success());
+    assert_eq!(captured.output.stdout, b"started\n");
+    assert_eq!(captured.output.stderr, b"warn\n");
+
+    let mut command = Command::new("true");
+    let captured = output_with_usage(
+        &mut command,
+        None,
+        Capture::Full,
+        false,
+        Some(Duration::from_secs(10)),
+        None,
+    )
+    .unwrap();
+    assert!(!captured.timed_out);
+    assert!(captured.output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn output_with_usage_on_line_test() {
+    use std::sync::Mutex;
+
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "echo 1; echo 2 >&2; printf 3"])
+        .stdin(Stdio::null());
+
+    let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink_lines = lines.clone();
+    let on_line: LineSink = Arc::new(move |line| sink_lines.lock().unwrap().push(line.to_string()));
+    let output = output_with_usage(
+        &mut command,
+        None,
+        Capture::Full,
+        true,
+        None,
+        Some(&on_line),
+    )
+    .unwrap()
+    .output;
+
+    assert_eq!(output.stdout, b"1\n2\n3");
+    assert_eq!(*lines.lock().unwrap(), vec!["1", "2", "3"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn output_with_usage_stdin_test() {
+    // larger than the pipe buffers, so writing and reading must not wait on
+    // each other
+    let input: Vec<u8> = (0..4 * 1024 * 1024)
+        .map(|i| b'a' + (i % 26) as u8)
+        .collect();
+    let mut command = Command::new("cat");
+
+    let output = output_with_usage(
+        &mut command,
+        Some(StdinInput::Data(input.clone())),
+        Capture::Full,
+        false,
+        None,
+        None,
+    )
+    .unwrap()
+    .output;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, input);
+
+    // a child exiting without reading its input is not an error
+    let mut command = Command::new("true");
+    let output = output_with_usage(
+        &mut command,
+        Some(StdinInput::Data(input)),
+        Capture::Full,
+        false,
+        None,
+        None,
+    )
+    .unwrap()
+    .output;
+    assert!(output.status.success());
+}
+
+#[test]
+fn keep_tail_test() {
+    let mut buf = b"0123456789".to_vec();
+    keep_tail(&mut buf, 4);
+    assert_eq!(buf, b"6789");
+
+    // the cut `é` is dropped rather than left half
+    let mut buf = "aé€".as_bytes().to_vec();
+    keep_tail(&mut buf, 4);
+    assert_eq!(buf, "€".as_bytes());
+
+    let mut buf = b"short".to_vec();
+    keep_tail(&mut buf, 64);
+    assert_eq!(buf, b"short");
+}
+
+#[cfg(unix)]
+#[test]
+fn output_with_usage_capture_test() {
+    let mut command = Command::new("sh");
+    command.args(["-c", "seq 1 100000; echo failed >&2"]);
+
+    let output = output_with_usage(&mut command, None, Capture::Truncated, false, None, None)
+        .unwrap()
+        .output;
+    assert!(output.stdout.len() <= MAX_TRUNCATED_OUTPUT_BYTES);
+    assert!(output.stdout.ends_with(b"99999\n100000\n"));
+    assert_eq!(output.stderr, b"failed\n");
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "seq 1 100000; echo failed >&2; exit 2"]);
+
+    let output = output_with_usage(&mut command, None, Capture::None, false, None, None)
+        .unwrap()
+        .output;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+}
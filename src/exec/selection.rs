@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+use std::error::Error;
+
+use super::{get_closest_label, parse_index_prerequisite, split_reference, ExecItem};
+
+/// Returns whether each item of `exec_list` is selected to run: the items
+/// labeled `only`, the items labeled `only_with_deps` and their prerequisites
+/// (transitively), or all items if both are empty, minus the items labeled
+/// `skip`. Labels no item has are an error.
+pub fn get_selected_items(
+    exec_list: &[ExecItem],
+    only: &[String],
+    only_with_deps: &[String],
+    skip: &[String],
+) -> Result<Vec<bool>, Box<dyn Error>> {
+    check_labels(
+        exec_list,
+        only.iter().chain(only_with_deps).chain(skip),
+        "select",
+    )?;
+
+    let has_label = |exec_item: &ExecItem, labels: &[String]| labels.contains(&exec_item.label);
+
+    let mut selected: Vec<bool> = if only.is_empty() && only_with_deps.is_empty() {
+        vec![true; exec_list.len()]
+    } else {
+        exec_list
+            .iter()
+            .map(|exec_item| has_label(exec_item, only))
+            .collect()
+    };
+
+    let mut visited = vec![false; exec_list.len()];
+    let mut queue: VecDeque<usize> = (0..exec_list.len())
+        .filter(|idx| has_label(&exec_list[*idx], only_with_deps))
+        .collect();
+    while let Some(idx) = queue.pop_front() {
+        if visited[idx] {
+            continue;
+        }
+        visited[idx] = true;
+        selected[idx] = true;
+
+        for reference in exec_list[idx].prerequisites.iter().flat_map(|p| p.refs()) {
+            let (prereq, _) = split_reference(reference);
+            let prereq_idxs: Vec<usize> = match parse_index_prerequisite(prereq) {
+                Some(prereq_idx) if prereq_idx >= 1 && prereq_idx <= exec_list.len() => {
+                    vec![prereq_idx - 1]
+                }
+                Some(_) => Vec::new(),
+                None => (0..exec_list.len())
+                    .filter(|prereq_idx| exec_list[*prereq_idx].label == prereq)
+                    .collect(),
+            };
+            queue.extend(prereq_idxs);
+        }
+    }
+
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        if has_label(exec_item, skip) {
+            selected[idx] = false;
+        }
+    }
+
+    Ok(selected)
+}
+
+/// Labels matched by a pattern of `--only`, see `match_label_pattern`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelMatch {
+    Labels(Vec<String>),
+
+    /// Several labels start with a pattern without wildcards
+    Ambiguous(Vec<String>),
+}
+
+/// Returns the labels of `exec_list` matched by `pattern`, trying in order:
+/// the label equal to it, the labels matching it as a glob (`*` and `?`) if it
+/// has wildcards, the labels starting with it. Labels are listed once, in the
+/// order of their first item; no match is an empty list.
+pub fn match_label_pattern(exec_list: &[ExecItem], pattern: &str) -> LabelMatch {
+    let mut labels: Vec<&str> = Vec::new();
+    for exec_item in exec_list {
+        if !exec_item.label.is_empty() && !labels.contains(&exec_item.label.as_str()) {
+            labels.push(&exec_item.label);
+        }
+    }
+
+    if labels.contains(&pattern) {
+        return LabelMatch::Labels(vec![String::from(pattern)]);
+    }
+    if pattern.contains(['*', '?']) {
+        return LabelMatch::Labels(
+            labels
+                .into_iter()
+                .filter(|label| matches_glob(pattern, label))
+                .map(String::from)
+                .collect(),
+        );
+    }
+
+    let prefixed: Vec<String> = labels
+        .into_iter()
+        .filter(|label| label.starts_with(pattern))
+        .map(String::from)
+        .collect();
+    if prefixed.len() > 1 {
+        LabelMatch::Ambiguous(prefixed)
+    } else {
+        LabelMatch::Labels(prefixed)
+    }
+}
+
+/// Returns the labels selected by the patterns of `--only` (see
+/// `match_label_pattern`), `pick` choosing among the labels of an ambiguous
+/// prefix. Patterns matching nothing are kept as they are, to be reported by
+/// `get_selected_items`.
+pub fn resolve_label_patterns<F>(
+    exec_list: &[ExecItem],
+    patterns: &[String],
+    mut pick: F,
+) -> Result<Vec<String>, Box<dyn Error>>
+where
+    F: FnMut(&str, &[String]) -> Result<Vec<String>, Box<dyn Error>>,
+{
+    let mut labels: Vec<String> = Vec::new();
+    for pattern in patterns {
+        let matched = match match_label_pattern(exec_list, pattern) {
+            LabelMatch::Labels(matched) if matched.is_empty() => vec![pattern.clone()],
+            LabelMatch::Labels(matched) => matched,
+            LabelMatch::Ambiguous(candidates) => pick(pattern, &candidates)?,
+        };
+        for label in matched {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+    }
+
+    Ok(labels)
+}
+
+/// Returns whether `text` matches the glob `pattern`, `*` matching any
+/// sequence of characters and `?` any single character.
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Returns whether a failure is injected into each item of `exec_list`
+/// (`--inject-failure`), the items labeled `labels`. Labels no item has are an
+/// error.
+pub fn get_injected_items(
+    exec_list: &[ExecItem],
+    labels: &[String],
+) -> Result<Vec<bool>, Box<dyn Error>> {
+    check_labels(exec_list, labels.iter(), "inject a failure into")?;
+
+    Ok(exec_list
+        .iter()
+        .map(|exec_item| labels.contains(&exec_item.label))
+        .collect())
+}
+
+/// Returns an error naming the first of `labels` no item has, `action` telling
+/// what was to be done with it.
+fn check_labels<'a, I: Iterator<Item = &'a String>>(
+    exec_list: &[ExecItem],
+    labels: I,
+    action: &str,
+) -> Result<(), Box<dyn Error>> {
+    let item_labels: Vec<&str> = exec_list
+        .iter()
+        .map(|item| item.label.as_str())
+        .filter(|label| !label.is_empty())
+        .collect();
+    for label in labels {
+        if !item_labels.contains(&label.as_str()) {
+            let msg = match get_closest_label(label, &item_labels) {
+                Some(closest) => format!(
+                    "Cannot {} {}: no item has this label, did you mean {}?",
+                    action, label, closest
+                ),
+                None => format!("Cannot {} {}: no item has this label", action, label),
+            };
+            return Err(msg.into());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn get_selected_items_test() {
+    let exec_list: Vec<ExecItem> = serde_json::from_str(
+        "[
+            {\"label\": \"fetch\", \"exec\": \"curl\"},
+            {\"label\": \"unpack\", \"exec\": \"tar\", \"prerequisites\": [\"fetch\"]},
+            {\"label\": \"build\", \"exec\": \"make\", \"prerequisites\": [\"#2\"]},
+            {\"label\": \"test\", \"exec\": \"make\", \"prerequisites\": [\"build\"]},
+            {\"exec\": \"ls\"}
+        ]",
+    )
+    .unwrap();
+    let labels = |labels: &[&str]| -> Vec<String> {
+        labels.iter().map(|label| String::from(*label)).collect()
+    };
+    let select = |only: &[&str], only_with_deps: &[&str], skip: &[&str]| {
+        get_selected_items(
+            &exec_list,
+            &labels(only),
+            &labels(only_with_deps),
+            &labels(skip),
+        )
+        .map_err(|e| e.to_string())
+    };
+
+    assert_eq!(select(&[], &[], &[]), Ok(vec![true; 5]));
+    assert_eq!(
+        select(&["build"], &[], &[]),
+        Ok(vec![false, false, true, false, false])
+    );
+    assert_eq!(
+        select(&[], &[], &["build", "test"]),
+        Ok(vec![true, true, false, false, true])
+    );
+    assert_eq!(
+        select(&[], &["build"], &[]),
+        Ok(vec![true, true, true, false, false])
+    );
+    assert_eq!(
+        select(&["test"], &["unpack"], &["fetch"]),
+        Ok(vec![false, true, false, true, false])
+    );
+    assert_eq!(
+        select(&["fetch"], &["test"], &[]),
+        Ok(vec![true, true, true, true, false])
+    );
+
+    assert_eq!(
+        select(&["biuld"], &[], &[]),
+        Err(String::from(
+            "Cannot select biuld: no item has this label, did you mean build?"
+        ))
+    );
+    assert_eq!(
+        select(&[], &[], &["deploy"]),
+        Err(String::from("Cannot select deploy: no item has this label"))
+    );
+}
+
+#[test]
+fn match_label_pattern_test() {
+    let exec_list: Vec<ExecItem> = serde_json::from_str(
+        "[
+            {\"label\": \"deploy\", \"exec\": \"true\"},
+            {\"label\": \"deploy-api\", \"exec\": \"true\"},
+            {\"label\": \"deploy-web\", \"exec\": \"true\"},
+            {\"label\": \"deploy-api\", \"exec\": \"true\"},
+            {\"label\": \"build\", \"exec\": \"make\"},
+            {\"exec\": \"ls\"}
+        ]",
+    )
+    .unwrap();
+    let labels = |labels: &[&str]| -> Vec<String> {
+        labels.iter().map(|label| String::from(*label)).collect()
+    };
+
+    // An exact label wins over the labels it is a prefix of
+    assert_eq!(
+        match_label_pattern(&exec_list, "deploy"),
+        LabelMatch::Labels(labels(&["deploy"]))
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "deploy-*"),
+        LabelMatch::Labels(labels(&["deploy-api", "deploy-web"]))
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "*"),
+        LabelMatch::Labels(labels(&["deploy", "deploy-api", "deploy-web", "build"]))
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "b?ild"),
+        LabelMatch::Labels(labels(&["build"]))
+    );
+    // A glob is never taken as a prefix
+    assert_eq!(
+        match_label_pattern(&exec_list, "dep*-a"),
+        LabelMatch::Labels(vec![])
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "deploy-w"),
+        LabelMatch::Labels(labels(&["deploy-web"]))
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "deploy-"),
+        LabelMatch::Ambiguous(labels(&["deploy-api", "deploy-web"]))
+    );
+    assert_eq!(
+        match_label_pattern(&exec_list, "test"),
+        LabelMatch::Labels(vec![])
+    );
+
+    let mut picked: Vec<String> = Vec::new();
+    let resolved = resolve_label_patterns(
+        &exec_list,
+        &labels(&["deploy-", "bu", "*-web", "biuld"]),
+        |pattern: &str, candidates: &[String]| {
+            picked.push(String::from(pattern));
+            Ok(vec![candidates[0].clone()])
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        resolved,
+        labels(&["deploy-api", "build", "deploy-web", "biuld"])
+    );
+    assert_eq!(picked, labels(&["deploy-"]));
+
+    assert!(!matches_glob("a*b", "a"));
+    assert!(matches_glob("a*b*c", "aXbYbc"));
+    assert!(matches_glob("**", ""));
+}
+
+#[test]
+fn get_injected_items_test() {
+    let exec_list: Vec<ExecItem> = serde_json::from_str(
+        "[{\"label\": \"build\", \"exec\": \"make\"}, {\"exec\": \"ls\"}, {\"label\": \"deploy\", \"exec\": \"rsync\"}]",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_injected_items(&exec_list, &[String::from("deploy")]).unwrap(),
+        vec![false, false, true]
+    );
+    assert_eq!(
+        get_injected_items(&exec_list, &[]).unwrap(),
+        vec![false, false, false]
+    );
+    assert_eq!(
+        get_injected_items(&exec_list, &[String::from("deplyo")])
+            .unwrap_err()
+            .to_string(),
+        "Cannot inject a failure into deplyo: no item has this label, did you mean deploy?"
+    );
+}
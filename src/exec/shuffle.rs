@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{parse_index_prerequisite, split_reference, ExecItem, NansiFile};
+
+/// Small deterministic PRNG (SplitMix64), enough to reproduce a permutation
+/// from its seed
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Returns a seed for `--shuffle` given without one.
+pub fn get_random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+        ^ ((std::process::id() as u64) << 32)
+}
+
+/// Returns the (0-based) indices of the items in a random order determined by
+/// `seed`.
+///
+/// Every item stays after the items its prerequisites refer to. Timer markers
+/// and `serial` items keep their position; the items between them are only
+/// shuffled among each other.
+pub fn get_shuffled_order(exec_list: &[ExecItem], seed: u64) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed);
+    let dependencies = get_dependencies(exec_list);
+    let mut order: Vec<usize> = Vec::with_capacity(exec_list.len());
+
+    let mut segment: Vec<usize> = Vec::new();
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        if exec_item.is_timer() || exec_item.serial {
+            shuffle_segment(&segment, &dependencies, &mut rng, &mut order);
+            segment.clear();
+            order.push(idx);
+        } else {
+            segment.push(idx);
+        }
+    }
+    shuffle_segment(&segment, &dependencies, &mut rng, &mut order);
+
+    order
+}
+
+/// Appends the items of `segment` to `order` in a random order in which every
+/// item follows its dependencies within the segment.
+fn shuffle_segment(
+    segment: &[usize],
+    dependencies: &[Vec<usize>],
+    rng: &mut SplitMix64,
+    order: &mut Vec<usize>,
+) {
+    let mut remaining: Vec<usize> = segment.to_vec();
+    while !remaining.is_empty() {
+        let ready: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|idx| {
+                dependencies[*idx]
+                    .iter()
+                    .all(|dependency| !remaining.contains(dependency))
+            })
+            .collect();
+
+        // dependencies precede their dependents, so the first remaining item
+        // is always ready
+        let next = ready[rng.below(ready.len())];
+
+        remaining.retain(|idx| *idx != next);
+        order.push(next);
+    }
+}
+
+/// Returns the (0-based) indices of the earlier items each item depends on: the
+/// items carrying a label or referred to by an index in its `prerequisites`.
+///
+/// Prerequisites declared after the item are never met when it runs, moving
+/// them before it would change the outcome rather than just the order.
+fn get_dependencies(exec_list: &[ExecItem]) -> Vec<Vec<usize>> {
+    exec_list
+        .iter()
+        .enumerate()
+        .map(|(idx, exec_item)| {
+            let mut dependencies: Vec<usize> = Vec::new();
+            for reference in exec_item.prerequisites.iter().flat_map(|p| p.refs()) {
+                let (prereq, _) = split_reference(reference);
+                match parse_index_prerequisite(prereq) {
+                    Some(prereq_idx) if prereq_idx > 0 && prereq_idx <= idx => {
+                        dependencies.push(prereq_idx - 1);
+                    }
+                    Some(_) => {}
+                    None => dependencies.extend(
+                        exec_list
+                            .iter()
+                            .enumerate()
+                            .take(idx)
+                            .filter(|(_, other)| other.label == prereq)
+                            .map(|(other_idx, _)| other_idx),
+                    ),
+                }
+            }
+            dependencies
+        })
+        .collect()
+}
+
+/// Returns a message for every item `order` runs before an item it depends on,
+/// and for every timer marker or `serial` item it moved.
+pub fn get_order_violations(exec_list: &[ExecItem], order: &[usize]) -> Vec<String> {
+    let dependencies = get_dependencies(exec_list);
+    let mut position = vec![0; exec_list.len()];
+    for (pos, idx) in order.iter().enumerate() {
+        position[*idx] = pos;
+    }
+
+    let mut violations: Vec<String> = Vec::new();
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        if (exec_item.is_timer() || exec_item.serial) && position[idx] != idx {
+            violations.push(format!(
+                "item {} moved from position {} to {}",
+                idx + 1,
+                idx + 1,
+                position[idx] + 1
+            ));
+        }
+        for dependency in &dependencies[idx] {
+            if position[*dependency] > position[idx] {
+                violations.push(format!(
+                    "item {} runs before item {} it depends on",
+                    idx + 1,
+                    dependency + 1
+                ));
+            }
+        }
+    }
+
+    violations
+}
+
+/// Computes `runs` permutations (seeds `seed`, `seed + 1`, ...) without
+/// executing anything and fails if one of them violates the declared order.
+pub fn check_shuffle(nansi_file: &NansiFile, runs: u64, seed: u64) -> Result<(), Box<dyn Error>> {
+    for run in 0..runs {
+        let run_seed = seed.wrapping_add(run);
+        let order = get_shuffled_order(&nansi_file.exec_list, run_seed);
+        let violations = get_order_violations(&nansi_file.exec_list, &order);
+        if !violations.is_empty() {
+            return Err(format!(
+                "{}: permutation with seed {} violates the declared order:\n{}",
+                nansi_file.file_path,
+                run_seed,
+                violations.join("\n")
+            )
+            .into());
+        }
+    }
+
+    println!(
+        "{} permutation(s) starting at seed {} respect the declared order.",
+        runs, seed
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn parse_exec_list(items: &[&str]) -> Vec<ExecItem> {
+    items
+        .iter()
+        .map(|item| serde_json::from_str(item).unwrap())
+        .collect()
+}
+
+#[test]
+fn get_shuffled_order_test() {
+    let exec_list = parse_exec_list(&[
+        "{\"label\": \"a\", \"exec\": \"ls\"}",
+        "{\"label\": \"b\", \"exec\": \"ls\"}",
+        "{\"exec\": \"ls\", \"prerequisites\": [\"a\"]}",
+        "{\"exec\": \"ls\", \"prerequisites\": [\"#2\"]}",
+        "{\"exec\": \"ls\"}",
+    ]);
+
+    assert_eq!(get_shuffled_order(&exec_list, 1), vec![4, 1, 0, 3, 2]);
+    assert_eq!(get_shuffled_order(&exec_list, 7), vec![0, 1, 2, 4, 3]);
+    assert_eq!(
+        get_shuffled_order(&exec_list, 1),
+        get_shuffled_order(&exec_list, 1)
+    );
+
+    for seed in 0..200 {
+        let order = get_shuffled_order(&exec_list, seed);
+        assert_eq!(
+            get_order_violations(&exec_list, &order),
+            Vec::<String>::new()
+        );
+
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+    }
+}
+
+#[test]
+fn get_shuffled_order_fixed_positions_test() {
+    let exec_list = parse_exec_list(&[
+        "{\"exec\": \"ls\"}",
+        "{\"exec\": \"ls\"}",
+        "{\"exec\": \"ls\", \"serial\": true}",
+        "{\"exec\": \"ls\"}",
+        "{\"timer_start\": \"t\"}",
+        "{\"exec\": \"ls\"}",
+    ]);
+
+    for seed in 0..50 {
+        let order = get_shuffled_order(&exec_list, seed);
+        assert_eq!(order[2], 2);
+        assert_eq!(order[3], 3);
+        assert_eq!(order[4], 4);
+        assert_eq!(order[5], 5);
+    }
+}
+
+#[test]
+fn get_order_violations_test() {
+    let exec_list = parse_exec_list(&[
+        "{\"label\": \"a\", \"exec\": \"ls\"}",
+        "{\"exec\": \"ls\", \"prerequisites\": [\"a\"]}",
+        "{\"exec\": \"ls\", \"serial\": true}",
+    ]);
+
+    assert!(get_order_violations(&exec_list, &[0, 1, 2]).is_empty());
+    assert_eq!(
+        get_order_violations(&exec_list, &[1, 0, 2]),
+        vec!["item 2 runs before item 1 it depends on"]
+    );
+    assert_eq!(
+        get_order_violations(&exec_list, &[0, 2, 1]),
+        vec!["item 3 moved from position 3 to 2"]
+    );
+}
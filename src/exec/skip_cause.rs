@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use super::{
+    parse_index_prerequisite, split_reference, ExecItem, ExecResult, ExecStatus, ItemDisplay,
+    Prerequisite,
+};
+
+/// Returns the index of the failed item that item `idx`, skipped for the
+/// prerequisites `unmet`, traces back to: a referenced item which failed, or
+/// the root cause of a referenced item which was itself skipped. Only the
+/// `results` of the items which ran so far are considered.
+pub fn get_skip_root_cause(
+    exec_list: &[ExecItem],
+    idx: usize,
+    unmet: &[&Prerequisite],
+    results: &[ExecResult],
+) -> Option<usize> {
+    unmet
+        .iter()
+        .flat_map(|prereq| prereq.refs())
+        .flat_map(|reference| get_ref_indices(exec_list, idx, reference))
+        .find_map(
+            |ref_idx| match results.iter().find(|result| result.index == ref_idx) {
+                Some(result) if result.status == ExecStatus::ERR.as_str() => Some(ref_idx),
+                Some(result) => result.skip_root_cause,
+                None => None,
+            },
+        )
+}
+
+/// Returns the indices of the items before item `idx` a prerequisite
+/// reference points at, the closest first.
+fn get_ref_indices(exec_list: &[ExecItem], idx: usize, reference: &str) -> Vec<usize> {
+    let (reference, _) = split_reference(reference);
+    match parse_index_prerequisite(reference) {
+        Some(ref_idx) if ref_idx < idx => vec![ref_idx],
+        Some(_) => Vec::new(),
+        None => (1..idx)
+            .rev()
+            .filter(|ref_idx| {
+                exec_list
+                    .get(ref_idx - 1)
+                    .is_some_and(|item| item.label == reference)
+            })
+            .collect(),
+    }
+}
+
+/// Returns a summary line per failed item which caused other items to be
+/// skipped, e.g. `7 item(s) skipped due to failure of [2][fetch].`
+pub fn get_skip_root_cause_lines(exec_list: &[ExecItem], results: &[ExecResult]) -> Vec<String> {
+    let mut skip_counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for root_cause in results.iter().filter_map(|result| result.skip_root_cause) {
+        *skip_counts.entry(root_cause).or_insert(0) += 1;
+    }
+
+    skip_counts
+        .into_iter()
+        .filter_map(|(root_cause, count)| {
+            exec_list.get(root_cause - 1).map(|exec_item| {
+                format!(
+                    "{} item(s) skipped due to failure of {}.",
+                    count,
+                    ItemDisplay::new(exec_item, root_cause)
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn simulate_skip_root_causes(exec_list: &[ExecItem], failed: &[usize]) -> Vec<ExecResult> {
+    use super::{RefState, Satisfaction};
+
+    struct Results<'a>(&'a [ExecItem], &'a [ExecResult]);
+
+    impl Satisfaction for Results<'_> {
+        fn get_state(&self, reference: &str) -> RefState {
+            let states: Vec<&str> = get_ref_indices(self.0, self.0.len() + 1, reference)
+                .into_iter()
+                .filter_map(|idx| self.1.iter().find(|result| result.index == idx))
+                .map(|result| result.status.as_str())
+                .collect();
+            match states.first() {
+                Some(&"ok") => RefState::Ok,
+                Some(&"err") => RefState::Failed,
+                _ => RefState::Skipped,
+            }
+        }
+    }
+
+    let mut results: Vec<ExecResult> = Vec::new();
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        let mut result = ExecResult::not_run(exec_item, idx + 1);
+        let unmet: Vec<&Prerequisite> = exec_item
+            .prerequisites
+            .iter()
+            .filter(|prereq| !prereq.is_met(&Results(exec_list, &results)))
+            .collect();
+        result.status = if !unmet.is_empty() {
+            result.skip_root_cause = get_skip_root_cause(exec_list, idx + 1, &unmet, &results);
+            String::from("skip")
+        } else if failed.contains(&(idx + 1)) {
+            String::from("err")
+        } else {
+            String::from("ok")
+        };
+        results.push(result);
+    }
+
+    results
+}
+
+#[test]
+fn skip_root_cause_test() {
+    let exec_list: Vec<ExecItem> = serde_json::from_str(
+        "[
+            {\"label\": \"setup\", \"exec\": \"true\"},
+            {\"label\": \"fetch\", \"exec\": \"curl\"},
+            {\"label\": \"unpack\", \"exec\": \"tar\", \"prerequisites\": [\"fetch\"]},
+            {\"label\": \"build\", \"exec\": \"make\", \"prerequisites\": [\"setup\", \"unpack\"]},
+            {\"label\": \"lint\", \"exec\": \"false\"},
+            {\"exec\": \"test\", \"prerequisites\": [\"#4\", \"lint\"]},
+            {\"exec\": \"report\", \"prerequisites\": [{\"any_of\": [\"lint\", \"setup\"], \"min\": 2}]},
+            {\"exec\": \"upload\", \"prerequisites\": [\"missing\"]}
+        ]",
+    )
+    .unwrap();
+
+    let results = simulate_skip_root_causes(&exec_list, &[2, 5]);
+    let root_causes: Vec<(usize, &str, Option<usize>)> = results
+        .iter()
+        .map(|result| (result.index, result.status.as_str(), result.skip_root_cause))
+        .collect();
+    assert_eq!(
+        root_causes,
+        vec![
+            (1, "ok", None),
+            (2, "err", None),
+            (3, "skip", Some(2)),
+            (4, "skip", Some(2)),
+            (5, "err", None),
+            (6, "skip", Some(2)),
+            (7, "skip", Some(5)),
+            (8, "skip", None),
+        ]
+    );
+
+    assert_eq!(
+        get_skip_root_cause_lines(&exec_list, &results),
+        vec![
+            "3 item(s) skipped due to failure of [2][fetch].",
+            "1 item(s) skipped due to failure of [5][lint].",
+        ]
+    );
+    assert!(get_skip_root_cause_lines(&exec_list, &results[..2]).is_empty());
+}
@@ -0,0 +1,730 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use super::{
+    create_tmp_dir, get_cache_path, get_warning_str, is_compiled_path, resolve_includes,
+    sha256_hex, CompiledNansiFile, NansiFile, OutputStream, SourceHash,
+};
+
+/// Maximum size of a `NansiFile` fetched from a URL
+pub const MAX_URL_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Maximum time in seconds fetching a `NansiFile` from a URL may take
+pub const URL_TIMEOUT_SECS: u64 = 30;
+
+/// Maximum number of redirects followed when fetching a `NansiFile`
+pub const MAX_URL_REDIRECTS: u32 = 10;
+
+/// Namespace and principal of the ssh signatures checked by `--verify-key`
+pub const SIGNATURE_NAMESPACE: &str = "nansi";
+
+/// Syntax of a `NansiFile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NansiFormat {
+    /// Yaml for `.yaml` and `.yml` files, json otherwise
+    #[default]
+    Auto,
+
+    Json,
+
+    Yaml,
+}
+
+impl FromStr for NansiFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NansiFormat, String> {
+        match s {
+            "auto" => Ok(NansiFormat::Auto),
+            "json" => Ok(NansiFormat::Json),
+            "yaml" => Ok(NansiFormat::Yaml),
+            _ => Err(format!(
+                "unknown format: {} (expected auto, json or yaml)",
+                s
+            )),
+        }
+    }
+}
+
+impl NansiFormat {
+    /// Resolves `Auto` from the extension of `source` (a path or URL, whose
+    /// query and fragment are ignored).
+    pub fn resolve(self, source: &str) -> NansiFormat {
+        if self != NansiFormat::Auto {
+            return self;
+        }
+
+        let path = source.split(['?', '#']).next().unwrap_or(source);
+        let extension = match path.rsplit_once('.') {
+            Some((_, extension)) if !extension.contains('/') => extension.to_ascii_lowercase(),
+            _ => String::new(),
+        };
+        match extension.as_str() {
+            "yaml" | "yml" => NansiFormat::Yaml,
+            _ => NansiFormat::Json,
+        }
+    }
+}
+
+/// Options controlling how a `NansiFile` is loaded and verified
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceOptions {
+    /// Parser to use, detected from the extension by default
+    pub format: NansiFormat,
+
+    /// Allow fetching from a plain `http://` URL
+    pub insecure_http: bool,
+
+    /// Hex SHA-256 digest the file bytes must match
+    pub expect_sha256: Option<String>,
+
+    /// Public ssh key the detached signature (`<source>.sig`) must be made with
+    pub verify_key: Option<String>,
+}
+
+/// `file_path` of a `NansiFile` given on the command line with `--inline`
+pub const INLINE_FILE_PATH: &str = "<inline>";
+
+/// Parses a `NansiFile` given on the command line with `--inline`, a json
+/// object or a bare array of items.
+pub fn load_inline_nansi_file(content: &str) -> Result<NansiFile, io::Error> {
+    NansiFile::parse(content, INLINE_FILE_PATH, NansiFormat::Json).map_err(|e| {
+        let msg = e.to_string();
+        let msg = msg
+            .strip_prefix(&format!("{}: ", INLINE_FILE_PATH))
+            .unwrap_or(&msg);
+        io::Error::new(
+            e.kind(),
+            format!("Invalid NansiFile given with --inline: {}", msg),
+        )
+    })
+}
+
+/// Returns `true` if `source` is a `http://` or `https://` URL.
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Loads a `NansiFile` from a path, a `file://` URL or a `https://` URL
+/// (`http://` only with `insecure_http`).
+///
+/// Files fetched from a URL get the final URL (after redirects) as their
+/// `file_path`; relative paths cannot be resolved against it.
+///
+/// The checksum and the signature are checked against the raw bytes before
+/// they are parsed.
+///
+/// A compiled `NansiFile` (see `CompiledNansiFile`) is loaded as is while its
+/// sources are unchanged, the file it was compiled from otherwise. A local file
+/// is loaded from its compiled `NansiFile` if one is next to it and up to date,
+/// unless its content is verified: the compiled file is not what was checked.
+pub fn load_nansi_file(source: &str, options: &SourceOptions) -> Result<NansiFile, io::Error> {
+    let (content, file_path) = read_nansi_file(source, options)?;
+
+    if is_compiled_path(&file_path) {
+        return load_compiled_nansi_file(&content, &file_path, options);
+    }
+    let verified = options.expect_sha256.is_some() || options.verify_key.is_some();
+    if !is_url(source) && !verified {
+        if let Some(nansi_file) = load_cached_nansi_file(&content, &file_path) {
+            return Ok(nansi_file);
+        }
+    }
+
+    NansiFile::parse(&content, &file_path, options.format.resolve(source))
+}
+
+/// Parses the local `NansiFile` at `source` with its includes resolved, along
+/// with the digests of the files it was made from (`--compile`).
+pub fn compile_nansi_file(
+    source: &str,
+    options: &SourceOptions,
+) -> Result<CompiledNansiFile, io::Error> {
+    if is_url(source) || is_compiled_path(source) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: only local NansiFiles can be compiled", source),
+        ));
+    }
+
+    let (content, file_path) = read_nansi_file(source, options)?;
+    let mut nansi_file = match options.format.resolve(source) {
+        NansiFormat::Yaml => NansiFile::from_yaml(&content, &file_path)?,
+        _ => NansiFile::from_json(&content, &file_path)?,
+    };
+    let canonical = match fs::canonicalize(&file_path) {
+        Ok(v) => v,
+        Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+    };
+
+    let mut sources = vec![SourceHash {
+        path: canonical.to_string_lossy().to_string(),
+        sha256: nansi_file.sha256.clone(),
+    }];
+    sources.extend(resolve_includes(&mut nansi_file)?);
+
+    Ok(CompiledNansiFile::new(nansi_file, sources))
+}
+
+/// Loads the compiled `NansiFile` read from `file_path`, or the file it was
+/// compiled from if it is out of date (an error if its content was verified).
+fn load_compiled_nansi_file(
+    content: &str,
+    file_path: &str,
+    options: &SourceOptions,
+) -> Result<NansiFile, io::Error> {
+    let compiled = CompiledNansiFile::from_json(content, file_path)?;
+    let root = compiled.sources[0].path.clone();
+
+    match compiled.get_stale_reason() {
+        None => {
+            let mut nansi_file = compiled.nansi_file;
+            nansi_file.file_path = root;
+            Ok(nansi_file)
+        }
+        Some(reason) if options.expect_sha256.is_some() || options.verify_key.is_some() => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: out of date, {}", file_path, reason),
+            ))
+        }
+        Some(reason) => {
+            eprintln!(
+                "{}",
+                get_warning_str(
+                    OutputStream::Stderr,
+                    &format!("Ignoring {}: {}, loading {}", file_path, reason, root)
+                )
+            );
+            NansiFile::from(&root)
+        }
+    }
+}
+
+/// Returns the `NansiFile` of the compiled `NansiFile` next to the file at
+/// `file_path` read as `content`, if there is one and it is up to date.
+fn load_cached_nansi_file(content: &str, file_path: &str) -> Option<NansiFile> {
+    let cache_path = get_cache_path(file_path);
+    if !cache_path.is_file() {
+        return None;
+    }
+    let cache_path = cache_path.to_string_lossy().to_string();
+    let ignore = |reason: String| {
+        eprintln!(
+            "{}",
+            get_warning_str(
+                OutputStream::Stderr,
+                &format!("Ignoring {}: {}", cache_path, reason)
+            )
+        );
+        None
+    };
+
+    let compiled = match CompiledNansiFile::from(&cache_path) {
+        Ok(v) => v,
+        Err(e) => return ignore(e.to_string()),
+    };
+    let root = &compiled.sources[0];
+    if !fs::canonicalize(file_path).is_ok_and(|path| path.to_string_lossy() == root.path) {
+        return ignore(format!("compiled from {}", root.path));
+    }
+    if root.sha256 != sha256_hex(content.as_bytes()) {
+        return ignore(format!("{} changed", root.path));
+    }
+    if let Some(reason) = compiled.get_stale_reason() {
+        return ignore(reason);
+    }
+
+    let mut nansi_file = compiled.nansi_file;
+    nansi_file.file_path = String::from(file_path);
+
+    Some(nansi_file)
+}
+
+/// Returns the content of `source` as text once its checksum and signature
+/// are checked, and the path (or final URL) it comes from.
+fn read_nansi_file(source: &str, options: &SourceOptions) -> Result<(String, String), io::Error> {
+    let (content, file_path) = read_source(source, options.insecure_http)?;
+
+    if let Some(expected) = &options.expect_sha256 {
+        check_sha256(source, &content, expected)?;
+    }
+    if let Some(key_path) = &options.verify_key {
+        let (signature, _) = read_source(&format!("{}.sig", source), options.insecure_http)?;
+        verify_signature(source, &content, &signature, key_path)?;
+    }
+
+    let content = match String::from_utf8(content) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", file_path, e),
+            ));
+        }
+    };
+
+    Ok((content, file_path))
+}
+
+/// Returns the bytes of `source` and the path (or final URL) they come from.
+fn read_source(source: &str, insecure_http: bool) -> Result<(Vec<u8>, String), io::Error> {
+    if !is_url(source) {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        return match fs::read(path) {
+            Ok(v) => Ok((v, String::from(path))),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", path, e))),
+        };
+    }
+
+    if source.starts_with("http://") && !insecure_http {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: plain http is refused, use https or pass --insecure-http",
+                source
+            ),
+        ));
+    }
+
+    fetch_url(source, insecure_http)
+}
+
+/// Fails unless the SHA-256 digest of `content` is `expected` (hex, any case).
+fn check_sha256(source: &str, content: &[u8], expected: &str) -> Result<(), io::Error> {
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--expect-sha256: \"{}\" is not a hex SHA-256 digest (64 characters)",
+                expected
+            ),
+        ));
+    }
+
+    let actual = sha256_hex(content);
+    if actual != expected.to_ascii_lowercase() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: sha256 mismatch, expected {}, got {}",
+                source,
+                expected.to_ascii_lowercase(),
+                actual
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails unless `signature` is an ssh signature of `content` made with the
+/// public key in `key_path` (`ssh-keygen -Y sign -n nansi -f <key> <file>`).
+fn verify_signature(
+    source: &str,
+    content: &[u8],
+    signature: &[u8],
+    key_path: &str,
+) -> Result<(), io::Error> {
+    let key = match fs::read_to_string(key_path) {
+        Ok(v) => v,
+        Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", key_path, e))),
+    };
+
+    let tmp_dir = TempDir::new()?;
+    let allowed_signers = tmp_dir.write(
+        "allowed_signers",
+        format!("{} {}\n", SIGNATURE_NAMESPACE, key.trim()).as_bytes(),
+    )?;
+    let signature_file = tmp_dir.write("sig", signature)?;
+
+    let mut child = match Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-f"])
+        .arg(&allowed_signers)
+        .args(["-I", SIGNATURE_NAMESPACE, "-n", SIGNATURE_NAMESPACE, "-s"])
+        .arg(&signature_file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("{}: ssh-keygen: {}", source, e),
+            ));
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content)?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        // `ssh-keygen` reports a bad signature on stdout, other errors on stderr
+        let message = format!(
+            "{} {}",
+            String::from_utf8_lossy(&output.stdout).trim(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: signature verification with {} failed: {}",
+                source,
+                key_path,
+                message.trim()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Directory only accessible by the current user in the temporary directory,
+/// removed with its files when dropped
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new() -> Result<TempDir, io::Error> {
+        Ok(TempDir {
+            path: create_tmp_dir()?,
+        })
+    }
+
+    /// Writes `content` to the new file `name` only readable by the current
+    /// user and returns its path; an existing file is never written to.
+    fn write(&self, name: &str, content: &[u8]) -> Result<PathBuf, io::Error> {
+        let path = self.path.join(name);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let written = options
+            .open(&path)
+            .and_then(|mut file| file.write_all(content));
+        if let Err(e) = written {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            ));
+        }
+
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Fetches `url`, following redirects, and returns the content and the final
+/// URL. Only https is used unless `insecure_http`, redirects included.
+#[cfg(feature = "url")]
+fn fetch_url(url: &str, insecure_http: bool) -> Result<(Vec<u8>, String), io::Error> {
+    let agent = ureq::AgentBuilder::new()
+        .https_only(!insecure_http)
+        .timeout(std::time::Duration::from_secs(URL_TIMEOUT_SECS))
+        .redirects(MAX_URL_REDIRECTS)
+        .build();
+
+    let response = match agent.get(url).call() {
+        Ok(v) => v,
+        Err(ureq::Error::Status(code, response)) => {
+            return Err(io::Error::other(format!(
+                "{}: HTTP {} {}",
+                url,
+                code,
+                response.status_text()
+            )));
+        }
+        Err(ureq::Error::Transport(e)) => {
+            return Err(io::Error::other(format!("{}: {}", url, e)));
+        }
+    };
+
+    let final_url = String::from(response.get_url());
+    let content = read_capped(url, response.into_reader())?;
+
+    Ok((content, final_url))
+}
+
+#[cfg(not(feature = "url"))]
+fn fetch_url(url: &str, _insecure_http: bool) -> Result<(Vec<u8>, String), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}: nansi was built without the url feature", url),
+    ))
+}
+
+/// Reads the content fetched from `url`, failing as soon as it is larger than
+/// `MAX_URL_SIZE` rather than once it was read entirely.
+#[cfg(feature = "url")]
+fn read_capped(url: &str, reader: impl io::Read) -> Result<Vec<u8>, io::Error> {
+    use std::io::Read;
+
+    let mut content: Vec<u8> = Vec::new();
+    if let Err(e) = reader.take(MAX_URL_SIZE + 1).read_to_end(&mut content) {
+        return Err(io::Error::new(e.kind(), format!("{}: {}", url, e)));
+    }
+    if content.len() as u64 > MAX_URL_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: larger than {} bytes", url, MAX_URL_SIZE),
+        ));
+    }
+
+    Ok(content)
+}
+
+#[test]
+fn is_url_test() {
+    assert!(is_url("https://example.com/setup.json"));
+    assert!(is_url("http://example.com/setup.json"));
+    assert!(!is_url("file:///srv/setup.json"));
+    assert!(!is_url("setup.json"));
+}
+
+#[cfg(unix)]
+#[test]
+fn temp_dir_test() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.write("sig", b"signature").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"signature");
+    let mode = |path: &PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&tmp_dir.path), 0o700);
+    assert_eq!(mode(&path), 0o600);
+    assert!(tmp_dir.write("sig", b"other").is_err());
+    assert_eq!(fs::read(&path).unwrap(), b"signature");
+
+    let dir = tmp_dir.path.clone();
+    drop(tmp_dir);
+    assert!(!dir.exists());
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn read_capped_test() {
+    let url = "https://example.com/setup.json";
+    let content = read_capped(url, &b"{\"exec_list\": []}"[..]).unwrap();
+    assert_eq!(content, b"{\"exec_list\": []}");
+
+    // an endless body fails once past the limit
+    let err = read_capped(url, io::repeat(b' ')).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        format!("{}: larger than {} bytes", url, MAX_URL_SIZE)
+    );
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn fetch_url_test() {
+    use std::io::BufRead;
+    use std::net::TcpListener;
+
+    // answers each request for `/old` with a redirect to `/new`
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(3) {
+            let mut stream = stream.unwrap();
+            let mut request_line = String::new();
+            io::BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .unwrap();
+            let response = if request_line.starts_with("GET /old ") {
+                "HTTP/1.1 302 Found\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n"
+            } else if request_line.starts_with("GET /new ") {
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]"
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let (content, final_url) = fetch_url(&format!("{}/old", base), true).unwrap();
+    assert_eq!(content, b"[]");
+    assert_eq!(final_url, format!("{}/new", base));
+
+    let err = fetch_url(&format!("{}/missing", base), true).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("{}/missing: HTTP 404 Not Found", base)
+    );
+
+    assert!(fetch_url(&format!("{}/old", base), false).is_err());
+}
+
+#[test]
+fn load_nansi_file_refuses_http_test() {
+    let err =
+        load_nansi_file("http://example.com/setup.json", &SourceOptions::default()).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "http://example.com/setup.json: plain http is refused, use https or pass --insecure-http"
+    );
+}
+
+#[test]
+fn nansi_format_resolve_test() {
+    let cases = [
+        (NansiFormat::Auto, "setup.json", NansiFormat::Json),
+        (NansiFormat::Auto, "setup.yaml", NansiFormat::Yaml),
+        (NansiFormat::Auto, "dir/setup.YML", NansiFormat::Yaml),
+        (NansiFormat::Auto, "setup", NansiFormat::Json),
+        (NansiFormat::Auto, "conf.d/setup", NansiFormat::Json),
+        (
+            NansiFormat::Auto,
+            "https://example.com/setup.yml?ref=main",
+            NansiFormat::Yaml,
+        ),
+        (NansiFormat::Json, "setup.yaml", NansiFormat::Json),
+        (NansiFormat::Yaml, "setup", NansiFormat::Yaml),
+    ];
+
+    for (format, source, expected) in cases {
+        assert_eq!(format.resolve(source), expected, "{:?} {}", format, source);
+    }
+}
+
+#[test]
+fn check_sha256_test() {
+    let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    assert!(check_sha256("setup.json", b"abc", digest).is_ok());
+    assert!(check_sha256("setup.json", b"abc", &digest.to_ascii_uppercase()).is_ok());
+
+    let err = check_sha256("setup.json", b"abd", digest).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err
+        .to_string()
+        .starts_with("setup.json: sha256 mismatch, expected ba7816bf"));
+
+    let err = check_sha256("setup.json", b"abc", "ba7816bf").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[cfg(test)]
+fn write_compile_test_files(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nansi_compile_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("setup.json"),
+        r#"[{"label": "start", "exec": "echo"}, {"include": "common.json"}]"#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("common.json"),
+        r#"[{"label": "common", "exec": "echo"}]"#,
+    )
+    .unwrap();
+
+    dir
+}
+
+#[cfg(test)]
+fn get_labels(nansi_file: &NansiFile) -> Vec<&str> {
+    nansi_file
+        .exec_list
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect()
+}
+
+#[test]
+fn load_nansi_file_compiled_cache_test() {
+    let dir = write_compile_test_files("cache");
+    let setup = dir.join("setup.json").to_string_lossy().to_string();
+    let options = SourceOptions::default();
+
+    // miss: no compiled file next to the source
+    let nansi_file = load_nansi_file(&setup, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "common"]);
+
+    let mut compiled = compile_nansi_file(&setup, &options).unwrap();
+    assert_eq!(compiled.sources.len(), 2);
+    assert_eq!(compiled.nansi_file, nansi_file);
+
+    // hit: the compiled file is used (marked to tell it from the source)
+    compiled.nansi_file.exec_list[1].label = String::from("cached");
+    let cache_path = get_cache_path(&setup).to_string_lossy().to_string();
+    compiled.save(&cache_path).unwrap();
+
+    let nansi_file = load_nansi_file(&setup, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "cached"]);
+    assert_eq!(nansi_file.file_path, setup);
+    let nansi_file = load_nansi_file(&cache_path, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "cached"]);
+
+    // verified: the checked source is loaded, not the compiled file next to it
+    let verified = SourceOptions {
+        expect_sha256: Some(sha256_hex(&fs::read(&setup).unwrap())),
+        ..Default::default()
+    };
+    let nansi_file = load_nansi_file(&setup, &verified).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "common"]);
+
+    // partial change: an included file changed, the sources are loaded
+    fs::write(
+        dir.join("common.json"),
+        r#"[{"label": "changed", "exec": "echo"}]"#,
+    )
+    .unwrap();
+    assert!(compiled
+        .get_stale_reason()
+        .unwrap()
+        .ends_with("common.json changed"));
+
+    let nansi_file = load_nansi_file(&setup, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "changed"]);
+    let nansi_file = load_nansi_file(&cache_path, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["start", "changed"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn load_nansi_file_compiled_root_change_test() {
+    let dir = write_compile_test_files("root");
+    let setup = dir.join("setup.json").to_string_lossy().to_string();
+    let options = SourceOptions::default();
+
+    let mut compiled = compile_nansi_file(&setup, &options).unwrap();
+    compiled.nansi_file.exec_list[0].label = String::from("cached");
+    let cache_path = get_cache_path(&setup).to_string_lossy().to_string();
+    compiled.save(&cache_path).unwrap();
+
+    fs::write(&setup, r#"[{"label": "edited", "exec": "echo"}]"#).unwrap();
+    let nansi_file = load_nansi_file(&setup, &options).unwrap();
+    assert_eq!(get_labels(&nansi_file), vec!["edited"]);
+
+    // a verified compiled file is never replaced with its sources
+    let options = SourceOptions {
+        expect_sha256: Some(sha256_hex(&fs::read(&cache_path).unwrap())),
+        ..Default::default()
+    };
+    let err = load_nansi_file(&cache_path, &options).unwrap_err();
+    assert!(err.to_string().contains("out of date, "));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// State persisted between runs, keyed by the canonical path of each `NansiFile`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct State {
+    #[serde(default)]
+    pub files: BTreeMap<String, FileState>,
+}
+
+/// State of a single `NansiFile`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileState {
+    /// `idempotence_key` of the last successful run of each item, keyed by
+    /// the item's label (or `#<index>` for unlabeled items)
+    #[serde(default)]
+    pub idempotence_keys: BTreeMap<String, String>,
+}
+
+impl State {
+    /// Returns the default location of the state file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|dir| dir.join("nansi").join("state.json"))
+    }
+
+    /// Loads the state from `path`; a missing file yields an empty state.
+    pub fn load(path: &Path) -> Result<State, io::Error> {
+        let state_str = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", path.display(), e),
+                ));
+            }
+        };
+
+        match serde_json::from_str(state_str.as_str()) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )),
+        }
+    }
+
+    /// Writes the state to `path`, creating missing parent directories.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let state_str = serde_json::to_string_pretty(self)?;
+        match fs::write(path, state_str) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            )),
+        }
+    }
+
+    pub fn get_idempotence_key(&self, file: &str, item_id: &str) -> Option<&String> {
+        match self.files.get(file) {
+            Some(file_state) => file_state.idempotence_keys.get(item_id),
+            None => None,
+        }
+    }
+
+    pub fn set_idempotence_key(&mut self, file: &str, item_id: &str, key: &str) {
+        self.files
+            .entry(String::from(file))
+            .or_default()
+            .idempotence_keys
+            .insert(String::from(item_id), String::from(key));
+    }
+
+    pub fn remove_idempotence_key(&mut self, file: &str, item_id: &str) {
+        if let Some(file_state) = self.files.get_mut(file) {
+            file_state.idempotence_keys.remove(item_id);
+        }
+    }
+}
+
+#[test]
+fn state_round_trip_test() {
+    let path = std::env::temp_dir()
+        .join(format!("nansi_state_test_{}", std::process::id()))
+        .join("state.json");
+
+    let mut state = State::load(&path).unwrap();
+    assert!(state.files.is_empty());
+
+    state.set_idempotence_key("/tmp/file.json", "install", "v1");
+    state.set_idempotence_key("/tmp/file.json", "#2", "v2");
+    state.save(&path).unwrap();
+
+    let mut state = State::load(&path).unwrap();
+    assert_eq!(
+        state.get_idempotence_key("/tmp/file.json", "install"),
+        Some(&String::from("v1"))
+    );
+
+    state.remove_idempotence_key("/tmp/file.json", "install");
+    assert_eq!(state.get_idempotence_key("/tmp/file.json", "install"), None);
+    assert_eq!(
+        state.get_idempotence_key("/tmp/file.json", "#2"),
+        Some(&String::from("v2"))
+    );
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::io::{self, BufRead};
+
+use super::{get_closest_label, shift_index_prerequisites, ExecItem, NansiFile};
+
+/// Name of the tag expanding to the line of the standard input a copy of an
+/// item made by `--stdin-each` runs with
+pub const STDIN_ITEM_VAR: &str = "item";
+
+/// Returns the lines of `reader` trimmed, blank lines left out.
+pub fn read_stdin_items<R: BufRead>(reader: R) -> Result<Vec<String>, io::Error> {
+    let mut items: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            items.push(String::from(line));
+        }
+    }
+
+    Ok(items)
+}
+
+/// Replaces the items of `nansi_file` labeled `label` with a copy per line of
+/// `items`, each expanding `{item}` to its line (`--stdin-each`).
+///
+/// The copies keep the label, so a prerequisite on it is met as for items
+/// sharing a label (see `LabelState`); `#N` prerequisites are renumbered to
+/// keep pointing at the same items, one on a replaced item pointing at its last
+/// copy (or the item before it if there are no lines).
+pub fn expand_stdin_each(
+    nansi_file: &mut NansiFile,
+    label: &str,
+    items: &[String],
+) -> Result<(), Box<dyn Error>> {
+    if !nansi_file
+        .exec_list
+        .iter()
+        .any(|exec_item| exec_item.label == label)
+    {
+        let labels: Vec<&str> = nansi_file
+            .exec_list
+            .iter()
+            .map(|exec_item| exec_item.label.as_str())
+            .filter(|label| !label.is_empty())
+            .collect();
+        let msg = match get_closest_label(label, &labels) {
+            Some(closest) => format!(
+                "Cannot use --stdin-each with {}: no item has this label, did you mean {}?",
+                label, closest
+            ),
+            None => format!(
+                "Cannot use --stdin-each with {}: no item has this label",
+                label
+            ),
+        };
+        return Err(msg.into());
+    }
+
+    let exec_list = std::mem::take(&mut nansi_file.exec_list);
+    let mut expanded: Vec<ExecItem> = Vec::new();
+    // 1-based index of each item of `exec_list` once expanded
+    let mut new_indexes: Vec<usize> = Vec::with_capacity(exec_list.len());
+
+    for exec_item in exec_list {
+        if exec_item.label != label {
+            expanded.push(exec_item);
+        } else {
+            for item in items {
+                let mut copy = exec_item.clone();
+                copy.stdin_item = Some(item.clone());
+                expanded.push(copy);
+            }
+        }
+        new_indexes.push(expanded.len());
+    }
+
+    for exec_item in &mut expanded {
+        shift_index_prerequisites(exec_item, |prereq_idx| {
+            new_indexes
+                .get(prereq_idx - 1)
+                .copied()
+                .unwrap_or(prereq_idx)
+        });
+    }
+    nansi_file.exec_list = expanded;
+
+    Ok(())
+}
+
+#[test]
+fn read_stdin_items_test() {
+    let items = read_stdin_items("host1\n\n  host2 \n\t\nhost3".as_bytes()).unwrap();
+
+    assert_eq!(items, vec!["host1", "host2", "host3"]);
+}
+
+#[test]
+fn expand_stdin_each_test() {
+    let mut nansi_file: NansiFile = serde_json::from_str(
+        "{\"exec_list\": [{\"label\": \"start\", \"exec\": \"echo\"}, {\"label\": \"deploy\", \"exec\": \"echo\", \"args\": [\"{item}\"], \"prerequisites\": [\"#1\"]}, {\"exec\": \"echo\", \"prerequisites\": [\"#2\", \"!#1\"]}]}",
+    )
+    .unwrap();
+    let items = vec![String::from("host1"), String::from("host2")];
+
+    expand_stdin_each(&mut nansi_file, "deploy", &items).unwrap();
+
+    let expanded: Vec<(&str, Option<&str>, Vec<String>)> = nansi_file
+        .exec_list
+        .iter()
+        .map(|item| {
+            let prereqs: Vec<String> = item.prerequisites.iter().map(|p| p.to_string()).collect();
+            (item.label.as_str(), item.stdin_item.as_deref(), prereqs)
+        })
+        .collect();
+    assert_eq!(
+        expanded,
+        vec![
+            ("start", None, vec![]),
+            ("deploy", Some("host1"), vec![String::from("#1")]),
+            ("deploy", Some("host2"), vec![String::from("#1")]),
+            ("", None, vec![String::from("#3"), String::from("!#1")]),
+        ]
+    );
+    assert!(!super::validate(&nansi_file)
+        .iter()
+        .any(|d| matches!(d.kind, super::DiagnosticKind::DuplicateLabel { .. })));
+
+    let err = expand_stdin_each(&mut nansi_file, "deplyo", &items).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Cannot use --stdin-each with deplyo: no item has this label, did you mean deploy?"
+    );
+}
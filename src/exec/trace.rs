@@ -0,0 +1,447 @@
+use std::fmt::Write as _;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    format_duration_ms, format_kib, ArgsOverride, ExecOptions, FailureReason, ItemDisplay,
+    NansiFile, ResourceUsage,
+};
+
+/// Version of the trace format, increased on incompatible changes.
+pub const TRACE_VERSION: u32 = 1;
+
+/// Transcript of a run written with `--trace`, meant to be attached to bug reports.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub trace_version: u32,
+
+    pub nansi_version: String,
+
+    /// Options in effect for the run, including those from `NANSI_OPTS`
+    pub options: ExecOptions,
+
+    /// Name of the machine the run happened on
+    #[serde(default)]
+    pub hostname: String,
+
+    /// Operating system and architecture, e.g. `linux x86_64`
+    #[serde(default)]
+    pub os: String,
+
+    /// UTC start of the run, e.g. `2024-03-01T12:00:00Z`
+    #[serde(default)]
+    pub started_at: String,
+
+    /// The `NansiFile` as parsed
+    pub nansi_file: NansiFile,
+
+    /// Names of the environment variables visible to executed items
+    pub env_names: Vec<String>,
+
+    pub items: Vec<TraceItem>,
+
+    /// Warnings not related to a particular item
+    pub warnings: Vec<String>,
+
+    /// Spans measured by `timer_start`/`timer_stop` markers
+    #[serde(default)]
+    pub timers: Vec<TimerSpan>,
+
+    pub duration_ms: u128,
+}
+
+/// Wall-clock span measured between a `timer_start` and a `timer_stop` marker
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimerSpan {
+    pub name: String,
+
+    pub duration_ms: u128,
+}
+
+/// Record of a single item of a traced run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceItem {
+    pub index: usize,
+
+    pub label: String,
+
+    pub exec: String,
+
+    /// Arguments after tag compilation, with secret values masked
+    pub args: Vec<String>,
+
+    pub status: String,
+
+    pub exit_code: Option<i32>,
+
+    pub duration_ms: u128,
+
+    pub stdout: String,
+
+    pub stderr: String,
+
+    /// Why the item failed, `null` unless its status is `err`
+    #[serde(default)]
+    pub failure_reason: Option<FailureReason>,
+
+    /// Reason the item was skipped or failed without being executed
+    pub message: Option<String>,
+
+    pub warnings: Vec<String>,
+
+    /// Expanded `success_message` of an item that succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_message: Option<String>,
+
+    /// Expanded `failure_hint` of an item that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_hint: Option<String>,
+
+    /// Resources used by the command, `null` where they cannot be measured
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+
+    /// Whether an item that succeeded changed something (see `changed_when`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed: Option<bool>,
+
+    /// Whether `stdout` holds both streams in the order they were written
+    /// (see `merge_streams`), `stderr` is empty then
+    #[serde(default)]
+    pub merged_streams: bool,
+
+    /// Arguments added from the command line, already part of `args`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args_override: Option<ArgsOverride>,
+}
+
+impl Trace {
+    pub fn new(nansi_file: &NansiFile, options: &ExecOptions, env_names: Vec<String>) -> Trace {
+        Trace {
+            trace_version: TRACE_VERSION,
+            nansi_version: String::from(env!("CARGO_PKG_VERSION")),
+            options: options.clone(),
+            hostname: get_hostname(),
+            os: format!("{} {}", env::consts::OS, env::consts::ARCH),
+            started_at: get_utc_now(),
+            nansi_file: nansi_file.clone(),
+            env_names,
+            items: Vec::new(),
+            warnings: Vec::new(),
+            timers: Vec::new(),
+            duration_ms: 0,
+        }
+    }
+
+    pub fn from(file_path: &str) -> Result<Trace, io::Error> {
+        let trace_str = match fs::read_to_string(file_path) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e)));
+            }
+        };
+
+        let version: TraceVersion = match serde_json::from_str(trace_str.as_str()) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", file_path, e),
+                ));
+            }
+        };
+
+        if version.trace_version != TRACE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: unsupported trace version {} (expected {})",
+                    file_path, version.trace_version, TRACE_VERSION
+                ),
+            ));
+        }
+
+        match serde_json::from_str(trace_str.as_str()) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", file_path, e),
+            )),
+        }
+    }
+
+    /// Returns the indices of the items which succeeded.
+    pub fn get_succeeded_indices(&self) -> Vec<usize> {
+        self.items
+            .iter()
+            .filter(|item| item.status == "ok")
+            .map(|item| item.index)
+            .collect()
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), io::Error> {
+        let trace_str = serde_json::to_string_pretty(self)?;
+        match fs::write(file_path, trace_str) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", file_path, e))),
+        }
+    }
+
+    /// Renders the trace in a human readable form (`nansi trace show`).
+    pub fn show(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "NansiFile: {}", self.nansi_file.file_path);
+        if !self.nansi_file.sha256.is_empty() {
+            let _ = writeln!(out, "sha256: {}", self.nansi_file.sha256);
+        }
+        let _ = writeln!(
+            out,
+            "nansi {} (trace version {}), {} item(s) in {} ms",
+            self.nansi_version,
+            self.trace_version,
+            self.nansi_file.exec_list.len(),
+            self.duration_ms
+        );
+        if !self.started_at.is_empty() {
+            let _ = writeln!(
+                out,
+                "Started {} on {} ({})",
+                self.started_at, self.hostname, self.os
+            );
+        }
+        let _ = writeln!(out, "Options: {:?}", self.options);
+        let _ = writeln!(out, "Environment: {}", self.env_names.join(", "));
+
+        for warning in &self.warnings {
+            let _ = writeln!(out, "Warning: {}", warning);
+        }
+
+        for item in &self.items {
+            let exit_code = match item.exit_code {
+                Some(code) => code.to_string(),
+                None => String::from("-"),
+            };
+
+            let reason = match &item.failure_reason {
+                Some(reason) => format!(" ({})", reason.short_str()),
+                None => String::new(),
+            };
+
+            let _ = writeln!(out);
+            let _ = writeln!(
+                out,
+                "{} {} exit={} {} ms{}",
+                ItemDisplay::from_label(Some(item.index), &item.label),
+                item.status,
+                exit_code,
+                item.duration_ms,
+                reason
+            );
+            let _ = writeln!(out, "  command: {} {}", item.exec, item.args.join(" "));
+            if let Some(args_override) = &item.args_override {
+                let _ = writeln!(out, "  override: {}", args_override.describe());
+            }
+            if let Some(message) = &item.message {
+                let _ = writeln!(out, "  message: {}", message);
+            }
+            for warning in &item.warnings {
+                let _ = writeln!(out, "  warning: {}", warning);
+            }
+            if let Some(success_message) = &item.success_message {
+                let _ = writeln!(out, "  success message: {}", success_message);
+            }
+            if let Some(failure_hint) = &item.failure_hint {
+                let _ = writeln!(out, "  failure hint: {}", failure_hint);
+            }
+            if item.merged_streams {
+                write_stream(&mut out, "stdout and stderr (merged)", &item.stdout);
+            } else {
+                write_stream(&mut out, "stdout", &item.stdout);
+                write_stream(&mut out, "stderr", &item.stderr);
+            }
+            if let Some(usage) = &item.resource_usage {
+                let _ = writeln!(
+                    out,
+                    "  resources: peak {}, user {}, system {}",
+                    format_kib(usage.max_rss_kib),
+                    format_duration_ms(usage.user_ms),
+                    format_duration_ms(usage.system_ms)
+                );
+            }
+        }
+
+        if !self.timers.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "Timers:");
+            for span in &self.timers {
+                let _ = writeln!(out, "  {}: {} ms", span.name, span.duration_ms);
+            }
+        }
+
+        out
+    }
+}
+
+#[derive(Deserialize)]
+struct TraceVersion {
+    trace_version: u32,
+}
+
+pub(crate) fn write_stream(out: &mut String, name: &str, content: &str) {
+    if content.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(out, "  {}:", name);
+    for line in content.lines() {
+        let _ = writeln!(out, "    {}", line);
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn sample_trace() -> Trace {
+    let nansi_file: NansiFile =
+        serde_json::from_str("{\"exec_list\": [{\"label\": \"ls\", \"exec\": \"ls\"}]}").unwrap();
+    let mut trace = Trace::new(
+        &nansi_file,
+        &ExecOptions::default(),
+        vec![String::from("PATH")],
+    );
+    trace.items.push(TraceItem {
+        index: 1,
+        label: String::from("ls"),
+        exec: String::from("ls"),
+        args: vec![String::from("-l")],
+        status: String::from("ok"),
+        exit_code: Some(0),
+        duration_ms: 3,
+        stdout: String::from("Cargo.toml\nsrc\n"),
+        stderr: String::new(),
+        failure_reason: None,
+        message: None,
+        warnings: vec![],
+        success_message: Some(String::from("Listed the workspace")),
+        failure_hint: None,
+        resource_usage: Some(ResourceUsage {
+            max_rss_kib: 2048,
+            user_ms: 1,
+            system_ms: 2,
+        }),
+        changed: Some(true),
+        merged_streams: false,
+        args_override: None,
+    });
+    trace.timers.push(TimerSpan {
+        name: String::from("listing"),
+        duration_ms: 4,
+    });
+    trace.duration_ms = 5;
+
+    trace
+}
+
+/// Returns the name of the machine, empty if it cannot be determined.
+#[cfg(unix)]
+fn get_hostname() -> String {
+    let mut buf = [0u8; 256];
+    // `buf` is writable for its whole length
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+/// Returns the name of the machine, empty if it cannot be determined.
+#[cfg(not(unix))]
+fn get_hostname() -> String {
+    env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Returns the current time as an RFC 3339 UTC timestamp.
+pub(crate) fn get_utc_now() -> String {
+    format_utc_timestamp(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    )
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+fn format_utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[test]
+fn format_utc_timestamp_test() {
+    assert_eq!(format_utc_timestamp(0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_utc_timestamp(951782400), "2000-02-29T00:00:00Z");
+    assert_eq!(format_utc_timestamp(1709294400), "2024-03-01T12:00:00Z");
+    assert_eq!(format_utc_timestamp(4102444799), "2099-12-31T23:59:59Z");
+}
+
+#[test]
+fn trace_round_trip_test() {
+    let trace = sample_trace();
+    let path = std::env::temp_dir().join(format!("nansi_trace_test_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    trace.save(path).unwrap();
+    let loaded = Trace::from(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(loaded, trace);
+}
+
+#[test]
+fn trace_unsupported_version_test() {
+    let mut trace = sample_trace();
+    trace.trace_version = TRACE_VERSION + 1;
+    let path =
+        std::env::temp_dir().join(format!("nansi_trace_version_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    trace.save(path).unwrap();
+    let err = Trace::from(path).unwrap_err();
+    fs::remove_file(path).unwrap();
+
+    assert!(err.to_string().contains("unsupported trace version"));
+}
+
+#[test]
+fn trace_show_test() {
+    let shown = sample_trace().show();
+
+    assert!(shown.contains(
+        "[1][ls] ok exit=0 3 ms\n  command: ls -l\n  success message: Listed the workspace\n  stdout:\n    Cargo.toml\n    src\n  resources: peak 2.0 MiB, user 1ms, system 2ms\n"
+    ));
+    assert!(shown.ends_with("\nTimers:\n  listing: 4 ms\n"));
+}
@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use serde::{de, Deserialize, Deserializer};
+
+/// Value of a duration or size field: a plain number in the unit of the field
+/// or a string with units (see `parse_duration`, `parse_size`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+/// Splits `s` into numbers and the units following them, e.g. `2m30s` into
+/// `[(2, "m"), (30, "s")]`; a number without unit is only allowed alone.
+fn split_units(s: &str) -> Result<Vec<(f64, String)>, String> {
+    let trimmed = s.trim();
+    if trimmed.is_empty() {
+        return Err(String::from("empty value"));
+    }
+
+    let mut parts: Vec<(f64, String)> = Vec::new();
+    let mut chars = trimmed.chars().peekable();
+    while chars.peek().is_some() {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+            number.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut unit = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+            unit.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let number = match number.parse::<f64>() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("expected a number in {:?}", s)),
+        };
+        parts.push((number, unit));
+    }
+
+    if parts.len() > 1 && parts.iter().any(|(_, unit)| unit.is_empty()) {
+        return Err(format!("missing unit in {:?}", s));
+    }
+
+    Ok(parts)
+}
+
+/// Parses a duration: a number of seconds (`90`) or numbers with the units
+/// `ms`, `s`, `m`, `h` and `d` (`2m30s`, `1.5h`, `500ms`, `1h 30m`).
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut total_ms = 0.0;
+    for (number, unit) in split_units(s)? {
+        let unit_ms = match unit.as_str() {
+            "ms" => 1.0,
+            "" | "s" => 1000.0,
+            "m" => 60.0 * 1000.0,
+            "h" => 60.0 * 60.0 * 1000.0,
+            "d" => 24.0 * 60.0 * 60.0 * 1000.0,
+            _ => {
+                return Err(format!(
+                    "unknown unit {:?} in {:?}, expected ms, s, m, h or d",
+                    unit, s
+                ))
+            }
+        };
+        total_ms += number * unit_ms;
+    }
+
+    Ok(Duration::from_millis(total_ms.round() as u64))
+}
+
+/// Parses a size: a number of bytes (`512`) or a number with a decimal (`kB`,
+/// `MB`, `GB`, `TB`) or binary (`KiB`, `MiB`, `GiB`, `TiB`) unit, e.g. `5MB`
+/// or `1.5GiB`; units are case insensitive.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let parts = split_units(s)?;
+    if parts.len() > 1 {
+        return Err(format!("expected a single number and unit in {:?}", s));
+    }
+
+    let (number, unit) = &parts[0];
+    let unit_bytes: f64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => {
+            return Err(format!(
+                "unknown unit {:?} in {:?}, expected B, kB, MB, GB, TB, KiB, MiB, GiB or TiB",
+                unit, s
+            ))
+        }
+    };
+
+    Ok((number * unit_bytes).round() as u64)
+}
+
+/// Deserializes a duration in whole seconds given as a number of seconds or
+/// as a string accepted by `parse_duration`; errors name `field`.
+pub(crate) fn deserialize_duration_secs<'de, D>(
+    deserializer: D,
+    field: &str,
+) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(secs)) => Ok(Some(secs)),
+        Some(NumberOrString::String(s)) => {
+            let duration =
+                parse_duration(&s).map_err(|e| de::Error::custom(format!("{}: {}", field, e)))?;
+            if duration.subsec_nanos() != 0 {
+                return Err(de::Error::custom(format!(
+                    "{}: {:?} is not a whole number of seconds",
+                    field, s
+                )));
+            }
+
+            Ok(Some(duration.as_secs()))
+        }
+    }
+}
+
+/// Deserializes a size in MiB given as a number of MiB or as a string
+/// accepted by `parse_size` (rounded up to whole MiB); errors name `field`.
+pub(crate) fn deserialize_size_mib<'de, D>(
+    deserializer: D,
+    field: &str,
+) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    const MIB: u64 = 1024 * 1024;
+
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(mib)) => Ok(Some(mib)),
+        Some(NumberOrString::String(s)) => {
+            let bytes =
+                parse_size(&s).map_err(|e| de::Error::custom(format!("{}: {}", field, e)))?;
+
+            Ok(Some(bytes.div_ceil(MIB)))
+        }
+    }
+}
+
+#[test]
+fn parse_duration_test() {
+    let cases = [
+        ("90", 90_000),
+        ("0", 0),
+        ("30s", 30_000),
+        ("500ms", 500),
+        ("2m30s", 150_000),
+        ("2m 30s", 150_000),
+        (" 1h ", 3_600_000),
+        ("1.5h", 5_400_000),
+        ("1d2h3m4s5ms", 93_784_005),
+        ("0.5s", 500),
+    ];
+    for (s, expected_ms) in cases {
+        assert_eq!(
+            parse_duration(s),
+            Ok(Duration::from_millis(expected_ms)),
+            "{}",
+            s
+        );
+    }
+
+    let errors = [
+        ("", "empty value"),
+        (
+            "2x",
+            "unknown unit \"x\" in \"2x\", expected ms, s, m, h or d",
+        ),
+        ("m", "expected a number in \"m\""),
+        ("-5s", "expected a number in \"-5s\""),
+        ("1..5s", "expected a number in \"1..5s\""),
+        ("2m30", "missing unit in \"2m30\""),
+        (
+            "1M",
+            "unknown unit \"M\" in \"1M\", expected ms, s, m, h or d",
+        ),
+    ];
+    for (s, expected) in errors {
+        assert_eq!(parse_duration(s), Err(String::from(expected)), "{}", s);
+    }
+}
+
+#[test]
+fn parse_size_test() {
+    let cases = [
+        ("512", 512),
+        ("0", 0),
+        ("512B", 512),
+        ("1kB", 1_000),
+        ("5MB", 5_000_000),
+        ("5mb", 5_000_000),
+        ("2GB", 2_000_000_000),
+        ("1TB", 1_000_000_000_000),
+        ("1KiB", 1024),
+        ("5MiB", 5 * 1024 * 1024),
+        ("1.5GiB", 1536 * 1024 * 1024),
+        ("1TiB", 1024 * 1024 * 1024 * 1024),
+        (" 10 MB ", 10_000_000),
+    ];
+    for (s, expected) in cases {
+        assert_eq!(parse_size(s), Ok(expected), "{}", s);
+    }
+
+    let errors = [
+        ("", "empty value"),
+        (
+            "5XB",
+            "unknown unit \"XB\" in \"5XB\", expected B, kB, MB, GB, TB, KiB, MiB, GiB or TiB",
+        ),
+        ("MB", "expected a number in \"MB\""),
+        (
+            "1MB 2kB",
+            "expected a single number and unit in \"1MB 2kB\"",
+        ),
+    ];
+    for (s, expected) in errors {
+        assert_eq!(parse_size(s), Err(String::from(expected)), "{}", s);
+    }
+}
+
+#[test]
+fn deserialize_units_test() {
+    #[derive(Deserialize, Debug)]
+    struct Fields {
+        #[serde(default, deserialize_with = "deserialize_timeout")]
+        timeout: Option<u64>,
+
+        #[serde(default, deserialize_with = "deserialize_space")]
+        space: Option<u64>,
+    }
+
+    fn deserialize_timeout<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        deserialize_duration_secs(d, "timeout")
+    }
+
+    fn deserialize_space<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        deserialize_size_mib(d, "space")
+    }
+
+    let parse = |s: &str| serde_json::from_str::<Fields>(s).map_err(|e| e.to_string());
+
+    let fields = parse("{\"timeout\": \"2m30s\", \"space\": \"5MB\"}").unwrap();
+    assert_eq!((fields.timeout, fields.space), (Some(150), Some(5)));
+
+    let fields = parse("{\"timeout\": 30, \"space\": 512}").unwrap();
+    assert_eq!((fields.timeout, fields.space), (Some(30), Some(512)));
+
+    let fields = parse("{}").unwrap();
+    assert_eq!((fields.timeout, fields.space), (None, None));
+
+    assert_eq!(
+        parse("{\"timeout\": \"1500ms\"}").unwrap_err(),
+        "timeout: \"1500ms\" is not a whole number of seconds at line 1 column 21"
+    );
+    assert!(parse("{\"space\": \"5 potatoes\"}")
+        .unwrap_err()
+        .starts_with("space: unknown unit \"potatoes\""));
+}
@@ -0,0 +1,1400 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde::Serialize;
+
+use super::{
+    compile_pattern, find_label_ignoring_case, find_var_cycles, get_closest_label, get_error_str,
+    get_exec_context, get_warning_str, parse_index_prerequisite, replace_reference_target,
+    scan_tags, share_batch, split_reference, split_tag, validate_env_constraints, Capture,
+    ChangedWhen, ExecContext, ExecItem, ItemDisplay, LabelMatching, NansiFile, OutputStream,
+    Prerequisite, PrintOutput, VarSource,
+};
+
+/// Severity of a `Diagnostic`
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Observation that does not indicate a problem
+    Info,
+
+    /// The `NansiFile` is likely to misbehave
+    Warning,
+
+    /// The `NansiFile` cannot be executed
+    Error,
+}
+
+/// Kind of problem found by `validate`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// The label is used by an earlier item as well
+    DuplicateLabel { first_index: usize },
+
+    /// The prerequisite refers to a label or index no item has
+    UnknownPrerequisite { prerequisite: String },
+
+    /// The prerequisite differs from a label only by case, so it can never be
+    /// met unless `label_matching` is `case_insensitive`
+    LabelCaseMismatch { prerequisite: String, label: String },
+
+    /// The prerequisite refers to a later item, so it can never be met
+    ForwardPrerequisite { prerequisite: String },
+
+    /// The prerequisite refers to the item itself
+    SelfPrerequisite { prerequisite: String },
+
+    /// `min` of an `any_of` prerequisite is 0 or more than the number of
+    /// references
+    InvalidPrerequisiteMin { min: usize },
+
+    /// The prerequisite refers to an item of the same batch of `parallel`
+    /// items, whose outcome is not known when the batch starts
+    ParallelPrerequisite { prerequisite: String },
+
+    /// The item has nothing to execute
+    EmptyExec,
+
+    /// The item sets both `exec` and `assert`
+    ExecAndAssert,
+
+    /// An argument contains `{}` tags that cannot be compiled
+    MalformedTag { arg: usize },
+
+    /// A key of `flags` does not start with `-` (likely a positional argument)
+    InvalidFlag { flag: String },
+
+    /// `chunk_args` cannot be used to split the arguments
+    InvalidChunkArgs,
+
+    /// `changed_when` is not a known condition
+    InvalidChangedWhen,
+
+    /// A `redact` pattern is not a valid regular expression
+    InvalidRedactPattern { pattern: String },
+
+    /// A variable of `vars` refers to itself, directly or through others
+    VarCycle { name: String },
+
+    /// `merge_streams` leaves no separate stderr for `print_stderr` to print
+    MergeStreamsAndPrintStderr,
+
+    /// The item sets both `stdin` and `stdin_file`
+    StdinAndStdinFile,
+
+    /// A field needing the output of the command (`print_output`,
+    /// `changed_when` with `output_matches`) is set with `"capture": "none"`
+    UncapturedOutput { field: String },
+
+    /// A timer marker also sets `exec` or `assert`
+    ExecAndTimer,
+
+    /// A timer is stopped without being started or started without being stopped
+    UnbalancedTimer { name: String },
+
+    /// A timer is started while running or stopped while a timer started
+    /// after it is still running
+    OverlappingTimer { name: String },
+
+    /// No item refers to the label, so it can be renamed or removed
+    UnusedLabel,
+
+    /// A variable in `requires_env` (or one used by a tag) is not set
+    MissingEnv { name: String },
+
+    /// A constraint of `require_env` or `forbid_env` is violated
+    EnvConstraint { name: String },
+
+    /// `features` contains a toggle this version does not know
+    UnknownFeature { name: String },
+
+    /// The file or an item has a field this version does not know, which is
+    /// ignored
+    UnknownField { field: String },
+}
+
+/// Problem found in a `NansiFile` by `validate`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// 1-based index of the item the diagnostic belongs to, 0 for diagnostics
+    /// about the file itself
+    pub index: usize,
+
+    pub label: String,
+
+    #[serde(flatten)]
+    pub kind: DiagnosticKind,
+
+    /// Human readable description
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(
+        severity: Severity,
+        exec_item: &ExecItem,
+        index: usize,
+        kind: DiagnosticKind,
+        message: String,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity,
+            index,
+            label: exec_item.label.clone(),
+            kind,
+            message,
+        }
+    }
+}
+
+/// Checks a `NansiFile` without executing it.
+///
+/// Diagnostics with `Severity::Error` make `execute` refuse the file; warnings
+/// point at items which are likely not to run as intended.
+pub fn validate(nansi_file: &NansiFile) -> Vec<Diagnostic> {
+    let exec_list = &nansi_file.exec_list;
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    let mut label_map: HashMap<&str, usize> = HashMap::new();
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        if !exec_item.label.is_empty() {
+            label_map.entry(exec_item.label.as_str()).or_insert(idx + 1);
+        }
+    }
+    // in the order of the items, for suggestions not to depend on the map
+    let mut labels: Vec<&str> = label_map.keys().copied().collect();
+    labels.sort_by_key(|label| label_map[label]);
+
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        let index = idx + 1;
+        let item_str = ItemDisplay::new(exec_item, index).to_string();
+
+        if let Some(first_index) = label_map.get(exec_item.label.as_str()) {
+            // the copies made by `--stdin-each` share the label on purpose
+            let is_stdin_copy =
+                exec_item.stdin_item.is_some() && exec_list[*first_index - 1].stdin_item.is_some();
+            if *first_index != index && !is_stdin_copy {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    exec_item,
+                    index,
+                    DiagnosticKind::DuplicateLabel {
+                        first_index: *first_index,
+                    },
+                    format!(
+                        "Label of item {} is already used by item [{}]",
+                        item_str, first_index
+                    ),
+                ));
+            }
+        }
+
+        for field in exec_item.unknown.keys() {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                exec_item,
+                index,
+                DiagnosticKind::UnknownField {
+                    field: field.clone(),
+                },
+                format!(
+                    "Unknown field \"{}\" of item {} is ignored",
+                    field, item_str
+                ),
+            ));
+        }
+
+        let has_exec = !exec_item.exec.trim().is_empty();
+        if exec_item.is_timer() {
+            if has_exec || exec_item.assert.is_some() {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::ExecAndTimer,
+                    format!(
+                        "Timer item {} cannot have exec or assert as it is only a marker",
+                        item_str
+                    ),
+                ));
+            }
+            continue;
+        }
+
+        match &exec_item.assert {
+            Some(_) if has_exec => diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::ExecAndAssert,
+                format!("Item {} cannot have both exec and assert", item_str),
+            )),
+            Some(assertions) if !assertions.is_empty() => {}
+            None if has_exec => {}
+            _ => diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::EmptyExec,
+                format!("Item {} has nothing to execute", item_str),
+            )),
+        }
+
+        for flag in exec_item.flags.keys().filter(|f| !f.starts_with('-')) {
+            diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::InvalidFlag { flag: flag.clone() },
+                format!(
+                    "Flag \"{}\" of item {} does not start with '-'",
+                    flag, item_str
+                ),
+            ));
+        }
+
+        for (arg_idx, arg) in exec_item.get_args().iter().enumerate() {
+            if let Err(e) = scan_tags(arg) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::MalformedTag { arg: arg_idx + 1 },
+                    format!("Argument {} of item {}: {}", arg_idx + 1, item_str, e),
+                ));
+            }
+        }
+
+        if let Some(chunk_args) = &exec_item.chunk_args {
+            if chunk_args.size == 0 {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::InvalidChunkArgs,
+                    format!("Chunk size of item {} must be greater than 0", item_str),
+                ));
+            }
+        }
+
+        if let Some(changed_when) = &exec_item.changed_when {
+            if let Err(e) = changed_when.is_changed(None, "") {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::InvalidChangedWhen,
+                    format!("Item {}: {}", item_str, e),
+                ));
+            }
+        }
+
+        for pattern in &exec_item.redact {
+            if let Err(e) = compile_pattern(pattern) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::InvalidRedactPattern {
+                        pattern: pattern.clone(),
+                    },
+                    format!("Redact pattern of item {} is invalid: {}", item_str, e),
+                ));
+            }
+        }
+
+        if exec_item.merge_streams && exec_item.print_stderr {
+            diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::MergeStreamsAndPrintStderr,
+                format!(
+                    "Item {} cannot have both merge_streams and print_stderr",
+                    item_str
+                ),
+            ));
+        }
+
+        if exec_item.capture == Capture::None {
+            let mut fields: Vec<&str> = Vec::new();
+            if exec_item.print_output != PrintOutput::None {
+                fields.push("print_output");
+            }
+            if let Some(ChangedWhen::OutputMatches { .. }) = exec_item.changed_when {
+                fields.push("changed_when");
+            }
+            for field in fields {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::UncapturedOutput {
+                        field: String::from(field),
+                    },
+                    format!(
+                        "Item {} cannot have {} as its output is not captured (capture is none)",
+                        item_str, field
+                    ),
+                ));
+            }
+        }
+
+        if exec_item.stdin.is_some() && exec_item.stdin_file.is_some() {
+            diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::StdinAndStdinFile,
+                format!("Item {} cannot have both stdin and stdin_file", item_str),
+            ));
+        }
+
+        for prereq in &exec_item.prerequisites {
+            if let Prerequisite::AnyOf { any_of, min } = prereq {
+                if *min == 0 || *min > any_of.len() {
+                    diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        exec_item,
+                        index,
+                        DiagnosticKind::InvalidPrerequisiteMin { min: *min },
+                        format!(
+                            "Prerequisite {} of item {} needs a min between 1 and {}",
+                            prereq,
+                            item_str,
+                            any_of.len()
+                        ),
+                    ));
+                }
+            }
+            for reference in prereq.refs() {
+                diagnostics.extend(validate_prerequisite(
+                    nansi_file, &label_map, &labels, exec_item, index, reference,
+                ));
+                diagnostics.extend(get_case_match_info(
+                    nansi_file, &label_map, &labels, exec_item, index, reference,
+                ));
+            }
+        }
+    }
+
+    diagnostics.extend(validate_timers(exec_list));
+
+    for (index, exec_item) in get_unused_labels(nansi_file) {
+        diagnostics.push(Diagnostic::new(
+            Severity::Info,
+            exec_item,
+            index,
+            DiagnosticKind::UnusedLabel,
+            format!(
+                "Label of item {} is not referenced by any item",
+                ItemDisplay::new(exec_item, index)
+            ),
+        ));
+    }
+
+    for pattern in &nansi_file.redact {
+        if let Err(e) = compile_pattern(pattern) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                index: 0,
+                label: String::new(),
+                kind: DiagnosticKind::InvalidRedactPattern {
+                    pattern: pattern.clone(),
+                },
+                message: format!("Redact pattern is invalid: {}", e),
+            });
+        }
+    }
+
+    for cycle in find_var_cycles(&nansi_file.vars) {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            index: 0,
+            label: String::new(),
+            kind: DiagnosticKind::VarCycle {
+                name: cycle[0].clone(),
+            },
+            message: format!(
+                "Variable {} refers to itself: {}",
+                cycle[0],
+                cycle.join(" -> ")
+            ),
+        });
+    }
+
+    for field in nansi_file.unknown.keys() {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            index: 0,
+            label: String::new(),
+            kind: DiagnosticKind::UnknownField {
+                field: field.clone(),
+            },
+            message: format!("Unknown field \"{}\" is ignored", field),
+        });
+    }
+
+    for name in nansi_file.features.unknown.keys() {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            index: 0,
+            label: String::new(),
+            kind: DiagnosticKind::UnknownFeature { name: name.clone() },
+            message: format!("Unknown feature \"{}\" is ignored", name),
+        });
+    }
+
+    diagnostics
+}
+
+/// Checks that the variables required by the items are available before
+/// anything runs, reporting all missing ones at once.
+///
+/// Variables in `requires_env` must be set in the environment (and not be
+/// empty unless `allow_empty` is set); those are errors. With `discover_tags`,
+/// variables used by `{}` tags in `args` and in the values of `vars` must be
+/// defined by any source of `ctx`; those are warnings as registers are only
+/// defined while running.
+pub fn validate_env(
+    nansi_file: &NansiFile,
+    ctx: &ExecContext,
+    discover_tags: bool,
+) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for (idx, exec_item) in nansi_file.exec_list.iter().enumerate() {
+        let index = idx + 1;
+        let item_str = ItemDisplay::new(exec_item, index).to_string();
+
+        for name in &exec_item.requires_env {
+            let msg = match ctx.get_source_var(VarSource::Env, name) {
+                None => format!("Item {} requires {} which is not set", item_str, name),
+                Some(value) if value.is_empty() && !exec_item.allow_empty => {
+                    format!("Item {} requires {} which is empty", item_str, name)
+                }
+                Some(_) => continue,
+            };
+            diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                exec_item,
+                index,
+                DiagnosticKind::MissingEnv { name: name.clone() },
+                msg,
+            ));
+        }
+
+        if !discover_tags {
+            continue;
+        }
+
+        let mut reported: Vec<String> = Vec::new();
+        for arg in &exec_item.get_args() {
+            let scan = match scan_tags(arg) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for tag in scan.tags {
+                let (name, default) = split_tag(&tag);
+                let tag = String::from(name);
+                if exec_item.requires_env.contains(&tag)
+                    || reported.contains(&tag)
+                    || default.is_some()
+                    || ctx.get_var(&tag).is_some()
+                {
+                    continue;
+                }
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    exec_item,
+                    index,
+                    DiagnosticKind::MissingEnv { name: tag.clone() },
+                    format!("Item {} uses {{{}}} which is not set", item_str, tag),
+                ));
+                reported.push(tag);
+            }
+        }
+    }
+
+    if discover_tags {
+        for (name, value) in &nansi_file.vars {
+            let scan = match scan_tags(value) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for tag in scan.tags {
+                let (tag_name, default) = split_tag(&tag);
+                let tag = String::from(tag_name);
+                if nansi_file.vars.contains_key(&tag)
+                    || default.is_some()
+                    || ctx.get_var(&tag).is_some()
+                {
+                    continue;
+                }
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    index: 0,
+                    label: String::new(),
+                    kind: DiagnosticKind::MissingEnv { name: tag.clone() },
+                    message: format!("Variable {} uses {{{}}} which is not set", name, tag),
+                });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the (1-based) index and the items whose label no item refers to.
+///
+/// Items referenced by index count as used as well, and items marked with
+/// `keep` (e.g. labels used by external tooling) are never reported.
+pub fn get_unused_labels(nansi_file: &NansiFile) -> Vec<(usize, &ExecItem)> {
+    let exec_list = &nansi_file.exec_list;
+
+    let mut referenced_labels: Vec<&str> = Vec::new();
+    let mut referenced_indexes: Vec<usize> = Vec::new();
+    for exec_item in exec_list {
+        for reference in exec_item.prerequisites.iter().flat_map(|p| p.refs()) {
+            let (prereq, _) = split_reference(reference);
+            match parse_index_prerequisite(prereq) {
+                Some(prereq_idx) => referenced_indexes.push(prereq_idx),
+                None => referenced_labels.push(prereq),
+            }
+        }
+    }
+
+    exec_list
+        .iter()
+        .enumerate()
+        .map(|(idx, exec_item)| (idx + 1, exec_item))
+        .filter(|(index, exec_item)| {
+            !exec_item.label.is_empty()
+                && !exec_item.keep
+                && !is_label_referenced(nansi_file, &exec_item.label, &referenced_labels)
+                && !referenced_indexes.contains(index)
+        })
+        .collect()
+}
+
+fn is_label_referenced(nansi_file: &NansiFile, label: &str, referenced_labels: &[&str]) -> bool {
+    match nansi_file.label_matching {
+        LabelMatching::Exact => referenced_labels.contains(&label),
+        LabelMatching::CaseInsensitive => {
+            find_label_ignoring_case(label, referenced_labels.iter().copied()).is_some()
+        }
+    }
+}
+
+/// Checks that every timer is started once before it is stopped and that the
+/// spans of timers are either nested or disjoint.
+fn validate_timers(exec_list: &[ExecItem]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut running: Vec<(&str, usize)> = Vec::new();
+
+    for (idx, exec_item) in exec_list.iter().enumerate() {
+        let index = idx + 1;
+        let item_str = ItemDisplay::new(exec_item, index).to_string();
+
+        if let Some(name) = &exec_item.timer_stop {
+            match running.iter().rposition(|(n, _)| n == name) {
+                Some(pos) => {
+                    if pos + 1 != running.len() {
+                        diagnostics.push(Diagnostic::new(
+                            Severity::Warning,
+                            exec_item,
+                            index,
+                            DiagnosticKind::OverlappingTimer { name: name.clone() },
+                            format!(
+                                "Timer {} is stopped by item {} while timer {} started after it is still running",
+                                name,
+                                item_str,
+                                running[running.len() - 1].0
+                            ),
+                        ));
+                    }
+                    running.remove(pos);
+                }
+                None => diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    exec_item,
+                    index,
+                    DiagnosticKind::UnbalancedTimer { name: name.clone() },
+                    format!(
+                        "Timer {} is stopped by item {} but is not running",
+                        name, item_str
+                    ),
+                )),
+            }
+        }
+
+        if let Some(name) = &exec_item.timer_start {
+            if running.iter().any(|(n, _)| n == name) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    exec_item,
+                    index,
+                    DiagnosticKind::OverlappingTimer { name: name.clone() },
+                    format!(
+                        "Timer {} is started by item {} while it is already running",
+                        name, item_str
+                    ),
+                ));
+            } else {
+                running.push((name.as_str(), index));
+            }
+        }
+    }
+
+    for (name, index) in running {
+        let exec_item = &exec_list[index - 1];
+        diagnostics.push(Diagnostic::new(
+            Severity::Warning,
+            exec_item,
+            index,
+            DiagnosticKind::UnbalancedTimer {
+                name: String::from(name),
+            },
+            format!(
+                "Timer {} is started by item {} but never stopped",
+                name,
+                ItemDisplay::new(exec_item, index)
+            ),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Returns the `Severity::Info` diagnostic of a label reference which matches a
+/// label only ignoring case, as allowed by `LabelMatching::CaseInsensitive`,
+/// so `--check` still points at the spelling differences.
+fn get_case_match_info(
+    nansi_file: &NansiFile,
+    label_map: &HashMap<&str, usize>,
+    labels: &[&str],
+    exec_item: &ExecItem,
+    index: usize,
+    prereq: &str,
+) -> Option<Diagnostic> {
+    let (target, _) = split_reference(prereq);
+    if nansi_file.label_matching != LabelMatching::CaseInsensitive
+        || parse_index_prerequisite(target).is_some()
+        || label_map.contains_key(target)
+    {
+        return None;
+    }
+
+    let label = find_label_ignoring_case(target, labels.iter().copied())?;
+    Some(Diagnostic::new(
+        Severity::Info,
+        exec_item,
+        index,
+        DiagnosticKind::LabelCaseMismatch {
+            prerequisite: String::from(prereq),
+            label: String::from(label),
+        },
+        format!(
+            "Prerequisite {} of item {} matches the label {} only ignoring case",
+            prereq,
+            ItemDisplay::new(exec_item, index),
+            label
+        ),
+    ))
+}
+
+/// Checks a single prerequisite of the item at (1-based) `index`.
+///
+/// Index references are errors as they are resolved before the run; a label
+/// reference which cannot be met only causes the item to be skipped.
+fn validate_prerequisite(
+    nansi_file: &NansiFile,
+    label_map: &HashMap<&str, usize>,
+    labels: &[&str],
+    exec_item: &ExecItem,
+    index: usize,
+    prereq: &str,
+) -> Option<Diagnostic> {
+    let exec_list = &nansi_file.exec_list;
+    let item_str = ItemDisplay::new(exec_item, index).to_string();
+    let prerequisite = String::from(prereq);
+    let (target, _) = split_reference(prereq);
+
+    let (severity, prereq_idx) = match parse_index_prerequisite(target) {
+        Some(prereq_idx) => {
+            if prereq_idx == 0 || prereq_idx > exec_list.len() {
+                return Some(Diagnostic::new(
+                    Severity::Error,
+                    exec_item,
+                    index,
+                    DiagnosticKind::UnknownPrerequisite { prerequisite },
+                    format!(
+                        "Prerequisite {} of item {} is out of range (1-{})",
+                        prereq,
+                        item_str,
+                        exec_list.len()
+                    ),
+                ));
+            }
+            (Severity::Error, prereq_idx)
+        }
+        None => match label_map.get(target) {
+            Some(prereq_idx) => (Severity::Warning, *prereq_idx),
+            None => {
+                if let Some(label) = find_label_ignoring_case(target, labels.iter().copied()) {
+                    if nansi_file.label_matching == LabelMatching::CaseInsensitive {
+                        (Severity::Warning, label_map[label])
+                    } else {
+                        return Some(Diagnostic::new(
+                            Severity::Warning,
+                            exec_item,
+                            index,
+                            DiagnosticKind::LabelCaseMismatch {
+                                prerequisite,
+                                label: String::from(label),
+                            },
+                            format!(
+                                "Prerequisite {} of item {} differs from the label {} only by case and can never be met (set label_matching to case_insensitive to match it)",
+                                prereq, item_str, label
+                            ),
+                        ));
+                    }
+                } else {
+                    // a label starting with `!` needs escaping to be referred to
+                    let hint = if label_map.contains_key(prereq) {
+                        format!(
+                            " (write {} to refer to the label {})",
+                            replace_reference_target(target, prereq),
+                            prereq
+                        )
+                    } else if let Some(closest) = get_closest_label(target, labels) {
+                        format!(", did you mean {}?", closest)
+                    } else {
+                        String::new()
+                    };
+                    return Some(Diagnostic::new(
+                        Severity::Warning,
+                        exec_item,
+                        index,
+                        DiagnosticKind::UnknownPrerequisite { prerequisite },
+                        format!(
+                            "Prerequisite {} of item {} is not the label of any item{}",
+                            prereq, item_str, hint
+                        ),
+                    ));
+                }
+            }
+        },
+    };
+
+    if prereq_idx == index {
+        Some(Diagnostic::new(
+            severity,
+            exec_item,
+            index,
+            DiagnosticKind::SelfPrerequisite { prerequisite },
+            format!(
+                "Prerequisite {} of item {} refers to the item itself",
+                prereq, item_str
+            ),
+        ))
+    } else if prereq_idx > index {
+        Some(Diagnostic::new(
+            severity,
+            exec_item,
+            index,
+            DiagnosticKind::ForwardPrerequisite { prerequisite },
+            format!(
+                "Prerequisite {} of item {} refers to a later item and can never be met",
+                prereq, item_str
+            ),
+        ))
+    } else if share_batch(&exec_list[prereq_idx - 1..index], usize::MAX) {
+        // the jobs limit is only known when the file is run, which refuses
+        // index prerequisites in the same batch with more than one job
+        Some(Diagnostic::new(
+            Severity::Warning,
+            exec_item,
+            index,
+            DiagnosticKind::ParallelPrerequisite { prerequisite },
+            format!(
+                "Prerequisite {} of item {} refers to an item of the same parallel batch when run with more than one job",
+                prereq, item_str
+            ),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Prints the diagnostics of a `NansiFile` (`--check`, `nansi validate`) and
+/// fails if any of them is an error.
+pub fn check(nansi_file: &NansiFile) -> Result<(), Box<dyn Error>> {
+    let mut diagnostics = validate(nansi_file);
+    diagnostics.extend(validate_env(
+        nansi_file,
+        &get_exec_context(nansi_file),
+        true,
+    ));
+    // informational as the environment of the run may differ
+    diagnostics.extend(validate_env_constraints(
+        nansi_file,
+        &get_exec_context(nansi_file),
+        Severity::Info,
+    ));
+
+    for diagnostic in &diagnostics {
+        match diagnostic.severity {
+            Severity::Info => println!("[INFO] {}", diagnostic.message),
+            Severity::Warning => println!(
+                "{}",
+                get_warning_str(OutputStream::Stdout, &diagnostic.message)
+            ),
+            Severity::Error => println!(
+                "{}",
+                get_error_str(OutputStream::Stdout, &diagnostic.message)
+            ),
+        }
+    }
+
+    let count = |severity: Severity| {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    };
+    if count(Severity::Error) > 0 {
+        return Err(format!(
+            "{}: {} error(s) found",
+            nansi_file.file_path,
+            count(Severity::Error)
+        )
+        .into());
+    }
+
+    println!(
+        "{}: {} warning(s) found",
+        nansi_file.file_path,
+        count(Severity::Warning)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn parse_str(nansi_file_str: &str) -> NansiFile {
+    serde_json::from_str(nansi_file_str).unwrap()
+}
+
+/// Validates the file, leaving out `Severity::Info` diagnostics.
+#[cfg(test)]
+fn validate_str(nansi_file_str: &str) -> Vec<Diagnostic> {
+    validate(&parse_str(nansi_file_str))
+        .into_iter()
+        .filter(|d| d.severity != Severity::Info)
+        .collect()
+}
+
+#[test]
+fn validate_valid_file_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\"}, {\"exec\": \"ls\", \"args\": [\"{HOME}\"], \"prerequisites\": [\"a\", \"#1\"]}]}",
+    );
+
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn validate_duplicate_label_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\"}, {\"label\": \"a\", \"exec\": \"ls\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].index, 2);
+    assert_eq!(diagnostics[0].label, "a");
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::DuplicateLabel { first_index: 1 }
+    );
+}
+
+#[test]
+fn validate_unknown_prerequisite_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"ls\", \"prerequisites\": [\"missing\", \"#5\"]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::UnknownPrerequisite {
+            prerequisite: String::from("missing")
+        }
+    );
+    assert_eq!(diagnostics[1].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[1].message,
+        "Prerequisite #5 of item [1] is out of range (1-1)"
+    );
+}
+
+#[test]
+fn validate_label_case_test() {
+    let content = "{\"exec_list\": [{\"label\": \"install-docker\", \"exec\": \"apt\"}, {\"exec\": \"docker\", \"prerequisites\": [\"Install-Docker\", \"instal-docker\"]}]}";
+    let diagnostics = validate_str(content);
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::LabelCaseMismatch {
+            prerequisite: String::from("Install-Docker"),
+            label: String::from("install-docker")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Prerequisite Install-Docker of item [2] differs from the label install-docker only by case and can never be met (set label_matching to case_insensitive to match it)"
+    );
+    assert_eq!(
+        diagnostics[1].message,
+        "Prerequisite instal-docker of item [2] is not the label of any item, did you mean install-docker?"
+    );
+
+    let mut nansi_file = parse_str(content);
+    nansi_file.label_matching = LabelMatching::CaseInsensitive;
+    let messages: Vec<(Severity, String)> = validate(&nansi_file)
+        .into_iter()
+        .filter(|d| d.index == 2)
+        .map(|d| (d.severity, d.message))
+        .collect();
+
+    assert_eq!(
+        messages,
+        vec![
+            (
+                Severity::Info,
+                String::from(
+                    "Prerequisite Install-Docker of item [2] matches the label install-docker only ignoring case"
+                )
+            ),
+            (
+                Severity::Warning,
+                String::from(
+                    "Prerequisite instal-docker of item [2] is not the label of any item, did you mean install-docker?"
+                )
+            ),
+        ]
+    );
+}
+
+#[test]
+fn validate_forward_prerequisite_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"ls\", \"prerequisites\": [\"b\", \"#2\"]}, {\"label\": \"b\", \"exec\": \"ls\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::ForwardPrerequisite {
+            prerequisite: String::from("b")
+        }
+    );
+    assert_eq!(diagnostics[1].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[1].kind,
+        DiagnosticKind::ForwardPrerequisite {
+            prerequisite: String::from("#2")
+        }
+    );
+}
+
+#[test]
+fn validate_self_prerequisite_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\", \"prerequisites\": [\"#1\"]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::SelfPrerequisite {
+            prerequisite: String::from("#1")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Prerequisite #1 of item [1][a] refers to the item itself"
+    );
+}
+
+#[test]
+fn validate_parallel_prerequisite_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\"}, {\"label\": \"b\", \"exec\": \"ls\", \"parallel\": true}, {\"exec\": \"ls\", \"parallel\": true, \"prerequisites\": [\"a\", \"#2\"]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::ParallelPrerequisite {
+            prerequisite: String::from("#2")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Prerequisite #2 of item [3] refers to an item of the same parallel batch when run with more than one job"
+    );
+
+    // a serial item runs in a batch of its own
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"ls\", \"parallel\": true}, {\"exec\": \"ls\", \"parallel\": true, \"serial\": true}, {\"exec\": \"ls\", \"parallel\": true, \"prerequisites\": [\"#1\", \"#2\"]}]}",
+    );
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+}
+
+#[test]
+fn validate_redact_test() {
+    let diagnostics = validate_str(
+        "{\"redact\": [\"[\"], \"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\", \"redact\": [\"ghp_\\\\w+\", \"(\"]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].index, 1);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::InvalidRedactPattern {
+            pattern: String::from("(")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Redact pattern of item [1][a] is invalid: \"(\": unclosed group"
+    );
+    assert_eq!(diagnostics[1].index, 0);
+    assert_eq!(
+        diagnostics[1].message,
+        "Redact pattern is invalid: \"[\": unclosed character class"
+    );
+}
+
+#[test]
+fn validate_empty_exec_test() {
+    let diagnostics = validate_str("{\"exec_list\": [{\"label\": \"a\", \"exec\": \" \"}]}");
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::EmptyExec);
+}
+
+#[test]
+fn validate_assert_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"assert\": {\"env_set\": \"HOME\"}}, {\"assert\": {}}, {\"exec\": \"ls\", \"assert\": {\"env_set\": \"HOME\"}}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].index, 2);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::EmptyExec);
+    assert_eq!(diagnostics[1].index, 3);
+    assert_eq!(diagnostics[1].kind, DiagnosticKind::ExecAndAssert);
+}
+
+#[test]
+fn validate_malformed_tag_test() {
+    let diagnostics =
+        validate_str("{\"exec_list\": [{\"exec\": \"echo\", \"args\": [\"ok\", \"{A{B}}\"]}]}");
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::MalformedTag { arg: 2 });
+}
+
+#[test]
+fn validate_invalid_flag_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"aws\", \"flags\": {\"--region\": \"eu-west-1\", \"s3\": true}}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::InvalidFlag {
+            flag: String::from("s3")
+        }
+    );
+}
+
+#[test]
+fn validate_unknown_feature_test() {
+    let diagnostics = validate_str(
+        "{\"features\": {\"null_stdin\": false, \"colour\": true}, \"exec_list\": [{\"exec\": \"ls\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].index, 0);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::UnknownFeature {
+            name: String::from("colour")
+        }
+    );
+}
+
+#[test]
+fn validate_unknown_field_test() {
+    let diagnostics = validate_str(
+        "{\"exec_lists\": [], \"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\", \"arg\": [\"-l\"]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+    assert_eq!(diagnostics[0].index, 1);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::UnknownField {
+            field: String::from("arg")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Unknown field \"arg\" of item [1][a] is ignored"
+    );
+    assert_eq!(diagnostics[1].index, 0);
+    assert_eq!(
+        diagnostics[1].message,
+        "Unknown field \"exec_lists\" is ignored"
+    );
+}
+
+#[test]
+fn validate_any_of_prerequisite_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\"}, {\"label\": \"b\", \"exec\": \"ls\"}, {\"exec\": \"ls\", \"prerequisites\": [{\"any_of\": [\"a\", \"b\"], \"min\": 3}, {\"any_of\": [\"a\", \"c\"]}, {\"any_of\": [\"a\", \"#2\"], \"min\": 2}]}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::InvalidPrerequisiteMin { min: 3 }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Prerequisite any_of [a, b] (min 3) of item [3] needs a min between 1 and 2"
+    );
+    assert_eq!(
+        diagnostics[1].kind,
+        DiagnosticKind::UnknownPrerequisite {
+            prerequisite: String::from("c")
+        }
+    );
+}
+
+#[test]
+fn validate_invalid_chunk_args_test() {
+    let diagnostics =
+        validate_str("{\"exec_list\": [{\"exec\": \"rm\", \"chunk_args\": {\"size\": 0}}]}");
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::InvalidChunkArgs);
+}
+
+#[test]
+fn validate_timers_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"timer_start\": \"a\"}, {\"timer_start\": \"b\"}, {\"exec\": \"ls\"}, {\"timer_stop\": \"a\"}, {\"timer_stop\": \"c\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::OverlappingTimer {
+            name: String::from("a")
+        }
+    );
+    assert_eq!(diagnostics[0].index, 4);
+    assert_eq!(
+        diagnostics[1].kind,
+        DiagnosticKind::UnbalancedTimer {
+            name: String::from("c")
+        }
+    );
+    assert_eq!(
+        diagnostics[2].message,
+        "Timer b is started by item [2] but never stopped"
+    );
+}
+
+#[test]
+fn validate_timer_with_exec_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"timer_start\": \"a\", \"exec\": \"ls\"}, {\"timer_stop\": \"a\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::ExecAndTimer);
+}
+
+#[test]
+fn validate_unused_label_test() {
+    let nansi_file = parse_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"ls\"}, {\"label\": \"b\", \"exec\": \"ls\"}, {\"label\": \"c\", \"exec\": \"ls\", \"keep\": true}, {\"label\": \"d\", \"exec\": \"ls\", \"prerequisites\": [\"a\", \"#2\"]}]}",
+    );
+    let diagnostics = validate(&nansi_file);
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Info);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::UnusedLabel);
+    assert_eq!(diagnostics[0].label, "d");
+}
+
+#[test]
+fn validate_env_test() {
+    let nansi_file = parse_str(
+        "{\"exec_list\": [{\"label\": \"a\", \"exec\": \"aws\", \"args\": [\"--profile\", \"{AWS_PROFILE}\", \"{REGION}\", \"{UNDEFINED}\", \"{PORT:8080}\"], \"requires_env\": [\"AWS_PROFILE\", \"EMPTY\", \"MISSING\"]}, {\"exec\": \"ls\", \"requires_env\": [\"EMPTY\"], \"allow_empty\": true}]}",
+    );
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "AWS_PROFILE", "dev");
+    ctx.set_var(VarSource::Env, "EMPTY", "");
+    ctx.set_var(VarSource::Vars, "REGION", "eu-west-1");
+    ctx.set_var(VarSource::Vars, "MISSING", "only in vars");
+
+    let diagnostics = validate_env(&nansi_file, &ctx, false);
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(
+        diagnostics[0].message,
+        "Item [1][a] requires EMPTY which is empty"
+    );
+    assert_eq!(
+        diagnostics[1].message,
+        "Item [1][a] requires MISSING which is not set"
+    );
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+
+    let diagnostics = validate_env(&nansi_file, &ctx, true);
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[2].severity, Severity::Warning);
+    assert_eq!(
+        diagnostics[2].kind,
+        DiagnosticKind::MissingEnv {
+            name: String::from("UNDEFINED")
+        }
+    );
+}
+
+#[test]
+fn validate_vars_test() {
+    let nansi_file = parse_str(
+        "{\"vars\": {\"A\": \"{B}\", \"B\": \"{A}\", \"URL\": \"{HOST}/{PATH}\"}, \"exec_list\": [{\"exec\": \"curl\", \"args\": [\"{URL}\"]}]}",
+    );
+
+    let diagnostics: Vec<Diagnostic> = validate(&nansi_file)
+        .into_iter()
+        .filter(|d| d.severity != Severity::Info)
+        .collect();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::VarCycle {
+            name: String::from("A")
+        }
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Variable A refers to itself: A -> B -> A"
+    );
+
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(VarSource::Env, "HOST", "example.com");
+    for (name, value) in super::resolve_vars(&nansi_file.vars, &ctx) {
+        ctx.set_var(VarSource::Vars, &name, &value);
+    }
+    let diagnostics = validate_env(&nansi_file, &ctx, true);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].index, 0);
+    assert_eq!(
+        diagnostics[0].message,
+        "Variable URL uses {PATH} which is not set"
+    );
+}
+
+#[test]
+fn validate_invalid_changed_when_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"ls\", \"changed_when\": \"exit_code:x\"}, {\"exec\": \"ls\", \"changed_when\": {\"output_matches\": \"a|b\"}}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].index, 1);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::InvalidChangedWhen);
+}
+
+#[test]
+fn validate_merge_streams_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"make\", \"merge_streams\": true}, {\"exec\": \"make\", \"merge_streams\": true, \"print_stderr\": true}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].index, 2);
+    assert_eq!(
+        diagnostics[0].kind,
+        DiagnosticKind::MergeStreamsAndPrintStderr
+    );
+}
+
+#[test]
+fn validate_capture_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"make\", \"capture\": \"none\"}, {\"exec\": \"make\", \"capture\": \"truncated\", \"print_output\": true}, {\"exec\": \"make\", \"capture\": \"none\", \"print_output\": \"stream\", \"changed_when\": {\"output_matches\": \"built\"}}]}",
+    );
+
+    let kinds: Vec<(usize, &DiagnosticKind)> =
+        diagnostics.iter().map(|d| (d.index, &d.kind)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                3,
+                &DiagnosticKind::UncapturedOutput {
+                    field: String::from("print_output")
+                }
+            ),
+            (
+                3,
+                &DiagnosticKind::UncapturedOutput {
+                    field: String::from("changed_when")
+                }
+            ),
+        ]
+    );
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+}
+
+#[test]
+fn validate_stdin_test() {
+    let diagnostics = validate_str(
+        "{\"exec_list\": [{\"exec\": \"psql\", \"stdin\": \"select 1;\"}, {\"exec\": \"psql\", \"stdin\": \"select 1;\", \"stdin_file\": \"query.sql\"}]}",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].index, 2);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::StdinAndStdinFile);
+}
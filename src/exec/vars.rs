@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{scan_tags, split_tag, ExecContext};
+
+/// Returns the names of the variables the value of a variable refers to with
+/// `{}` tags, escaped tags excluded.
+fn get_var_tags(value: &str) -> Vec<String> {
+    match scan_tags(value) {
+        Ok(scan) => scan.tags,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Returns the `vars` of a `NansiFile` with their `{}` tags expanded: a tag
+/// naming another variable of `vars` takes its expanded value, any other tag
+/// is resolved by `ctx` (the environment). Tags which resolve to nothing, and
+/// the tag closing a cycle, are left as they are.
+pub fn resolve_vars(vars: &BTreeMap<String, String>, ctx: &ExecContext) -> Vec<(String, String)> {
+    let mut resolved: HashMap<&str, String> = HashMap::new();
+    let mut stack: Vec<&str> = Vec::new();
+
+    vars.keys()
+        .map(|name| {
+            let value = resolve_var(name, vars, ctx, &mut resolved, &mut stack);
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+fn resolve_var<'a>(
+    name: &'a str,
+    vars: &'a BTreeMap<String, String>,
+    ctx: &ExecContext,
+    resolved: &mut HashMap<&'a str, String>,
+    stack: &mut Vec<&'a str>,
+) -> String {
+    if let Some(value) = resolved.get(name) {
+        return value.clone();
+    }
+
+    let value = &vars[name];
+    stack.push(name);
+    let mut resolved_value = value.clone();
+    for tag in get_var_tags(value) {
+        let (tag_name, default) = split_tag(&tag);
+        let tag_value = match vars.get_key_value(tag_name) {
+            Some((tag_name, _)) if !stack.contains(&tag_name.as_str()) => {
+                Some(resolve_var(tag_name, vars, ctx, resolved, stack))
+            }
+            Some(_) => None,
+            None => ctx.get_var(tag_name).or_else(|| default.map(String::from)),
+        };
+        if let Some(tag_value) = tag_value {
+            resolved_value = resolved_value.replace(&format!("{{{}}}", tag), &tag_value);
+        }
+    }
+    stack.pop();
+
+    resolved.insert(name, resolved_value.clone());
+    resolved_value
+}
+
+/// Returns the cycles between the `vars` of a `NansiFile`, each as the names
+/// of the variables it goes through, starting and ending with the same one.
+pub fn find_var_cycles(vars: &BTreeMap<String, String>) -> Vec<Vec<String>> {
+    let mut cycles: Vec<Vec<String>> = Vec::new();
+    let mut done: Vec<&str> = Vec::new();
+    let mut stack: Vec<&str> = Vec::new();
+
+    for name in vars.keys() {
+        find_cycles_from(name, vars, &mut done, &mut stack, &mut cycles);
+    }
+
+    cycles
+}
+
+fn find_cycles_from<'a>(
+    name: &'a str,
+    vars: &'a BTreeMap<String, String>,
+    done: &mut Vec<&'a str>,
+    stack: &mut Vec<&'a str>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    if done.contains(&name) {
+        return;
+    }
+    if let Some(start) = stack.iter().position(|n| *n == name) {
+        let mut cycle: Vec<String> = stack[start..].iter().map(|n| n.to_string()).collect();
+        cycle.push(name.to_string());
+        cycles.push(cycle);
+        return;
+    }
+
+    stack.push(name);
+    for tag in get_var_tags(&vars[name]) {
+        if let Some((tag_name, _)) = vars.get_key_value(split_tag(&tag).0) {
+            find_cycles_from(tag_name, vars, done, stack, cycles);
+        }
+    }
+    stack.pop();
+    done.push(name);
+}
+
+#[cfg(test)]
+fn vars_from(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn resolve_vars_test() {
+    let mut ctx = ExecContext::from_map(HashMap::new());
+    ctx.set_var(super::VarSource::Env, "HOME", "/home/nansi");
+    ctx.set_var(super::VarSource::Env, "REGION", "us-east-1");
+
+    let vars = vars_from(&[
+        ("CACHE", "{BUILD}/cache"),
+        ("BUILD", "{HOME}/build"),
+        ("REGION", "eu-west-1"),
+        ("URL", "https://{REGION}.example.com/{PATH}"),
+        ("LITERAL", "${HOME} \\{HOME}"),
+        ("PORT", "{BUILD_PORT:8080}/{REGION:eu-central-1}"),
+    ]);
+
+    assert_eq!(
+        resolve_vars(&vars, &ctx),
+        vec![
+            (String::from("BUILD"), String::from("/home/nansi/build")),
+            (
+                String::from("CACHE"),
+                String::from("/home/nansi/build/cache")
+            ),
+            (String::from("LITERAL"), String::from("${HOME} \\{HOME}")),
+            (String::from("PORT"), String::from("8080/eu-west-1")),
+            (String::from("REGION"), String::from("eu-west-1")),
+            (
+                String::from("URL"),
+                String::from("https://eu-west-1.example.com/{PATH}")
+            ),
+        ]
+    );
+
+    let vars = vars_from(&[("A", "a{B}"), ("B", "b{A}")]);
+    assert_eq!(
+        resolve_vars(&vars, &ctx),
+        vec![
+            (String::from("A"), String::from("ab{A}")),
+            (String::from("B"), String::from("b{A}")),
+        ]
+    );
+}
+
+#[test]
+fn find_var_cycles_test() {
+    assert!(find_var_cycles(&vars_from(&[("A", "{B}"), ("B", "{HOME}")])).is_empty());
+
+    assert_eq!(
+        find_var_cycles(&vars_from(&[
+            ("A", "{B}"),
+            ("B", "{C}"),
+            ("C", "{A}"),
+            ("D", "{D}"),
+            ("E", "{A}"),
+        ])),
+        vec![vec!["A", "B", "C", "A"], vec!["D", "D"]]
+    );
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fmt;
+
+use serde_json::Value;
+
+/// Error of `parse_yaml` with the position (1-based) it occurred at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YamlError {
+    pub message: String,
+
+    /// Line and column, unknown for errors about the whole document
+    pub position: Option<(usize, usize)>,
+}
+
+impl fmt::Display for YamlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for YamlError {}
+
+impl From<serde_yaml_ng::Error> for YamlError {
+    fn from(e: serde_yaml_ng::Error) -> YamlError {
+        let position = e.location().map(|l| (l.line(), l.column()));
+        let mut message = e.to_string();
+        // the position is kept apart, e.g. to prefix the message with it
+        if let Some((line, column)) = position {
+            let suffix = format!(" at line {} column {}", line, column);
+            if let Some(idx) = message.find(&suffix) {
+                message.replace_range(idx..idx + suffix.len(), "");
+            }
+        }
+
+        YamlError { message, position }
+    }
+}
+
+/// Parses a single YAML document into the equivalent json value. Mappings
+/// with duplicate keys are rejected, tagged values become a mapping of the
+/// tag to the value.
+pub fn parse_yaml(content: &str) -> Result<Value, YamlError> {
+    let value: serde_yaml_ng::Value = serde_yaml_ng::from_str(content)?;
+
+    serde_json::to_value(value).map_err(|e| YamlError {
+        message: e.to_string(),
+        position: None,
+    })
+}
+
+#[test]
+fn parse_yaml_test() {
+    let content = r##"
+# comment
+---
+exec_list:
+  - label: list   # trailing comment
+    exec: ls
+    args: [-la, "~/a b", 'it''s', 10]
+    print_output: true
+  -   label: script
+      exec: /bin/bash
+      args:
+      - -c
+      - |
+        echo "# not a comment"
+        echo done
+  - {exec: echo, args: [a, b], serial: yes}
+max_failures: 2
+hint: >-
+  folded
+  text
+
+  next
+empty:
+nothing: ~
+url: https://example.com/x#y
+ratio: 1.5
+version: 1.2.3
+"##;
+
+    assert_eq!(
+        parse_yaml(content).unwrap(),
+        serde_json::json!({
+            "exec_list": [
+                {
+                    "label": "list",
+                    "exec": "ls",
+                    "args": ["-la", "~/a b", "it's", 10],
+                    "print_output": true
+                },
+                {
+                    "label": "script",
+                    "exec": "/bin/bash",
+                    "args": ["-c", "echo \"# not a comment\"\necho done\n"]
+                },
+                {"exec": "echo", "args": ["a", "b"], "serial": "yes"}
+            ],
+            "max_failures": 2,
+            "hint": "folded text\nnext",
+            "empty": null,
+            "nothing": null,
+            "url": "https://example.com/x#y",
+            "ratio": 1.5,
+            "version": "1.2.3"
+        })
+    );
+}
+
+#[test]
+fn parse_yaml_scalars_test() {
+    assert_eq!(
+        parse_yaml("- \"a\\tb\\u00e9\"\n- 'x'\n- -12\n- 0x1f\n- +\n- [ ]\n- {}\n").unwrap(),
+        serde_json::json!(["a\tb\u{e9}", "x", -12, 31, "+", [], {}])
+    );
+    assert_eq!(
+        parse_yaml("a: |+\n  keep\n\nb: |\n  clip\n\n\nc: [1,\n  2]\n").unwrap(),
+        serde_json::json!({"a": "keep\n\n", "b": "clip\n", "c": [1, 2]})
+    );
+    assert_eq!(
+        parse_yaml("a: &x [1]\nb: *x\nc: !env HOME\n").unwrap(),
+        serde_json::json!({"a": [1], "b": [1], "c": {"!env": "HOME"}})
+    );
+    assert_eq!(parse_yaml("").unwrap(), Value::Null);
+    assert_eq!(parse_yaml("# only a comment\n").unwrap(), Value::Null);
+}
+
+#[test]
+fn parse_yaml_errors_test() {
+    let cases = [
+        (
+            "a: 1\n  b: 2\n",
+            "2:4: mapping values are not allowed in this context",
+        ),
+        (
+            "a: 1\nb: {c: 1, c: 2}\n",
+            "2:4: b: duplicate entry with key \"c\"",
+        ),
+        (
+            "a:\n\t- b\n",
+            "2:1: found character that cannot start any token, while scanning for the next token",
+        ),
+        (
+            "a: [\"x\" y]\n",
+            "1:9: did not find expected ',' or ']', while parsing a flow sequence at line 1 column 4",
+        ),
+        (
+            "a: 1\n---\nb: 2\n",
+            "deserializing from YAML containing more than one document is not supported",
+        ),
+    ];
+
+    for (content, expected) in cases {
+        assert_eq!(
+            parse_yaml(content).unwrap_err().to_string(),
+            expected,
+            "{:?}",
+            content
+        );
+    }
+}
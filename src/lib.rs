@@ -1,9 +1,16 @@
 mod args;
-mod exec;
+pub mod exec;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 use std::error::Error;
+use std::io::{self, IsTerminal};
 
-use args::Args;
+use args::{Args, Commands, LabelsCommand, TraceCommand};
+
+pub use exec::{validate, Diagnostic, DiagnosticKind, Severity};
 
 pub fn run() -> Result<(), Box<dyn Error>> {
     
@@ -14,9 +21,205 @@ pub fn run() -> Result<(), Box<dyn Error>> {
         }
     };
 
-    let nansi_file = exec::NansiFile::from(args.nansi_file.as_str())?;
-    exec::execute(&nansi_file)?;
+    exec::set_stream_colors(exec::get_stream_colors(
+        args.color.parse()?,
+        &exec::ColorEnv::from_env(),
+        io::stdout().is_terminal(),
+        io::stderr().is_terminal(),
+    ));
+
+    match &args.command {
+        Some(Commands::Trace { command }) => {
+            match command {
+                TraceCommand::Show { trace_file } => {
+                    print!("{}", exec::Trace::from(trace_file)?.show());
+                }
+            }
+
+            return Ok(());
+        }
+        Some(Commands::List {
+            nansi_file,
+            unused_labels,
+        }) => {
+            let nansi_file =
+                exec::load_nansi_file(nansi_file.as_str(), &exec::SourceOptions::default())?;
+            return exec::list(&nansi_file, *unused_labels);
+        }
+        Some(Commands::Validate { nansi_file }) => {
+            let nansi_file =
+                exec::load_nansi_file(nansi_file.as_str(), &exec::SourceOptions::default())?;
+            return exec::check(&nansi_file);
+        }
+        Some(Commands::Flaky {
+            nansi_file,
+            reports,
+            output,
+        }) => {
+            let nansi_file =
+                exec::load_nansi_file(nansi_file.as_str(), &exec::SourceOptions::default())?;
+            return exec::flaky(&nansi_file, reports, output.parse()?);
+        }
+        Some(Commands::HelpFormat) => {
+            print!("{}", exec::get_format_help());
+            return Ok(());
+        }
+        Some(Commands::Labels { command }) => {
+            match command {
+                LabelsCommand::Rename {
+                    nansi_file,
+                    old,
+                    new,
+                    write,
+                } => exec::rename_label(nansi_file, old, new, *write)?,
+            }
+
+            return Ok(());
+        }
+        #[cfg(feature = "serve")]
+        Some(Commands::Serve {
+            listen,
+            allow_exec,
+            max_runs,
+        }) => {
+            let options = serve::ServeOptions {
+                allow_exec: allow_exec.clone(),
+                max_runs: *max_runs,
+                runs_dir: None,
+            };
+            let server = serve::Server::bind(listen, options)?;
+            println!("Listening on {}", server.local_addr()?);
+            return server.run();
+        }
+        None => {}
+    }
+
+    let args_overrides = args.get_args_overrides()?;
+    let source_options = exec::SourceOptions {
+        format: args.format.parse()?,
+        insecure_http: args.insecure_http,
+        expect_sha256: args.expect_sha256,
+        verify_key: args.verify_key,
+    };
+    if let Some(out) = &args.compile {
+        let compiled = exec::compile_nansi_file(
+            args.nansi_file.unwrap_or_default().as_str(),
+            &source_options,
+        )?;
+        exec::check(&compiled.nansi_file)?;
+        compiled.save(out)?;
+        println!(
+            "Compiled {} ({} source file(s)) to {}",
+            compiled.nansi_file.file_path,
+            compiled.sources.len(),
+            out
+        );
+        return Ok(());
+    }
+    let mut nansi_file = match &args.inline {
+        Some(content) => exec::load_inline_nansi_file(content)?,
+        None => exec::load_nansi_file(
+            args.nansi_file.unwrap_or_default().as_str(),
+            &source_options,
+        )?,
+    };
+    let shuffle_seed = args
+        .shuffle
+        .map(|seed| seed.unwrap_or_else(exec::get_random_seed));
+    if args.check {
+        if let Some(runs) = args.shuffle_validate {
+            let seed = shuffle_seed.unwrap_or_else(exec::get_random_seed);
+            exec::check_shuffle(&nansi_file, runs, seed)?;
+        }
+        return exec::check(&nansi_file);
+    }
+    if args.list_labels {
+        return exec::list_labels(&nansi_file, args.output.parse()?);
+    }
+    if let Some(label) = &args.stdin_each {
+        let items = exec::read_stdin_items(io::stdin().lock())?;
+        exec::expand_stdin_each(&mut nansi_file, label, &items)?;
+    }
+
+    let (status_stream, output_stream) = if args.porcelain {
+        (exec::OutputStream::Stderr, exec::OutputStream::Stderr)
+    } else {
+        (args.status_stream.parse()?, args.output_stream.parse()?)
+    };
+    let options = exec::ExecOptions {
+        strict: args.strict,
+        strict_skips: args.strict_skips,
+        strict_prereqs: args.strict_prereqs,
+        force_env_checks: args.force_env_checks,
+        status_fd: args.status_fd,
+        status_pipe: args.status_pipe,
+        force: args.force,
+        state_file: args.state_file,
+        max_failures: args.max_failures,
+        fail_fast: args.fail_fast,
+        jobs: args.jobs,
+        elevation_command: args.elevation_command,
+        interactive_elevation: args.interactive_elevation,
+        yes: args.yes,
+        quiet: args.quiet,
+        porcelain: args.porcelain,
+        verbose: args.verbose,
+        trace: args.trace,
+        log_file: args.log_file,
+        report: args.report,
+        report_format: args.report_format.parse()?,
+        echo_item_on_failure: args.echo_item_on_failure,
+        notify_desktop: args.notify_desktop,
+        answers: args.answers,
+        timings: args.timings,
+        output_stream,
+        status_stream,
+        keep_tmp: args.keep_tmp,
+        ci_folding: args.ci_folding.parse()?,
+        import_state: args.import_state,
+        shuffle_seed,
+        changed_exit_code: args.changed_exit_code,
+        failure_preview_lines: args.failure_preview_lines,
+        progress: exec::detect_progress_mode(args.progress.parse()?, status_stream),
+        dry_run: args.dry_run,
+        args_overrides,
+        only: args.only,
+        only_with_deps: args.only_with_deps,
+        skip: args.skip,
+        inject_failure: args.inject_failure,
+        cancel: exec::CancelToken::new(),
+    };
+    if args.summary_line {
+        exec::cancel_on_interrupt(&options.cancel);
+    }
+
+    let (summary, result) = match exec::execute(&nansi_file, &options) {
+        Ok(summary) if options.cancel.is_cancelled() => {
+            (Some(summary), Err("Run interrupted".into()))
+        }
+        Ok(summary) => (Some(summary), Ok(())),
+        Err(e) => {
+            let summary = match e.downcast_ref::<exec::RunAborted>() {
+                Some(aborted) => Some(aborted.summary),
+                None => e
+                    .downcast_ref::<exec::RunFailed>()
+                    .map(|failed| failed.summary),
+            };
+            (summary, Err(e))
+        }
+    };
+    let exit_code = match (&result, options.changed_exit_code, summary) {
+        (Err(_), _, _) => 1,
+        (Ok(()), Some(code), Some(summary)) if summary.changed > 0 => code,
+        _ => 0,
+    };
+    if let (true, Some(summary)) = (args.summary_line, summary) {
+        println!("{}", summary.summary_line(exit_code));
+    }
+    if result.is_ok() && exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 
-    Ok(())
+    result
 
 }
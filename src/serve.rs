@@ -0,0 +1,573 @@
+//! Long-running server executing NansiFiles submitted over a minimal HTTP API
+//! (`nansi serve`):
+//!
+//! - `POST /runs` with a json NansiFile as body starts a run and returns its id,
+//! - `GET /runs/{id}/events` streams the ndjson events of the run (see
+//!   `Event`) until it finishes,
+//! - `GET /runs/{id}/report` returns the state of the run, its summary and,
+//!   once finished, its trace,
+//! - `POST /runs/{id}/cancel` skips the items of the run not started yet.
+//!
+//! Only NansiFiles whose items all run an allowed `exec` (as written, before
+//! tags are expanded) are accepted, and at most `max_runs` run at once. So
+//! that the allowed `exec` is what runs, a NansiFile may neither `include`
+//! other files nor set the variables the program is looked up or loaded with
+//! (see `get_disallowed_env`).
+//!
+//! Enabled by the `serve` feature.
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::exec::{self, CancelToken, ExecOptions, NansiFile, ProgressMode, RunSummary};
+
+/// Largest NansiFile accepted by `POST /runs`
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// How often `GET /runs/{id}/events` checks for new events
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Options of `Server`
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// `exec` values items of submitted NansiFiles may use
+    pub allow_exec: Vec<String>,
+
+    /// Number of runs executed at the same time, further submissions are
+    /// refused until one finishes
+    pub max_runs: usize,
+
+    /// Directory holding the submitted NansiFiles, events and traces of the
+    /// runs, a directory in the temporary directory if not set
+    pub runs_dir: Option<PathBuf>,
+}
+
+/// State of a run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunPhase {
+    Running,
+
+    /// All items were run (whether they succeeded or not)
+    Finished,
+
+    /// Cancelled before all items were run
+    Cancelled,
+
+    /// The run could not be executed (e.g. invalid NansiFile)
+    Failed,
+}
+
+struct Run {
+    phase: RunPhase,
+
+    cancel: CancelToken,
+
+    dir: PathBuf,
+
+    summary: Option<RunSummary>,
+
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Runs {
+    next_id: u64,
+
+    runs: HashMap<u64, Run>,
+}
+
+/// Request received by the server
+struct Request {
+    method: String,
+
+    path: String,
+
+    body: Vec<u8>,
+}
+
+/// Response to a request
+struct Response {
+    code: u16,
+
+    body: serde_json::Value,
+}
+
+impl Response {
+    fn new(code: u16, body: serde_json::Value) -> Response {
+        Response { code, body }
+    }
+
+    fn error(code: u16, message: String) -> Response {
+        Response::new(code, serde_json::json!({ "error": message }))
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+
+    runs_dir: PathBuf,
+
+    options: ServeOptions,
+
+    runs: Arc<Mutex<Runs>>,
+}
+
+impl Server {
+    /// Listens on `addr` (e.g. `127.0.0.1:7878`, port 0 picks a free port).
+    pub fn bind(addr: &str, options: ServeOptions) -> Result<Server, Box<dyn Error>> {
+        if options.allow_exec.is_empty() {
+            return Err("At least one exec must be allowed (--allow-exec)".into());
+        }
+        if options.max_runs == 0 {
+            return Err("The number of concurrent runs must be at least 1".into());
+        }
+
+        let listener = match TcpListener::bind(addr) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("{}: {}", addr, e).into()),
+        };
+        let runs_dir = match &options.runs_dir {
+            Some(dir) => dir.clone(),
+            None => env::temp_dir().join(format!("nansi-serve-{}", process::id())),
+        };
+        if let Err(e) = fs::create_dir_all(&runs_dir) {
+            return Err(format!("{}: {}", runs_dir.display(), e).into());
+        }
+
+        Ok(Server {
+            listener,
+            runs_dir,
+            options,
+            runs: Arc::new(Mutex::new(Runs::default())),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Serves requests, each on its own thread, until the listener fails.
+    pub fn run(self) -> Result<(), Box<dyn Error>> {
+        let server = Arc::new(self);
+        for stream in server.listener.incoming() {
+            let stream = stream?;
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                // the client went away, there is nobody to report the error to
+                let _ = server.handle(stream);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<(), io::Error> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut stream = stream;
+
+        let Request { method, path, body } = match read_request(&mut reader)? {
+            Ok(v) => v,
+            Err(response) => return write_response(&mut stream, &response),
+        };
+        let segments: Vec<&str> = path
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let response = match (method.as_str(), segments.as_slice()) {
+            ("POST", ["runs"]) => self.start_run(&body),
+            ("GET", ["runs", id, "events"]) => match self.get_run_dir(id) {
+                Some(dir) => return self.stream_events(&mut stream, id, dir),
+                None => unknown_run(id),
+            },
+            ("GET", ["runs", id, "report"]) => self.get_report(id),
+            ("POST", ["runs", id, "cancel"]) => self.cancel_run(id),
+            (_, ["runs"]) | (_, ["runs", _, "events" | "report" | "cancel"]) => {
+                Response::error(405, format!("{} is not allowed on {}", method, path))
+            }
+            _ => Response::error(404, format!("{} not found", path)),
+        };
+
+        write_response(&mut stream, &response)
+    }
+
+    /// Checks the submitted NansiFile and starts executing it on a new thread.
+    fn start_run(&self, body: &[u8]) -> Response {
+        let content = match std::str::from_utf8(body) {
+            Ok(v) => v,
+            Err(e) => return Response::error(400, format!("NansiFile is not utf-8: {}", e)),
+        };
+        let nansi_file = match NansiFile::from_json(content, "request") {
+            Ok(v) => v,
+            Err(e) => return Response::error(400, e.to_string()),
+        };
+
+        if nansi_file
+            .exec_list
+            .iter()
+            .any(|item| item.include.is_some())
+        {
+            let msg = String::from("include not allowed: the NansiFile must hold all its items");
+            return Response::error(403, msg);
+        }
+        let disallowed = get_disallowed_execs(&nansi_file, &self.options.allow_exec);
+        if !disallowed.is_empty() {
+            return Response::error(403, format!("exec not allowed: {}", disallowed.join(", ")));
+        }
+        let disallowed = get_disallowed_env(&nansi_file);
+        if !disallowed.is_empty() {
+            return Response::error(403, format!("env not allowed: {}", disallowed.join(", ")));
+        }
+
+        let mut runs = self.runs.lock().unwrap();
+        let running = runs
+            .runs
+            .values()
+            .filter(|run| run.phase == RunPhase::Running)
+            .count();
+        if running >= self.options.max_runs {
+            return Response::error(
+                429,
+                format!("{} run(s) in progress, try again later", running),
+            );
+        }
+
+        runs.next_id += 1;
+        let id = runs.next_id;
+        let dir = self.runs_dir.join(id.to_string());
+        let file_path = dir.join("nansifile.json");
+        let events_path = dir.join("events.ndjson");
+        let prepared = fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&file_path, content))
+            .and_then(|_| File::create(&events_path).map(|_| ()));
+        if let Err(e) = prepared {
+            return Response::error(500, format!("{}: {}", dir.display(), e));
+        }
+
+        let cancel = CancelToken::new();
+        runs.runs.insert(
+            id,
+            Run {
+                phase: RunPhase::Running,
+                cancel: cancel.clone(),
+                dir: dir.clone(),
+                summary: None,
+                error: None,
+            },
+        );
+        drop(runs);
+
+        let options = ExecOptions {
+            status_pipe: Some(events_path.to_string_lossy().to_string()),
+            trace: Some(dir.join("trace.json").to_string_lossy().to_string()),
+            quiet: true,
+            progress: ProgressMode::Plain,
+            cancel,
+            ..ExecOptions::default()
+        };
+        let runs = Arc::clone(&self.runs);
+        thread::spawn(move || {
+            let result = match exec::load_nansi_file(
+                &file_path.to_string_lossy(),
+                &exec::SourceOptions::default(),
+            ) {
+                Ok(nansi_file) => exec::execute(&nansi_file, &options),
+                Err(e) => Err(e.into()),
+            };
+
+            let mut runs = runs.lock().unwrap();
+            if let Some(run) = runs.runs.get_mut(&id) {
+                match result {
+                    Ok(summary) => {
+                        run.phase = if options.cancel.is_cancelled() {
+                            RunPhase::Cancelled
+                        } else {
+                            RunPhase::Finished
+                        };
+                        run.summary = Some(summary);
+                    }
+                    Err(e) => {
+                        run.phase = RunPhase::Failed;
+                        run.error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        Response::new(201, serde_json::json!({ "id": id.to_string() }))
+    }
+
+    fn get_run_dir(&self, id: &str) -> Option<PathBuf> {
+        let runs = self.runs.lock().unwrap();
+        let run = runs.runs.get(&id.parse::<u64>().ok()?)?;
+
+        Some(run.dir.clone())
+    }
+
+    fn get_phase(&self, id: &str) -> Option<RunPhase> {
+        let runs = self.runs.lock().unwrap();
+        let run = runs.runs.get(&id.parse::<u64>().ok()?)?;
+
+        Some(run.phase)
+    }
+
+    /// Writes the events of the run as they are emitted, closing the
+    /// connection once the run is over and all its events were sent.
+    fn stream_events(
+        &self,
+        stream: &mut TcpStream,
+        id: &str,
+        dir: PathBuf,
+    ) -> Result<(), io::Error> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n"
+        )?;
+
+        let mut events = File::open(dir.join("events.ndjson"))?;
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            let over = self.get_phase(id) != Some(RunPhase::Running);
+            events.read_to_end(&mut buf)?;
+            if !buf.is_empty() {
+                stream.write_all(&buf)?;
+                stream.flush()?;
+                buf.clear();
+            }
+            if over {
+                return Ok(());
+            }
+
+            thread::sleep(EVENTS_POLL_INTERVAL);
+        }
+    }
+
+    fn get_report(&self, id: &str) -> Response {
+        let runs = self.runs.lock().unwrap();
+        let run = match id.parse::<u64>().ok().and_then(|n| runs.runs.get(&n)) {
+            Some(v) => v,
+            None => return unknown_run(id),
+        };
+
+        let trace = match run.phase {
+            RunPhase::Running => None,
+            _ => fs::read_to_string(run.dir.join("trace.json"))
+                .ok()
+                .and_then(|trace| serde_json::from_str::<serde_json::Value>(&trace).ok()),
+        };
+
+        Response::new(
+            200,
+            serde_json::json!({
+                "id": id,
+                "state": run.phase,
+                "summary": run.summary,
+                "error": run.error,
+                "trace": trace,
+            }),
+        )
+    }
+
+    fn cancel_run(&self, id: &str) -> Response {
+        let runs = self.runs.lock().unwrap();
+        let run = match id.parse::<u64>().ok().and_then(|n| runs.runs.get(&n)) {
+            Some(v) => v,
+            None => return unknown_run(id),
+        };
+        if run.phase != RunPhase::Running {
+            return Response::error(409, format!("run {} is not running", id));
+        }
+
+        run.cancel.cancel();
+
+        Response::new(202, serde_json::json!({ "id": id, "state": run.phase }))
+    }
+}
+
+/// Returns the `exec` values of the items which are not in `allow_exec`;
+/// timers and `assert` items run nothing and are always allowed. Items marked
+/// `elevate` also need their elevation command to be allowed.
+fn get_disallowed_execs(nansi_file: &NansiFile, allow_exec: &[String]) -> Vec<String> {
+    let elevation_command = nansi_file
+        .elevation_command
+        .as_deref()
+        .unwrap_or(exec::DEFAULT_ELEVATION_COMMAND);
+
+    let mut disallowed: Vec<String> = Vec::new();
+    for exec_item in &nansi_file.exec_list {
+        if exec_item.is_timer() || exec_item.assert.is_some() {
+            continue;
+        }
+        let mut execs = vec![exec_item.exec.as_str()];
+        if exec_item.elevate {
+            execs.push(elevation_command);
+        }
+        for exec in execs {
+            if !allow_exec.iter().any(|allowed| allowed == exec)
+                && !disallowed.iter().any(|found| found == exec)
+            {
+                disallowed.push(String::from(exec));
+            }
+        }
+    }
+
+    disallowed
+}
+
+/// Returns the variables set in the `env` of the items which change the
+/// program an `exec` runs: `PATH`, which it is looked up in, and the `LD_*`
+/// and `DYLD_*` variables of the dynamic loader, e.g. `LD_PRELOAD`.
+fn get_disallowed_env(nansi_file: &NansiFile) -> Vec<String> {
+    let mut disallowed: Vec<String> = Vec::new();
+    for name in nansi_file.exec_list.iter().flat_map(|item| item.env.keys()) {
+        let upper = name.to_uppercase();
+        if (upper == "PATH" || upper.starts_with("LD_") || upper.starts_with("DYLD_"))
+            && !disallowed.contains(name)
+        {
+            disallowed.push(name.clone());
+        }
+    }
+
+    disallowed
+}
+
+fn unknown_run(id: &str) -> Response {
+    Response::error(404, format!("run {} not found", id))
+}
+
+/// Reads the request line, headers and body of a request, or returns the
+/// response to send if it cannot be served.
+fn read_request(reader: &mut impl BufRead) -> Result<Result<Request, Response>, io::Error> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(Response::error(400, String::from("malformed request")))),
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse::<usize>() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        let msg = String::from("invalid Content-Length");
+                        return Ok(Err(Response::error(400, msg)));
+                    }
+                };
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        let msg = format!("the body is larger than {} bytes", MAX_BODY_BYTES);
+        return Ok(Err(Response::error(413, msg)));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Ok(Request { method, path, body }))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<(), io::Error> {
+    let body = response.body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.code,
+        get_reason_phrase(response.code),
+        body.len(),
+        body
+    )?;
+
+    stream.flush()
+}
+
+fn get_reason_phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        _ => "Internal Server Error",
+    }
+}
+
+#[test]
+fn get_disallowed_execs_test() {
+    let nansi_file = NansiFile::from_json(
+        "{\"exec_list\": [{\"exec\": \"echo\"}, {\"exec\": \"rm\"}, {\"exec\": \"rm\"}, {\"timer_start\": \"t\"}, {\"assert\": {\"env_set\": \"HOME\"}}, {\"exec\": \"/bin/echo\"}]}",
+        "nansi.json",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_disallowed_execs(&nansi_file, &[String::from("echo")]),
+        vec!["rm", "/bin/echo"]
+    );
+
+    let nansi_file = NansiFile::from_json(
+        "{\"exec_list\": [{\"exec\": \"echo\", \"elevate\": true}]}",
+        "nansi.json",
+    )
+    .unwrap();
+    assert_eq!(
+        get_disallowed_execs(&nansi_file, &[String::from("echo")]),
+        vec!["sudo"]
+    );
+}
+
+#[test]
+fn get_disallowed_env_test() {
+    let nansi_file = NansiFile::from_json(
+        "{\"exec_list\": [{\"exec\": \"echo\", \"env\": {\"PATH\": \"/tmp\", \"HOME\": \"/tmp\"}}, {\"exec\": \"echo\", \"env\": {\"LD_PRELOAD\": \"/tmp/x.so\", \"Path\": \"/tmp\", \"PATH\": \"/tmp\"}}]}",
+        "nansi.json",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_disallowed_env(&nansi_file),
+        vec!["PATH", "LD_PRELOAD", "Path"]
+    );
+}
+
+#[test]
+fn read_request_test() {
+    let mut request: &[u8] =
+        b"POST /runs HTTP/1.1\r\nHost: localhost\r\ncontent-length: 4\r\n\r\n{}\r\nignored";
+    let request = read_request(&mut request).unwrap().ok().unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/runs");
+    assert_eq!(request.body, b"{}\r\n");
+
+    let mut request: &[u8] = b"\r\n";
+    assert_eq!(read_request(&mut request).unwrap().err().unwrap().code, 400);
+
+    let mut request: &[u8] = b"POST /runs HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n";
+    assert_eq!(read_request(&mut request).unwrap().err().unwrap().code, 413);
+}
@@ -0,0 +1,304 @@
+//! Helpers for integration tests of NansiFiles: run a fixture with the `nansi`
+//! binary, normalize its output and compare it to golden files.
+//!
+//! Enabled by the `testing` feature.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::exec::{strip_ansi, Trace, TraceItem};
+
+/// Options of `run_fixture`
+#[derive(Debug, Clone, Default)]
+pub struct FixtureOptions {
+    /// Path of the `nansi` binary, `nansi` from `PATH` if unset
+    pub binary: Option<PathBuf>,
+
+    /// Additional command line arguments
+    pub args: Vec<String>,
+
+    /// Additional environment variables
+    pub env: Vec<(String, String)>,
+}
+
+/// Outcome of a run of a fixture
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    /// Exit code of `nansi`, `None` if it was killed by a signal
+    pub code: Option<i32>,
+
+    pub stdout: String,
+
+    pub stderr: String,
+
+    /// Trace of the run, `None` if `nansi` failed before executing items
+    pub trace: Option<Trace>,
+}
+
+impl ExecutionReport {
+    /// Returns the traced items with the label `label`.
+    pub fn items(&self, label: &str) -> Vec<&TraceItem> {
+        match &self.trace {
+            Some(trace) => trace
+                .items
+                .iter()
+                .filter(|item| item.label == label)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Status of an item as recorded in a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Err,
+    Warn,
+    Skip,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Err => "err",
+            Status::Warn => "warn",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+/// Runs the `NansiFile` at `path` with the `nansi` binary, recording a trace.
+pub fn run_fixture(
+    path: &str,
+    options: &FixtureOptions,
+) -> Result<ExecutionReport, Box<dyn Error>> {
+    // tests of a binary share its pid and may run the same fixture at once
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let trace_path = env::temp_dir().join(format!(
+        "nansi-fixture-{}-{}-{}.json",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::SeqCst),
+        Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    ));
+    let _ = fs::remove_file(&trace_path);
+
+    let binary = match &options.binary {
+        Some(binary) => binary.clone(),
+        None => PathBuf::from("nansi"),
+    };
+    let output = Command::new(&binary)
+        .arg("--trace")
+        .arg(&trace_path)
+        .args(&options.args)
+        .arg(path)
+        .envs(options.env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("{}: {}", binary.display(), e))?;
+
+    let trace = if trace_path.exists() {
+        let trace = Trace::from(&trace_path.to_string_lossy())?;
+        fs::remove_file(&trace_path)?;
+        Some(trace)
+    } else {
+        None
+    };
+
+    Ok(ExecutionReport {
+        code: output.status.code(),
+        stdout: String::from_utf8(output.stdout)?,
+        stderr: String::from_utf8(output.stderr)?,
+        trace,
+    })
+}
+
+/// Returns `output` without colors, with durations replaced by `<duration>`,
+/// paths in the temporary directory (which carry PIDs) by `<tmp>` and the
+/// checksum of the `NansiFile` by `<sha256>`, which changes with any edit of
+/// the fixture.
+pub fn normalize_output(output: &str) -> String {
+    let output = strip_ansi(output);
+    let output = replace_tmp_paths(&output);
+    let output = replace_sha256(&output);
+
+    replace_durations(&output)
+}
+
+/// Panics unless every item labeled `label` has the status `status`.
+pub fn assert_status(report: &ExecutionReport, label: &str, status: Status) {
+    let items = report.items(label);
+    assert!(
+        !items.is_empty(),
+        "no item labeled {} was traced\nstdout:\n{}\nstderr:\n{}",
+        label,
+        report.stdout,
+        report.stderr
+    );
+
+    for item in items {
+        assert_eq!(
+            item.status,
+            status.as_str(),
+            "status of item [{}][{}]\nstdout:\n{}",
+            item.index,
+            label,
+            report.stdout
+        );
+    }
+}
+
+/// Panics unless the normalized `output` equals the content of the golden
+/// file at `path`. With `NANSI_UPDATE_GOLDEN=1` the file is (re)written
+/// instead.
+pub fn assert_golden(output: &str, path: &str) {
+    let output = normalize_output(output);
+
+    if env::var("NANSI_UPDATE_GOLDEN").is_ok_and(|v| v == "1") {
+        if let Err(e) = fs::write(path, &output) {
+            panic!("{}: {}", path, e);
+        }
+        return;
+    }
+
+    let golden = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => panic!(
+            "{}: {} (run with NANSI_UPDATE_GOLDEN=1 to create it)",
+            path, e
+        ),
+    };
+    assert_eq!(output, golden, "output differs from golden file {}", path);
+}
+
+/// Replaces paths starting with the temporary directory by `<tmp>`.
+fn replace_tmp_paths(s: &str) -> String {
+    let tmp_dir = env::temp_dir()
+        .to_string_lossy()
+        .trim_end_matches('/')
+        .to_string();
+    if tmp_dir.is_empty() {
+        return String::from(s);
+    }
+
+    let mut replaced = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find(&tmp_dir) {
+        replaced.push_str(&rest[..pos]);
+        let after = &rest[pos + tmp_dir.len()..];
+        if after.starts_with('/') {
+            let end = after
+                .find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+                .unwrap_or(after.len());
+            replaced.push_str("<tmp>");
+            rest = &after[end..];
+        } else {
+            replaced.push_str(&tmp_dir);
+            rest = after;
+        }
+    }
+    replaced.push_str(rest);
+
+    replaced
+}
+
+/// Replaces the checksum of the `NansiFile sha256: ...` lines by `<sha256>`.
+fn replace_sha256(s: &str) -> String {
+    const PREFIX: &str = "NansiFile sha256: ";
+
+    s.split_inclusive('\n')
+        .map(|line| match line.strip_prefix(PREFIX) {
+            Some(rest) if rest.ends_with('\n') => format!("{}<sha256>\n", PREFIX),
+            Some(_) => format!("{}<sha256>", PREFIX),
+            None => String::from(line),
+        })
+        .collect()
+}
+
+/// Replaces durations such as `850ms`, `12 ms`, `4.2s`, `2m14s` and `1h05m`
+/// (see `format_duration_ms`) by `<duration>`.
+fn replace_durations(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut replaced = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let starts_word = i == 0 || !(chars[i - 1].is_alphanumeric() || chars[i - 1] == '.');
+        if starts_word && chars[i].is_ascii_digit() {
+            if let Some(len) = get_duration_len(&chars[i..]) {
+                replaced.push_str("<duration>");
+                i += len;
+                continue;
+            }
+        }
+        replaced.push(chars[i]);
+        i += 1;
+    }
+
+    replaced
+}
+
+/// Returns the length of the duration at the start of `chars`.
+fn get_duration_len(chars: &[char]) -> Option<usize> {
+    let digits = |from: usize| {
+        chars[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit() || **c == '.')
+            .count()
+    };
+    let suffix = |from: usize, suffix: &str| {
+        let suffix: Vec<char> = suffix.chars().collect();
+        chars.len() >= from + suffix.len() && chars[from..from + suffix.len()] == suffix[..]
+    };
+
+    let number = digits(0);
+    let len = if suffix(number, "ms") {
+        number + 2
+    } else if suffix(number, " ms") {
+        number + 3
+    } else if suffix(number, "s") {
+        number + 1
+    } else if (suffix(number, "m") && digits(number + 1) == 2 && suffix(number + 3, "s"))
+        || (suffix(number, "h") && digits(number + 1) == 2 && suffix(number + 3, "m"))
+    {
+        number + 4
+    } else {
+        return None;
+    };
+
+    match chars.get(len) {
+        Some(c) if c.is_alphanumeric() => None,
+        _ => Some(len),
+    }
+}
+
+#[test]
+fn normalize_output_test() {
+    let tmp_dir = env::temp_dir()
+        .to_string_lossy()
+        .trim_end_matches('/')
+        .to_string();
+    let output = format!(
+        "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][build] make (2m14s, peak 3.1 GiB)\n\
+         [2][run] sh {}/nansi-123-456-0 took 850ms, 4.2s, 1h05m and 12 ms\n\
+         ls -12345 4s3 v1.2s\n\
+         NansiFile sha256: 571e45f20dcb9922c7fa8f043ae7da99c07e60cad3b729cfa94b7754a01748ca\n",
+        tmp_dir
+    );
+
+    assert_eq!(
+        normalize_output(&output),
+        "[OK] [1][build] make (<duration>, peak 3.1 GiB)\n\
+         [2][run] sh <tmp> took <duration>, <duration>, <duration> and <duration>\n\
+         ls -12345 4s3 v1.2s\n\
+         NansiFile sha256: <sha256>\n"
+    );
+}
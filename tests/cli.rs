@@ -1,54 +1,2714 @@
 use std::error::Error;
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use assert_cmd::prelude::*;
+use nansi::exec::{FailureReason, RunReport};
+use nansi::testing::{
+    assert_golden, assert_status, normalize_output, run_fixture, FixtureOptions, Status,
+};
 use predicates::prelude::*;
 
+/// Returns the `nansi` command with colors forced, as the expected outputs
+/// contain them while the output is captured through a pipe.
+fn nansi_cmd() -> Result<Command, Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("nansi")?;
+    cmd.env_remove("NO_COLOR").env("CLICOLOR_FORCE", "1");
+
+    Ok(cmd)
+}
+
+fn fixture_options(args: &[&str]) -> FixtureOptions {
+    FixtureOptions {
+        binary: Some(env!("CARGO_BIN_EXE_nansi").into()),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        env: Vec::new(),
+    }
+}
+
 #[test]
 fn file_doesnt_exist() -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::cargo_bin("nansi")?;
+    let mut cmd = nansi_cmd()?;
 
     cmd.arg("test/file/doesnt/exist");
-    cmd.assert().failure().stderr(predicate::str::contains("No such file or directory"));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("No such file or directory"));
 
     Ok(())
 }
 
 #[test]
 fn linux_file() -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::cargo_bin("nansi")?;
+    let report = run_fixture(
+        "testdata/nansifile_linux.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
 
-    cmd.arg("testdata/nansifile_linux.json");
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "ls", Status::Ok);
+    assert_status(&report, "l2", Status::Err);
+    assert_status(&report, "asd", Status::Err);
+    assert_status(&report, "bash", Status::Ok);
+    assert_golden(&report.stdout, "testdata/golden/nansifile_linux.txt");
+
+    Ok(())
+}
 
-    let output = "Using NansiFile: testdata/nansifile_linux.json\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][ls] ls \n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [2][l2] ls -12345\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][asd] aaa \nNo such file or directory (os error 2)\n[\u{1b}[38;5;10mOK\u{1b}[39m] [4][bash] /bin/bash -c ls -ltra | grep README\n";
-    
-    cmd.assert().success().stdout(predicate::str::contains(output.to_string()));
+#[test]
+fn linux_color_flag() -> Result<(), Box<dyn Error>> {
+    let run = |args: &[&str], env: &[(&str, &str)]| -> Result<bool, Box<dyn Error>> {
+        let output = Command::cargo_bin("nansi")?
+            .env_remove("NO_COLOR")
+            .env_remove("CLICOLOR_FORCE")
+            .envs(env.iter().copied())
+            .args(args)
+            .arg("testdata/nansifile_linux.json")
+            .output()?;
+        assert!(output.status.success());
+
+        Ok(String::from_utf8(output.stdout)?.contains('\u{1b}'))
+    };
+
+    // stdout is a pipe
+    assert!(!run(&[], &[])?);
+    assert!(!run(&["--color", "auto"], &[])?);
+    assert!(run(&["--color", "always"], &[])?);
+    assert!(run(&[], &[("CLICOLOR_FORCE", "1")])?);
+    assert!(!run(&[], &[("CLICOLOR_FORCE", "0")])?);
+    assert!(!run(&[], &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")])?);
+    assert!(run(&["--color=always"], &[("NO_COLOR", "1")])?);
+    assert!(!run(&["--color=never"], &[("CLICOLOR_FORCE", "1")])?);
+    assert!(run(
+        &[],
+        &[("NANSI_OPTS", "--color=always"), ("NO_COLOR", "1")]
+    )?);
+    assert!(run(
+        &["--color=always"],
+        &[("NANSI_OPTS", "--color=never")]
+    )?);
 
     Ok(())
 }
 
 #[test]
 fn linux_duplicate_labels_file() -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::cargo_bin("nansi")?;
+    let report = run_fixture(
+        "testdata/nansifile_linux_duplicate_labels.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "ls", Status::Ok);
+    assert_status(&report, "asd", Status::Err);
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_duplicate_labels.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_yaml_file() -> Result<(), Box<dyn Error>> {
+    let json = nansi_cmd()?.arg("testdata/nansifile_linux.json").output()?;
+    let yaml = nansi_cmd()?.arg("testdata/nansifile_linux.yaml").output()?;
+    assert!(yaml.status.success());
+
+    // apart from the path and digest of the file, the runs are the same
+    let items = |stdout: &[u8]| -> Vec<String> {
+        String::from_utf8_lossy(stdout)
+            .lines()
+            .filter(|line| !line.starts_with("Using NansiFile:"))
+            .filter(|line| !line.starts_with("NansiFile sha256:"))
+            .map(normalize_output)
+            .collect()
+    };
+    assert_eq!(items(&yaml.stdout), items(&json.stdout));
+
+    Ok(())
+}
+
+#[test]
+fn linux_yaml_prereq_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_prereq.yml",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "ls", Status::Ok);
+    assert_status(&report, "lsls", Status::Skip);
+    assert_status(&report, "l2", Status::Err);
+    assert_status(&report, "asd", Status::Err);
+    assert_status(&report, "bash", Status::Skip);
+
+    Ok(())
+}
+
+#[test]
+fn linux_yaml_format_flag() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .args(["--format", "yaml", "testdata/nansifile_linux_yaml"])
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.contains("[1][ls] ls"));
+
+    // without an extension the file is parsed as json
+    let output = nansi_cmd()?.arg("testdata/nansifile_linux_yaml").output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("testdata/nansifile_linux_yaml: expected value at line 1 column 1"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_yaml_invalid_file() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .arg("testdata/nansifile_linux_invalid.yaml")
+        .output()?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("testdata/nansifile_linux_invalid.yaml:4:10: mapping values are not allowed in this context"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_dry_run() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .args(["--dry-run", "testdata/nansifile_linux_dry_run.json"])
+        .env("NANSI_TEST_HOME", "/home/nansi")
+        .env("NANSI_TEST_TOKEN", "hunter2")
+        .env("NANSI_TEST_UNSET", "set")
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        normalize_output(&String::from_utf8(output.stdout)?),
+        "Using NansiFile: testdata/nansifile_linux_dry_run.json\n\
+         [DRY] [1][fail] false\n\
+         [DRY] [2][after] echo /home/nansi/bin\n\
+         [DRY] [3][secret] echo --token ***\n\
+         [DRY] [4][never] echo set\n\
+         [DRY] [5][blocked] ls\n\
+         Dry run: 5 item(s) would run, 0 would be skipped.\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_dry_run_invalid_plan() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .args(["--dry-run", "testdata/nansifile_linux_dry_run.json"])
+        .env("NANSI_TEST_HOME", "/home/nansi")
+        .env("NANSI_TEST_TOKEN", "hunter2")
+        .env_remove("NANSI_TEST_UNSET")
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = normalize_output(&String::from_utf8(output.stdout)?);
+    assert!(stdout.contains(
+        "[ERR] Argument 1 of item [4][never] refers to a variable which is not set: {NANSI_TEST_UNSET}\n\
+         [SKIP] [5][blocked] ls \n\
+         Prerequisites for item [5][blocked] would not be met: never.\n\
+         Dry run: 3 item(s) would run, 1 would be skipped.\n"
+    ));
+    assert!(String::from_utf8(output.stderr)?.contains("The plan is invalid, 1 error(s) found"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_dry_run_duplicate_labels() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .args([
+            "--dry-run",
+            "testdata/nansifile_linux_duplicate_labels.json",
+        ])
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = normalize_output(&String::from_utf8(output.stdout)?);
+    assert!(stdout.contains("[WARN] The following aliases are duplicated"));
+    assert!(stdout.contains("[DRY] [3][asd] aaa\n"));
+    assert!(!stdout.contains("No such file or directory"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_merge_streams_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_merge_streams.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
 
-    cmd.arg("testdata/nansifile_linux_duplicate_labels.json");
+    assert_eq!(report.code, Some(0));
+    assert!(normalize_output(&report.stdout).contains(
+        "[FAIL] [1][merged] /bin/sh -c echo one; echo two >&2; echo three; exit 1 (exit 1)\none\ntwo\nthree\n"
+    ));
+    let merged = &report.items("merged")[0];
+    assert!(merged.merged_streams);
+    assert_eq!(merged.stdout, "one\ntwo\nthree\n");
+    assert_eq!(merged.stderr, "");
+    let separate = &report.items("separate")[0];
+    assert!(!separate.merged_streams);
+    assert_eq!(separate.stdout, "one\nthree\n");
+    assert_eq!(separate.stderr, "two\n");
 
-    let output = "Using NansiFile: testdata/nansifile_linux_duplicate_labels.json\n\u{1b}[38;5;11m[WARN]\u{1b}[39m The following aliases are duplicated which may cause issues with conditional execution:\n[\"asd\", \"ls\"]\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][ls] ls \n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [2] ls -12345\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][asd] aaa \nNo such file or directory (os error 2)\n[\u{1b}[38;5;10mOK\u{1b}[39m] [4][ls] ls \n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [5][asd] aaa \nNo such file or directory (os error 2)\n[\u{1b}[38;5;10mOK\u{1b}[39m] [6] /bin/bash -c ls -ltra | grep README\n";
+    let output = nansi_cmd()?
+        .arg("testdata/nansifile_linux_merge_streams_invalid.json")
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("Item [1][merged] cannot have both merge_streams and print_stderr"));
 
-    cmd.assert().success().stdout(predicate::str::contains(output.to_string()));
+    Ok(())
+}
+
+#[test]
+fn linux_cwd_file() -> Result<(), Box<dyn Error>> {
+    let cwd = std::env::current_dir()?;
+    let mut options = fixture_options(&["--failure-preview-lines", "0"]);
+    options
+        .env
+        .push((String::from("NANSI_TEST_CWD"), String::from("/")));
+    let report = run_fixture("testdata/nansifile_linux_cwd.json", &options)?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "relative", Status::Ok);
+    assert_eq!(
+        report.items("relative")[0].stdout,
+        format!("{}\n", cwd.join("testdata/golden").display())
+    );
+    assert_eq!(report.items("tagged")[0].stdout, "/\n");
+    assert_status(&report, "missing", Status::Err);
+    assert_eq!(
+        report.items("missing")[0].stderr,
+        "working directory testdata/surely_missing_dir does not exist"
+    );
+    assert_eq!(
+        report.items("inherited")[0].stdout,
+        format!("{}\n", cwd.display())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_args_override_file() -> Result<(), Box<dyn Error>> {
+    let options = fixture_options(&[
+        "--prepend-args",
+        "greet=-n",
+        "--append-args",
+        "greet=\"big world\" again",
+        "--append-args",
+        "greet=!",
+    ]);
+    let report = run_fixture("testdata/nansifile_linux_args_override.json", &options)?;
+
+    assert_eq!(report.code, Some(0));
+    assert_eq!(
+        report.items("greet")[0].stdout,
+        "hello --name x big world again !"
+    );
+    assert_eq!(report.items("other")[0].stdout, "unchanged\n");
+    assert!(report.stdout.contains(
+        "[INFO] Arguments of item [1][greet] overridden from the command line: prepended -n, appended big world again !\n"
+    ));
+
+    let output = nansi_cmd()?
+        .args([
+            "testdata/nansifile_linux_args_override.json",
+            "--append-args",
+            "gret=x",
+        ])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains(
+        "Cannot override the arguments of gret: no item has this label, did you mean greet?"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_timeout_file() -> Result<(), Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    let report = run_fixture(
+        "testdata/nansifile_linux_timeout.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(20));
+    assert_status(&report, "hung", Status::Err);
+    let hung = &report.items("hung")[0];
+    assert_eq!(hung.stdout, "partial\n");
+    assert_eq!(hung.stderr, "failing\n");
+    assert_eq!(
+        hung.failure_reason,
+        Some(FailureReason::TimedOut { limit_secs: 1 })
+    );
+    assert!(report
+        .stdout
+        .contains("[FAIL] [1][hung] sh -c echo partial; echo failing >&2; sleep 30 (timed out after 1s)\nfailing\n"));
+    assert_status(&report, "quick", Status::Ok);
+
+    Ok(())
+}
+
+#[test]
+fn linux_parallel_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_parallel.json",
+        &fixture_options(&["--jobs", "2"]),
+    )?;
+
+    assert_status(&report, "slow", Status::Ok);
+    assert_status(&report, "fast", Status::Ok);
+    assert!(report
+        .stdout
+        .contains("[OK] [2][fast] echo fast\n[OK] [1][slow] sleep 1\n[OK] [3] echo after\n"));
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_parallel.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(
+            "(?s)\\[1\\]\\[slow\\].*\\[2\\]\\[fast\\]",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn linux_parallel_output_blocks() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_parallel_output.json",
+        &fixture_options(&["--jobs", "8"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    let stdout = normalize_output(&report.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    for item in 1..=4 {
+        assert_status(&report, &format!("stream{}", item), Status::Ok);
+    }
+    // the lines streamed meanwhile by other items never split a block
+    for item in 1..=16 {
+        let label = format!("out{}", item);
+        assert_status(&report, &label, Status::Ok);
+
+        let status_line = format!("][{}] sh -c ", label);
+        let start = lines
+            .iter()
+            .position(|line| line.starts_with("[OK] [") && line.contains(&status_line))
+            .unwrap();
+        let expected: Vec<String> = (1..=2000)
+            .map(|line| format!("{} line {}", label, line))
+            .collect();
+        assert_eq!(
+            lines[start + 1..=start + 2000].join("\n"),
+            expected.join("\n"),
+            "output of item {} is interleaved",
+            label
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn linux_serial_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_serial.json",
+        &fixture_options(&["--jobs", "4", "--verbose"]),
+    )?;
+
+    assert_status(&report, "slow", Status::Ok);
+    assert_status(&report, "migrate", Status::Ok);
+    assert_status(&report, "fast", Status::Ok);
+    assert!(report.stdout.contains(
+        "[INFO] Item [2][migrate] is serial: it runs alone, the items before it are done and those after it wait"
+    ));
+    assert!(predicate::str::is_match(
+        "(?s)\\[OK\\] \\[1\\]\\[slow\\].*\\[OK\\] \\[2\\]\\[migrate\\].*\\[OK\\] \\[3\\]\\[fast\\]"
+    )?
+    .eval(&report.stdout));
 
     Ok(())
 }
 
 #[test]
 fn linux_prereq_file() -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::cargo_bin("nansi")?;
+    let report = run_fixture(
+        "testdata/nansifile_linux_prereq.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "ls", Status::Ok);
+    assert_status(&report, "lsls", Status::Skip);
+    assert_status(&report, "l2", Status::Err);
+    assert_status(&report, "asd", Status::Err);
+    assert_status(&report, "bash", Status::Skip);
+    assert_golden(&report.stdout, "testdata/golden/nansifile_linux_prereq.txt");
+
+    Ok(())
+}
+
+#[test]
+fn linux_failure_preview_flag() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture("testdata/nansifile_linux.json", &fixture_options(&[]))?;
+
+    assert!(report
+        .stdout
+        .contains("Failures:\n  [2][l2] ls -12345\n    ls: "));
+    assert!(report.stdout.contains(
+        "\n  [3][asd] aaa\n    No such file or directory (os error 2)\nNansiFile sha256: "
+    ));
+
+    let report = run_fixture(
+        "testdata/nansifile_linux.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert!(!report.stdout.contains("Failures:"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_progress_flag() -> Result<(), Box<dyn Error>> {
+    // stdout is a pipe, so auto falls back to plain lines
+    for args in [&[][..], &["--progress", "plain"][..]] {
+        let report = run_fixture("testdata/nansifile_linux.json", &fixture_options(args))?;
+        assert!(!report.stdout.contains('\r'));
+    }
+
+    let report = run_fixture(
+        "testdata/nansifile_linux.json",
+        &fixture_options(&["--progress", "fancy"]),
+    )?;
+    assert!(report
+        .stdout
+        .contains("\r\u{1b}[2K[2/4] [2][l2] ls -12345 ...\r\u{1b}[2K"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_empty_expansion_file() -> Result<(), Box<dyn Error>> {
+    let mut options = fixture_options(&[]);
+    options
+        .env
+        .push((String::from("NANSI_TEST_EMPTY"), String::new()));
+    let report = run_fixture("testdata/nansifile_linux_empty_expansion.json", &options)?;
+
+    assert_status(&report, "guarded", Status::Err);
+    assert_status(&report, "allowed", Status::Ok);
+    assert!(normalize_output(&report.stdout).contains(
+        "[ERR] Argument 3 of item [1][guarded] expands to \"/\" because {NANSI_TEST_EMPTY} is empty\n\
+         [FAIL] [1][guarded] echo rm -rf {NANSI_TEST_EMPTY}/ (empty variable NANSI_TEST_EMPTY)\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_unexpanded_tag_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_unexpanded_tag.json");
+    cmd.env("NANSI_TEST_NESTED", "{UNDEFINED}");
+
+    let output = "\u{1b}[38;5;11m[WARN]\u{1b}[39m Argument 1 of item [1][echo] contains an unexpanded tag: {UNDEFINED}\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][echo] echo {NANSI_TEST_NESTED} ${HOME} \\{ESCAPED}\n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn linux_unexpanded_tag_strict_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--strict", "testdata/nansifile_linux_unexpanded_tag.json"]);
+    cmd.env("NANSI_TEST_NESTED", "{UNDEFINED}");
+
+    let output = "\u{1b}[38;5;9m[ERR]\u{1b}[39m Argument 1 of item [1][echo] contains an unexpanded tag: {UNDEFINED}\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [1][echo] echo {NANSI_TEST_NESTED} ${HOME} \\{ESCAPED} (missing variable UNDEFINED)\n";
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stderr(predicate::str::contains("1 item(s) failed"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_undefined_var_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_unexpanded_tag.json");
+    cmd.env_remove("NANSI_TEST_NESTED");
+
+    let output = "\u{1b}[38;5;9m[ERR]\u{1b}[39m item [1][echo]: undefined variable 'NANSI_TEST_NESTED' in arg 1\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [1][echo] echo {NANSI_TEST_NESTED} ${HOME} \\{ESCAPED} (missing variable NANSI_TEST_NESTED)\n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stderr(predicate::str::contains("panicked").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_undefined_fields_file() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args(["--color=never", "testdata/nansifile_linux_undefined_fields.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[ERR] item [1][key]: undefined variable 'NANSI_TEST_UNDEFINED_KEY' in \"{NANSI_TEST_UNDEFINED_KEY}\"\n[FAIL] [1][key] echo key (missing variable NANSI_TEST_UNDEFINED_KEY)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[ERR] item [2][env]: undefined variable 'NANSI_TEST_UNDEFINED_ENV' in \"{NANSI_TEST_UNDEFINED_ENV}\"\n[FAIL] [2][env] echo env (missing variable NANSI_TEST_UNDEFINED_ENV)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[FAIL] [3][message] echo message (missing variable NANSI_TEST_UNDEFINED_MESSAGE)\n[ERR] item [3][message]: undefined variable 'NANSI_TEST_UNDEFINED_MESSAGE' in \"Deployed to {NANSI_TEST_UNDEFINED_MESSAGE}\"\n",
+        ))
+        .stdout(predicate::str::contains("[OK] [4][after] echo after\n"))
+        .stderr(predicate::str::contains("panicked").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_silent_failure() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_silent_failure.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;9mFAIL\u{1b}[39m] [2][silent] (status output suppressed)\n",
+        ))
+        .stdout(predicate::str::contains("[1][noisy]").not());
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--quiet", "testdata/nansifile_linux_silent_failure.json"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("status output suppressed").not())
+        .stdout(predicate::str::contains(
+            "Failures:\n  [2][silent] ls -12345\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_select_only() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_select.json",
+        &fixture_options(&["--only", "build"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "fetch", Status::Skip);
+    assert_status(&report, "build", Status::Skip);
+    assert_status(&report, "test", Status::Skip);
+    assert_golden(&report.stdout, "testdata/golden/nansifile_linux_select.txt");
+
+    Ok(())
+}
+
+#[test]
+fn linux_select_only_with_deps_and_skip() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--only-with-deps",
+        "build",
+        "testdata/nansifile_linux_select.json",
+    ]);
+
+    let output = "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][fetch] echo fetch\n[\u{1b}[38;5;10mOK\u{1b}[39m] [2][build] echo build\n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [3][test] echo test\nItem [3][test] skipped: not selected.\n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--skip", "test", "testdata/nansifile_linux_select.json"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][build] echo build\n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [3][test] echo test\nItem [3][test] skipped: not selected.\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_select_only_patterns() -> Result<(), Box<dyn Error>> {
+    let inline = "[{\"label\": \"deploy-api\", \"exec\": \"echo\"}, {\"label\": \"deploy-web\", \"exec\": \"echo\"}, {\"label\": \"build\", \"exec\": \"echo\"}]";
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--only", "deploy-*", "--inline", inline]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][deploy-api] echo \n[\u{1b}[38;5;10mOK\u{1b}[39m] [2][deploy-web] echo \n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [3][build] echo \n",
+        ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--only", "deploy-", "--inline", inline]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Using NansiFile").not())
+        .stderr(predicate::str::contains(
+            "Cannot select deploy-: several labels start with it (deploy-api, deploy-web), use one of them or a glob",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_select_unknown_label() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--skip", "tset", "testdata/nansifile_linux_select.json"]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Using NansiFile").not())
+        .stderr(predicate::str::contains(
+            "Cannot select tset: no item has this label, did you mean test?",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_label_state_sticky_success() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_label_state_sticky_success.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [3][after_failure] echo \n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [5][after_success] echo \n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_label_state_last_result() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_label_state_last_result.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;3mSKIP\u{1b}[39m] [3][after_failure] echo \nPrerequisites for item [3][after_failure] are not met: setup.\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [5][after_success] echo \n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_label_state_all_must_succeed() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_label_state_all_must_succeed.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;3mSKIP\u{1b}[39m] [3][after_failure] echo \nPrerequisites for item [3][after_failure] are not met: setup.\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;3mSKIP\u{1b}[39m] [5][after_success] echo \nPrerequisites for item [5][after_success] are not met: setup.\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_prerequisite_conditions() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .arg("testdata/nansifile_linux_prerequisite_conditions.json")
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = normalize_output(&String::from_utf8(output.stdout)?);
+    assert!(stdout.contains("[SKIP] [2][deploy] echo"));
+    assert!(stdout.contains("[OK] [3][cleanup] echo"));
+    assert!(stdout.contains("[OK] [4][report_failure] echo"));
+    assert!(stdout.contains("[SKIP] [5][report_success] echo"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_strict_failed_items() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux.json");
+
+    cmd.assert().success();
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--strict", "testdata/nansifile_linux.json"]);
+
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains("[4][bash]"))
+        .stderr(predicate::str::contains("2 item(s) failed"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_strict_prereqs() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args(["--strict-prereqs", "testdata/nansifile_linux_prereq.json"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("[1][ls]").not())
+        .stderr(predicate::str::contains(
+            "Prerequisites which can never be met (--strict-prereqs):\nPrerequisite bash of item [2][lsls] refers to a later item and can never be met\n",
+        ));
+
+    nansi_cmd()?
+        .args(["--strict-prereqs", "testdata/nansifile_linux_select.json"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn linux_label_matching() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args([
+            "--color=never",
+            "testdata/nansifile_linux_label_matching.json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[OK] [2][run] echo running"));
+
+    nansi_cmd()?
+        .args(["--check", "testdata/nansifile_linux_label_matching.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[INFO] Prerequisite Install-Docker of item [2][run] matches the label install-docker only ignoring case",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_strict_skips() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--strict", "testdata/nansifile_linux_strict.json"]);
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Prerequisites for item [2][dependent] are not met: optional.",
+    ));
+
+    let mut cmd = nansi_cmd()?;
 
-    cmd.arg("testdata/nansifile_linux_prereq.json");
+    cmd.args([
+        "--strict",
+        "--strict-skips",
+        "testdata/nansifile_linux_strict.json",
+    ]);
 
-    let output = "Using NansiFile: testdata/nansifile_linux_prereq.json\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][ls] ls \n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [2][lsls] ls \nPrerequisites for item [1][lsls] are not met.\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][l2] ls -12345\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [4][asd] aaa \nNo such file or directory (os error 2)\n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [5][bash] /bin/bash -c ls -ltra | grep README\nPrerequisites for item [4][bash] are not met.\n[\u{1b}[38;5;10mOK\u{1b}[39m] [6] ls \n";
+    cmd.assert().code(1).stderr(predicate::str::contains(
+        "1 item(s) skipped for unmet prerequisites",
+    ));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn linux_status_pipe() -> Result<(), Box<dyn Error>> {
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader};
+
+    let fifo = std::env::temp_dir().join(format!("nansi_status_{}", std::process::id()));
+    assert!(Command::new("mkfifo").arg(&fifo).status()?.success());
+
+    let mut child = nansi_cmd()?
+        .arg("--status-pipe")
+        .arg(&fifo)
+        .arg("testdata/nansifile_linux_status.json")
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    let mut lines = BufReader::new(File::open(&fifo)?).lines();
+
+    let expected = [
+        "{\"event\":\"run_started\",\"file\":\"testdata/nansifile_linux_status.json\",\"items\":2}",
+        "{\"event\":\"item_started\",\"index\":1,\"label\":\"echo\",\"exec\":\"echo\",\"args\":[\"hello\"]}",
+        "{\"event\":\"item_output\",\"index\":1,\"label\":\"echo\",\"data\":\"hello\\n\"}",
+        "{\"event\":\"item_finished\",\"index\":1,\"label\":\"echo\",\"status\":\"ok\"}",
+    ];
+    for event in expected {
+        assert_eq!(lines.next().unwrap()?, event);
+    }
+
+    // the second item is still sleeping, so the events must have been flushed early
+    assert!(child.try_wait()?.is_none());
+
+    let remaining: Vec<String> = lines.collect::<Result<_, _>>()?;
+    assert_eq!(
+        remaining.last().unwrap(),
+        "{\"event\":\"run_finished\",\"ok\":2,\"err\":0,\"warn\":0,\"skip\":0,\"changed\":2}"
+    );
+    assert!(child.wait()?.success());
+
+    fs::remove_file(&fifo)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_idempotence_file() -> Result<(), Box<dyn Error>> {
+    let state_file =
+        std::env::temp_dir().join(format!("nansi_idempotence_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&state_file);
+
+    let first_run = "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][install] echo installing\n[\u{1b}[38;5;10mOK\u{1b}[39m] [2][configure] echo configuring\n";
+    let second_run = "[\u{1b}[38;5;3mSKIP\u{1b}[39m] [1][install] echo installing\nItem [1][install] is unchanged since last successful run.\n[\u{1b}[38;5;10mOK\u{1b}[39m] [2][configure] echo configuring\n1 item(s) unchanged since last successful run:\n[\"[1][install]\"]\n";
+
+    nansi_cmd()?
+        .arg("--state-file")
+        .arg(&state_file)
+        .arg("testdata/nansifile_linux_idempotence.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(first_run));
+
+    nansi_cmd()?
+        .arg("--state-file")
+        .arg(&state_file)
+        .arg("testdata/nansifile_linux_idempotence.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(second_run));
+
+    nansi_cmd()?
+        .arg("--force")
+        .arg("--state-file")
+        .arg(&state_file)
+        .arg("testdata/nansifile_linux_idempotence.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(first_run));
+
+    std::fs::remove_file(&state_file)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_index_prereq_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_index_prereq.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "ls", Status::Ok);
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_index_prereq.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_index_prereq_invalid_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_index_prereq_invalid.json");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Prerequisite #3 of item [2] refers to a later item and can never be met",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_summary_line() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args([
+            "--summary-line",
+            "--quiet",
+            "testdata/nansifile_linux_fail_fast.json",
+        ])
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_match(
+            "\nnansi: ok=0 err=2 warn=0 skip=2 duration_ms=[0-9]+ exit=1\n$",
+        )?);
+
+    nansi_cmd()?
+        .args([
+            "--summary-line",
+            "--color",
+            "never",
+            "testdata/nansifile_linux_max_failures.json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(
+            "\nnansi: ok=0 err=3 warn=0 skip=1 duration_ms=[0-9]+ exit=0\n$",
+        )?);
+
+    Ok(())
+}
+
+/// The porcelain format is a compatibility contract: the golden file may only
+/// change when fields are appended.
+#[test]
+fn linux_porcelain_golden() -> Result<(), Box<dyn Error>> {
+    let mut options = fixture_options(&["--porcelain"]);
+    options
+        .env
+        .push((String::from("CLICOLOR_FORCE"), String::from("1")));
+    let report = run_fixture("testdata/nansifile_linux_porcelain.json", &options)?;
+
+    assert_eq!(report.code, Some(0));
+    assert!(!report.stdout.contains('\u{1b}'));
+    assert!(report.stderr.contains("building\n"));
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_porcelain.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_summary_line_interrupted() -> Result<(), Box<dyn Error>> {
+    let child = Command::cargo_bin("nansi")?
+        .args([
+            "--summary-line",
+            "--progress",
+            "plain",
+            "testdata/nansifile_linux_interrupt.json",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("Item [2] skipped: the run was cancelled."));
+    assert!(predicate::str::is_match(
+        "\nnansi: ok=1 err=0 warn=0 skip=1 duration_ms=[0-9]+ exit=1\n$"
+    )?
+    .eval(&stdout));
+
+    Ok(())
+}
+
+#[test]
+fn linux_interrupt_timeout_item() -> Result<(), Box<dyn Error>> {
+    let marker = std::env::temp_dir().join(format!("nansi_marker_{}", std::process::id()));
+    let started = marker.with_extension("started");
+    let interrupted = marker.with_extension("interrupted");
+    let wait_for = |path: &std::path::Path| {
+        let start = std::time::Instant::now();
+        while !path.exists() && start.elapsed().as_secs() < 5 {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        path.exists()
+    };
+
+    // the item runs in its own process group, only `nansi` is interrupted: once
+    // cancelling the run, once terminating `nansi`
+    for summary_line in [true, false] {
+        let _ = std::fs::remove_file(&started);
+        let _ = std::fs::remove_file(&interrupted);
+        let mut cmd = Command::cargo_bin("nansi")?;
+        if summary_line {
+            cmd.arg("--summary-line");
+        }
+        let child = cmd
+            .args([
+                "--color=never",
+                "testdata/nansifile_linux_interrupt_timeout.json",
+            ])
+            .env("NANSI_MARKER", &marker)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        assert!(wait_for(&started));
+        Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()?;
+        let output = child.wait_with_output()?;
+        let stdout = String::from_utf8(output.stdout)?;
+
+        if summary_line {
+            assert!(stdout.contains("[FAIL] [1][slow] sh -c "));
+            assert!(stdout.contains("\ninterrupted\n"));
+            assert!(stdout.contains("Item [2] skipped: the run was cancelled."));
+        } else {
+            assert_eq!(output.status.code(), None);
+        }
+        assert!(wait_for(&interrupted));
+    }
+    std::fs::remove_file(&started)?;
+    std::fs::remove_file(&interrupted)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_max_failures_reached_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_max_failures.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_max_failures.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_max_failures_not_reached_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--max-failures",
+        "3",
+        "testdata/nansifile_linux_max_failures.json",
+    ]);
+
+    let output =
+        "[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3] ls -12345 (exit 2)\n[\u{1b}[38;5;10mOK\u{1b}[39m] [4] ls \n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stdout(predicate::str::contains("failure threshold").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_fail_fast_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_fail_fast.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(1));
+    assert_status(&report, "optional", Status::Err);
+    assert!(report.stderr.contains("Execution aborted: item [3] failed"));
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_fail_fast.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_redact_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--verbose", "testdata/nansifile_linux_redact.json"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("token *** ***\n"))
+        .stdout(predicate::str::contains(
+            "2 match(es) redacted from the output of item [1][secrets]",
+        ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_redact.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("token *** ***\n"))
+        .stdout(predicate::str::contains("redacted").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_vars_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_TEST_NAME", "world")
+        .env("NANSI_TEST_HOME", "/from/env")
+        .arg("testdata/nansifile_linux_vars.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("hello world from /from/vars\n"))
+        .stdout(predicate::str::contains("/from/env").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_vars_cycle_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_vars_cycle.json");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Variable A refers to itself: A -> B -> A",
+    ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--check", "testdata/nansifile_linux_vars.json"]);
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Variable GREETING uses {NANSI_TEST_NAME} which is not set",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_include_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/include/nansifile_linux_include.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][packages] echo packages\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [4][deploy] echo deploy\n",
+        ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/include/nansifile_linux_include_cycle.json");
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Include cycle: testdata/include/nansifile_linux_include_cycle.json -> testdata/include/common/cycle.json -> ",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_any_of_prerequisites() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_any_of.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [6][download] echo download\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Prerequisites for item [7][serve] are not met: 1 of required 2 satisfied: [health_a ok, health_b failed, health_c skipped].\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_inline() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--inline",
+        "[{\"exec\": \"echo\", \"args\": [\"-n\"]}, {\"label\": \"greet\", \"exec\": \"echo\", \"args\": [\"hi\"], \"prerequisites\": [\"#1\"]}]",
+    ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Using NansiFile: <inline>\n"))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][greet] echo hi\n",
+        ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--inline", "[{\"exec\": \"echo\",}]"]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid NansiFile given with --inline: trailing comma at line 1 column 18",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_stream_output_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_stream.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[1][stream] one\n[1][stream] two\n[1][stream] token ***\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][stream] sh -c",
+        ))
+        .stdout(predicate::str::contains("[1][stream] token ghp").not())
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][buffered] echo three\nthree\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_stream_output_quiet() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--quiet",
+        "--color=never",
+        "testdata/nansifile_linux_stream_quiet.json",
+    ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("[1][passing]").not())
+        .stdout(predicate::str::contains("one").not())
+        .stdout(predicate::str::contains(
+            "[FAIL] [2][failing] sh -c echo three >&2; exit 1 (exit 1)\nthree\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_elevate_refused() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_elevate.json");
+
+    let output = "\u{1b}[38;5;9m[ERR]\u{1b}[39m passwordless sudo required or run with --interactive-elevation\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [1][root] echo elevated (elevation failed)\n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][user] echo unprivileged\nunprivileged\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_elevate_verbose() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--verbose",
+        "--elevation-command",
+        "env",
+        "testdata/nansifile_linux_elevate.json",
+    ]);
+
+    let output = "[INFO] Elevated command of item [1][root]: env echo elevated\n[\u{1b}[38;5;10mOK\u{1b}[39m] [1][root] echo elevated\nelevated\n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stdout(predicate::str::contains("Elevated command of item [2]").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_elevate_interactive_refresh() -> Result<(), Box<dyn Error>> {
+    let sudo_log = std::env::temp_dir().join(format!("nansi_sudo_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&sudo_log);
+
+    nansi_cmd()?
+        .env("NANSI_SUDO_LOG", &sudo_log)
+        .args([
+            "--interactive-elevation",
+            "testdata/nansifile_linux_elevate_refresh.json",
+        ])
+        .assert()
+        .success();
+
+    let log = std::fs::read_to_string(&sudo_log)?;
+    std::fs::remove_file(&sudo_log)?;
+
+    // the cached credentials are extended before each elevated item
+    assert_eq!(log, "-v\n-n echo one\n-v\n-n echo two\n");
+
+    Ok(())
+}
+
+#[test]
+fn linux_fail_fast_flag() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--fail-fast", "testdata/nansifile_linux_max_failures.json"]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "Item [2] skipped: execution aborted after item [1] failed.",
+        ))
+        .stdout(predicate::str::contains("failure threshold").not())
+        .stderr(predicate::str::contains(
+            "Execution aborted: item [1] failed",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_empty_output_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_empty_output.json",
+        &fixture_options(&[]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "stderr", Status::Ok);
+    assert_status(&report, "silent", Status::Ok);
+    assert_status(&report, "print_stderr", Status::Ok);
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_empty_output.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_quiet_verbose_file() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args(["--quiet", "testdata/nansifile_linux_duplicate_labels.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Using NansiFile").not())
+        .stdout(predicate::str::contains("aliases are duplicated").not())
+        .stdout(predicate::str::contains("[1][ls]").not())
+        .stdout(predicate::str::contains("NansiFile sha256").not())
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;9mFAIL\u{1b}[39m] [2] ls -12345 (exit 2)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Failures:\n  [2] ls -12345\n    ls: invalid option -- '2'\n",
+        ));
+
+    nansi_cmd()?
+        .args(["--verbose", "testdata/nansifile_linux_prereq.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[INFO] Running item [3][l2]: [\"ls\", \"-12345\"]\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][l2] ls -12345 (exit 2)\n[INFO] Item [3][l2] exited with code 2\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[INFO] Item [4][asd] exited without an exit code\n",
+        ))
+        .stdout(predicate::str::contains("Running item [2][lsls]").not());
+
+    nansi_cmd()?
+        .args([
+            "--quiet",
+            "--verbose",
+            "testdata/nansifile_linux_prereq.json",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_empty_output_quiet_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["--quiet", "testdata/nansifile_linux_empty_output.json"]);
+
+    // nothing but failures is printed
+    cmd.assert().success().stdout(predicate::str::diff(""));
+
+    Ok(())
+}
+
+#[test]
+fn trace_show_previous_version() -> Result<(), Box<dyn Error>> {
+    // written before hostname, os, started_at and the newer options were recorded
+    nansi_cmd()?
+        .args(["trace", "show", "testdata/trace_v1.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "NansiFile: testdata/nansifile_linux_prereq.json\n",
+        ))
+        .stdout(predicate::str::contains("Started ").not())
+        .stdout(predicate::str::contains("[3][l2] err exit=2 "));
+
+    Ok(())
+}
+
+#[test]
+fn linux_log_file() -> Result<(), Box<dyn Error>> {
+    let log_file = std::env::temp_dir().join(format!("nansi_log_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log_file);
+
+    for _ in 0..2 {
+        nansi_cmd()?
+            .arg("--log-file")
+            .arg(&log_file)
+            .arg("testdata/nansifile_linux_prereq.json")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][ls] ls \n",
+            ));
+    }
+
+    let log = std::fs::read_to_string(&log_file)?;
+    std::fs::remove_file(&log_file)?;
+
+    assert!(!log.contains('\u{1b}'));
+    assert_eq!(
+        log.matches(" using NansiFile: testdata/nansifile_linux_prereq.json\n")
+            .count(),
+        2
+    );
+    assert!(log.contains("] [3][l2] err exit=2 "));
+    assert!(log.contains("  command: ls -12345\n  stderr:\n    ls: invalid option -- '2'\n"));
+    assert!(log.contains("  message: Prerequisites for item [2][lsls] are not met: bash.\n"));
+    assert!(log.contains("] Finished: ok=2 err=2 warn=0 skip=2 in "));
+
+    nansi_cmd()?
+        .args([
+            "--log-file",
+            "/nansi/surely/missing/run.log",
+            "testdata/nansifile_linux_prereq.json",
+        ])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("[1][ls]").not())
+        .stderr(predicate::str::contains(
+            "/nansi/surely/missing/run.log: No such file or directory",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_capture_file() -> Result<(), Box<dyn Error>> {
+    let report_file =
+        std::env::temp_dir().join(format!("nansi_capture_{}.json", std::process::id()));
+
+    nansi_cmd()?
+        .arg("--color=never")
+        .arg("--report")
+        .arg(&report_file)
+        .arg("testdata/nansifile_linux_capture.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[OK] [1][flood]"));
+
+    let report: RunReport = serde_json::from_str(&std::fs::read_to_string(&report_file)?)?;
+    std::fs::remove_file(&report_file)?;
+
+    assert_eq!(report.items[0].stdout, "<not captured>");
+    assert_eq!(report.items[0].stderr, "<not captured>");
+    assert!(report.items[1].stdout.len() <= 64 * 1024);
+    assert!(report.items[1].stdout.ends_with("99999\n100000\n"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_report_file() -> Result<(), Box<dyn Error>> {
+    let report_file =
+        std::env::temp_dir().join(format!("nansi_report_{}.json", std::process::id()));
+
+    nansi_cmd()?
+        .arg("--report")
+        .arg(&report_file)
+        .arg("testdata/nansifile_linux_fail_fast.json")
+        .assert()
+        .failure();
+
+    let report: RunReport = serde_json::from_str(&std::fs::read_to_string(&report_file)?)?;
+    std::fs::remove_file(&report_file)?;
+
+    assert_eq!(report.nansi_file, "testdata/nansifile_linux_fail_fast.json");
+    let items: Vec<(usize, &str, &str, Option<i32>)> = report
+        .items
+        .iter()
+        .map(|item| {
+            (
+                item.index,
+                item.exec.as_str(),
+                item.status.as_str(),
+                item.exit_code,
+            )
+        })
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            (1, "ls", "err", Some(2)),
+            (2, "ls", "skip", None),
+            (3, "ls", "err", Some(2)),
+            (4, "ls", "not_run", None),
+        ]
+    );
+    assert_eq!(report.items[2].args, vec!["-12345"]);
+    assert!(report.items[2]
+        .stderr
+        .starts_with("ls: invalid option -- '2'\n"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_inject_failure() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args([
+            "--inject-failure",
+            "ls",
+            "testdata/nansifile_linux_prereq.json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Failure drill: items labeled ls fail without being run (--inject-failure)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Failure of item [1][ls] is injected, its command was not run\n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [1][ls] ls  (failure injected)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Prerequisites for item [4][asd] are not met: ls.\n",
+        ))
+        .stdout(predicate::str::contains("  [1][ls] ls\n    failure injected\n"));
+
+    nansi_cmd()?
+        .args([
+            "--dry-run",
+            "--inject-failure",
+            "ls,bash",
+            "testdata/nansifile_linux_prereq.json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;9mFAIL\u{1b}[39m] [5][bash] /bin/bash -c ls -ltra | grep README (failure injected)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Prerequisites for item [2][lsls] would not be met: bash.\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Dry run: 2 item(s) would run, 2 would be skipped, 2 would fail (injected).\n",
+        ));
+
+    nansi_cmd()?
+        .args([
+            "--inject-failure",
+            "lss",
+            "testdata/nansifile_linux_prereq.json",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Cannot inject a failure into lss: no item has this label, did you mean ls?",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_trace_file() -> Result<(), Box<dyn Error>> {
+    let trace_file = std::env::temp_dir().join(format!("nansi_trace_{}.json", std::process::id()));
+
+    nansi_cmd()?
+        .arg("--trace")
+        .arg(&trace_file)
+        .arg("testdata/nansifile_linux_prereq.json")
+        .assert()
+        .success();
+
+    nansi_cmd()?
+        .args(["trace", "show"])
+        .arg(&trace_file)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "NansiFile: testdata/nansifile_linux_prereq.json\n",
+        ))
+        .stdout(predicate::str::contains("[2][lsls] skip exit=- 0 ms\n"))
+        .stdout(predicate::str::contains("Started "))
+        .stdout(predicate::str::contains("[3][l2] err exit=2 "))
+        .stdout(predicate::str::contains(
+            "  command: ls -12345\n  stderr:\n",
+        ));
+
+    std::fs::remove_file(&trace_file)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_check_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_prereq.json",
+        &fixture_options(&["--check"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_prereq_check.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_check_invalid_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--check",
+        "testdata/nansifile_linux_index_prereq_invalid.json",
+    ]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "Prerequisite #3 of item [2] refers to a later item and can never be met",
+        ))
+        .stderr(predicate::str::contains("1 error(s) found"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_validate_subcommand() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args(["validate", "testdata/nansifile_linux_validate.json"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Unknown field \"arg\" of item [1][build] is ignored",
+        ))
+        .stdout(predicate::str::contains(
+            "Prerequisite fetch of item [1][build] refers to a later item and can never be met",
+        ))
+        .stdout(predicate::str::contains(
+            "Item [2][fetch] uses {NANSI_TEST_SURELY_NOT_SET} which is not set",
+        ))
+        .stdout(predicate::str::contains("3 warning(s) found"));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "validate",
+        "testdata/nansifile_linux_index_prereq_invalid.json",
+    ]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("1 error(s) found"));
+
+    Ok(())
+}
+
+#[test]
+fn help_format_subcommand() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("help-format");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "NansiFile fields:\n  exec_list: array of items (default: required)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "\n  timeout_secs: integer or duration (default: none)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "\n  label_state: string (default: \"sticky_success\")\n",
+        ))
+        .stdout(predicate::str::contains(
+            "      Consecutive parallel items form a batch whose commands run at the same time, up to `--jobs` of them",
+        ))
+        .stdout(predicate::str::contains("::").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_chunk_args_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_chunk_args.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "chunked", Status::Ok);
+    assert_status(&report, "chunked_fail", Status::Err);
+    assert_golden(
+        &report.stdout,
+        "testdata/golden/nansifile_linux_chunk_args.txt",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_assert_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_assert.json",
+        &fixture_options(&["--failure-preview-lines", "0"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "env", Status::Ok);
+    assert_status(&report, "missing", Status::Err);
+    assert_status(&report, "after_env", Status::Ok);
+    assert_golden(&report.stdout, "testdata/golden/nansifile_linux_assert.txt");
+
+    Ok(())
+}
+
+#[test]
+fn linux_timers_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_timers.json");
+
+    let output = "[\u{1b}[38;5;10mOK\u{1b}[39m] [2][sleep] sleep 0.2\n[\u{1b}[38;5;10mOK\u{1b}[39m] [3][ls] ls \n";
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(output.to_string()))
+        .stdout(predicate::str::contains("Timers:\n  section: "));
+
+    Ok(())
+}
+
+#[test]
+fn linux_echo_item_on_failure_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_TEST_USER", "andy")
+        .env("NANSI_TEST_TOKEN", "s3cr3t")
+        .args([
+            "--echo-item-on-failure",
+            "testdata/nansifile_linux_echo_item.json",
+        ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "{\"index\":1,\"label\":\"login\",\"exec\":\"ls\",\"args\":[\"--user\",\"andy\",\"--password\",\"***\",\"--token=***\"],\"cwd\":",
+        ))
+        .stdout(predicate::str::contains(
+            "\"vars\":[\"NANSI_TEST_USER\",\"NANSI_TEST_TOKEN\"],\"var_precedence\":[\"cli\",\"registers\",\"vars\",\"env\"],\"ignore_errors\":false}\n",
+        ))
+        .stdout(predicate::str::contains("\"env\":[\"GITHUB_TOKEN\",\"LANG\"],\"vars\":"))
+        .stdout(predicate::str::contains("s3cr3t").not())
+        .stdout(predicate::str::contains("ghp_").not())
+        .stdout(predicate::str::contains("\"index\":2").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_list_unused_labels_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "list",
+        "--unused-labels",
+        "testdata/nansifile_linux_prereq.json",
+    ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("[2][lsls] ls \n[4][asd] aaa \n"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_flaky() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "flaky",
+        "testdata/nansifile_linux_prereq.json",
+        "testdata/flaky/report_1.json",
+        "testdata/flaky/report_2.json",
+        "testdata/flaky/report_3.json",
+    ]);
+
+    cmd.assert().success().stdout(predicate::str::diff(
+        "ITEM  RUNS  FAILURES  RETRIED  FLAKE RATE  MEDIAN ATTEMPTS\n\
+         ls    3     0         1        0%          1\n\
+         l2    3     2         0        100%        1\n",
+    ));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "flaky",
+        "--output",
+        "json",
+        "testdata/nansifile_linux_prereq.json",
+        "testdata/flaky/report_1.json",
+    ]);
+
+    cmd.assert().success().stdout(predicate::str::diff("[]\n"));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "flaky",
+        "testdata/nansifile_linux_prereq.json",
+        "testdata/flaky/report_1.json",
+        "testdata/flaky/missing.json",
+    ]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("testdata/flaky/missing.json"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_env_checks() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .env("NANSI_TEST_PROFILE", "prod")
+        .env_remove("NANSI_TEST_CI")
+        .arg("testdata/nansifile_linux_env_checks.json")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("[deploy]").not())
+        .stderr(predicate::str::contains(
+            "Environment checks failed (use --force-env-checks to run anyway):\nNANSI_TEST_CI is required but not set\nNANSI_TEST_PROFILE must not be prod\n",
+        ));
+
+    nansi_cmd()?
+        .env("NANSI_TEST_PROFILE", "dev")
+        .env("NANSI_TEST_CI", "1")
+        .args(["--color=never", "testdata/nansifile_linux_env_checks.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[OK] [1][deploy]"));
+
+    nansi_cmd()?
+        .env("NANSI_TEST_PROFILE", "prod")
+        .env("NANSI_TEST_CI", "1")
+        .args([
+            "--color=never",
+            "--force-env-checks",
+            "testdata/nansifile_linux_env_checks.json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Environment check failed: NANSI_TEST_PROFILE must not be prod",
+        ))
+        .stdout(predicate::str::contains("[OK] [1][deploy]"));
+
+    nansi_cmd()?
+        .env("NANSI_TEST_PROFILE", "prod")
+        .env("NANSI_TEST_CI", "1")
+        .args(["--check", "testdata/nansifile_linux_env_checks.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[INFO] NANSI_TEST_PROFILE must not be prod",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_requires_env_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_TEST_PROFILE", "dev")
+        .env_remove("NANSI_TEST_REGION")
+        .arg("testdata/nansifile_linux_requires_env.json");
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("[deploy]").not())
+        .stderr(predicate::str::contains(
+            "Required environment variables are missing:\nItem [1][deploy] requires NANSI_TEST_REGION which is not set\nItem [2][cleanup] requires NANSI_TEST_REGION which is not set\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_notify_desktop_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    // Without a notification daemon the run only warns, the exit code is unaffected.
+    cmd.env("DBUS_SESSION_BUS_ADDRESS", "unix:path=/nonexistent/bus")
+        .args([
+            "--notify-desktop",
+            "testdata/nansifile_linux_duplicate_labels.json",
+        ]);
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Desktop notification could not be sent: ",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_nansi_opts_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_OPTS", "--quiet")
+        .arg("testdata/nansifile_linux_empty_output.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("(no output)").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_nansi_opts_precedence_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_OPTS", "--max-failures 1").args([
+        "--max-failures",
+        "3",
+        "testdata/nansifile_linux_max_failures.json",
+    ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("failure threshold reached").not());
+
+    // --verbose on the command line drops the conflicting --quiet of the
+    // variable instead of failing
+    nansi_cmd()?
+        .env("NANSI_OPTS", "--quiet --jobs 2")
+        .args(["--verbose", "testdata/nansifile_linux_empty_output.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[INFO] Running item [1]"))
+        .stdout(predicate::str::contains("(no output)"));
+
+    Ok(())
+}
+
+#[test]
+fn nansi_opts_invalid() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_OPTS", "--quiet --bogus")
+        .arg("testdata/nansifile_linux.json");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "NANSI_OPTS: error: Found argument '--bogus'",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_messages_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.env("NANSI_TEST_DIR", "testdata")
+        .arg("testdata/nansifile_linux_messages.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(" Listed the files of testdata\n"))
+        .stdout(predicate::str::contains(
+            " Is the VPN connected? Run 'vpnctl up' and retry\n",
+        ))
+        .stdout(predicate::str::contains("Assets fetched").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_confirm() -> Result<(), Box<dyn Error>> {
+    let run = |args: &[&str]| -> Result<String, Box<dyn Error>> {
+        let output = nansi_cmd()?
+            .args(args)
+            .arg("testdata/nansifile_linux_confirm.json")
+            .stdin(Stdio::null())
+            .output()?;
+        assert_eq!(output.status.code(), Some(0));
+
+        Ok(normalize_output(&String::from_utf8(output.stdout)?))
+    };
+
+    let stdout = run(&[])?;
+    assert!(stdout.contains(
+        "[WARN] Item [1][wipe] needs a confirmation but stdin is not a terminal, use --yes to run it\n"
+    ));
+    assert!(stdout.contains("[SKIP] [1][wipe] echo wiping {NANSI_TEST_DEVICE:/dev/null}\nItem [1][wipe] skipped: not confirmed.\n"));
+    assert!(stdout.contains("[SKIP] [2][after] echo"));
+
+    let stdout = run(&["--yes"])?;
+    assert!(stdout.contains("[OK] [1][wipe] echo wiping {NANSI_TEST_DEVICE:/dev/null}\n"));
+    assert!(stdout.contains("[OK] [2][after] echo"));
+
+    let stdout = run(&["--answers", "testdata/answers_confirm_declined.json"])?;
+    assert!(stdout.contains("Item [1][wipe] skipped: declined by user.\n"));
+    assert!(!stdout.contains("Run? [y/N]"));
+
+    let stdout = run(&["--answers", "testdata/answers_confirm_accepted.json"])?;
+    assert!(stdout.contains("[OK] [1][wipe] echo wiping {NANSI_TEST_DEVICE:/dev/null}\n"));
+
+    Ok(())
+}
+
+#[test]
+fn answers_file_missing() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--answers",
+        "testdata/surely_missing_answers.json",
+        "testdata/nansifile_linux.json",
+    ]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "testdata/surely_missing_answers.json: ",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_flags_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_flags.json");
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "[1][list] ls testdata -d\ntestdata\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_self_modifying_file() -> Result<(), Box<dyn Error>> {
+    let nansi_file =
+        std::env::temp_dir().join(format!("nansi_self_modifying_{}.json", std::process::id()));
+    let nansi_file_str = nansi_file.to_str().unwrap();
+    std::fs::write(
+        &nansi_file,
+        format!(
+            "{{\"exec_list\": [{{\"exec\": \"touch\", \"args\": [\"{0}\"]}}, {{\"exec\": \"sh\", \"args\": [\"-c\", \"echo >> {0}\"]}}]}}",
+            nansi_file_str
+        ),
+    )?;
+
+    let assert = nansi_cmd()?.arg(&nansi_file).assert();
+    std::fs::remove_file(&nansi_file)?;
+
+    assert
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "[INFO] Item [1] references the NansiFile itself: {}\n",
+            nansi_file_str
+        )))
+        .stdout(predicate::str::contains(format!(
+            "NansiFile {} was modified during the run",
+            nansi_file_str
+        )));
+
+    Ok(())
+}
+
+#[test]
+fn linux_null_stdin_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = assert_cmd::Command::from_std(nansi_cmd()?);
+
+    cmd.arg("testdata/nansifile_linux_stdin.json")
+        .write_stdin("from stdin\n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("from stdin").not());
+
+    let mut cmd = assert_cmd::Command::from_std(nansi_cmd()?);
+
+    cmd.arg("testdata/nansifile_linux_inherit_stdin.json")
+        .write_stdin("from stdin\n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Unknown feature \"colour\" is ignored",
+        ))
+        .stdout(predicate::str::contains("[1][read] cat \nfrom stdin\n"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_stdin_each() -> Result<(), Box<dyn Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("nansi")?;
+
+    cmd.args([
+        "--color=never",
+        "--stdin-each",
+        "deploy",
+        "testdata/nansifile_linux_stdin_each.json",
+    ])
+    .write_stdin("host1\n\n  host2  \n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[OK] [1][deploy] echo deploying {item}\ndeploying host1\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[OK] [2][deploy] echo deploying {item}\ndeploying host2\n",
+        ))
+        .stdout(predicate::str::contains("[OK] [3][done] echo done\n"))
+        .stdout(predicate::str::contains("duplicated").not());
+
+    let mut cmd = assert_cmd::Command::cargo_bin("nansi")?;
+
+    cmd.args([
+        "--stdin-each",
+        "deplyo",
+        "testdata/nansifile_linux_stdin_each.json",
+    ])
+    .write_stdin("host1\n");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Cannot use --stdin-each with deplyo: no item has this label, did you mean deploy?",
+    ));
+
+    // with dedupe_identical, lines used only in env are not duplicates
+    let mut cmd = assert_cmd::Command::cargo_bin("nansi")?;
+
+    cmd.args([
+        "--color=never",
+        "--stdin-each",
+        "deploy",
+        "testdata/nansifile_linux_stdin_each_dedupe.json",
+    ])
+    .write_stdin("host1\nhost2\n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("deploying host1\n"))
+        .stdout(predicate::str::contains("deploying host2\n"))
+        .stdout(predicate::str::contains("is a duplicate").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_timings_flag() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_flags.json",
+        &fixture_options(&["--timings"]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "list", Status::Ok);
+    assert!(
+        normalize_output(&report.stdout).contains("[1][list] ls testdata -d (<duration>, peak ")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn linux_output_stream_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_output_stream.json");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[1][to_default] echo default output\ndefault output\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[2][to_stderr] echo stderr output\n",
+        ))
+        .stdout(predicate::str::contains("stderr output\n\n").not())
+        .stderr(predicate::str::diff("stderr output\n\n"));
+
+    let mut cmd = nansi_cmd()?;
+
+    cmd.args([
+        "--status-stream",
+        "stderr",
+        "testdata/nansifile_linux_output_stream.json",
+    ]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("default output\n\n"))
+        .stderr(predicate::str::contains("Using NansiFile: "))
+        .stderr(predicate::str::contains(
+            "[2][to_stderr] echo stderr output\nstderr output\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_duration_file() -> Result<(), Box<dyn Error>> {
+    let report = run_fixture(
+        "testdata/nansifile_linux_duration.json",
+        &fixture_options(&[]),
+    )?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "fast", Status::Ok);
+    assert_status(&report, "slow", Status::Err);
+    assert!(normalize_output(&report.stdout).contains(
+        "[FAIL] [2][slow] sleep 1.2 (over <duration>)\nCompleted in <duration>, limit <duration>\n"
+    ));
+
+    let slow = &report.items("slow")[0];
+    assert_eq!(slow.exit_code, Some(0));
+    assert!(slow.duration_ms >= 1000);
+
+    Ok(())
+}
+
+#[test]
+fn linux_file_url() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg(format!(
+        "file://{}/testdata/nansifile_linux_flags.json",
+        env!("CARGO_MANIFEST_DIR")
+    ));
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("[1][list] ls testdata -d\n"));
+
+    Ok(())
+}
+
+#[test]
+fn http_url_refused() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("http://localhost/setup.json");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "http://localhost/setup.json: plain http is refused, use https or pass --insecure-http",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_expect_sha256_file() -> Result<(), Box<dyn Error>> {
+    let digest = "8a1f5185b08b104de02273745146fcb5af7febff3863bbdb512d3e44861b2e68";
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_signed.json")
+        .args(["--expect-sha256", digest])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signed\n"))
+        .stdout(predicate::str::contains(format!(
+            "NansiFile sha256: {}\n",
+            digest
+        )));
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_signed.json")
+        .args(["--expect-sha256", &digest.replace('8', "9")])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("signed").not())
+        .stderr(predicate::str::contains(format!(
+            "testdata/nansifile_linux_signed.json: sha256 mismatch, expected {}, got {}",
+            digest.replace('8', "9"),
+            digest
+        )));
+
+    Ok(())
+}
+
+#[test]
+fn linux_verify_key_file() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_signed.json")
+        .args(["--verify-key", "testdata/nansifile_signing_key.pub"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signed\n"));
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_signed.json")
+        .args(["--verify-key", "testdata/nansifile_other_key.pub"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("signed").not())
+        .stderr(predicate::str::contains(
+            "testdata/nansifile_linux_signed.json: signature verification with testdata/nansifile_other_key.pub failed",
+        ));
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_stdin.json")
+        .args(["--verify-key", "testdata/nansifile_signing_key.pub"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "testdata/nansifile_linux_stdin.json.sig: No such file or directory",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_stdin_field_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = assert_cmd::Command::from_std(nansi_cmd()?);
+
+    cmd.args(["--color=never", "testdata/nansifile_linux_stdin_field.json"])
+        .write_stdin("from stdin\n");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[OK] [1][query] cat \nselect * from users;\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[OK] [2][payload] cat \nline 1\nline 2\n",
+        ))
+        .stdout(predicate::str::contains(
+            "stdin testdata/stdin/missing.txt: No such file or directory",
+        ))
+        .stdout(predicate::str::contains("from stdin").not());
+
+    Ok(())
+}
+
+#[test]
+fn linux_dedupe_identical_file() -> Result<(), Box<dyn Error>> {
+    let mut options = fixture_options(&[]);
+    options
+        .env
+        .push((String::from("HOME"), String::from("/home/nansi")));
+    let report = run_fixture("testdata/nansifile_linux_dedupe.json", &options)?;
+
+    assert_eq!(report.code, Some(0));
+    assert_status(&report, "first", Status::Ok);
+    assert_status(&report, "again", Status::Skip);
+    assert_status(&report, "after", Status::Ok);
+    assert_golden(&report.stdout, "testdata/golden/nansifile_linux_dedupe.txt");
+
+    Ok(())
+}
+
+#[test]
+fn list_labels_flag() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args(["testdata/nansifile_linux_prereq.json", "--list-labels"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[1][ls] ls \n[2][lsls] ls \n"))
+        .stdout(predicate::str::contains("Using NansiFile").not());
+
+    nansi_cmd()?
+        .args(["testdata/nansifile_linux_prereq.json", "--list-labels"])
+        .args(["--output", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "  {\n    \"command\": \"ls -12345\",\n    \"index\": 3,\n    \"label\": \"l2\"\n  },\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_env_passthrough_file() -> Result<(), Box<dyn Error>> {
+    let mut cmd = nansi_cmd()?;
+
+    cmd.arg("testdata/nansifile_linux_env_passthrough.json")
+        .env("NANSI_CANARY", "canary")
+        .env("NANSI_KEPT", "kept");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[1][file] env \nNANSI_KEPT=kept\nPATH=",
+        ))
+        .stdout(predicate::str::contains(
+            "[2][item] env \nNANSI_CANARY=canary\n\n",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn linux_tmp_dir_file() -> Result<(), Box<dyn Error>> {
+    let output = nansi_cmd()?
+        .arg("testdata/nansifile_linux_tmp_dir.json")
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let tmp_dirs: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.contains("nansi-"))
+        .collect();
+
+    assert_eq!(tmp_dirs.len(), 2, "{}", stdout);
+    assert_eq!(tmp_dirs[0], tmp_dirs[1]);
+    assert!(stdout.contains("scratch\n"));
+    assert!(!std::path::Path::new(tmp_dirs[0]).exists());
+
+    Ok(())
+}
+
+#[test]
+fn linux_ci_folding_flag() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args([
+            "testdata/nansifile_linux_signed.json",
+            "--ci-folding",
+            "github",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[1][hello] echo signed\n::group::[1][hello]\nsigned\n\n::endgroup::\n",
+        ));
+
+    nansi_cmd()?
+        .args(["testdata/nansifile_linux_signed.json", "--ci-folding", "gitlab"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(
+            "\\[1\\]\\[hello\\] echo signed\n\x1b\\[0Ksection_start:[0-9]+:nansi_1_hello\\[collapsed=true\\]\r\x1b\\[0K\\[1\\]\\[hello\\]\nsigned\n\n\x1b\\[0Ksection_end:[0-9]+:nansi_1_hello\r\x1b\\[0K\n",
+        )?);
+
+    nansi_cmd()?
+        .arg("testdata/nansifile_linux_signed.json")
+        .env("GITHUB_ACTIONS", "true")
+        .env_remove("NANSI_OPTS")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("::group::[1][hello]\n"));
+
+    Ok(())
+}
+
+#[test]
+fn linux_import_state_file() -> Result<(), Box<dyn Error>> {
+    let trace_file =
+        std::env::temp_dir().join(format!("nansi_import_state_{}.json", std::process::id()));
+
+    nansi_cmd()?
+        .arg("--trace")
+        .arg(&trace_file)
+        .arg("testdata/nansifile_linux_prereq.json")
+        .assert()
+        .success();
+
+    nansi_cmd()?
+        .arg("--import-state")
+        .arg(&trace_file)
+        .arg("testdata/nansifile_linux_prereq.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;3mSKIP\u{1b}[39m] [1][ls] ls \nItem [1][ls] is satisfied by imported state.\n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][l2] ls -12345 (exit 2)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "Item [6] is satisfied by imported state.\n",
+        ));
+
+    nansi_cmd()?
+        .arg("--import-state")
+        .arg(&trace_file)
+        .arg("testdata/nansifile_linux.json")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "imported state comes from a different NansiFile (sha256 ",
+        ));
+
+    std::fs::remove_file(&trace_file)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_shuffle_flag() -> Result<(), Box<dyn Error>> {
+    nansi_cmd()?
+        .args(["testdata/nansifile_linux_prereq.json", "--shuffle=3"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Items shuffled with seed 3 (reproduce with --shuffle=3)\n[\u{1b}[38;5;3mSKIP\u{1b}[39m] [2][lsls] ls \n",
+        ))
+        .stdout(predicate::str::contains(
+            "[\u{1b}[38;5;10mOK\u{1b}[39m] [1][ls] ls \n[\u{1b}[38;5;9mFAIL\u{1b}[39m] [3][l2] ls -12345 (exit 2)\n[\u{1b}[38;5;10mOK\u{1b}[39m] [6] ls \n",
+        ));
+
+    nansi_cmd()?
+        .args(["testdata/nansifile_linux_prereq.json", "--check"])
+        .args(["--shuffle-validate", "100", "--shuffle=10"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "100 permutation(s) starting at seed 10 respect the declared order.\n",
+        ));
+
+    // without a seed, the flag takes no value and the file may follow it
+    nansi_cmd()?
+        .args(["--shuffle", "testdata/nansifile_linux_prereq.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Items shuffled with seed "));
+
+    nansi_cmd()?
+        .env("NANSI_OPTS", "--shuffle")
+        .arg("testdata/nansifile_linux_prereq.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Items shuffled with seed "));
+
+    Ok(())
+}
+
+#[test]
+fn labels_rename() -> Result<(), Box<dyn Error>> {
+    let nansi_file =
+        std::env::temp_dir().join(format!("nansi_labels_rename_{}.json", std::process::id()));
+    std::fs::copy("testdata/nansifile_linux_prereq.json", &nansi_file)?;
+
+    nansi_cmd()?
+        .args(["labels", "rename"])
+        .arg(&nansi_file)
+        .args(["ls", "bash"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("the label bash already exists"));
+
+    nansi_cmd()?
+        .args(["labels", "rename"])
+        .arg(&nansi_file)
+        .args(["ls", "listing", "--write"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Renamed 1 label(s) and 1 reference(s) from ls to listing.\n",
+        ));
+
+    let expected = std::fs::read_to_string("testdata/nansifile_linux_prereq.json")?
+        .replace("\"label\": \"ls\"", "\"label\": \"listing\"")
+        .replace("                \"ls\"\n", "                \"listing\"\n");
+    assert_eq!(std::fs::read_to_string(&nansi_file)?, expected);
+
+    std::fs::remove_file(&nansi_file)?;
+
+    Ok(())
+}
+
+#[test]
+fn linux_changed_when_file() -> Result<(), Box<dyn Error>> {
+    for (args, code) in [(&[][..], 0), (&["--changed-exit-code", "3"][..], 3)] {
+        let report = run_fixture(
+            "testdata/nansifile_linux_changed.json",
+            &fixture_options(args),
+        )?;
 
-    cmd.assert().success().stdout(predicate::str::contains(output.to_string()));
+        assert_eq!(report.code, Some(code));
+        assert_status(&report, "install", Status::Ok);
+        assert_status(&report, "upgrade", Status::Ok);
+        assert_eq!(report.items("install")[0].changed, Some(false));
+        assert_eq!(report.items("upgrade")[0].changed, Some(true));
+        assert_golden(
+            &report.stdout,
+            "testdata/golden/nansifile_linux_changed.txt",
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nansi::serve::{ServeOptions, Server};
+
+fn start_server(max_runs: usize) -> Result<SocketAddr, Box<dyn Error>> {
+    let options = ServeOptions {
+        allow_exec: vec![String::from("echo"), String::from("sleep")],
+        max_runs,
+        runs_dir: Some(std::env::temp_dir().join(format!(
+            "nansi-serve-test-{}-{}",
+            std::process::id(),
+            max_runs
+        ))),
+    };
+    let server = Server::bind("127.0.0.1:0", options)?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || {
+        let _ = server.run();
+    });
+
+    Ok(addr)
+}
+
+/// Sends a request and returns the status code and body of the response.
+fn request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Result<(u16, String), Box<dyn Error>> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let code = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or_default();
+
+    Ok((code, body.to_string()))
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+/// Polls the report of the run until it is no longer running.
+fn wait_for_report(addr: SocketAddr, id: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    let start = Instant::now();
+    loop {
+        let (code, body) = request(addr, "GET", &format!("/runs/{}/report", id), "")?;
+        assert_eq!(code, 200);
+        let report = json(&body);
+        if report["state"] != "running" {
+            return Ok(report);
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "run {} hangs",
+            id
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn serve_run() -> Result<(), Box<dyn Error>> {
+    let addr = start_server(2)?;
+
+    let (code, body) = request(
+        addr,
+        "POST",
+        "/runs",
+        "{\"exec_list\": [{\"exec\": \"echo\"}, {\"exec\": \"rm\", \"args\": [\"-rf\", \"/tmp/x\"]}]}",
+    )?;
+    assert_eq!(code, 403);
+    assert_eq!(json(&body)["error"], "exec not allowed: rm");
+
+    let (code, body) = request(
+        addr,
+        "POST",
+        "/runs",
+        "{\"exec_list\": [{\"exec\": \"echo\", \"env\": {\"PATH\": \"/tmp/evil\"}}, {\"exec\": \"echo\", \"env\": {\"LD_PRELOAD\": \"/tmp/evil.so\"}}]}",
+    )?;
+    assert_eq!(code, 403);
+    assert_eq!(json(&body)["error"], "env not allowed: PATH, LD_PRELOAD");
+
+    let (code, body) = request(
+        addr,
+        "POST",
+        "/runs",
+        "{\"exec_list\": [{\"exec\": \"echo\"}, {\"include\": \"/etc/nansi/admin.json\"}]}",
+    )?;
+    assert_eq!(code, 403);
+    assert_eq!(
+        json(&body)["error"],
+        "include not allowed: the NansiFile must hold all its items"
+    );
+
+    let (code, _) = request(addr, "POST", "/runs", "{\"exec_list\": ")?;
+    assert_eq!(code, 400);
+
+    let (code, body) = request(
+        addr,
+        "POST",
+        "/runs",
+        "{\"exec_list\": [{\"label\": \"hello\", \"exec\": \"echo\", \"args\": [\"hello\"], \"print_output\": true}, {\"exec\": \"echo\", \"prerequisites\": [\"hello\"]}]}",
+    )?;
+    assert_eq!(code, 201);
+    let id = json(&body)["id"].as_str().unwrap().to_string();
+
+    let (code, events) = request(addr, "GET", &format!("/runs/{}/events", id), "")?;
+    assert_eq!(code, 200);
+    let events: Vec<serde_json::Value> = events.lines().map(json).collect();
+    assert_eq!(events.first().unwrap()["event"], "run_started");
+    assert!(events
+        .iter()
+        .any(|e| e["event"] == "item_output" && e["data"] == "hello\n"));
+    assert_eq!(events.last().unwrap()["event"], "run_finished");
+
+    let report = wait_for_report(addr, &id)?;
+    assert_eq!(report["state"], "finished");
+    assert_eq!(report["summary"]["ok"], 2);
+    assert_eq!(report["trace"]["items"][0]["stdout"], "hello\n");
+
+    let (code, _) = request(addr, "POST", &format!("/runs/{}/cancel", id), "")?;
+    assert_eq!(code, 409);
+    let (code, _) = request(addr, "GET", "/runs/999/report", "")?;
+    assert_eq!(code, 404);
+    let (code, _) = request(addr, "DELETE", "/runs", "")?;
+    assert_eq!(code, 405);
+
+    Ok(())
+}
+
+#[test]
+fn serve_cancel_and_limit() -> Result<(), Box<dyn Error>> {
+    let addr = start_server(1)?;
+    let nansi_file =
+        "{\"exec_list\": [{\"exec\": \"sleep\", \"args\": [\"1\"]}, {\"exec\": \"echo\"}]}";
+
+    let (code, body) = request(addr, "POST", "/runs", nansi_file)?;
+    assert_eq!(code, 201);
+    let id = json(&body)["id"].as_str().unwrap().to_string();
+
+    let (code, body) = request(addr, "POST", "/runs", nansi_file)?;
+    assert_eq!(code, 429);
+    assert_eq!(
+        json(&body)["error"],
+        "1 run(s) in progress, try again later"
+    );
+
+    let (code, _) = request(addr, "POST", &format!("/runs/{}/cancel", id), "")?;
+    assert_eq!(code, 202);
+
+    let report = wait_for_report(addr, &id)?;
+    assert_eq!(report["state"], "cancelled");
+    assert_eq!(report["summary"]["ok"], 1);
+    assert_eq!(report["summary"]["skip"], 1);
+    assert_eq!(
+        report["trace"]["items"][1]["message"],
+        "Item [2] skipped: the run was cancelled."
+    );
+
+    let (code, _) = request(addr, "POST", "/runs", nansi_file)?;
+    assert_eq!(code, 201);
+
+    Ok(())
+}